@@ -0,0 +1,226 @@
+use std::collections::{BTreeMap, HashMap};
+
+use crate::{
+    environment::Environment,
+    natives,
+    resolver::{self, Declaration, Resolver},
+    stmt::Stmt,
+    symbol::Symbol,
+    token::Token,
+    value::Value,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Kind {
+    Variable,
+    Parameter,
+    Function,
+    Native,
+}
+
+// A variable, parameter or function, with everywhere it is declared and
+// used. A global can be declared more than once, and natives never are.
+#[derive(Debug)]
+pub struct Definition {
+    pub name: Symbol,
+    pub kind: Kind,
+    pub declarations: Vec<Token>,
+    pub references: Vec<Token>,
+    // of functions; natives have an arity but no parameter names
+    pub params: Vec<Symbol>,
+    pub arity: Option<usize>,
+    pub doc: Option<String>,
+}
+
+// What each name in a program refers to, as the resolver finds it, for
+// editors to jump between declarations and uses.
+pub struct Analysis {
+    pub definitions: Vec<Definition>,
+    // the line and column of every name, with where it ends and what it is
+    names: BTreeMap<(usize, usize), (usize, usize)>,
+}
+
+impl Analysis {
+    pub fn new(statements: &[Stmt]) -> Self {
+        let mut analyzer = Analyzer {
+            definitions: Vec::new(),
+            globals: HashMap::new(),
+            names: BTreeMap::new(),
+        };
+
+        let mut natives = Environment::default();
+        natives::define(&mut natives, Vec::new());
+        for (name, value) in natives.values() {
+            if let Value::Native(native) = value {
+                analyzer
+                    .globals
+                    .insert(name.clone(), analyzer.definitions.len());
+                analyzer.definitions.push(Definition {
+                    name: name.clone(),
+                    kind: Kind::Native,
+                    declarations: Vec::new(),
+                    references: Vec::new(),
+                    params: Vec::new(),
+                    arity: Some(native.arity),
+                    doc: None,
+                });
+            }
+        }
+
+        let mut resolver = Resolver::new();
+        resolver.resolve(statements);
+        // the definition of each declaration the resolver found
+        let locals = resolver
+            .declarations()
+            .iter()
+            .map(|declaration| analyzer.declare(declaration))
+            .collect::<Vec<_>>();
+        for usage in resolver.uses() {
+            let index = match usage.declaration {
+                Some(declaration) => locals[declaration],
+                None => analyzer.global(usage.name.lexeme.clone()),
+            };
+            analyzer.definitions[index]
+                .references
+                .push(usage.name.clone());
+            analyzer.name(&usage.name, index);
+        }
+
+        Analysis {
+            definitions: analyzer.definitions,
+            names: analyzer.names,
+        }
+    }
+
+    // The definition of the name at `line` and `column`, counted in
+    // characters like those of tokens.
+    pub fn at(&self, line: usize, column: usize) -> Option<&Definition> {
+        let (&(start_line, _), &(end, index)) = self.names.range(..=(line, column)).next_back()?;
+        (start_line == line && column < end).then(|| &self.definitions[index])
+    }
+}
+
+struct Analyzer {
+    definitions: Vec<Definition>,
+    globals: HashMap<Symbol, usize>,
+    names: BTreeMap<(usize, usize), (usize, usize)>,
+}
+
+impl Analyzer {
+    fn declare(&mut self, declaration: &Declaration) -> usize {
+        let name = &declaration.name;
+        let index = if declaration.depth > 0 {
+            self.definitions.push(definition(name.lexeme.clone()));
+            self.definitions.len() - 1
+        } else {
+            self.global(name.lexeme.clone())
+        };
+
+        let definition = &mut self.definitions[index];
+        // a global used before its declaration, or redeclaring a native
+        if definition.declarations.is_empty() {
+            definition.kind = match declaration.kind {
+                resolver::Kind::Variable | resolver::Kind::Catch => Kind::Variable,
+                resolver::Kind::Parameter => Kind::Parameter,
+                resolver::Kind::Function => Kind::Function,
+            };
+            if let Some(function) = &declaration.function {
+                definition.params = function
+                    .params
+                    .iter()
+                    .map(|param| param.lexeme.clone())
+                    .collect();
+                definition.arity = Some(function.params.len());
+            }
+        }
+        if definition.doc.is_none() {
+            definition.doc.clone_from(&declaration.doc);
+        }
+        definition.declarations.push(name.clone());
+        self.name(name, index);
+        index
+    }
+
+    fn global(&mut self, name: Symbol) -> usize {
+        let definitions = &mut self.definitions;
+        *self.globals.entry(name.clone()).or_insert_with(|| {
+            definitions.push(definition(name));
+            definitions.len() - 1
+        })
+    }
+
+    fn name(&mut self, name: &Token, index: usize) {
+        let end = name.column + name.lexeme.chars().count();
+        self.names.insert((name.line, name.column), (end, index));
+    }
+}
+
+fn definition(name: Symbol) -> Definition {
+    Definition {
+        name,
+        kind: Kind::Variable,
+        declarations: Vec::new(),
+        references: Vec::new(),
+        params: Vec::new(),
+        arity: None,
+        doc: None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{parser::Parser, scanner::Scanner};
+
+    fn analyze(source: &str) -> Analysis {
+        let tokens = Scanner::new(source).scan_tokens();
+        Analysis::new(&Parser::new(tokens).parse())
+    }
+
+    fn lines(tokens: &[Token]) -> Vec<(usize, usize)> {
+        tokens
+            .iter()
+            .map(|token| (token.line, token.column))
+            .collect()
+    }
+
+    #[test]
+    fn test_scopes() {
+        let analysis = analyze(
+            "var a = 1;\n{\n  var a = a;\n  print a;\n}\nfun f(a) { return g(a); }\nfun g(b) {}\nprint a;",
+        );
+
+        let global = analysis.at(1, 5).unwrap();
+        assert_eq!(lines(&global.declarations), vec![(1, 5)]);
+        // the initializer of the inner `a` still sees the global
+        assert_eq!(lines(&global.references), vec![(3, 11), (8, 7)]);
+
+        let inner = analysis.at(4, 9).unwrap();
+        assert_eq!(lines(&inner.declarations), vec![(3, 7)]);
+
+        let param = analysis.at(6, 21).unwrap();
+        assert_eq!(
+            (param.kind, lines(&param.declarations)),
+            (Kind::Parameter, vec![(6, 7)])
+        );
+
+        // used before it is declared
+        let g = analysis.at(6, 19).unwrap();
+        assert_eq!(g.kind, Kind::Function);
+        assert_eq!(lines(&g.declarations), vec![(7, 5)]);
+        assert_eq!(g.params, vec![Symbol::intern("b")]);
+
+        assert!(analysis.at(6, 18).is_none());
+        assert!(analysis.at(6, 20).is_none());
+    }
+
+    #[test]
+    fn test_natives_and_docs() {
+        let analysis = analyze("/// Seconds since the epoch.\nvar now = clock();\nprint now;");
+        let clock = analysis.at(2, 11).unwrap();
+        assert_eq!((clock.kind, clock.arity), (Kind::Native, Some(0)));
+
+        let now = analysis.at(3, 7).unwrap();
+        assert_eq!(now.doc.as_deref(), Some("Seconds since the epoch."));
+    }
+}
@@ -0,0 +1,113 @@
+use crate::{
+    expr::{Evaluation, Expr, RuntimeError},
+    gc::{GcConfig, Heap},
+    module::Modules,
+    stmt::Stmt,
+    value::Value,
+    vm::Vm,
+};
+
+// The most calls in progress before a stack overflow, unless set otherwise:
+// deep enough for any reasonable recursion, but stops a runaway one before
+// it eats all the memory.
+pub const MAX_DEPTH: usize = 10_000;
+
+// The deepest `--max-depth` allowed. The tree-walking interpreter recurses on
+// the Rust stack, and a call nested in blocks, loops and expressions takes up
+// to about 40 KiB of it in a debug build, so this many fit in `STACK_SIZE`.
+pub const MAX_DEPTH_LIMIT: usize = 15_000;
+
+// The stack of the thread `main` runs programs on, and how much of it the
+// tree-walking interpreter expects to have unless told otherwise. Only what
+// is used is allocated.
+pub const STACK_SIZE: usize = 1 << 30;
+
+// What runs a program: the tree-walking `Evaluation` or the bytecode `Vm`.
+// Both keep their globals from one program to the next, for the REPL.
+pub trait Interpreter {
+    // Expects `statements` to have been resolved.
+    fn interpret(&mut self, statements: &[Stmt]) -> Result<(), RuntimeError>;
+    fn evaluate(&mut self, expr: &Expr) -> Result<Value, RuntimeError>;
+    fn globals(&self) -> Vec<(String, Value)>;
+    fn heap(&mut self) -> &mut Heap;
+    fn set_max_depth(&mut self, max_depth: usize);
+    fn set_modules(&mut self, modules: Modules);
+}
+
+impl Interpreter for Evaluation {
+    fn interpret(&mut self, statements: &[Stmt]) -> Result<(), RuntimeError> {
+        Evaluation::interpret(self, statements)
+    }
+    fn evaluate(&mut self, expr: &Expr) -> Result<Value, RuntimeError> {
+        Evaluation::evaluate(self, expr)
+    }
+    fn globals(&self) -> Vec<(String, Value)> {
+        Evaluation::globals(self)
+    }
+    fn heap(&mut self) -> &mut Heap {
+        Evaluation::heap(self)
+    }
+    fn set_max_depth(&mut self, max_depth: usize) {
+        Evaluation::set_max_depth(self, max_depth)
+    }
+    fn set_modules(&mut self, modules: Modules) {
+        Evaluation::set_modules(self, modules)
+    }
+}
+
+impl Interpreter for Vm {
+    fn interpret(&mut self, statements: &[Stmt]) -> Result<(), RuntimeError> {
+        Vm::interpret(self, statements)
+    }
+    fn evaluate(&mut self, expr: &Expr) -> Result<Value, RuntimeError> {
+        Vm::evaluate(self, expr)
+    }
+    fn globals(&self) -> Vec<(String, Value)> {
+        Vm::globals(self)
+    }
+    fn heap(&mut self) -> &mut Heap {
+        Vm::heap(self)
+    }
+    fn set_max_depth(&mut self, max_depth: usize) {
+        Vm::set_max_depth(self, max_depth)
+    }
+    fn set_modules(&mut self, modules: Modules) {
+        Vm::set_modules(self, modules)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, clap::ValueEnum)]
+pub enum Backend {
+    Tree,
+    Vm,
+}
+
+impl Backend {
+    // `script_args` are the command-line arguments returned by `args()`.
+    pub fn interpreter(self, script_args: Vec<String>, gc: GcConfig) -> Box<dyn Interpreter> {
+        let mut interpreter: Box<dyn Interpreter> = match self {
+            Backend::Tree => Box::new(Evaluation::with_args(script_args)),
+            Backend::Vm => Box::new(Vm::with_args(script_args)),
+        };
+        interpreter.heap().configure(gc);
+        interpreter
+    }
+}
+
+// Runs `test` on every backend, which must behave the same, also while
+// collecting garbage on every allocation.
+#[cfg(test)]
+pub fn each_backend(script_args: &[&str], test: impl Fn(&mut dyn Interpreter)) {
+    use clap::ValueEnum;
+
+    for backend in Backend::value_variants() {
+        for stress in [false, true] {
+            let script_args = script_args.iter().map(|arg| arg.to_string()).collect();
+            let gc = GcConfig {
+                stress,
+                ..GcConfig::default()
+            };
+            test(&mut *backend.interpreter(script_args, gc));
+        }
+    }
+}
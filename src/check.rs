@@ -0,0 +1,104 @@
+use serde::Serialize;
+
+use crate::{parser::Parser, resolver::Resolver, scanner::Scanner, stmt::Stmt};
+
+// An error found in a file without running it.
+#[derive(Debug, Serialize)]
+pub struct Diagnostic {
+    pub file: String,
+    pub line: usize,
+    pub column: usize,
+    pub message: String,
+}
+
+impl std::fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}:{}:{}: error: {}",
+            self.file, self.line, self.column, self.message
+        )
+    }
+}
+
+// Scans, parses and resolves `source`, collecting the errors of every phase
+// in the order they appear in the file.
+// The parser recovers from errors, so the resolver still sees most of the
+// program when there are syntax errors.
+pub fn check(file: &str, source: &str) -> Vec<Diagnostic> {
+    parse(file, source).1
+}
+
+// Like `check`, also returning the statements, which can only be run when
+// there are no errors.
+pub fn parse(file: &str, source: &str) -> (Vec<Stmt>, Vec<Diagnostic>) {
+    let diagnostic = |line, column, message: String| Diagnostic {
+        file: file.to_string(),
+        line,
+        column,
+        message,
+    };
+    let mut diagnostics = Vec::new();
+
+    let mut scanner = Scanner::new(source);
+    let tokens = scanner.scan_tokens();
+    for error in scanner.errors() {
+        diagnostics.push(diagnostic(error.line, error.column, error.message.clone()));
+    }
+
+    let mut parser = Parser::new(tokens);
+    let statements = parser.parse();
+    for error in parser.errors() {
+        let message = format!("at {}: {}", error.location, error.message);
+        diagnostics.push(diagnostic(error.line, error.column, message));
+    }
+
+    let mut resolver = Resolver::new();
+    resolver.resolve(&statements);
+    for error in resolver.errors() {
+        let message = format!("at {}: {}", error.location, error.message);
+        diagnostics.push(diagnostic(error.line, error.column, message));
+    }
+
+    diagnostics.sort_by_key(|d| (d.line, d.column));
+    (statements, diagnostics)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_check_reports_every_phase() {
+        let diagnostics = check("a.lox", "var s = \"open;\n");
+        let lines: Vec<_> = diagnostics.iter().map(|d| d.to_string()).collect();
+        assert_eq!(
+            lines,
+            vec![
+                "a.lox:1:9: error: unterminated string",
+                "a.lox:2:1: error: at end: Expect expression.",
+            ]
+        );
+
+        let diagnostics = check("b.lox", "print 1;\n  return 2;\n{ var x = x; }");
+        let lines: Vec<_> = diagnostics.iter().map(|d| d.to_string()).collect();
+        assert_eq!(
+            lines,
+            vec![
+                "b.lox:2:3: error: at 'return': Can't return from top-level code.",
+                "b.lox:3:11: error: at 'x': Can't read local variable in its own initializer.",
+            ]
+        );
+
+        assert!(check("c.lox", "fun f(a) { return a; }").is_empty());
+    }
+
+    #[test]
+    fn test_check_json() {
+        let diagnostics = check("a.lox", "1 = 2;");
+        assert_eq!(
+            serde_json::to_string(&diagnostics).unwrap(),
+            r#"[{"file":"a.lox","line":1,"column":3,"message":"at '=': Invalid assignment target."}]"#
+        );
+    }
+}
@@ -0,0 +1,221 @@
+use std::{fmt, rc::Rc};
+
+use crate::symbol::Symbol;
+
+// Operands follow their opcode in the code: constant and global name
+// indexes, jump offsets and the `Closure` constant take two bytes, big
+// endian, and local slots, upvalue indexes and argument counts one.
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[repr(u8)]
+pub enum OpCode {
+    Constant,
+    Nil,
+    True,
+    False,
+    Pop,
+    GetLocal,
+    SetLocal,
+    GetGlobal,
+    DefineGlobal,
+    SetGlobal,
+    GetUpvalue,
+    SetUpvalue,
+    Equal,
+    NotEqual,
+    Greater,
+    GreaterEqual,
+    Less,
+    LessEqual,
+    Add,
+    Subtract,
+    Multiply,
+    Divide,
+    Not,
+    Negate,
+    Print,
+    Jump,
+    JumpIfFalse,
+    Loop,
+    Call,
+    // followed by a pair of bytes for every upvalue of the function: 1 if it
+    // captures a local of the enclosing function, 0 if one of its upvalues,
+    // and the slot or index
+    Closure,
+    CloseUpvalue,
+    Return,
+    // the property named by a constant, of the module or error on the stack
+    GetProperty,
+    // runs the module at the path in a string constant, unless it ran
+    // already, and pushes it
+    Import,
+    // throws the value on the stack, as the error it is or with it as the
+    // message of a new one
+    Throw,
+}
+
+impl OpCode {
+    const ALL: [OpCode; 35] = [
+        OpCode::Constant,
+        OpCode::Nil,
+        OpCode::True,
+        OpCode::False,
+        OpCode::Pop,
+        OpCode::GetLocal,
+        OpCode::SetLocal,
+        OpCode::GetGlobal,
+        OpCode::DefineGlobal,
+        OpCode::SetGlobal,
+        OpCode::GetUpvalue,
+        OpCode::SetUpvalue,
+        OpCode::Equal,
+        OpCode::NotEqual,
+        OpCode::Greater,
+        OpCode::GreaterEqual,
+        OpCode::Less,
+        OpCode::LessEqual,
+        OpCode::Add,
+        OpCode::Subtract,
+        OpCode::Multiply,
+        OpCode::Divide,
+        OpCode::Not,
+        OpCode::Negate,
+        OpCode::Print,
+        OpCode::Jump,
+        OpCode::JumpIfFalse,
+        OpCode::Loop,
+        OpCode::Call,
+        OpCode::Closure,
+        OpCode::CloseUpvalue,
+        OpCode::Return,
+        OpCode::GetProperty,
+        OpCode::Import,
+        OpCode::Throw,
+    ];
+}
+
+impl TryFrom<u8> for OpCode {
+    type Error = u8;
+
+    fn try_from(byte: u8) -> Result<Self, Self::Error> {
+        OpCode::ALL.get(byte as usize).copied().ok_or(byte)
+    }
+}
+
+// Values known at compile time, and the names of globals.
+#[derive(Debug, Clone)]
+pub enum Constant {
+    Number(f64),
+    String(Rc<str>),
+    Function(Rc<Function>),
+    Name(Symbol),
+}
+
+impl fmt::Display for Constant {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Constant::Number(n) => write!(f, "{}", n),
+            Constant::String(s) => write!(f, "{}", s),
+            Constant::Function(function) => write!(f, "<fn {}>", function.name),
+            Constant::Name(name) => write!(f, "{}", name),
+        }
+    }
+}
+
+// A compiled function. The top-level code of a script is a function too,
+// named `script`.
+#[derive(Debug)]
+pub struct Function {
+    pub name: String,
+    pub arity: usize,
+    pub upvalues: usize,
+    pub chunk: Chunk,
+}
+
+#[derive(Debug, Default)]
+pub struct Chunk {
+    pub code: Vec<u8>,
+    pub constants: Vec<Constant>,
+    // run-length encoded source line of every byte, as (line, count)
+    pub lines: Vec<(usize, usize)>,
+    // innermost first, so the first one around an instruction catches its
+    // errors
+    pub handlers: Vec<Handler>,
+}
+
+// Catches the errors of the code from `start` up to `end`, or of the calls
+// it makes: the stack is cut back to `depth` slots above the base of the
+// frame, the error pushed, and the code continues at `target`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Handler {
+    pub start: usize,
+    pub end: usize,
+    pub target: usize,
+    pub depth: usize,
+}
+
+impl Chunk {
+    pub fn write(&mut self, byte: u8, line: usize) {
+        self.code.push(byte);
+        match self.lines.last_mut() {
+            Some((last, count)) if *last == line => *count += 1,
+            _ => self.lines.push((line, 1)),
+        }
+    }
+
+    pub fn write_op(&mut self, op: OpCode, line: usize) {
+        self.write(op as u8, line);
+    }
+
+    pub fn add_constant(&mut self, constant: Constant) -> usize {
+        self.constants.push(constant);
+        self.constants.len() - 1
+    }
+
+    pub fn read_u16(&self, offset: usize) -> u16 {
+        u16::from_be_bytes([self.code[offset], self.code[offset + 1]])
+    }
+
+    // The handler catching an error in the instruction `offset` is part of.
+    pub fn handler(&self, offset: usize) -> Option<&Handler> {
+        self.handlers
+            .iter()
+            .find(|handler| handler.start <= offset && offset < handler.end)
+    }
+
+    pub fn line(&self, offset: usize) -> usize {
+        let mut end = 0;
+        for &(line, count) in &self.lines {
+            end += count;
+            if offset < end {
+                return line;
+            }
+        }
+        0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_line_table() {
+        let mut chunk = Chunk::default();
+        chunk.write_op(OpCode::Nil, 1);
+        chunk.write_op(OpCode::Nil, 1);
+        chunk.write_op(OpCode::Pop, 3);
+        chunk.write_op(OpCode::Return, 4);
+
+        assert_eq!(chunk.lines, vec![(1, 2), (3, 1), (4, 1)]);
+        let lines = (0..4).map(|offset| chunk.line(offset)).collect::<Vec<_>>();
+        assert_eq!(lines, vec![1, 1, 3, 4]);
+    }
+
+    #[test]
+    fn test_opcodes_round_trip() {
+        for op in OpCode::ALL {
+            assert_eq!(OpCode::try_from(op as u8), Ok(op));
+        }
+        assert_eq!(OpCode::try_from(OpCode::ALL.len() as u8), Err(35));
+    }
+}
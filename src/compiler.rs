@@ -0,0 +1,844 @@
+use std::{cell::Cell, rc::Rc};
+
+use crate::{
+    chunk::{Chunk, Constant, Function, Handler, OpCode},
+    expr::{self, Expr},
+    stmt::{self, Catch, FunctionDecl, Stmt},
+    symbol::Symbol,
+    token::{LiteralValue, Token, TokenType, Trivia},
+};
+
+// Local slots and upvalue indexes are a single byte.
+const MAX_LOCALS: usize = 256;
+const MAX_UPVALUES: usize = 256;
+
+#[derive(Debug, Clone)]
+pub struct CompileError {
+    pub line: usize,
+    pub message: String,
+}
+
+impl std::fmt::Display for CompileError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "error at line {}: {}", self.line, self.message)
+    }
+}
+
+type Compiled = Result<(), CompileError>;
+
+// Compiles a resolved program into the function run by the VM.
+pub fn compile(statements: &[Stmt]) -> Result<Function, CompileError> {
+    let mut compiler = Compiler::new();
+    for stmt in statements {
+        stmt.accept(&mut compiler)?;
+    }
+    compiler.emit(OpCode::Nil);
+    compiler.emit(OpCode::Return);
+    Ok(compiler.finish())
+}
+
+// Compiles a function returning the value of `expr`, e.g., for the REPL.
+pub fn compile_expression(expr: &Expr) -> Result<Function, CompileError> {
+    let mut compiler = Compiler::new();
+    expr.accept(&mut compiler)?;
+    compiler.emit(OpCode::Return);
+    Ok(compiler.finish())
+}
+
+struct Local {
+    name: Symbol,
+    depth: usize,
+    // closed over by a nested function, so it must outlive its scope
+    captured: bool,
+}
+
+struct Upvalue {
+    index: u8,
+    is_local: bool,
+}
+
+// A `try` whose body, or whose `catch` for the `finally` of one, is being
+// compiled.
+struct Try {
+    // the locals when it started, which its handler cuts the stack back to
+    depth: usize,
+    // where the code it protects starts again, after the last `return` in it
+    start: usize,
+    // the code protected so far, leaving out the `finally` blocks run for
+    // a `return`
+    ranges: Vec<(usize, usize)>,
+    // to run before returning from inside it
+    finally: Option<Vec<Stmt>>,
+}
+
+struct FunctionState {
+    name: String,
+    arity: usize,
+    chunk: Chunk,
+    // slot 0 holds the function being called
+    locals: Vec<Local>,
+    upvalues: Vec<Upvalue>,
+    scope_depth: usize,
+    // innermost last
+    tries: Vec<Try>,
+}
+
+impl FunctionState {
+    fn new(name: &str, arity: usize) -> Self {
+        FunctionState {
+            name: name.to_string(),
+            arity,
+            chunk: Chunk::default(),
+            locals: vec![Local {
+                name: Symbol::intern(""),
+                depth: 0,
+                captured: false,
+            }],
+            upvalues: Vec::new(),
+            scope_depth: 0,
+            tries: Vec::new(),
+        }
+    }
+}
+
+// Resolves variables itself, in the same scopes as the resolver, since the
+// VM keeps locals on its stack instead of in environments.
+struct Compiler {
+    // the function being compiled, after the ones enclosing it
+    functions: Vec<FunctionState>,
+    // line of the last token seen, for the instructions being emitted
+    line: usize,
+}
+
+impl Compiler {
+    fn new() -> Self {
+        Compiler {
+            functions: vec![FunctionState::new("script", 0)],
+            line: 1,
+        }
+    }
+
+    fn finish(mut self) -> Function {
+        let state = self.functions.pop().expect("the script is never popped");
+        Function {
+            name: state.name,
+            arity: state.arity,
+            upvalues: state.upvalues.len(),
+            chunk: state.chunk,
+        }
+    }
+
+    fn state(&mut self) -> &mut FunctionState {
+        self.functions
+            .last_mut()
+            .expect("there is always a function being compiled")
+    }
+
+    fn error(&self, message: &str) -> CompileError {
+        CompileError {
+            line: self.line,
+            message: message.to_string(),
+        }
+    }
+
+    fn emit(&mut self, op: OpCode) {
+        let line = self.line;
+        self.state().chunk.write_op(op, line);
+    }
+
+    fn emit_byte(&mut self, byte: u8) {
+        let line = self.line;
+        self.state().chunk.write(byte, line);
+    }
+
+    fn emit_u16(&mut self, value: u16) {
+        for byte in value.to_be_bytes() {
+            self.emit_byte(byte);
+        }
+    }
+
+    fn emit_with_u16(&mut self, op: OpCode, operand: u16) {
+        self.emit(op);
+        self.emit_u16(operand);
+    }
+
+    fn make_constant(&mut self, constant: Constant) -> Result<u16, CompileError> {
+        let index = self.state().chunk.add_constant(constant);
+        u16::try_from(index).map_err(|_| self.error("Too many constants in one chunk."))
+    }
+
+    // Names of globals are reused rather than added for every use.
+    fn name_constant(&mut self, name: Symbol) -> Result<u16, CompileError> {
+        let existing = self
+            .state()
+            .chunk
+            .constants
+            .iter()
+            .position(|constant| matches!(constant, Constant::Name(n) if *n == name));
+        match existing {
+            Some(index) => {
+                u16::try_from(index).map_err(|_| self.error("Too many constants in one chunk."))
+            }
+            None => self.make_constant(Constant::Name(name)),
+        }
+    }
+
+    // Returns the offset of the jump, to be patched once its target is known.
+    fn emit_jump(&mut self, op: OpCode) -> usize {
+        self.emit_with_u16(op, u16::MAX);
+        self.state().chunk.code.len() - 2
+    }
+
+    fn patch_jump(&mut self, offset: usize) -> Compiled {
+        let jump = self.state().chunk.code.len() - offset - 2;
+        let jump = u16::try_from(jump).map_err(|_| self.error("Too much code to jump over."))?;
+        let code = &mut self.state().chunk.code;
+        code[offset..offset + 2].copy_from_slice(&jump.to_be_bytes());
+        Ok(())
+    }
+
+    fn emit_loop(&mut self, start: usize) -> Compiled {
+        // the offset is counted from after the operand
+        let jump = self.state().chunk.code.len() + 3 - start;
+        let jump = u16::try_from(jump).map_err(|_| self.error("Loop body too large."))?;
+        self.emit_with_u16(OpCode::Loop, jump);
+        Ok(())
+    }
+
+    fn begin_scope(&mut self) {
+        self.state().scope_depth += 1;
+    }
+
+    fn end_scope(&mut self) {
+        self.state().scope_depth -= 1;
+        loop {
+            let state = self.state();
+            let captured = match state.locals.last() {
+                Some(local) if local.depth > state.scope_depth => local.captured,
+                _ => break,
+            };
+            state.locals.pop();
+            match captured {
+                true => self.emit(OpCode::CloseUpvalue),
+                false => self.emit(OpCode::Pop),
+            }
+        }
+    }
+
+    fn add_local(&mut self, name: &Token) -> Compiled {
+        self.push_local(name.lexeme.clone())
+    }
+
+    // A slot the compiler keeps a value in, which no variable can name.
+    fn add_hidden_local(&mut self) -> Compiled {
+        self.push_local(Symbol::intern(""))
+    }
+
+    fn push_local(&mut self, name: Symbol) -> Compiled {
+        if self.state().locals.len() >= MAX_LOCALS {
+            return Err(self.error("Too many local variables in function."));
+        }
+        let state = self.state();
+        let depth = state.scope_depth;
+        state.locals.push(Local {
+            name,
+            depth,
+            captured: false,
+        });
+        Ok(())
+    }
+
+    // Forgets the locals of the scope being ended, without popping them,
+    // for code that never gets past its end.
+    fn abandon_scope(&mut self) {
+        let state = self.state();
+        state.scope_depth -= 1;
+        let depth = state.scope_depth;
+        state.locals.retain(|local| local.depth <= depth);
+    }
+
+    fn block(&mut self, statements: &[Stmt]) -> Compiled {
+        self.begin_scope();
+        for stmt in statements {
+            stmt.accept(&mut *self)?;
+        }
+        self.end_scope();
+        Ok(())
+    }
+
+    // Starts protecting the code that follows, up to `end_try`.
+    fn begin_try(&mut self, finally: Option<&[Stmt]>) {
+        let state = self.state();
+        let (depth, start) = (state.locals.len(), state.chunk.code.len());
+        state.tries.push(Try {
+            depth,
+            start,
+            ranges: Vec::new(),
+            finally: finally.map(<[Stmt]>::to_vec),
+        });
+    }
+
+    // Stops protecting code, returning the `Try` begun last.
+    fn end_try(&mut self) -> Try {
+        let state = self.state();
+        let mut current = state.tries.pop().expect("begun by begin_try");
+        let end = state.chunk.code.len();
+        if current.start < end {
+            current.ranges.push((current.start, end));
+        }
+        current
+    }
+
+    // Has the errors of the code `current` protected caught by the code
+    // that follows.
+    fn catch(&mut self, current: Try) {
+        let state = self.state();
+        let target = state.chunk.code.len();
+        state
+            .chunk
+            .handlers
+            .extend(current.ranges.iter().map(|&(start, end)| Handler {
+                start,
+                end,
+                target,
+                depth: current.depth,
+            }));
+    }
+
+    fn resolve_local(&self, function: usize, name: &Symbol) -> Option<u8> {
+        self.functions[function]
+            .locals
+            .iter()
+            .rposition(|local| local.name == *name)
+            .map(|slot| slot as u8)
+    }
+
+    fn resolve_upvalue(
+        &mut self,
+        function: usize,
+        name: &Symbol,
+    ) -> Result<Option<u8>, CompileError> {
+        if function == 0 {
+            return Ok(None);
+        }
+
+        let enclosing = function - 1;
+        if let Some(slot) = self.resolve_local(enclosing, name) {
+            self.functions[enclosing].locals[slot as usize].captured = true;
+            return self.add_upvalue(function, slot, true).map(Some);
+        }
+        match self.resolve_upvalue(enclosing, name)? {
+            Some(index) => self.add_upvalue(function, index, false).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    fn add_upvalue(
+        &mut self,
+        function: usize,
+        index: u8,
+        is_local: bool,
+    ) -> Result<u8, CompileError> {
+        let upvalues = &self.functions[function].upvalues;
+        if let Some(existing) = upvalues
+            .iter()
+            .position(|upvalue| upvalue.index == index && upvalue.is_local == is_local)
+        {
+            return Ok(existing as u8);
+        }
+        if upvalues.len() >= MAX_UPVALUES {
+            return Err(self.error("Too many closure variables in function."));
+        }
+
+        let upvalues = &mut self.functions[function].upvalues;
+        upvalues.push(Upvalue { index, is_local });
+        Ok((upvalues.len() - 1) as u8)
+    }
+
+    fn variable(&mut self, name: &Token, assign: bool) -> Compiled {
+        self.line = name.line;
+        let function = self.functions.len() - 1;
+        let (op, operand) = if let Some(slot) = self.resolve_local(function, &name.lexeme) {
+            let op = if assign {
+                OpCode::SetLocal
+            } else {
+                OpCode::GetLocal
+            };
+            (op, slot as u16)
+        } else if let Some(index) = self.resolve_upvalue(function, &name.lexeme)? {
+            let op = if assign {
+                OpCode::SetUpvalue
+            } else {
+                OpCode::GetUpvalue
+            };
+            (op, index as u16)
+        } else {
+            let op = if assign {
+                OpCode::SetGlobal
+            } else {
+                OpCode::GetGlobal
+            };
+            let index = self.name_constant(name.lexeme.clone())?;
+            self.emit_with_u16(op, index);
+            return Ok(());
+        };
+
+        self.emit(op);
+        self.emit_byte(operand as u8);
+        Ok(())
+    }
+
+    // Declares the variable `name` holding the value on top of the stack.
+    fn declare(&mut self, name: &Token) -> Compiled {
+        if self.state().scope_depth > 0 {
+            self.add_local(name)?;
+        }
+        self.define(name)
+    }
+
+    // Locals are left on the stack, in the slot added for them.
+    fn define(&mut self, name: &Token) -> Compiled {
+        if self.state().scope_depth > 0 {
+            return Ok(());
+        }
+        let index = self.name_constant(name.lexeme.clone())?;
+        self.emit_with_u16(OpCode::DefineGlobal, index);
+        Ok(())
+    }
+
+    fn function(&mut self, declaration: &FunctionDecl) -> Compiled {
+        let name = &declaration.name.lexeme;
+        self.functions
+            .push(FunctionState::new(name, declaration.params.len()));
+        self.begin_scope();
+        for param in &declaration.params {
+            self.add_local(param)?;
+        }
+        for stmt in &declaration.body {
+            stmt.accept(&mut *self)?;
+        }
+        self.emit(OpCode::Nil);
+        self.emit(OpCode::Return);
+
+        let state = self.functions.pop().expect("pushed above");
+        let function = Function {
+            name: state.name,
+            arity: state.arity,
+            upvalues: state.upvalues.len(),
+            chunk: state.chunk,
+        };
+        self.line = declaration.name.line;
+        let index = self.make_constant(Constant::Function(Rc::new(function)))?;
+        self.emit_with_u16(OpCode::Closure, index);
+        for upvalue in state.upvalues {
+            self.emit_byte(upvalue.is_local as u8);
+            self.emit_byte(upvalue.index);
+        }
+        Ok(())
+    }
+}
+
+impl expr::Visitor<Compiled> for &mut Compiler {
+    fn visit_binary(self, left: &Expr, op: &Token, right: &Expr) -> Compiled {
+        left.accept(&mut *self)?;
+        right.accept(&mut *self)?;
+        self.line = op.line;
+        self.emit(match op.ttype {
+            TokenType::Minus => OpCode::Subtract,
+            TokenType::Plus => OpCode::Add,
+            TokenType::Star => OpCode::Multiply,
+            TokenType::Slash => OpCode::Divide,
+            TokenType::EqualEqual => OpCode::Equal,
+            TokenType::BangEqual => OpCode::NotEqual,
+            TokenType::Less => OpCode::Less,
+            TokenType::LessEqual => OpCode::LessEqual,
+            TokenType::Greater => OpCode::Greater,
+            TokenType::GreaterEqual => OpCode::GreaterEqual,
+            _ => unreachable!("the parser only builds binary expressions from binary operators"),
+        });
+        Ok(())
+    }
+
+    fn visit_unary(self, op: &Token, right: &Expr) -> Compiled {
+        right.accept(&mut *self)?;
+        self.line = op.line;
+        self.emit(match op.ttype {
+            TokenType::Bang => OpCode::Not,
+            TokenType::Minus => OpCode::Negate,
+            _ => unreachable!("the parser only builds unary expressions from unary operators"),
+        });
+        Ok(())
+    }
+
+    fn visit_literal(self, value: &Option<LiteralValue>, token: &Token) -> Compiled {
+        self.line = token.line;
+        let constant = match value {
+            Some(LiteralValue::Number(n)) => Constant::Number(*n),
+            Some(LiteralValue::String(s)) => Constant::String(s.to_rc()),
+            Some(LiteralValue::Boolean(true)) => {
+                self.emit(OpCode::True);
+                return Ok(());
+            }
+            Some(LiteralValue::Boolean(false)) => {
+                self.emit(OpCode::False);
+                return Ok(());
+            }
+            Some(LiteralValue::Nil) | None => {
+                self.emit(OpCode::Nil);
+                return Ok(());
+            }
+        };
+        let index = self.make_constant(constant)?;
+        self.emit_with_u16(OpCode::Constant, index);
+        Ok(())
+    }
+
+    fn visit_grouped(self, expr: &Expr) -> Compiled {
+        expr.accept(self)
+    }
+
+    // Short-circuits, leaving the operand that decided the result.
+    fn visit_logical(self, left: &Expr, op: &Token, right: &Expr) -> Compiled {
+        left.accept(&mut *self)?;
+        self.line = op.line;
+        let end = match op.ttype {
+            TokenType::And => self.emit_jump(OpCode::JumpIfFalse),
+            _ => {
+                let right = self.emit_jump(OpCode::JumpIfFalse);
+                let end = self.emit_jump(OpCode::Jump);
+                self.patch_jump(right)?;
+                end
+            }
+        };
+        self.emit(OpCode::Pop);
+        right.accept(&mut *self)?;
+        self.patch_jump(end)
+    }
+
+    fn visit_variable(self, name: &Token, _depth: &Cell<Option<usize>>) -> Compiled {
+        self.variable(name, false)
+    }
+
+    fn visit_assign(self, name: &Token, value: &Expr, _depth: &Cell<Option<usize>>) -> Compiled {
+        value.accept(&mut *self)?;
+        self.variable(name, true)
+    }
+
+    fn visit_call(self, callee: &Expr, paren: &Token, arguments: &[Expr]) -> Compiled {
+        callee.accept(&mut *self)?;
+        for argument in arguments {
+            argument.accept(&mut *self)?;
+        }
+        self.line = paren.line;
+        self.emit(OpCode::Call);
+        // the parser allows at most 255 arguments
+        self.emit_byte(arguments.len() as u8);
+        Ok(())
+    }
+
+    fn visit_get(self, object: &Expr, name: &Token) -> Compiled {
+        object.accept(&mut *self)?;
+        self.line = name.line;
+        let index = self.name_constant(name.lexeme.clone())?;
+        self.emit_with_u16(OpCode::GetProperty, index);
+        Ok(())
+    }
+}
+
+impl stmt::Visitor<Compiled> for &mut Compiler {
+    fn visit_expression(self, expr: &Expr) -> Compiled {
+        expr.accept(&mut *self)?;
+        self.emit(OpCode::Pop);
+        Ok(())
+    }
+
+    fn visit_print(self, expr: &Expr) -> Compiled {
+        expr.accept(&mut *self)?;
+        self.emit(OpCode::Print);
+        Ok(())
+    }
+
+    fn visit_var(self, name: &Token, initializer: Option<&Expr>, _doc: Option<&str>) -> Compiled {
+        self.line = name.line;
+        match initializer {
+            Some(initializer) => initializer.accept(&mut *self)?,
+            None => self.emit(OpCode::Nil),
+        }
+        self.line = name.line;
+        self.declare(name)
+    }
+
+    fn visit_block(self, statements: &[Stmt]) -> Compiled {
+        self.block(statements)
+    }
+
+    fn visit_if(
+        self,
+        _keyword: &Token,
+        condition: &Expr,
+        then_branch: &Stmt,
+        else_branch: Option<&Stmt>,
+    ) -> Compiled {
+        condition.accept(&mut *self)?;
+        let else_jump = self.emit_jump(OpCode::JumpIfFalse);
+        self.emit(OpCode::Pop);
+        then_branch.accept(&mut *self)?;
+        let end_jump = self.emit_jump(OpCode::Jump);
+
+        self.patch_jump(else_jump)?;
+        self.emit(OpCode::Pop);
+        if let Some(else_branch) = else_branch {
+            else_branch.accept(&mut *self)?;
+        }
+        self.patch_jump(end_jump)
+    }
+
+    fn visit_while(self, condition: &Expr, body: &Stmt) -> Compiled {
+        let start = self.state().chunk.code.len();
+        condition.accept(&mut *self)?;
+        let exit = self.emit_jump(OpCode::JumpIfFalse);
+        self.emit(OpCode::Pop);
+        body.accept(&mut *self)?;
+        self.emit_loop(start)?;
+
+        self.patch_jump(exit)?;
+        self.emit(OpCode::Pop);
+        Ok(())
+    }
+
+    fn visit_for(
+        self,
+        initializer: Option<&Stmt>,
+        condition: Option<&Expr>,
+        increment: Option<&Expr>,
+        body: &Stmt,
+    ) -> Compiled {
+        self.begin_scope();
+        if let Some(initializer) = initializer {
+            initializer.accept(&mut *self)?;
+        }
+
+        let start = self.state().chunk.code.len();
+        let exit = match condition {
+            Some(condition) => {
+                condition.accept(&mut *self)?;
+                let exit = self.emit_jump(OpCode::JumpIfFalse);
+                self.emit(OpCode::Pop);
+                Some(exit)
+            }
+            None => None,
+        };
+        body.accept(&mut *self)?;
+        if let Some(increment) = increment {
+            increment.accept(&mut *self)?;
+            self.emit(OpCode::Pop);
+        }
+        self.emit_loop(start)?;
+
+        if let Some(exit) = exit {
+            self.patch_jump(exit)?;
+            self.emit(OpCode::Pop);
+        }
+        self.end_scope();
+        Ok(())
+    }
+
+    // A local function is added before its body is compiled, so it can call
+    // itself.
+    fn visit_function(self, declaration: &Rc<FunctionDecl>) -> Compiled {
+        self.line = declaration.name.line;
+        if self.state().scope_depth > 0 {
+            self.add_local(&declaration.name)?;
+        }
+        self.function(declaration)?;
+        self.define(&declaration.name)
+    }
+
+    // Inside a `try`, the `finally` blocks around the `return` run first,
+    // innermost first, each protected only by the `try`s around it, while
+    // the value waits in a local of its own.
+    fn visit_return(self, keyword: &Token, value: Option<&Expr>) -> Compiled {
+        self.line = keyword.line;
+        match value {
+            Some(value) => value.accept(&mut *self)?,
+            None => self.emit(OpCode::Nil),
+        }
+        if self.state().tries.is_empty() {
+            self.emit(OpCode::Return);
+            return Ok(());
+        }
+
+        self.begin_scope();
+        self.add_hidden_local()?;
+        let mut left = Vec::new();
+        while !self.state().tries.is_empty() {
+            let current = self.end_try();
+            if let Some(finally) = &current.finally {
+                self.block(finally)?;
+            }
+            left.push(current);
+        }
+        self.line = keyword.line;
+        self.emit(OpCode::Return);
+        self.abandon_scope();
+
+        let start = self.state().chunk.code.len();
+        while let Some(mut current) = left.pop() {
+            current.start = start;
+            self.state().tries.push(current);
+        }
+        Ok(())
+    }
+
+    fn visit_throw(self, keyword: &Token, value: &Expr) -> Compiled {
+        value.accept(&mut *self)?;
+        self.line = keyword.line;
+        self.emit(OpCode::Throw);
+        Ok(())
+    }
+
+    // Compiled as a `try` with only a `catch` inside one with only the
+    // `finally`. The handler of the `catch` finds the error where its
+    // variable goes, and that of the `finally` runs it and throws the error
+    // again. A `finally` is compiled once more for each `return` inside.
+    fn visit_try(
+        self,
+        keyword: &Token,
+        body: &[Stmt],
+        catch: Option<&Catch>,
+        finally: Option<&[Stmt]>,
+    ) -> Compiled {
+        self.line = keyword.line;
+        if finally.is_some() {
+            self.begin_try(finally);
+        }
+        if catch.is_some() {
+            self.begin_try(None);
+        }
+        self.block(body)?;
+
+        if let Some(catch) = catch {
+            let current = self.end_try();
+            let end = self.emit_jump(OpCode::Jump);
+            self.catch(current);
+            self.begin_scope();
+            self.line = catch.name.line;
+            self.add_local(&catch.name)?;
+            for stmt in &catch.body {
+                stmt.accept(&mut *self)?;
+            }
+            self.end_scope();
+            self.patch_jump(end)?;
+        }
+
+        if let Some(finally) = finally {
+            let current = self.end_try();
+            self.block(finally)?;
+            let end = self.emit_jump(OpCode::Jump);
+            self.catch(current);
+            self.begin_scope();
+            self.add_hidden_local()?;
+            self.block(finally)?;
+            let slot = self.state().locals.len() - 1;
+            self.line = keyword.line;
+            self.emit(OpCode::GetLocal);
+            self.emit_byte(slot as u8);
+            self.emit(OpCode::Throw);
+            self.abandon_scope();
+            self.patch_jump(end)?;
+        }
+        Ok(())
+    }
+
+    // Each of `names` imports the module again, which only runs it the
+    // first time, and takes the variable from it.
+    fn visit_import(
+        self,
+        keyword: &Token,
+        path: &Token,
+        alias: Option<&Token>,
+        names: &[Token],
+    ) -> Compiled {
+        self.line = keyword.line;
+        let path = self.make_constant(Constant::String(path.lexeme.to_rc()))?;
+        if let Some(alias) = alias {
+            self.emit_with_u16(OpCode::Import, path);
+            return self.declare(alias);
+        }
+        for name in names {
+            self.line = keyword.line;
+            self.emit_with_u16(OpCode::Import, path);
+            self.line = name.line;
+            let index = self.name_constant(name.lexeme.clone())?;
+            self.emit_with_u16(OpCode::GetProperty, index);
+            self.declare(name)?;
+        }
+        Ok(())
+    }
+
+    fn visit_trivia(self, _trivia: &Trivia) -> Compiled {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{parser::Parser, resolver::Resolver, scanner::Scanner};
+
+    fn compile_source(source: &str) -> Result<Function, CompileError> {
+        let tokens = Scanner::new(source).scan_tokens();
+        let statements = Parser::new(tokens).parse();
+        Resolver::new().resolve(&statements);
+        compile(&statements)
+    }
+
+    #[test]
+    fn test_compile() {
+        let function = compile_source("var a = 1;\n{ var b = a; print -b; }").unwrap();
+        #[rustfmt::skip]
+        let code = [
+            OpCode::Constant as u8, 0, 0,
+            OpCode::DefineGlobal as u8, 0, 1,
+            OpCode::GetGlobal as u8, 0, 1,
+            OpCode::GetLocal as u8, 1,
+            OpCode::Negate as u8,
+            OpCode::Print as u8,
+            OpCode::Pop as u8,
+            OpCode::Nil as u8,
+            OpCode::Return as u8,
+        ];
+        assert_eq!(function.chunk.code, code);
+        assert_eq!(function.chunk.line(0), 1);
+        assert_eq!(function.chunk.line(6), 2);
+    }
+
+    #[test]
+    fn test_constants_are_on_their_own_line() {
+        let function = compile_source("var a;\nvar b = 0;\nprint\n  \"c\";").unwrap();
+        let expected = "\
+== script ==
+0000    1 Nil
+0001    | DefineGlobal        0 'a'
+0004    2 Constant            1 '0'
+0007    | DefineGlobal        2 'b'
+0010    4 Constant            3 'c'
+0013    | Print
+0014    | Nil
+0015    | Return
+";
+        assert_eq!(crate::disassembler::disassemble(&function), expected);
+    }
+
+    #[test]
+    fn test_compile_errors() {
+        let locals = (0..256)
+            .map(|i| format!("var v{} = {};", i, i))
+            .collect::<String>();
+        let error = compile_source(&format!("{{ {} }}", locals)).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "error at line 1: Too many local variables in function."
+        );
+    }
+}
@@ -0,0 +1,274 @@
+use std::{cell::RefCell, collections::BTreeMap, fmt::Write, rc::Rc};
+
+use crate::{
+    debugger,
+    expr::{Evaluation, Hook, RuntimeError},
+    scanner::Scanner,
+    stmt::Stmt,
+    symbol::Symbol,
+    token::{Token, TokenType},
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, clap::ValueEnum)]
+pub enum Format {
+    /// The tracefile of lcov and genhtml
+    Lcov,
+    /// The XML of Cobertura
+    Cobertura,
+}
+
+// What ran of a program, recorded by the `Collector` hook: the statements
+// run on each line, and how often the condition of each `if`, `and` and
+// `or` held and didn't. Lines and branches that never ran are there too.
+pub struct Coverage {
+    lines: BTreeMap<usize, u64>,
+    // by the line and column of the `if`, `and` or `or`
+    branches: BTreeMap<(usize, usize), [u64; 2]>,
+}
+
+impl Coverage {
+    // `statements` must have been parsed from `source`, whose tokens tell
+    // where the branches are.
+    pub fn new(source: &str, statements: &[Stmt]) -> Self {
+        let lines = debugger::lines(statements)
+            .into_iter()
+            .map(|line| (line, 0))
+            .collect();
+        let branches = Scanner::new(source)
+            .scan_tokens()
+            .into_iter()
+            .filter(|token| matches!(token.ttype, TokenType::If | TokenType::And | TokenType::Or))
+            .map(|token| ((token.line, token.column), [0, 0]))
+            .collect();
+        Coverage { lines, branches }
+    }
+
+    fn statement(&mut self, line: usize) {
+        *self.lines.entry(line).or_default() += 1;
+    }
+
+    fn branch(&mut self, token: &Token, held: bool) {
+        let counts = self.branches.entry((token.line, token.column)).or_default();
+        counts[usize::from(!held)] += 1;
+    }
+
+    // The branches on each line, in the order they are on it.
+    fn branches_by_line(&self) -> BTreeMap<usize, Vec<[u64; 2]>> {
+        let mut lines: BTreeMap<usize, Vec<[u64; 2]>> = BTreeMap::new();
+        for (&(line, _), &counts) in &self.branches {
+            lines.entry(line).or_default().push(counts);
+        }
+        lines
+    }
+
+    fn lines_hit(&self) -> usize {
+        self.lines.values().filter(|&&count| count > 0).count()
+    }
+
+    fn branches_hit(&self) -> usize {
+        self.branches
+            .values()
+            .flatten()
+            .filter(|&&count| count > 0)
+            .count()
+    }
+
+    // For the script at `path`, with branch `0` taken when the condition
+    // held and `1` when it didn't, and `-` for those never reached.
+    pub fn lcov(&self, path: &str) -> String {
+        let mut lcov = format!("TN:\nSF:{}\n", path);
+        for (line, branches) in self.branches_by_line() {
+            for (block, counts) in branches.iter().enumerate() {
+                for (branch, count) in counts.iter().enumerate() {
+                    let taken = match counts {
+                        [0, 0] => "-".to_string(),
+                        _ => count.to_string(),
+                    };
+                    let _ = writeln!(lcov, "BRDA:{},{},{},{}", line, block, branch, taken);
+                }
+            }
+        }
+        let _ = writeln!(lcov, "BRF:{}", self.branches.len() * 2);
+        let _ = writeln!(lcov, "BRH:{}", self.branches_hit());
+        for (line, count) in &self.lines {
+            let _ = writeln!(lcov, "DA:{},{}", line, count);
+        }
+        let _ = writeln!(lcov, "LF:{}", self.lines.len());
+        let _ = writeln!(lcov, "LH:{}", self.lines_hit());
+        lcov.push_str("end_of_record\n");
+        lcov
+    }
+
+    // For the script at `path`, as one class in a package of its own.
+    // `timestamp` is in seconds since the epoch.
+    pub fn cobertura(&self, path: &str, timestamp: u64) -> String {
+        let line_rate = rate(self.lines_hit(), self.lines.len());
+        let branch_rate = rate(self.branches_hit(), self.branches.len() * 2);
+        let rates = format!(
+            "line-rate=\"{}\" branch-rate=\"{}\" complexity=\"0\"",
+            line_rate, branch_rate
+        );
+
+        let mut xml = String::from("<?xml version=\"1.0\"?>\n");
+        xml.push_str(
+            "<!DOCTYPE coverage SYSTEM \"http://cobertura.sourceforge.net/xml/coverage-04.dtd\">\n",
+        );
+        let _ = writeln!(
+            xml,
+            "<coverage {} lines-covered=\"{}\" lines-valid=\"{}\" branches-covered=\"{}\" branches-valid=\"{}\" version=\"{}\" timestamp=\"{}\">",
+            rates,
+            self.lines_hit(),
+            self.lines.len(),
+            self.branches_hit(),
+            self.branches.len() * 2,
+            env!("CARGO_PKG_VERSION"),
+            timestamp
+        );
+        xml.push_str("  <sources>\n    <source>.</source>\n  </sources>\n  <packages>\n");
+        let path = escape(path);
+        let _ = writeln!(xml, "    <package name=\"{}\" {}>", path, rates);
+        xml.push_str("      <classes>\n");
+        let _ = writeln!(
+            xml,
+            "        <class name=\"{}\" filename=\"{}\" {}>",
+            path, path, rates
+        );
+        xml.push_str("          <methods/>\n          <lines>\n");
+
+        let branches = self.branches_by_line();
+        for (line, count) in &self.lines {
+            let _ = write!(
+                xml,
+                "            <line number=\"{}\" hits=\"{}\"",
+                line, count
+            );
+            match branches.get(line) {
+                Some(branches) => {
+                    let taken = branches.iter().flatten().filter(|&&n| n > 0).count();
+                    let total = branches.len() * 2;
+                    let _ = writeln!(
+                        xml,
+                        " branch=\"true\" condition-coverage=\"{}% ({}/{})\"/>",
+                        taken * 100 / total,
+                        taken,
+                        total
+                    );
+                }
+                None => xml.push_str(" branch=\"false\"/>\n"),
+            }
+        }
+        xml.push_str("          </lines>\n        </class>\n      </classes>\n    </package>\n");
+        xml.push_str("  </packages>\n</coverage>\n");
+        xml
+    }
+}
+
+// Of nothing, everything was covered.
+fn rate(hit: usize, total: usize) -> String {
+    match total {
+        0 => "1".to_string(),
+        _ => format!("{:.4}", hit as f64 / total as f64),
+    }
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+// Records the program it is the hook of into the shared coverage.
+pub struct Collector(pub Rc<RefCell<Coverage>>);
+
+impl Hook for Collector {
+    fn statement(&mut self, _evaluation: &mut Evaluation, line: usize) -> Result<(), RuntimeError> {
+        self.0.borrow_mut().statement(line);
+        Ok(())
+    }
+
+    fn call(&mut self, _evaluation: &mut Evaluation, _name: Symbol, _line: usize) {}
+
+    fn returned(&mut self, _evaluation: &mut Evaluation) {}
+
+    fn branch(&mut self, _evaluation: &mut Evaluation, token: &Token, held: bool) {
+        self.0.borrow_mut().branch(token, held);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{parser::Parser, resolver::Resolver};
+
+    const PROGRAM: &str = "\
+fun sign(n) {
+  if (n < 0) return -1;
+  if (n == 0 or n != n) return 0;
+  return 1;
+}
+print sign(5) + sign(-5);
+if (false) print true and \"never\";";
+
+    fn cover(source: &str) -> Coverage {
+        let statements = Parser::new(Scanner::new(source).scan_tokens()).parse();
+        Resolver::new().resolve(&statements);
+
+        let coverage = Rc::new(RefCell::new(Coverage::new(source, &statements)));
+        let mut evaluation = Evaluation::with_args(Vec::new());
+        evaluation.set_output(Box::new(std::io::sink()));
+        evaluation.set_hook(Box::new(Collector(Rc::clone(&coverage))));
+        evaluation.interpret(&statements).unwrap();
+        drop(evaluation);
+        Rc::into_inner(coverage).unwrap().into_inner()
+    }
+
+    #[test]
+    fn test_lcov() {
+        assert_eq!(
+            cover(PROGRAM).lcov("sign.lox"),
+            "\
+TN:
+SF:sign.lox
+BRDA:2,0,0,1
+BRDA:2,0,1,1
+BRDA:3,0,0,0
+BRDA:3,0,1,1
+BRDA:3,1,0,0
+BRDA:3,1,1,1
+BRDA:7,0,0,0
+BRDA:7,0,1,1
+BRDA:7,1,0,-
+BRDA:7,1,1,-
+BRF:10
+BRH:5
+DA:1,1
+DA:2,3
+DA:3,1
+DA:4,1
+DA:6,1
+DA:7,1
+LF:6
+LH:6
+end_of_record
+"
+        );
+    }
+
+    #[test]
+    fn test_cobertura() {
+        let xml = cover("var a = 1;\nif (a > 1)\n  print a;").cobertura("a&b.lox", 0);
+        assert!(xml.contains(
+            "<coverage line-rate=\"0.6667\" branch-rate=\"0.5000\" complexity=\"0\" \
+             lines-covered=\"2\" lines-valid=\"3\" branches-covered=\"1\" branches-valid=\"2\""
+        ));
+        assert!(xml.contains("<class name=\"a&amp;b.lox\" filename=\"a&amp;b.lox\""));
+        assert!(xml.contains(
+            "\
+            <line number=\"1\" hits=\"1\" branch=\"false\"/>
+            <line number=\"2\" hits=\"1\" branch=\"true\" condition-coverage=\"50% (1/2)\"/>
+            <line number=\"3\" hits=\"0\" branch=\"false\"/>
+"
+        ));
+    }
+}
@@ -0,0 +1,765 @@
+use std::{
+    cell::RefCell,
+    collections::{BTreeSet, VecDeque},
+    io::{self, BufRead, BufReader, Write},
+    path::PathBuf,
+    rc::Rc,
+    sync::mpsc,
+    thread,
+};
+
+use serde_json::{json, Value as Json};
+
+use crate::{
+    check,
+    debugger::{self, Frame, Session, Step, Stop},
+    environment::Environment,
+    expr::{Evaluation, Hook, RuntimeError},
+    module::{self, Modules},
+    parser::Parser,
+    resolver::Resolver,
+    scanner::Scanner,
+    stmt::Stmt,
+    symbol::Symbol,
+    value::Value,
+};
+
+// The only thread a program has.
+const THREAD: u64 = 1;
+
+// The requests answered while the program runs. The others wait until it
+// stops, as a client only sends them then.
+const WHILE_RUNNING: [&str; 5] = [
+    "pause",
+    "setBreakpoints",
+    "setExceptionBreakpoints",
+    "threads",
+    "disconnect",
+];
+
+// Debugs a script for a client of the debug adapter protocol talking on
+// stdin and stdout, until it disconnects.
+pub fn run() -> io::Result<()> {
+    serve(BufReader::new(io::stdin()), Box::new(io::stdout()))
+}
+
+// Answers the requests read from `reader` on `writer`. A client launches a
+// script, sets its breakpoints and then runs it on the tree-walking
+// interpreter, whose output is sent as events.
+pub fn serve(reader: impl BufRead + Send + 'static, writer: Box<dyn Write>) -> io::Result<()> {
+    // read on another thread, so a pause can arrive while the program runs
+    let (sender, receiver) = mpsc::channel();
+    thread::spawn(move || {
+        let mut reader = reader;
+        while let Ok(Some(message)) = read(&mut reader) {
+            if message["type"] == "request" && sender.send(message).is_err() {
+                break;
+            }
+        }
+    });
+
+    let client = Rc::new(RefCell::new(Client {
+        writer,
+        seq: 0,
+        error: None,
+    }));
+    let mut adapter = Adapter {
+        client: Rc::clone(&client),
+        requests: Requests {
+            receiver,
+            pending: VecDeque::new(),
+        },
+        session: Session::new(),
+        program: None,
+        entry: false,
+        frames: Vec::new(),
+        handles: Vec::new(),
+        disconnected: false,
+    };
+
+    if let Some(program) = adapter.configure() {
+        let (statements, args) = (program.statements, program.args);
+        let modules = Modules::new(Some(&program.path), module::search_path(&[]));
+        adapter.program = Some(Program {
+            statements: Vec::new(),
+            args: Vec::new(),
+            ..program
+        });
+
+        let adapter = Rc::new(RefCell::new(adapter));
+        let mut evaluation = Evaluation::with_args(args);
+        evaluation.set_modules(modules);
+        evaluation.set_output(Box::new(Output {
+            client: Rc::clone(&client),
+            line: Vec::new(),
+        }));
+        evaluation.set_hook(Box::new(Debuggee(Rc::clone(&adapter))));
+        let result = evaluation.interpret(&statements);
+        drop(evaluation);
+
+        let mut adapter = adapter.borrow_mut();
+        if !adapter.disconnected {
+            adapter.terminated(result);
+        }
+    }
+
+    let error = client.borrow_mut().error.take();
+    error.map_or(Ok(()), Err)
+}
+
+// A message of the protocol: a header with the length of the JSON that
+// follows, as in the language server protocol. `None` at the end of input.
+fn read(reader: &mut impl BufRead) -> io::Result<Option<Json>> {
+    let mut length = None;
+    loop {
+        let mut header = String::new();
+        if reader.read_line(&mut header)? == 0 {
+            return Ok(None);
+        }
+        let header = header.trim_end();
+        if header.is_empty() {
+            break;
+        }
+        if let Some(value) = header.strip_prefix("Content-Length:") {
+            length = Some(value.trim().parse::<usize>().map_err(io::Error::other)?);
+        }
+    }
+
+    let length = length.ok_or_else(|| io::Error::other("missing Content-Length header"))?;
+    let mut body = vec![0; length];
+    reader.read_exact(&mut body)?;
+    serde_json::from_slice(&body)
+        .map(Some)
+        .map_err(io::Error::other)
+}
+
+fn write(writer: &mut impl Write, message: &Json) -> io::Result<()> {
+    let body = message.to_string();
+    write!(writer, "Content-Length: {}\r\n\r\n{}", body.len(), body)?;
+    writer.flush()
+}
+
+fn command(request: &Json) -> &str {
+    request["command"].as_str().unwrap_or_default()
+}
+
+// Sends responses and events. Once writing fails nothing more is sent, and
+// the error is returned when the session ends.
+struct Client {
+    writer: Box<dyn Write>,
+    seq: u64,
+    error: Option<io::Error>,
+}
+
+impl Client {
+    fn send(&mut self, mut message: Json) {
+        self.seq += 1;
+        message["seq"] = json!(self.seq);
+        if self.error.is_none() {
+            self.error = write(&mut self.writer, &message).err();
+        }
+    }
+
+    fn respond(&mut self, request: &Json, body: Result<Json, String>) {
+        let mut response = json!({
+            "type": "response",
+            "request_seq": request["seq"],
+            "command": command(request),
+        });
+        match body {
+            Ok(body) => {
+                response["success"] = json!(true);
+                response["body"] = body;
+            }
+            Err(message) => {
+                response["success"] = json!(false);
+                response["message"] = json!(message);
+            }
+        }
+        self.send(response);
+    }
+
+    fn event(&mut self, event: &str, body: Json) {
+        self.send(json!({ "type": "event", "event": event, "body": body }));
+    }
+}
+
+// What `print` writes, sent a line at a time, as stdout is the client's.
+struct Output {
+    client: Rc<RefCell<Client>>,
+    line: Vec<u8>,
+}
+
+impl Write for Output {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.line.extend_from_slice(buf);
+        if buf.contains(&b'\n') {
+            self.flush()?;
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        if !self.line.is_empty() {
+            let output = String::from_utf8_lossy(&self.line).into_owned();
+            let body = json!({ "category": "stdout", "output": output });
+            self.client.borrow_mut().event("output", body);
+            self.line.clear();
+        }
+        Ok(())
+    }
+}
+
+// The requests read so far, in order.
+struct Requests {
+    receiver: mpsc::Receiver<Json>,
+    pending: VecDeque<Json>,
+}
+
+impl Requests {
+    // Waits for the next request, or `None` once the client is gone.
+    fn next(&mut self) -> Option<Json> {
+        self.pending
+            .pop_front()
+            .or_else(|| self.receiver.recv().ok())
+    }
+
+    // The next request, if it has arrived and can be answered while the
+    // program runs.
+    fn next_while_running(&mut self) -> Option<Json> {
+        self.pending.extend(self.receiver.try_iter());
+        let request = self.pending.front()?;
+        match WHILE_RUNNING.contains(&command(request)) {
+            true => self.pending.pop_front(),
+            false => None,
+        }
+    }
+}
+
+// The script being debugged.
+struct Program {
+    path: PathBuf,
+    statements: Vec<Stmt>,
+    args: Vec<String>,
+    // those where statements start, the only ones breakpoints can be on
+    lines: BTreeSet<usize>,
+}
+
+// A value shown in the variables view whose contents can be expanded.
+enum Handle {
+    Scope(Rc<RefCell<Environment>>),
+    List(Rc<RefCell<Vec<Value>>>),
+}
+
+struct Adapter {
+    client: Rc<RefCell<Client>>,
+    requests: Requests,
+    session: Session,
+    program: Option<Program>,
+    // whether to stop before the first statement
+    entry: bool,
+    // those of the stopped program, by their ids, and the values that can
+    // be expanded, numbered from 1
+    frames: Vec<Frame>,
+    handles: Vec<Handle>,
+    disconnected: bool,
+}
+
+impl Adapter {
+    // Answers requests until the client has launched a script and is done
+    // configuring it, returning the script.
+    fn configure(&mut self) -> Option<Program> {
+        let mut configured = false;
+        while !configured || self.program.is_none() {
+            let request = self.requests.next()?;
+            let response = match command(&request) {
+                "initialize" => Ok(json!({
+                    "supportsConfigurationDoneRequest": true,
+                    "supportsEvaluateForHovers": true,
+                })),
+                "launch" => self.launch(&request["arguments"]),
+                "configurationDone" => {
+                    configured = true;
+                    Ok(json!({}))
+                }
+                _ if self.common(&request) => match self.disconnected {
+                    true => return None,
+                    false => continue,
+                },
+                _ => Err("The program isn't running.".to_string()),
+            };
+
+            let launched = command(&request) == "launch" && response.is_ok();
+            self.client.borrow_mut().respond(&request, response);
+            // ready for breakpoints, which are checked against the script
+            if launched {
+                self.client.borrow_mut().event("initialized", json!({}));
+            }
+        }
+        self.program.take()
+    }
+
+    fn launch(&mut self, arguments: &Json) -> Result<Json, String> {
+        let path = arguments["program"]
+            .as_str()
+            .ok_or("Missing the 'program' to launch.")?;
+        let source =
+            std::fs::read_to_string(path).map_err(|e| format!("cannot read '{}': {}", path, e))?;
+
+        let diagnostics = check::check(path, &source);
+        if !diagnostics.is_empty() {
+            let errors: Vec<_> = diagnostics.iter().map(|d| d.to_string()).collect();
+            return Err(errors.join("\n"));
+        }
+        let statements = Parser::new(Scanner::new(&source).scan_tokens()).parse();
+        Resolver::new().resolve(&statements);
+
+        let args = arguments["args"]
+            .as_array()
+            .into_iter()
+            .flatten()
+            .filter_map(|arg| arg.as_str().map(str::to_string))
+            .collect();
+        self.entry = arguments["stopOnEntry"].as_bool().unwrap_or(false);
+        self.program = Some(Program {
+            path: canonical(path),
+            lines: debugger::lines(&statements),
+            statements,
+            args,
+        });
+        Ok(json!({}))
+    }
+
+    // Answers the requests that can come at any time, returning whether
+    // `request` was one of them.
+    fn common(&mut self, request: &Json) -> bool {
+        let response = match command(request) {
+            "setBreakpoints" => self.set_breakpoints(&request["arguments"]),
+            "setExceptionBreakpoints" => Ok(json!({})),
+            "threads" => Ok(json!({ "threads": [{ "id": THREAD, "name": "main" }] })),
+            "disconnect" => {
+                self.disconnected = true;
+                Ok(json!({}))
+            }
+            _ => return false,
+        };
+        self.client.borrow_mut().respond(request, response);
+        true
+    }
+
+    fn set_breakpoints(&mut self, arguments: &Json) -> Result<Json, String> {
+        let program = self
+            .program
+            .as_ref()
+            .ok_or("No program has been launched.")?;
+        let path = arguments["source"]["path"].as_str().unwrap_or_default();
+        let other = canonical(path) != program.path;
+
+        let mut breakpoints = Vec::new();
+        let mut lines = BTreeSet::new();
+        for breakpoint in arguments["breakpoints"].as_array().into_iter().flatten() {
+            let line = breakpoint["line"].as_u64().unwrap_or_default() as usize;
+            let mut response = json!({ "line": line });
+            if other {
+                response["verified"] = json!(false);
+                response["message"] = json!("Not in the program being debugged.");
+            } else if program.lines.contains(&line) {
+                lines.insert(line);
+                response["verified"] = json!(true);
+            } else {
+                response["verified"] = json!(false);
+                response["message"] = json!("No statement starts on this line.");
+            }
+            breakpoints.push(response);
+        }
+
+        if !other {
+            self.session.breakpoints = lines;
+        }
+        Ok(json!({ "breakpoints": breakpoints }))
+    }
+
+    fn statement(&mut self, evaluation: &mut Evaluation, line: usize) -> Result<(), RuntimeError> {
+        while let Some(request) = self.requests.next_while_running() {
+            self.request(evaluation, &request);
+        }
+
+        let stop = self.session.statement(line);
+        let reason = match (std::mem::take(&mut self.entry), stop) {
+            (true, _) => "entry",
+            (false, Some(Stop::Breakpoint)) => "breakpoint",
+            (false, Some(Stop::Step)) => "step",
+            (false, Some(Stop::Pause)) => "pause",
+            (false, None) => return self.running(line),
+        };
+
+        self.frames = self.session.frames(evaluation);
+        self.handles.clear();
+        let body = json!({ "reason": reason, "threadId": THREAD, "allThreadsStopped": true });
+        self.client.borrow_mut().event("stopped", body);
+        while !self.disconnected {
+            let Some(request) = self.requests.next() else {
+                self.disconnected = true;
+                break;
+            };
+            if self.request(evaluation, &request) {
+                break;
+            }
+        }
+        self.running(line)
+    }
+
+    // Whether the program can go on, as it can't once the client is gone.
+    fn running(&self, line: usize) -> Result<(), RuntimeError> {
+        match self.disconnected {
+            true => Err(RuntimeError {
+                line,
+                message: "Stopped by the debugger.".to_string(),
+                trace: Vec::new(),
+            }),
+            false => Ok(()),
+        }
+    }
+
+    // Answers a request while the program is stopped, or running for those
+    // allowed then, returning whether it should go on.
+    fn request(&mut self, evaluation: &mut Evaluation, request: &Json) -> bool {
+        let arguments = &request["arguments"];
+        let step = match command(request) {
+            "continue" => Some(Step::Continue),
+            "next" => Some(Step::Over),
+            "stepIn" => Some(Step::In),
+            "stepOut" => Some(Step::Out),
+            _ => None,
+        };
+        if let Some(step) = step {
+            self.session.resume(step);
+            let body = match step {
+                Step::Continue => json!({ "allThreadsContinued": true }),
+                _ => json!({}),
+            };
+            self.client.borrow_mut().respond(request, Ok(body));
+            return true;
+        }
+
+        let response = match command(request) {
+            "pause" => {
+                self.session.resume(Step::Pause);
+                Ok(json!({}))
+            }
+            "stackTrace" => Ok(self.stack_trace()),
+            "scopes" => self.scopes(arguments),
+            "variables" => self.variables(arguments),
+            "evaluate" => self.evaluate(evaluation, arguments),
+            _ if self.common(request) => return false,
+            "initialize" | "launch" | "configurationDone" => {
+                Err("The program is already running.".to_string())
+            }
+            command => Err(format!("Unsupported request '{}'.", command)),
+        };
+        self.client.borrow_mut().respond(request, response);
+        false
+    }
+
+    fn source(&self) -> Json {
+        let path = self.program.as_ref().map(|program| &program.path);
+        let name = path.and_then(|path| path.file_name());
+        json!({
+            "name": name.map(|name| name.to_string_lossy()),
+            "path": path.map(|path| path.to_string_lossy()),
+        })
+    }
+
+    fn stack_trace(&self) -> Json {
+        let frames: Vec<_> = self
+            .frames
+            .iter()
+            .enumerate()
+            .map(|(id, frame)| {
+                json!({
+                    "id": id,
+                    "name": frame.name,
+                    "line": frame.line,
+                    "column": 1,
+                    "source": self.source(),
+                })
+            })
+            .collect();
+        json!({ "totalFrames": frames.len(), "stackFrames": frames })
+    }
+
+    fn frame(&self, arguments: &Json) -> Result<&Frame, String> {
+        let id = arguments["frameId"].as_u64().unwrap_or_default() as usize;
+        self.frames
+            .get(id)
+            .ok_or_else(|| format!("Unknown frame {}.", id))
+    }
+
+    // Each scope of the frame from the innermost out, which for a function
+    // are its locals, those of the functions around it and the globals.
+    fn scopes(&mut self, arguments: &Json) -> Result<Json, String> {
+        let scopes = debugger::scopes(&self.frame(arguments)?.environment);
+        let count = scopes.len();
+        let scopes: Vec<_> = scopes
+            .into_iter()
+            .enumerate()
+            .map(|(index, scope)| {
+                let name = match index {
+                    _ if index == count - 1 => "Globals",
+                    0 => "Locals",
+                    _ => "Enclosing",
+                };
+                self.handles.push(Handle::Scope(scope));
+                json!({
+                    "name": name,
+                    "variablesReference": self.handles.len(),
+                    "expensive": false,
+                })
+            })
+            .collect();
+        Ok(json!({ "scopes": scopes }))
+    }
+
+    fn variables(&mut self, arguments: &Json) -> Result<Json, String> {
+        let reference = arguments["variablesReference"].as_u64().unwrap_or_default() as usize;
+        let variables: Vec<(String, Value)> =
+            match reference.checked_sub(1).and_then(|i| self.handles.get(i)) {
+                Some(Handle::Scope(scope)) => debugger::variables(&scope.borrow())
+                    .into_iter()
+                    .map(|(name, value)| (name.to_string(), value))
+                    .collect(),
+                Some(Handle::List(list)) => list
+                    .borrow()
+                    .iter()
+                    .enumerate()
+                    .map(|(index, value)| (index.to_string(), value.clone()))
+                    .collect(),
+                None => return Err(format!("Unknown variables reference {}.", reference)),
+            };
+
+        let variables: Vec<_> = variables
+            .into_iter()
+            .map(|(name, value)| {
+                json!({
+                    "name": name,
+                    "value": value.to_string(),
+                    "type": value.type_name(),
+                    "variablesReference": self.reference(&value),
+                })
+            })
+            .collect();
+        Ok(json!({ "variables": variables }))
+    }
+
+    // Evaluates an expression in a frame, or in the globals without one.
+    fn evaluate(&mut self, evaluation: &mut Evaluation, arguments: &Json) -> Result<Json, String> {
+        let environment = match arguments.get("frameId") {
+            Some(_) => Rc::clone(&self.frame(arguments)?.environment),
+            None => {
+                let scopes = debugger::scopes(evaluation.environment());
+                Rc::clone(scopes.last().unwrap())
+            }
+        };
+        let expression = arguments["expression"].as_str().unwrap_or_default();
+        let value = debugger::evaluate(evaluation, &environment, expression)?;
+        Ok(json!({
+            "result": value.to_string(),
+            "type": value.type_name(),
+            "variablesReference": self.reference(&value),
+        }))
+    }
+
+    // That of a value whose contents can be expanded, or 0.
+    fn reference(&mut self, value: &Value) -> usize {
+        match value {
+            Value::List(list) => {
+                self.handles.push(Handle::List(Rc::clone(list)));
+                self.handles.len()
+            }
+            _ => 0,
+        }
+    }
+
+    // Reports how the program ended, then answers requests until the client
+    // disconnects.
+    fn terminated(&mut self, result: Result<(), RuntimeError>) {
+        let exit_code = match result {
+            Ok(()) => 0,
+            Err(error) => {
+                let body = json!({ "category": "stderr", "output": format!("{}\n", error) });
+                self.client.borrow_mut().event("output", body);
+                70
+            }
+        };
+        self.client
+            .borrow_mut()
+            .event("exited", json!({ "exitCode": exit_code }));
+        self.client.borrow_mut().event("terminated", json!({}));
+
+        while !self.disconnected {
+            let Some(request) = self.requests.next() else {
+                return;
+            };
+            if !self.common(&request) {
+                let response = Err("The program has ended.".to_string());
+                self.client.borrow_mut().respond(&request, response);
+            }
+        }
+    }
+}
+
+// Breakpoints name files by their paths, which may be relative.
+fn canonical(path: &str) -> PathBuf {
+    std::fs::canonicalize(path).unwrap_or_else(|_| PathBuf::from(path))
+}
+
+// Gives the adapter the program as it runs, while the adapter also answers
+// requests before and after.
+struct Debuggee(Rc<RefCell<Adapter>>);
+
+impl Hook for Debuggee {
+    fn statement(&mut self, evaluation: &mut Evaluation, line: usize) -> Result<(), RuntimeError> {
+        self.0.borrow_mut().statement(evaluation, line)
+    }
+
+    fn call(&mut self, evaluation: &mut Evaluation, name: Symbol, line: usize) {
+        self.0.borrow_mut().session.call(evaluation, name, line);
+    }
+
+    fn returned(&mut self, _evaluation: &mut Evaluation) {
+        self.0.borrow_mut().session.returned();
+    }
+
+    fn iteration(&mut self, _evaluation: &mut Evaluation) {
+        self.0.borrow_mut().session.iteration();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A session as a client would have it, sending each request (`->`) once
+    // it has received the messages before it (`<-`). Bodies are compared as
+    // JSON, and `PROGRAM` is the path of the script.
+    const SESSION: &str = r#"
+-> initialize {"adapterID": "lox"}
+<- initialize {"supportsConfigurationDoneRequest": true, "supportsEvaluateForHovers": true}
+-> launch {"program": "PROGRAM", "args": ["x", "y"]}
+<- launch {}
+<- event initialized {}
+-> setBreakpoints {"source": {"path": "PROGRAM"}, "breakpoints": [{"line": 2}, {"line": 4}]}
+<- setBreakpoints {"breakpoints": [{"line": 2, "verified": true}, {"line": 4, "verified": false, "message": "No statement starts on this line."}]}
+-> configurationDone {}
+<- configurationDone {}
+<- event stopped {"reason": "breakpoint", "threadId": 1, "allThreadsStopped": true}
+-> stackTrace {"threadId": 1}
+<- stackTrace {"totalFrames": 2, "stackFrames": [{"id": 0, "name": "add", "line": 2, "column": 1, "source": SOURCE}, {"id": 1, "name": "script", "line": 6, "column": 1, "source": SOURCE}]}
+-> scopes {"frameId": 0}
+<- scopes {"scopes": [{"name": "Locals", "variablesReference": 1, "expensive": false}, {"name": "Globals", "variablesReference": 2, "expensive": false}]}
+-> variables {"variablesReference": 1}
+<- variables {"variables": [{"name": "a", "value": "1", "type": "number", "variablesReference": 0}, {"name": "b", "value": "[\"x\", \"y\"]", "type": "list", "variablesReference": 3}]}
+-> variables {"variablesReference": 3}
+<- variables {"variables": [{"name": "0", "value": "\"x\"", "type": "string", "variablesReference": 0}, {"name": "1", "value": "\"y\"", "type": "string", "variablesReference": 0}]}
+-> variables {"variablesReference": 2}
+<- variables {"variables": [{"name": "add", "value": "<fn add>", "type": "function", "variablesReference": 0}, {"name": "list", "value": "[\"x\", \"y\"]", "type": "list", "variablesReference": 4}]}
+-> evaluate {"expression": "a + len(b)", "frameId": 0}
+<- evaluate {"result": "3", "type": "number", "variablesReference": 0}
+-> evaluate {"expression": "a +", "frameId": 0}
+<- evaluate error: at end: Expect expression.
+-> next {"threadId": 1}
+<- next {}
+<- event stopped {"reason": "step", "threadId": 1, "allThreadsStopped": true}
+-> stackTrace {"threadId": 1}
+<- stackTrace {"totalFrames": 2, "stackFrames": [{"id": 0, "name": "add", "line": 3, "column": 1, "source": SOURCE}, {"id": 1, "name": "script", "line": 6, "column": 1, "source": SOURCE}]}
+-> stepOut {"threadId": 1}
+<- stepOut {}
+<- event stopped {"reason": "step", "threadId": 1, "allThreadsStopped": true}
+-> stackTrace {"threadId": 1}
+<- stackTrace {"totalFrames": 1, "stackFrames": [{"id": 0, "name": "script", "line": 7, "column": 1, "source": SOURCE}]}
+-> continue {"threadId": 1}
+<- continue {"allThreadsContinued": true}
+<- event output {"category": "stdout", "output": "3\n"}
+<- event stopped {"reason": "breakpoint", "threadId": 1, "allThreadsStopped": true}
+-> continue {"threadId": 1}
+<- continue {"allThreadsContinued": true}
+<- event output {"category": "stderr", "output": "error at line 2: Operands must be two numbers or two strings.\n[line 2] in add()\n[line 8] in script\n"}
+<- event exited {"exitCode": 70}
+<- event terminated {}
+-> disconnect {}
+<- disconnect {}
+"#;
+
+    const PROGRAM: &str = "\
+fun add(a, b) {
+  var sum = a + len(b);
+  return sum;
+}
+var list = args();
+var total = add(1, list);
+print total;
+print add(\"a\", list);
+";
+
+    fn send(writer: &mut impl Write, seq: u64, command: &str, arguments: Json) {
+        let request = json!({
+            "seq": seq,
+            "type": "request",
+            "command": command,
+            "arguments": arguments,
+        });
+        write(writer, &request).unwrap();
+    }
+
+    #[test]
+    fn test_recorded_session() {
+        let name = format!("interpret-dap-{}.lox", std::process::id());
+        let path = std::env::temp_dir().join(&name);
+        std::fs::write(&path, PROGRAM).unwrap();
+        let program = path.to_string_lossy().into_owned();
+        let source = json!({ "name": name, "path": canonical(&program) });
+
+        let (server_reader, mut writer) = io::pipe().unwrap();
+        let (reader, server_writer) = io::pipe().unwrap();
+        let server =
+            thread::spawn(move || serve(BufReader::new(server_reader), Box::new(server_writer)));
+        let mut reader = BufReader::new(reader);
+
+        let mut seq = 0;
+        for line in SESSION.trim().lines() {
+            let line = line
+                .replace("PROGRAM", &program.replace('\\', "\\\\"))
+                .replace("SOURCE", &source.to_string());
+            let (arrow, line) = line.split_at(3);
+            let (name, body) = line.split_once(' ').unwrap();
+            if arrow == "-> " {
+                seq += 1;
+                send(&mut writer, seq, name, serde_json::from_str(body).unwrap());
+                continue;
+            }
+
+            let message = read(&mut reader).unwrap().unwrap();
+            match (name, body.strip_prefix("error: ")) {
+                ("event", _) => {
+                    let (event, body) = body.split_once(' ').unwrap();
+                    assert_eq!(message["event"], event, "{}", message);
+                    assert_eq!(message["body"], serde_json::from_str::<Json>(body).unwrap());
+                }
+                (_, Some(error)) => {
+                    assert_eq!(message["command"], name, "{}", message);
+                    assert_eq!(message["success"], false);
+                    assert_eq!(message["message"], error);
+                }
+                (_, None) => {
+                    assert_eq!(message["command"], name, "{}", message);
+                    assert_eq!(message["request_seq"], seq);
+                    assert_eq!(message["body"], serde_json::from_str::<Json>(body).unwrap());
+                }
+            }
+        }
+
+        drop(writer);
+        server.join().unwrap().unwrap();
+        std::fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,483 @@
+use std::{
+    cell::RefCell,
+    collections::BTreeSet,
+    io::{self, BufRead, Write},
+    path::Path,
+    rc::Rc,
+};
+
+use crate::{
+    debugger::{self, Session, Step, Stop},
+    environment::Environment,
+    expr::{Evaluation, Hook, RuntimeError},
+    module::{self, Modules},
+    stmt::Stmt,
+    symbol::Symbol,
+};
+
+const HELP: &str = "\
+break <line>     stop before the statement on a line (b)
+watch <var>      stop after a variable changes
+run              run the program from the start (r)
+continue         run to the next breakpoint (c)
+step             run to the next line, stepping into calls (s)
+next             run to the next line, stepping over calls (n)
+finish           run until the current function returns
+print <expr>     evaluate an expression where the program stopped (p)
+backtrace        list the calls that led to where the program stopped (bt)
+quit             leave the debugger (q, or press Ctrl-D)
+An empty line repeats the last command.";
+
+// Debugs `statements`, of the script at `path`, from a gdb-style prompt on
+// stdin and stdout.
+pub fn run(path: &Path, source: &str, statements: &[Stmt], args: Vec<String>) {
+    let output = Rc::new(RefCell::new(io::stdout()));
+    let console = Console::new(source, statements, Box::new(io::stdin().lock()), output);
+    debug(console, path, statements, args);
+}
+
+fn debug(console: Console, path: &Path, statements: &[Stmt], args: Vec<String>) {
+    let console = Rc::new(RefCell::new(console));
+    loop {
+        let next = console.borrow_mut().prompt(None);
+        match next {
+            Next::Run => {}
+            Next::Quit => return,
+            Next::Prompt | Next::Resume => continue,
+        }
+
+        let mut evaluation = Evaluation::with_args(args.clone());
+        evaluation.set_modules(Modules::new(Some(path), module::search_path(&[])));
+        evaluation.set_output(Box::new(Output(Rc::clone(&console.borrow().output))));
+        evaluation.set_hook(Box::new(Running(Rc::clone(&console))));
+        console.borrow_mut().start();
+        let result = evaluation.interpret(statements);
+
+        let console = console.borrow_mut();
+        match result {
+            _ if console.quit => return,
+            Ok(()) => console.say("Program exited."),
+            Err(error) => {
+                console.say(error);
+                console.say("Program exited with an error.");
+            }
+        }
+    }
+}
+
+// What to do after a command.
+enum Next {
+    Prompt,
+    Run,
+    Resume,
+    Quit,
+}
+
+// A variable to stop after each change of. One watched before the program
+// runs is a global, which may not be defined yet.
+struct Watch {
+    name: Symbol,
+    scope: Option<Rc<RefCell<Environment>>>,
+    value: Option<String>,
+}
+
+impl Watch {
+    fn value(&self, evaluation: &Evaluation) -> Option<String> {
+        let scope = match &self.scope {
+            Some(scope) => Rc::clone(scope),
+            None => debugger::scopes(evaluation.environment()).pop().unwrap(),
+        };
+        let scope = scope.borrow();
+        scope.values().get(&self.name).map(ToString::to_string)
+    }
+}
+
+pub struct Console {
+    source: Vec<String>,
+    lines: BTreeSet<usize>,
+    input: Box<dyn BufRead>,
+    // shared with the program, so what it prints is in order with the rest
+    output: Rc<RefCell<dyn Write>>,
+    session: Session,
+    watches: Vec<Watch>,
+    last: String,
+    quit: bool,
+}
+
+impl Console {
+    pub fn new(
+        source: &str,
+        statements: &[Stmt],
+        input: Box<dyn BufRead>,
+        output: Rc<RefCell<dyn Write>>,
+    ) -> Self {
+        Console {
+            source: source.lines().map(str::to_string).collect(),
+            lines: debugger::lines(statements),
+            input,
+            output,
+            session: Session::new(),
+            watches: Vec::new(),
+            last: String::new(),
+            quit: false,
+        }
+    }
+
+    fn say(&self, text: impl std::fmt::Display) {
+        writeln!(self.output.borrow_mut(), "{}", text).expect("failed printing to stdout");
+    }
+
+    // Reads commands until one runs the program or quits.
+    fn prompt(&mut self, mut evaluation: Option<&mut Evaluation>) -> Next {
+        loop {
+            {
+                let mut output = self.output.borrow_mut();
+                write!(output, "(lox) ").expect("failed printing to stdout");
+                output.flush().expect("failed printing to stdout");
+            }
+            let mut line = String::new();
+            if self.input.read_line(&mut line).unwrap_or(0) == 0 {
+                self.say("");
+                return Next::Quit;
+            }
+            match self.command(&line, evaluation.as_deref_mut()) {
+                Next::Prompt => {}
+                next => return next,
+            }
+        }
+    }
+
+    fn command(&mut self, line: &str, evaluation: Option<&mut Evaluation>) -> Next {
+        let line = match line.trim() {
+            "" => self.last.clone(),
+            line => {
+                self.last = line.to_string();
+                line.to_string()
+            }
+        };
+        let (name, argument) = match line.split_once(char::is_whitespace) {
+            Some((name, argument)) => (name, argument.trim()),
+            None => (line.as_str(), ""),
+        };
+
+        match (name, evaluation) {
+            ("", _) => {}
+            ("help" | "h", _) => self.say(HELP),
+            ("quit" | "q", _) => return Next::Quit,
+            ("break" | "b", _) => self.set_breakpoint(argument),
+            ("watch", evaluation) => self.watch(argument, evaluation),
+            ("run" | "r", None) => return Next::Run,
+            ("run" | "r", Some(_)) => self.say("The program is already running."),
+            ("continue" | "c", Some(_)) => return self.resume(Step::Continue),
+            ("step" | "s", Some(_)) => return self.resume(Step::In),
+            ("next" | "n", Some(_)) => return self.resume(Step::Over),
+            ("finish", Some(_)) => return self.resume(Step::Out),
+            ("print" | "p", Some(evaluation)) => self.print(evaluation, argument),
+            ("backtrace" | "bt", Some(evaluation)) => self.backtrace(evaluation),
+            (
+                "continue" | "c" | "step" | "s" | "next" | "n" | "finish" | "print" | "p"
+                | "backtrace" | "bt",
+                None,
+            ) => self.say("The program is not running."),
+            (name, _) => self.say(format!("Unknown command '{}'. Try 'help'.", name)),
+        }
+        Next::Prompt
+    }
+
+    fn resume(&mut self, step: Step) -> Next {
+        self.session.resume(step);
+        Next::Resume
+    }
+
+    // Before running the program again, keeping breakpoints and the watches
+    // of globals.
+    fn start(&mut self) {
+        let breakpoints = std::mem::take(&mut self.session.breakpoints);
+        self.session = Session::new();
+        self.session.breakpoints = breakpoints;
+        self.watches.retain(|watch| watch.scope.is_none());
+        for watch in &mut self.watches {
+            watch.value = None;
+        }
+    }
+
+    fn set_breakpoint(&mut self, argument: &str) {
+        match argument.parse::<usize>() {
+            Ok(line) if self.lines.contains(&line) => {
+                self.session.breakpoints.insert(line);
+                self.say(format!("Breakpoint at line {}.", line));
+            }
+            Ok(line) => self.say(format!("No statement starts on line {}.", line)),
+            Err(_) => self.say("Usage: break <line>"),
+        }
+    }
+
+    fn watch(&mut self, argument: &str, evaluation: Option<&mut Evaluation>) {
+        let is_name = argument.starts_with(|c: char| c.is_alphabetic() || c == '_')
+            && argument.chars().all(|c| c.is_alphanumeric() || c == '_');
+        if !is_name {
+            self.say("Usage: watch <var>");
+            return;
+        }
+
+        let name = Symbol::intern(argument);
+        let mut watch = Watch {
+            name: name.clone(),
+            scope: None,
+            value: None,
+        };
+        if let Some(evaluation) = evaluation {
+            let environment = self.session.frames(evaluation).swap_remove(0).environment;
+            let mut scopes = debugger::scopes(&environment);
+            let globals = scopes.pop().unwrap();
+            let scope = scopes
+                .into_iter()
+                .find(|scope| scope.borrow().values().contains_key(&name));
+            if scope.is_none() && !globals.borrow().values().contains_key(&name) {
+                self.say(format!("No variable '{}' here.", name.as_str()));
+                return;
+            }
+            watch.scope = scope;
+            watch.value = watch.value(evaluation);
+        }
+        self.say(format!("Watching {}.", name.as_str()));
+        self.watches.push(watch);
+    }
+
+    fn print(&mut self, evaluation: &mut Evaluation, argument: &str) {
+        let environment = self.session.frames(evaluation).swap_remove(0).environment;
+        match debugger::evaluate(evaluation, &environment, argument) {
+            Ok(value) => self.say(value),
+            Err(message) => self.say(format!("error: {}", message)),
+        }
+    }
+
+    fn backtrace(&mut self, evaluation: &Evaluation) {
+        for (index, frame) in self.session.frames(evaluation).iter().enumerate() {
+            self.say(format!(
+                "#{:<3}{} at line {}",
+                index, frame.name, frame.line
+            ));
+        }
+    }
+
+    // Stops before the statement on `line` for a breakpoint, a step or a
+    // watched variable that changed, until a command resumes the program.
+    fn statement(&mut self, evaluation: &mut Evaluation, line: usize) -> Result<(), RuntimeError> {
+        let mut changed = false;
+        for index in 0..self.watches.len() {
+            let value = self.watches[index].value(evaluation);
+            if value == self.watches[index].value {
+                continue;
+            }
+            let watch = &self.watches[index];
+            let describe =
+                |value: &Option<String>| value.as_deref().unwrap_or("undefined").to_string();
+            self.say(format!(
+                "Watch {}: {} -> {}",
+                watch.name.as_str(),
+                describe(&watch.value),
+                describe(&value)
+            ));
+            self.watches[index].value = value;
+            changed = true;
+        }
+
+        let stop = self.session.statement(line);
+        if stop.is_none() && !changed {
+            return Ok(());
+        }
+        if stop == Some(Stop::Breakpoint) {
+            let frames = self.session.frames(evaluation);
+            self.say(format!(
+                "Breakpoint in {} at line {}.",
+                frames[0].name, line
+            ));
+        }
+        let text = self.source.get(line - 1).map_or("", String::as_str);
+        self.say(format!("{}\t{}", line, text));
+
+        match self.prompt(Some(evaluation)) {
+            Next::Quit => {
+                self.quit = true;
+                Err(RuntimeError {
+                    line,
+                    message: "Stopped by the debugger.".to_string(),
+                    trace: Vec::new(),
+                })
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+// Where the program prints, which is where the debugger does.
+struct Output(Rc<RefCell<dyn Write>>);
+
+impl Write for Output {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.borrow_mut().write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.0.borrow_mut().flush()
+    }
+}
+
+// The debugger as the hook of the program it runs, which it outlives.
+struct Running(Rc<RefCell<Console>>);
+
+impl Hook for Running {
+    fn statement(&mut self, evaluation: &mut Evaluation, line: usize) -> Result<(), RuntimeError> {
+        self.0.borrow_mut().statement(evaluation, line)
+    }
+
+    fn call(&mut self, evaluation: &mut Evaluation, name: Symbol, line: usize) {
+        self.0.borrow_mut().session.call(evaluation, name, line);
+    }
+
+    fn returned(&mut self, _evaluation: &mut Evaluation) {
+        self.0.borrow_mut().session.returned();
+    }
+
+    fn iteration(&mut self, _evaluation: &mut Evaluation) {
+        self.0.borrow_mut().session.iteration();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{parser::Parser, resolver::Resolver, scanner::Scanner};
+
+    const PROGRAM: &str = "\
+fun add(a, b) {
+  var sum = a + b;
+  return sum;
+}
+var total = 0;
+total = add(total, 1);
+total = add(total, 2);
+print total;";
+
+    const LOOPS: &str = "\
+var i = 0;
+while (i < 3)
+  i = i + 1;
+for (var j = 0; j < 2; j = j + 1) {
+  print j;
+  i = i + j;
+}
+print i;";
+
+    // What the debugger prints for `commands`, each after a prompt.
+    fn transcript(commands: &str) -> String {
+        transcript_of(PROGRAM, commands)
+    }
+
+    fn transcript_of(program: &str, commands: &str) -> String {
+        let statements = Parser::new(Scanner::new(program).scan_tokens()).parse();
+        Resolver::new().resolve(&statements);
+
+        let output = Rc::new(RefCell::new(Vec::new()));
+        let input = Box::new(io::Cursor::new(commands.to_string()));
+        let console = Console::new(program, &statements, input, output.clone());
+        debug(console, Path::new("test.lox"), &statements, Vec::new());
+
+        let output = String::from_utf8(output.borrow().clone()).unwrap();
+        output.replace("(lox) ", "> ")
+    }
+
+    #[test]
+    fn test_breakpoints_and_stepping() {
+        let output =
+            transcript("break 4\nbreak 2\nrun\nbt\np a + b\n\nnext\nfinish\nc\np sum\nc\n");
+        assert_eq!(
+            output,
+            "\
+> No statement starts on line 4.
+> Breakpoint at line 2.
+> Breakpoint in add at line 2.
+2\t  var sum = a + b;
+> #0  add at line 2
+#1  script at line 6
+> 1
+> 1
+> 3\t  return sum;
+> 7\ttotal = add(total, 2);
+> Breakpoint in add at line 2.
+2\t  var sum = a + b;
+> error: Undefined variable 'sum'.
+> 3
+Program exited.
+> 
+"
+        );
+    }
+
+    #[test]
+    fn test_breakpoints_in_loops() {
+        let output = transcript_of(LOOPS, "break 3\nrun\np i\nc\np i\nc\np i\nc\n");
+        assert_eq!(
+            output,
+            "\
+> Breakpoint at line 3.
+> Breakpoint in script at line 3.
+3\t  i = i + 1;
+> 0
+> Breakpoint in script at line 3.
+3\t  i = i + 1;
+> 1
+> Breakpoint in script at line 3.
+3\t  i = i + 1;
+> 2
+> 0
+1
+4
+Program exited.
+> 
+"
+        );
+    }
+
+    #[test]
+    fn test_next_stays_in_loops() {
+        let output = transcript_of(LOOPS, "break 5\nrun\nnext\nnext\nnext\nnext\n");
+        assert_eq!(
+            output,
+            "\
+> Breakpoint at line 5.
+> Breakpoint in script at line 5.
+5\t  print j;
+> 0
+6\t  i = i + j;
+> 5\t  print j;
+> 1
+6\t  i = i + j;
+> 8\tprint i;
+> 
+"
+        );
+    }
+
+    #[test]
+    fn test_watch() {
+        let output = transcript("watch total\nrun\nc\nc\nc\nrun\nquit\n");
+        assert_eq!(
+            output,
+            "\
+> Watching total.
+> Watch total: undefined -> 0
+6\ttotal = add(total, 1);
+> Watch total: 0 -> 1
+7\ttotal = add(total, 2);
+> Watch total: 1 -> 3
+8\tprint total;
+> 3
+Program exited.
+> Watch total: undefined -> 0
+6\ttotal = add(total, 1);
+> "
+        );
+    }
+}
@@ -0,0 +1,430 @@
+use std::{cell::Cell, cell::RefCell, collections::BTreeSet, rc::Rc};
+
+use crate::{
+    environment::Environment,
+    expr::{self, Evaluation, Expr},
+    parser::Parser,
+    scanner::Scanner,
+    stmt::Stmt,
+    symbol::Symbol,
+    token::{LiteralValue, Token},
+    value::Value,
+};
+
+// How far to run a stopped program.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Step {
+    // to the next breakpoint
+    Continue,
+    // to the next line, in whatever function it is
+    In,
+    // to the next line of this function or the one that called it
+    Over,
+    // to the next line of the function that called this one
+    Out,
+    // to the next statement
+    Pause,
+}
+
+// Why a program stopped.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Stop {
+    Breakpoint,
+    Step,
+    Pause,
+}
+
+// A function being run, with the line it is at and the scope of the
+// statement there. The script itself is at the bottom.
+pub struct Frame {
+    pub name: String,
+    pub line: usize,
+    pub environment: Rc<RefCell<Environment>>,
+}
+
+// A call in progress, with where it was called from.
+struct Call {
+    name: Symbol,
+    line: usize,
+    environment: Rc<RefCell<Environment>>,
+}
+
+// What a debugger knows of the program it runs: where it is, the calls that
+// led there, and where to stop next. Kept up to date by the debugger's
+// `expr::Hook`.
+//
+// A line is only stopped at when it is entered, so a breakpoint on a line
+// with several statements stops once. Each run of the body of a loop enters
+// its lines again, however few there are.
+pub struct Session {
+    pub breakpoints: BTreeSet<usize>,
+    calls: Vec<Call>,
+    step: Step,
+    // the number of calls in progress when the step started
+    depth: usize,
+    // the line of the last statement, and the number of calls then
+    line: usize,
+    previous: Option<(usize, usize)>,
+}
+
+impl Session {
+    pub fn new() -> Self {
+        Session {
+            breakpoints: BTreeSet::new(),
+            calls: Vec::new(),
+            step: Step::Continue,
+            depth: 0,
+            line: 0,
+            previous: None,
+        }
+    }
+
+    pub fn resume(&mut self, step: Step) {
+        self.step = step;
+        self.depth = self.calls.len();
+    }
+
+    // Whether to stop before the statement on `line`, and why.
+    pub fn statement(&mut self, line: usize) -> Option<Stop> {
+        let depth = self.calls.len();
+        let entered = self.previous != Some((line, depth));
+        self.line = line;
+        self.previous = Some((line, depth));
+
+        match self.step {
+            Step::Pause => Some(Stop::Pause),
+            Step::In if entered => Some(Stop::Step),
+            Step::Over if entered && depth <= self.depth => Some(Stop::Step),
+            Step::Out if depth < self.depth => Some(Stop::Step),
+            _ if entered && self.breakpoints.contains(&line) => Some(Stop::Breakpoint),
+            _ => None,
+        }
+    }
+
+    pub fn call(&mut self, evaluation: &Evaluation, name: Symbol, line: usize) {
+        self.calls.push(Call {
+            name,
+            line,
+            environment: Rc::clone(evaluation.environment()),
+        });
+    }
+
+    // A loop is back at its condition, so its body is entered again.
+    pub fn iteration(&mut self) {
+        self.previous = None;
+    }
+
+    // Back on the line of the call, which isn't entered again.
+    pub fn returned(&mut self) {
+        if let Some(call) = self.calls.pop() {
+            self.line = call.line;
+            self.previous = Some((call.line, self.calls.len()));
+        }
+    }
+
+    // The innermost first.
+    pub fn frames(&self, evaluation: &Evaluation) -> Vec<Frame> {
+        let mut frames = Vec::new();
+        let mut line = self.line;
+        let mut environment = Rc::clone(evaluation.environment());
+        for call in self.calls.iter().rev() {
+            frames.push(Frame {
+                name: call.name.to_string(),
+                line,
+                environment,
+            });
+            line = call.line;
+            environment = Rc::clone(&call.environment);
+        }
+        frames.push(Frame {
+            name: "script".to_string(),
+            line,
+            environment,
+        });
+        frames
+    }
+}
+
+// The lines where statements start, the only ones a breakpoint can be on.
+pub fn lines(statements: &[Stmt]) -> BTreeSet<usize> {
+    fn walk(stmt: &Stmt, lines: &mut BTreeSet<usize>) {
+        lines.extend(stmt.line());
+        match stmt {
+            Stmt::Block { statements } => statements.iter().for_each(|stmt| walk(stmt, lines)),
+            Stmt::If {
+                then_branch,
+                else_branch,
+                ..
+            } => {
+                walk(then_branch, lines);
+                else_branch.iter().for_each(|stmt| walk(stmt, lines));
+            }
+            Stmt::While { body, .. } | Stmt::For { body, .. } => walk(body, lines),
+            Stmt::Function(declaration) => {
+                declaration.body.iter().for_each(|stmt| walk(stmt, lines));
+            }
+            Stmt::Try {
+                body,
+                catch,
+                finally,
+                ..
+            } => {
+                let catch = catch.iter().flat_map(|catch| &catch.body);
+                let finally = finally.iter().flatten();
+                body.iter()
+                    .chain(catch)
+                    .chain(finally)
+                    .for_each(|stmt| walk(stmt, lines));
+            }
+            _ => {}
+        }
+    }
+
+    let mut lines = BTreeSet::new();
+    statements.iter().for_each(|stmt| walk(stmt, &mut lines));
+    lines
+}
+
+// The scopes `environment` is in, from the innermost out to the globals.
+pub fn scopes(environment: &Rc<RefCell<Environment>>) -> Vec<Rc<RefCell<Environment>>> {
+    let mut scopes = vec![Rc::clone(environment)];
+    loop {
+        let enclosing = scopes.last().unwrap().borrow().enclosing().cloned();
+        match enclosing {
+            Some(enclosing) => scopes.push(enclosing),
+            None => return scopes,
+        }
+    }
+}
+
+// The variables of a scope by name, leaving out the natives, which are
+// always there.
+pub fn variables(environment: &Environment) -> Vec<(Symbol, Value)> {
+    let mut variables: Vec<_> = environment
+        .values()
+        .iter()
+        .filter(|(_, value)| !matches!(value, Value::Native(_)))
+        .map(|(name, value)| (name.clone(), value.clone()))
+        .collect();
+    variables.sort_by(|(a, _), (b, _)| a.as_str().cmp(b.as_str()));
+    variables
+}
+
+// Evaluates the expression `source` as if it were in the scope
+// `environment`, seeing every variable there by name.
+pub fn evaluate(
+    evaluation: &mut Evaluation,
+    environment: &Rc<RefCell<Environment>>,
+    source: &str,
+) -> Result<Value, String> {
+    let mut scanner = Scanner::new(source);
+    let tokens = scanner.scan_tokens();
+    if let Some(error) = scanner.errors().first() {
+        return Err(error.message.clone());
+    }
+    let expr = Parser::new(tokens)
+        .parse_single_expression()
+        .map_err(|error| format!("at {}: {}", error.location, error.message))?;
+
+    expr.accept(&Locate { environment });
+    evaluation
+        .evaluate_in(Rc::clone(environment), &expr)
+        .map_err(|error| error.message)
+}
+
+// Resolves the variables of an expression against the scopes of a running
+// program, rather than those of the source like the resolver.
+struct Locate<'a> {
+    environment: &'a Rc<RefCell<Environment>>,
+}
+
+impl Locate<'_> {
+    // Globals are looked up by name, as are variables that don't exist.
+    fn depth(&self, name: &Token) -> Option<usize> {
+        let mut environment = Rc::clone(self.environment);
+        let mut depth = 0;
+        loop {
+            let enclosing = {
+                let scope = environment.borrow();
+                if scope.values().contains_key(&name.lexeme) {
+                    return scope.enclosing().map(|_| depth);
+                }
+                scope.enclosing().cloned()?
+            };
+            environment = enclosing;
+            depth += 1;
+        }
+    }
+}
+
+impl expr::Visitor<()> for &Locate<'_> {
+    fn visit_binary(self, left: &Expr, _op: &Token, right: &Expr) {
+        left.accept(self);
+        right.accept(self);
+    }
+
+    fn visit_unary(self, _op: &Token, right: &Expr) {
+        right.accept(self);
+    }
+
+    fn visit_literal(self, _value: &Option<LiteralValue>, _token: &Token) {}
+
+    fn visit_grouped(self, expr: &Expr) {
+        expr.accept(self);
+    }
+
+    fn visit_logical(self, left: &Expr, _op: &Token, right: &Expr) {
+        left.accept(self);
+        right.accept(self);
+    }
+
+    fn visit_variable(self, name: &Token, depth: &Cell<Option<usize>>) {
+        depth.set(self.depth(name));
+    }
+
+    fn visit_assign(self, name: &Token, value: &Expr, depth: &Cell<Option<usize>>) {
+        value.accept(self);
+        depth.set(self.depth(name));
+    }
+
+    fn visit_call(self, callee: &Expr, _paren: &Token, arguments: &[Expr]) {
+        callee.accept(self);
+        for argument in arguments {
+            argument.accept(self);
+        }
+    }
+
+    fn visit_get(self, object: &Expr, _name: &Token) {
+        object.accept(self);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(source: &str) -> Vec<Stmt> {
+        Parser::new(Scanner::new(source).scan_tokens()).parse()
+    }
+
+    #[test]
+    fn test_stepping() {
+        let mut session = Session::new();
+        session.breakpoints.insert(3);
+        assert_eq!(session.statement(1), None);
+        // once per line
+        assert_eq!(session.statement(3), Some(Stop::Breakpoint));
+        assert_eq!(session.statement(3), None);
+
+        session.resume(Step::Over);
+        let evaluation = Evaluation::with_args(Vec::new());
+        session.call(&evaluation, Symbol::intern("f"), 3);
+        assert_eq!(session.statement(7), None);
+        session.returned();
+        assert_eq!(session.statement(3), None);
+        assert_eq!(session.statement(4), Some(Stop::Step));
+
+        session.resume(Step::In);
+        session.call(&evaluation, Symbol::intern("f"), 4);
+        assert_eq!(session.statement(7), Some(Stop::Step));
+        let frames = session.frames(&evaluation);
+        let frames: Vec<_> = frames.iter().map(|f| (f.name.as_str(), f.line)).collect();
+        assert_eq!(frames, vec![("f", 7), ("script", 4)]);
+
+        session.resume(Step::Out);
+        assert_eq!(session.statement(8), None);
+        session.returned();
+        assert_eq!(session.statement(5), Some(Stop::Step));
+    }
+
+    // Runs `source`, resuming with `step` at every stop, and returns the
+    // lines stopped at.
+    fn stops(source: &str, breakpoints: &[usize], step: Step) -> Vec<usize> {
+        struct Stepper(Rc<RefCell<(Session, Vec<usize>)>>, Step);
+        impl expr::Hook for Stepper {
+            fn statement(
+                &mut self,
+                _: &mut Evaluation,
+                line: usize,
+            ) -> Result<(), expr::RuntimeError> {
+                let (session, stops) = &mut *self.0.borrow_mut();
+                if session.statement(line).is_some() {
+                    stops.push(line);
+                    session.resume(self.1);
+                }
+                Ok(())
+            }
+            fn call(&mut self, evaluation: &mut Evaluation, name: Symbol, line: usize) {
+                self.0.borrow_mut().0.call(evaluation, name, line);
+            }
+            fn returned(&mut self, _: &mut Evaluation) {
+                self.0.borrow_mut().0.returned();
+            }
+            fn iteration(&mut self, _: &mut Evaluation) {
+                self.0.borrow_mut().0.iteration();
+            }
+        }
+
+        let mut session = Session::new();
+        session.breakpoints.extend(breakpoints);
+        let state = Rc::new(RefCell::new((session, Vec::new())));
+        let mut evaluation = Evaluation::with_args(Vec::new());
+        evaluation.set_output(Box::new(std::io::sink()));
+        evaluation.set_hook(Box::new(Stepper(Rc::clone(&state), step)));
+        let statements = parse(source);
+        crate::resolver::Resolver::new().resolve(&statements);
+        evaluation.interpret(&statements).unwrap();
+        let stops = std::mem::take(&mut state.borrow_mut().1);
+        stops
+    }
+
+    #[test]
+    fn test_loops_enter_their_body_on_each_iteration() {
+        let source = "var i = 0;\nwhile (i < 3)\n  i = i + 1;\nprint i;";
+        assert_eq!(stops(source, &[3], Step::Continue), [3, 3, 3]);
+
+        let source = "for (var i = 0; i < 3; i = i + 1) {\n  var j = i;\n  print j;\n}\nprint 0;";
+        assert_eq!(stops(source, &[2], Step::Continue), [2, 2, 2]);
+        assert_eq!(stops(source, &[2], Step::Over), [2, 3, 2, 3, 2, 3, 5]);
+
+        // a loop on a single line, once it starts
+        let source = "for (var i = 0; i < 3; i = i + 1) print i;";
+        assert_eq!(stops(source, &[1], Step::Continue), [1, 1, 1]);
+    }
+
+    #[test]
+    fn test_lines_and_evaluate() {
+        let statements = parse("fun f(a) {\n  {\n    print a;\n  }\n}\n\nwhile (false)\n  f(1);");
+        assert_eq!(lines(&statements), BTreeSet::from([1, 3, 7, 8]));
+
+        let mut evaluation = Evaluation::with_args(Vec::new());
+        evaluation.interpret(&parse("var a = 1;")).unwrap();
+        let mut local = Environment::with_enclosing(Rc::clone(evaluation.environment()));
+        local.define(Symbol::intern("b"), Value::Number(2.0));
+        let local = Rc::new(RefCell::new(local));
+
+        let value = evaluate(&mut evaluation, &local, "a + b").unwrap();
+        assert!(matches!(value, Value::Number(n) if n == 3.0));
+        evaluate(&mut evaluation, &local, "b = 5").unwrap();
+        let names: Vec<_> = scopes(&local)
+            .iter()
+            .map(|scope| variables(&scope.borrow()))
+            .map(|variables| {
+                variables
+                    .iter()
+                    .map(|(name, value)| format!("{} = {}", name.as_str(), value))
+                    .collect::<Vec<_>>()
+            })
+            .collect();
+        assert_eq!(names, vec![vec!["b = 5"], vec!["a = 1"]]);
+
+        assert_eq!(
+            evaluate(&mut evaluation, &local, "c").unwrap_err(),
+            "Undefined variable 'c'."
+        );
+        assert_eq!(
+            evaluate(&mut evaluation, &local, "a +").unwrap_err(),
+            "at end: Expect expression."
+        );
+    }
+}
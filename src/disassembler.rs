@@ -0,0 +1,155 @@
+use std::fmt::Write;
+
+use crate::chunk::{Chunk, Constant, Function, OpCode};
+
+// Lists the instructions of `function` and the code its handlers catch the
+// errors of, then those of the functions it defines, e.g.,
+//
+//     == script ==
+//     0000    1 Constant            0 '1'
+//     0003    | DefineGlobal        1 'a'
+pub fn disassemble(function: &Function) -> String {
+    let mut output = String::new();
+    let mut functions = vec![function];
+    while let Some(function) = functions.pop() {
+        if !output.is_empty() {
+            output.push('\n');
+        }
+        writeln!(output, "== {} ==", function.name).unwrap();
+
+        let chunk = &function.chunk;
+        let mut offset = 0;
+        while offset < chunk.code.len() {
+            let (text, next) = instruction(chunk, offset);
+            writeln!(output, "{}", text).unwrap();
+            offset = next;
+        }
+        for handler in &chunk.handlers {
+            writeln!(
+                output,
+                "catch {:04}..{:04} -> {:04}, depth {}",
+                handler.start, handler.end, handler.target, handler.depth
+            )
+            .unwrap();
+        }
+
+        // in the order they are defined
+        for constant in chunk.constants.iter().rev() {
+            if let Constant::Function(function) = constant {
+                functions.push(function);
+            }
+        }
+    }
+    output
+}
+
+// Formats the instruction at `offset` with its offset, source line and
+// operands, returning the offset of the next instruction.
+pub fn instruction(chunk: &Chunk, offset: usize) -> (String, usize) {
+    let mut text = format!("{:04} ", offset);
+    match offset > 0 && chunk.line(offset) == chunk.line(offset - 1) {
+        true => text.push_str("   | "),
+        false => write!(text, "{:4} ", chunk.line(offset)).unwrap(),
+    }
+
+    let op = match OpCode::try_from(chunk.code[offset]) {
+        Ok(op) => op,
+        Err(byte) => {
+            write!(text, "<invalid opcode {}>", byte).unwrap();
+            return (text, offset + 1);
+        }
+    };
+    let name = format!("{:?}", op);
+
+    let next = match op {
+        OpCode::Constant
+        | OpCode::GetGlobal
+        | OpCode::DefineGlobal
+        | OpCode::SetGlobal
+        | OpCode::GetProperty
+        | OpCode::Import => {
+            let index = chunk.read_u16(offset + 1);
+            let constant = &chunk.constants[index as usize];
+            write!(text, "{:<16} {:4} '{}'", name, index, constant).unwrap();
+            offset + 3
+        }
+        OpCode::GetLocal
+        | OpCode::SetLocal
+        | OpCode::GetUpvalue
+        | OpCode::SetUpvalue
+        | OpCode::Call => {
+            write!(text, "{:<16} {:4}", name, chunk.code[offset + 1]).unwrap();
+            offset + 2
+        }
+        OpCode::Jump | OpCode::JumpIfFalse | OpCode::Loop => {
+            let jump = chunk.read_u16(offset + 1) as usize;
+            let target = match op {
+                OpCode::Loop => offset + 3 - jump,
+                _ => offset + 3 + jump,
+            };
+            write!(text, "{:<16} {:4} -> {}", name, offset, target).unwrap();
+            offset + 3
+        }
+        OpCode::Closure => {
+            let index = chunk.read_u16(offset + 1);
+            let constant = &chunk.constants[index as usize];
+            write!(text, "{:<16} {:4} {}", name, index, constant).unwrap();
+
+            let mut next = offset + 3;
+            if let Constant::Function(function) = constant {
+                for _ in 0..function.upvalues {
+                    let kind = match chunk.code[next] {
+                        1 => "local",
+                        _ => "upvalue",
+                    };
+                    let index = chunk.code[next + 1];
+                    write!(text, "\n{:04}    |  {:>16} {}", next, kind, index).unwrap();
+                    next += 2;
+                }
+            }
+            next
+        }
+        _ => {
+            text.push_str(&name);
+            offset + 1
+        }
+    };
+    (text, next)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{compiler, parser::Parser, resolver::Resolver, scanner::Scanner};
+
+    #[test]
+    fn test_disassemble() {
+        let source = "var a = 1;\nfun f() {\n  while (a) return a;\n}";
+        let tokens = Scanner::new(source).scan_tokens();
+        let statements = Parser::new(tokens).parse();
+        Resolver::new().resolve(&statements);
+        let function = compiler::compile(&statements).unwrap();
+
+        let expected = "\
+== script ==
+0000    1 Constant            0 '1'
+0003    | DefineGlobal        1 'a'
+0006    2 Closure             2 <fn f>
+0009    | DefineGlobal        3 'f'
+0012    | Nil
+0013    | Return
+
+== f ==
+0000    3 GetGlobal           0 'a'
+0003    | JumpIfFalse         3 -> 14
+0006    | Pop
+0007    | GetGlobal           0 'a'
+0010    | Return
+0011    | Loop               11 -> 0
+0014    | Pop
+0015    | Nil
+0016    | Return
+";
+        assert_eq!(disassemble(&function), expected);
+    }
+}
@@ -0,0 +1,77 @@
+use std::{cell::RefCell, collections::HashMap, rc::Rc};
+
+use crate::{expr::RuntimeError, symbol::Symbol, token::Token, value::Value};
+
+#[derive(Debug, Default)]
+pub struct Environment {
+    values: HashMap<Symbol, Value>,
+    enclosing: Option<Rc<RefCell<Environment>>>,
+}
+
+impl Environment {
+    pub fn with_enclosing(enclosing: Rc<RefCell<Environment>>) -> Self {
+        Environment {
+            values: HashMap::new(),
+            enclosing: Some(enclosing),
+        }
+    }
+
+    // Redefining an existing variable is allowed, e.g., `var a = 1; var a = 2;`
+    pub fn define(&mut self, name: Symbol, value: Value) {
+        self.values.insert(name, value);
+    }
+
+    // Walks `depth` environments up the chain, as computed by the resolver.
+    pub fn ancestor(
+        environment: &Rc<RefCell<Environment>>,
+        depth: usize,
+    ) -> Rc<RefCell<Environment>> {
+        let mut environment = Rc::clone(environment);
+        for _ in 0..depth {
+            let enclosing = environment
+                .borrow()
+                .enclosing
+                .clone()
+                .expect("the resolver only counts scopes that exist");
+            environment = enclosing;
+        }
+        environment
+    }
+
+    pub fn get(&self, name: &Token) -> Result<Value, RuntimeError> {
+        if let Some(value) = self.values.get(&name.lexeme) {
+            return Ok(value.clone());
+        }
+
+        match &self.enclosing {
+            Some(enclosing) => enclosing.borrow().get(name),
+            None => Err(RuntimeError::new(
+                name,
+                format!("Undefined variable '{}'.", name.lexeme),
+            )),
+        }
+    }
+
+    pub fn assign(&mut self, name: &Token, value: Value) -> Result<(), RuntimeError> {
+        if let Some(slot) = self.values.get_mut(&name.lexeme) {
+            *slot = value;
+            return Ok(());
+        }
+
+        match &self.enclosing {
+            Some(enclosing) => enclosing.borrow_mut().assign(name, value),
+            None => Err(RuntimeError::new(
+                name,
+                format!("Undefined variable '{}'.", name.lexeme),
+            )),
+        }
+    }
+
+    pub fn values(&self) -> &HashMap<Symbol, Value> {
+        &self.values
+    }
+
+    pub fn enclosing(&self) -> Option<&Rc<RefCell<Environment>>> {
+        self.enclosing.as_ref()
+    }
+}
@@ -1,6 +1,22 @@
-use crate::token::{LiteralValue, Token, TokenType};
+use std::{
+    cell::{Cell, RefCell},
+    io::{self, Write},
+    rc::Rc,
+};
 
-#[derive(Debug)]
+use crate::{
+    backend::{MAX_DEPTH, STACK_SIZE},
+    environment::Environment,
+    gc::{Heap, Object},
+    module::{self, Modules},
+    natives,
+    stmt::{self, Catch, FunctionDecl, Stmt},
+    symbol::Symbol,
+    token::{LiteralValue, Token, TokenType, Trivia},
+    value::{stringify, LoxFunction, Module, Value},
+};
+
+#[derive(Debug, Clone)]
 pub enum Expr {
     Binary {
         left: Box<Expr>,
@@ -16,6 +32,33 @@ pub enum Expr {
     },
     Literal {
         value: Option<LiteralValue>,
+        token: Token,
+    },
+    Logical {
+        left: Box<Expr>,
+        op: Token,
+        right: Box<Expr>,
+    },
+    // `depth` is the number of scopes between the use of a local variable
+    // and its declaration, filled in by the resolver. Globals have none.
+    Variable {
+        name: Token,
+        depth: Cell<Option<usize>>,
+    },
+    Assign {
+        name: Token,
+        value: Box<Expr>,
+        depth: Cell<Option<usize>>,
+    },
+    Call {
+        callee: Box<Expr>,
+        paren: Token,
+        arguments: Vec<Expr>,
+    },
+    // a variable of a module, `m.name`
+    Get {
+        object: Box<Expr>,
+        name: Token,
     },
 }
 
@@ -24,8 +67,30 @@ impl Expr {
         match self {
             Expr::Binary { left, op, right } => visitor.visit_binary(left, op, right),
             Expr::Unary { op, right } => visitor.visit_unary(op, right),
-            Expr::Literal { value } => visitor.visit_literal(value),
+            Expr::Literal { value, token } => visitor.visit_literal(value, token),
             Expr::Grouped { expr } => visitor.visit_grouped(expr),
+            Expr::Logical { left, op, right } => visitor.visit_logical(left, op, right),
+            Expr::Variable { name, depth } => visitor.visit_variable(name, depth),
+            Expr::Assign { name, value, depth } => visitor.visit_assign(name, value, depth),
+            Expr::Call {
+                callee,
+                paren,
+                arguments,
+            } => visitor.visit_call(callee, paren, arguments),
+            Expr::Get { object, name } => visitor.visit_get(object, name),
+        }
+    }
+
+    // The line of its first token.
+    pub fn line(&self) -> usize {
+        match self {
+            Expr::Binary { left, .. } | Expr::Logical { left, .. } => left.line(),
+            Expr::Unary { op, .. } => op.line,
+            Expr::Grouped { expr } => expr.line(),
+            Expr::Literal { token, .. } => token.line,
+            Expr::Variable { name, .. } | Expr::Assign { name, .. } => name.line,
+            Expr::Call { callee, .. } => callee.line(),
+            Expr::Get { object, .. } => object.line(),
         }
     }
 }
@@ -33,20 +98,33 @@ impl Expr {
 pub trait Visitor<R> {
     fn visit_binary(self, left: &Expr, op: &Token, right: &Expr) -> R;
     fn visit_unary(self, op: &Token, right: &Expr) -> R;
-    fn visit_literal(self, value: &Option<LiteralValue>) -> R;
+    fn visit_literal(self, value: &Option<LiteralValue>, token: &Token) -> R;
     fn visit_grouped(self, expr: &Expr) -> R;
+    fn visit_logical(self, left: &Expr, op: &Token, right: &Expr) -> R;
+    fn visit_variable(self, name: &Token, depth: &Cell<Option<usize>>) -> R;
+    fn visit_assign(self, name: &Token, value: &Expr, depth: &Cell<Option<usize>>) -> R;
+    fn visit_call(self, callee: &Expr, paren: &Token, arguments: &[Expr]) -> R;
+    fn visit_get(self, object: &Expr, name: &Token) -> R;
 }
 
-pub struct Prettify {
-    expr: Expr,
-}
+pub struct Prettify;
 
 impl Prettify {
-    pub fn new(expr: Expr) -> Self {
-        Prettify { expr }
+    // Comments and blank lines are left out.
+    pub fn pretty_print(&self, statements: &[Stmt]) {
+        for stmt in statements {
+            if !matches!(stmt, Stmt::Trivia(_)) {
+                println!("{}", stmt.accept(self));
+            }
+        }
     }
-    pub fn pretty_print(&self) {
-        println!("{}", self.expr.accept(self));
+
+    fn statements(&self, statements: &[Stmt]) -> String {
+        statements
+            .iter()
+            .filter(|stmt| !matches!(stmt, Stmt::Trivia(_)))
+            .map(|stmt| format!(" {}", stmt.accept(self)))
+            .collect()
     }
 }
 
@@ -62,118 +140,1253 @@ impl Visitor<String> for &Prettify {
     fn visit_unary(self, op: &Token, right: &Expr) -> String {
         format!("({} {})", op.lexeme, right.accept(self))
     }
-    fn visit_literal(self, value: &Option<LiteralValue>) -> String {
+    fn visit_literal(self, value: &Option<LiteralValue>, _token: &Token) -> String {
         match value {
             Some(t) => format!("{}", t),
-            None => format!("unknown"),
+            None => "unknown".to_string(),
         }
     }
     fn visit_grouped(self, expr: &Expr) -> String {
         format!("(group {})", expr.accept(self))
     }
+    fn visit_logical(self, left: &Expr, op: &Token, right: &Expr) -> String {
+        format!(
+            "({} {} {})",
+            op.lexeme,
+            left.accept(self),
+            right.accept(self)
+        )
+    }
+    fn visit_variable(self, name: &Token, _depth: &Cell<Option<usize>>) -> String {
+        name.lexeme.to_string()
+    }
+    fn visit_assign(self, name: &Token, value: &Expr, _depth: &Cell<Option<usize>>) -> String {
+        format!("(= {} {})", name.lexeme, value.accept(self))
+    }
+    fn visit_call(self, callee: &Expr, _paren: &Token, arguments: &[Expr]) -> String {
+        let arguments = arguments
+            .iter()
+            .map(|argument| format!(" {}", argument.accept(self)))
+            .collect::<String>();
+        format!("(call {}{})", callee.accept(self), arguments)
+    }
+    fn visit_get(self, object: &Expr, name: &Token) -> String {
+        format!("(. {} {})", object.accept(self), name.lexeme)
+    }
+}
+
+impl stmt::Visitor<String> for &Prettify {
+    fn visit_expression(self, expr: &Expr) -> String {
+        format!("(; {})", expr.accept(self))
+    }
+    fn visit_print(self, expr: &Expr) -> String {
+        format!("(print {})", expr.accept(self))
+    }
+    fn visit_var(self, name: &Token, initializer: Option<&Expr>, doc: Option<&str>) -> String {
+        let var = match initializer {
+            Some(initializer) => format!("(var {} {})", name.lexeme, initializer.accept(self)),
+            None => format!("(var {})", name.lexeme),
+        };
+        match doc {
+            Some(doc) => format!("(doc {:?} {})", doc, var),
+            None => var,
+        }
+    }
+    fn visit_block(self, statements: &[Stmt]) -> String {
+        format!("(block{})", self.statements(statements))
+    }
+    fn visit_if(
+        self,
+        _keyword: &Token,
+        condition: &Expr,
+        then_branch: &Stmt,
+        else_branch: Option<&Stmt>,
+    ) -> String {
+        match else_branch {
+            Some(else_branch) => format!(
+                "(if {} {} {})",
+                condition.accept(self),
+                then_branch.accept(self),
+                else_branch.accept(self)
+            ),
+            None => format!(
+                "(if {} {})",
+                condition.accept(self),
+                then_branch.accept(self)
+            ),
+        }
+    }
+    fn visit_while(self, condition: &Expr, body: &Stmt) -> String {
+        format!("(while {} {})", condition.accept(self), body.accept(self))
+    }
+    fn visit_for(
+        self,
+        initializer: Option<&Stmt>,
+        condition: Option<&Expr>,
+        increment: Option<&Expr>,
+        body: &Stmt,
+    ) -> String {
+        let initializer = initializer.map_or("()".to_string(), |stmt| stmt.accept(self));
+        let condition = condition.map_or("()".to_string(), |expr| expr.accept(self));
+        let increment = increment.map_or("()".to_string(), |expr| expr.accept(self));
+        format!(
+            "(for {} {} {} {})",
+            initializer,
+            condition,
+            increment,
+            body.accept(self)
+        )
+    }
+    fn visit_function(self, declaration: &Rc<FunctionDecl>) -> String {
+        let params = declaration
+            .params
+            .iter()
+            .map(|param| param.lexeme.as_str())
+            .collect::<Vec<_>>()
+            .join(" ");
+        let body = self.statements(&declaration.body);
+        let fun = format!("(fun {} ({}){})", declaration.name.lexeme, params, body);
+        match &declaration.doc {
+            Some(doc) => format!("(doc {:?} {})", doc, fun),
+            None => fun,
+        }
+    }
+    fn visit_return(self, _keyword: &Token, value: Option<&Expr>) -> String {
+        match value {
+            Some(value) => format!("(return {})", value.accept(self)),
+            None => "(return)".to_string(),
+        }
+    }
+    fn visit_throw(self, _keyword: &Token, value: &Expr) -> String {
+        format!("(throw {})", value.accept(self))
+    }
+    fn visit_try(
+        self,
+        _keyword: &Token,
+        body: &[Stmt],
+        catch: Option<&Catch>,
+        finally: Option<&[Stmt]>,
+    ) -> String {
+        let catch = catch.map_or(String::new(), |catch| {
+            format!(
+                " (catch {}{})",
+                catch.name.lexeme,
+                self.statements(&catch.body)
+            )
+        });
+        let finally = finally.map_or(String::new(), |finally| {
+            format!(" (finally{})", self.statements(finally))
+        });
+        format!("(try (block{}){}{})", self.statements(body), catch, finally)
+    }
+    fn visit_import(
+        self,
+        _keyword: &Token,
+        path: &Token,
+        alias: Option<&Token>,
+        names: &[Token],
+    ) -> String {
+        match alias {
+            Some(alias) => format!("(import \"{}\" {})", path.lexeme, alias.lexeme),
+            None => {
+                let names = names
+                    .iter()
+                    .map(|name| format!(" {}", name.lexeme))
+                    .collect::<String>();
+                format!("(from \"{}\"{})", path.lexeme, names)
+            }
+        }
+    }
+    fn visit_trivia(self, _trivia: &Trivia) -> String {
+        String::new()
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct RuntimeError {
+    pub line: usize,
+    pub message: String,
+    // the calls in progress, innermost first, once the error has left them
+    pub trace: Vec<TraceFrame>,
+}
+
+// A call in progress when an error happened: the function, or None for the
+// top level of a module or the script, and the line it had got to.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TraceFrame {
+    pub function: Option<String>,
+    // the path of the module, for its top level
+    pub module: Option<String>,
+    pub line: usize,
+}
+
+impl RuntimeError {
+    pub fn new(token: &Token, message: String) -> Self {
+        RuntimeError {
+            line: token.line,
+            message,
+            trace: Vec::new(),
+        }
+    }
+
+    // The traceback, e.g., `[line 2] in add()`, `[line 4] in lib/math.lox`
+    // and then `[line 6] in script`, one frame per line. Recursion repeats
+    // the same frame over and over, so it is shown at most three times in a
+    // row.
+    pub fn stack(&self) -> String {
+        let mut lines = Vec::new();
+        let mut frames = self.trace.iter().peekable();
+        while let Some(frame) = frames.next() {
+            let mut repeated = 0;
+            while frames.next_if_eq(&frame).is_some() {
+                repeated += 1;
+            }
+            let line = match (&frame.function, &frame.module) {
+                (Some(function), _) => format!("[line {}] in {}()", frame.line, function),
+                (None, Some(module)) => format!("[line {}] in {}", frame.line, module),
+                (None, None) => format!("[line {}] in script", frame.line),
+            };
+            lines.extend(std::iter::repeat_n(line, repeated.min(2) + 1));
+            if repeated > 2 {
+                lines.push(format!(
+                    "[previous line repeated {} more times]",
+                    repeated - 2
+                ));
+            }
+        }
+        lines.join("\n")
+    }
+
+    pub fn at(line: usize, message: String) -> Self {
+        RuntimeError {
+            line,
+            message,
+            trace: Vec::new(),
+        }
+    }
+
+    // The `message`, `line` and `stack` of the error as a caught value.
+    pub fn property(&self, name: &Symbol, line: usize) -> Evaluated {
+        match name.as_str() {
+            "message" => Ok(Value::String(self.message.as_str().into())),
+            "line" => Ok(Value::Number(self.line as f64)),
+            "stack" => Ok(Value::String(self.stack().into())),
+            _ => Err(RuntimeError::at(
+                line,
+                format!("Errors have no property '{}'.", name),
+            )),
+        }
+    }
+}
+
+// Followed by the traceback, if the error has one.
+impl std::fmt::Display for RuntimeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "error at line {}: {}", self.line, self.message)?;
+        if !self.trace.is_empty() {
+            write!(f, "\n{}", self.stack())?;
+        }
+        Ok(())
+    }
+}
+
+// Unwinds the statements being executed, up to the enclosing call for a
+// return or all the way up for an error.
+enum Interrupt {
+    Return(Value),
+    Error(RuntimeError),
+}
+
+impl From<RuntimeError> for Interrupt {
+    fn from(error: RuntimeError) -> Self {
+        Interrupt::Error(error)
+    }
+}
+
+type Evaluated = Result<Value, RuntimeError>;
+type Executed = Result<(), Interrupt>;
+
+// Told about the statements and calls of a running program, for debuggers,
+// which can inspect it through `evaluation`. Nothing is reported while a hook
+// runs, so it can evaluate expressions without stopping in them.
+pub trait Hook {
+    // Before each statement but blocks, which starts on `line`. An error
+    // stops the program.
+    fn statement(&mut self, evaluation: &mut Evaluation, line: usize) -> Result<(), RuntimeError>;
+    // Before the body of the function `name`, called on `line`.
+    fn call(&mut self, evaluation: &mut Evaluation, name: Symbol, line: usize);
+    // After the body of the last function called, however it ended.
+    fn returned(&mut self, evaluation: &mut Evaluation);
+    // After the condition of the `if`, `and` or `or` at `token` was
+    // evaluated, with whether it held. Only coverage needs this.
+    fn branch(&mut self, _evaluation: &mut Evaluation, _token: &Token, _held: bool) {}
+    // After each run of the body of a loop, which then goes back to its
+    // condition, not a statement of its own. Only debuggers need this.
+    fn iteration(&mut self, _evaluation: &mut Evaluation) {}
+}
+
+// A function being run, or the top level of a module, and the line it was
+// called or imported on.
+struct Call {
+    // of the function, or the path of the module
+    name: Symbol,
+    line: usize,
+    module: bool,
+}
+
+// Left free on the stack for the expressions of a statement, which nest
+// without a check of their own.
+const STACK_RESERVE: usize = 1 << 20;
+
+// The address of the top of the stack, more or less.
+#[inline(never)]
+fn stack_address() -> usize {
+    let top = 0u8;
+    std::ptr::from_ref(std::hint::black_box(&top)) as usize
 }
 
 pub struct Evaluation {
-    expr: Expr,
+    // those of the script, or of the module whose code is being run
+    globals: Rc<RefCell<Environment>>,
+    script: Rc<RefCell<Environment>>,
+    environment: Rc<RefCell<Environment>>,
+    heap: Heap,
+    calls: Vec<Call>,
+    // the most calls in progress before a stack overflow
+    max_depth: usize,
+    // the size of the Rust stack programs run on, and the address it was at
+    // when the outermost of `interpret` and `evaluate` started
+    stack_size: usize,
+    stack_base: Option<usize>,
+    // of the statement being run, for errors of blocks, which keep no token
+    line: usize,
+    modules: Modules,
+    // for the natives of every module
+    script_args: Vec<String>,
+    // where `print` writes
+    output: Box<dyn Write>,
+    hook: Option<Box<dyn Hook>>,
+    // whether the hook stopped the program, which no `catch` can prevent
+    stopped: bool,
 }
 
 impl Evaluation {
-    pub fn new(expr: Expr) -> Self {
-        Evaluation { expr }
+    // `script_args` are the command-line arguments returned by `args()`.
+    pub fn with_args(script_args: Vec<String>) -> Self {
+        let mut globals = Environment::default();
+        natives::define(&mut globals, script_args.clone());
+
+        let globals = Rc::new(RefCell::new(globals));
+        let mut heap = Heap::default();
+        heap.track_environment(&globals);
+        Evaluation {
+            environment: Rc::clone(&globals),
+            script: Rc::clone(&globals),
+            globals,
+            heap,
+            calls: Vec::new(),
+            max_depth: MAX_DEPTH,
+            stack_size: STACK_SIZE,
+            stack_base: None,
+            line: 0,
+            modules: Modules::new(None, module::search_path(&[])),
+            script_args,
+            output: Box::new(io::stdout()),
+            hook: None,
+            stopped: false,
+        }
+    }
+
+    // Where imports are found, by default from the current directory and
+    // then `LOX_PATH`.
+    pub fn set_modules(&mut self, modules: Modules) {
+        self.modules = modules;
+    }
+
+    pub fn set_output(&mut self, output: Box<dyn Write>) {
+        self.output = output;
+    }
+
+    pub fn set_max_depth(&mut self, max_depth: usize) {
+        self.max_depth = max_depth;
+    }
+
+    // The size of the stack of the thread programs run on, which is
+    // `STACK_SIZE` unless set otherwise.
+    #[cfg(test)]
+    pub fn set_stack_size(&mut self, stack_size: usize) {
+        self.stack_size = stack_size;
+    }
+
+    pub fn set_hook(&mut self, hook: Box<dyn Hook>) {
+        self.hook = Some(hook);
     }
 
-    pub fn interpret(&self) {
-        let value = self.expr.accept(self);
-        println!("{}", value);
+    // The innermost scope of the statement being run.
+    pub fn environment(&self) -> &Rc<RefCell<Environment>> {
+        &self.environment
+    }
+
+    pub fn heap(&mut self) -> &mut Heap {
+        &mut self.heap
+    }
+
+    pub fn globals(&self) -> Vec<(String, Value)> {
+        self.globals
+            .borrow()
+            .values()
+            .iter()
+            .map(|(name, value)| (name.to_string(), value.clone()))
+            .collect()
+    }
+
+    // Expects `statements` to have been resolved.
+    pub fn interpret(&mut self, statements: &[Stmt]) -> Result<(), RuntimeError> {
+        self.stopped = false;
+        self.on_stack(|evaluation| {
+            for stmt in statements {
+                match evaluation.execute(stmt) {
+                    Ok(()) => {}
+                    Err(Interrupt::Error(error)) => return Err(evaluation.traced(error)),
+                    // the resolver rejects `return` outside of functions
+                    Err(Interrupt::Return(_)) => return Ok(()),
+                }
+            }
+
+            Ok(())
+        })
+    }
+
+    pub fn evaluate(&mut self, expr: &Expr) -> Evaluated {
+        self.on_stack(|evaluation| {
+            expr.accept(&mut *evaluation)
+                .map_err(|error| evaluation.traced(error))
+        })
+    }
+
+    // Evaluates `expr` as if it were in the scope `environment`, for
+    // debuggers. It must have been resolved against that scope.
+    pub fn evaluate_in(&mut self, environment: Rc<RefCell<Environment>>, expr: &Expr) -> Evaluated {
+        let previous = std::mem::replace(&mut self.environment, environment);
+        let value = self.on_stack(|evaluation| expr.accept(&mut *evaluation));
+        self.environment = previous;
+        value
+    }
+
+    // Runs `run`, measuring the stack it uses from here unless something
+    // that called it already does.
+    fn on_stack<R>(&mut self, run: impl FnOnce(&mut Self) -> R) -> R {
+        let outermost = self.stack_base.is_none();
+        if outermost {
+            self.stack_base = Some(stack_address());
+        }
+        let result = run(self);
+        if outermost {
+            self.stack_base = None;
+        }
+        result
+    }
+
+    // Blocks, loops and calls nest on the Rust stack however few calls are
+    // in progress, so each statement checks it has room before it runs.
+    fn execute(&mut self, stmt: &Stmt) -> Executed {
+        if let Some(line) = stmt.line() {
+            self.line = line;
+        }
+        let used = self
+            .stack_base
+            .map_or(0, |base| base.abs_diff(stack_address()));
+        if used + STACK_RESERVE > self.stack_size {
+            return Err(Interrupt::Error(RuntimeError {
+                line: self.line,
+                message: "Stack overflow.".to_string(),
+                trace: Vec::new(),
+            }));
+        }
+        if let Some(line) = self.hook.as_ref().and_then(|_| stmt.line()) {
+            self.report(|hook, evaluation| hook.statement(evaluation, line))
+                .transpose()
+                .inspect_err(|_| self.stopped = true)?;
+        }
+        stmt.accept(self)
+    }
+
+    // Gives the hook, if there is one, to `report`. Only the script is
+    // reported, since hooks know nothing of the lines of modules, so their
+    // code and the functions declared in them run unseen.
+    fn report<R>(&mut self, report: impl FnOnce(&mut dyn Hook, &mut Self) -> R) -> Option<R> {
+        if !Rc::ptr_eq(&self.globals, &self.script) {
+            return None;
+        }
+        let mut hook = self.hook.take()?;
+        let result = report(&mut *hook, self);
+        self.hook = Some(hook);
+        Some(result)
+    }
+
+    // Gives `error` the traceback of the calls in progress, unless it left
+    // a call that already did.
+    fn traced(&self, mut error: RuntimeError) -> RuntimeError {
+        if error.trace.is_empty() {
+            let mut line = error.line;
+            for call in self.calls.iter().rev() {
+                let name = Some(call.name.to_string());
+                let (function, module) = match call.module {
+                    true => (None, name),
+                    false => (name, None),
+                };
+                error.trace.push(TraceFrame {
+                    function,
+                    module,
+                    line,
+                });
+                line = call.line;
+            }
+            error.trace.push(TraceFrame {
+                function: None,
+                module: None,
+                line,
+            });
+        }
+        error
+    }
+
+    fn execute_block(&mut self, statements: &[Stmt], environment: Environment) -> Executed {
+        self.with_environment(environment, |evaluation| {
+            statements
+                .iter()
+                .try_for_each(|stmt| evaluation.execute(stmt))
+        })
+    }
+
+    // Runs `run` in `environment`, restoring the current one afterwards.
+    fn with_environment(
+        &mut self,
+        environment: Environment,
+        run: impl FnOnce(&mut Self) -> Executed,
+    ) -> Executed {
+        let environment = Rc::new(RefCell::new(environment));
+        self.heap.track_environment(&environment);
+        let previous = std::mem::replace(&mut self.environment, environment);
+        let result = run(self);
+        self.environment = previous;
+
+        result
+    }
+
+    fn call(&mut self, callee: Value, paren: &Token, arguments: Vec<Value>) -> Evaluated {
+        let arity = match &callee {
+            Value::Native(native) => native.arity,
+            Value::Function(function) => function.declaration.params.len(),
+            _ => {
+                return Err(RuntimeError::new(
+                    paren,
+                    "Can only call functions and classes.".to_string(),
+                ))
+            }
+        };
+        if arguments.len() != arity {
+            return Err(RuntimeError::new(
+                paren,
+                format!("Expected {} arguments but got {}.", arity, arguments.len()),
+            ));
+        }
+
+        match callee {
+            Value::Native(native) => {
+                let value = (native.function)(&arguments)
+                    .map_err(|message| RuntimeError::new(paren, message))?;
+                self.heap.track_value(&value);
+                Ok(value)
+            }
+            Value::Function(function) => {
+                let mut environment = Environment::with_enclosing(Rc::clone(&function.closure));
+                for (param, argument) in function.declaration.params.iter().zip(arguments) {
+                    environment.define(param.lexeme.clone(), argument);
+                }
+
+                if self.calls.len() >= self.max_depth {
+                    return Err(RuntimeError::new(paren, "Stack overflow.".to_string()));
+                }
+                let name = function.declaration.name.lexeme.clone();
+                self.calls.push(Call {
+                    name: name.clone(),
+                    line: paren.line,
+                    module: false,
+                });
+                let globals = std::mem::replace(&mut self.globals, Rc::clone(&function.globals));
+                self.report(|hook, evaluation| hook.call(evaluation, name, paren.line));
+                let result = self.execute_block(&function.declaration.body, environment);
+                self.report(|hook, evaluation| hook.returned(evaluation));
+                self.globals = globals;
+
+                let result = match result {
+                    Ok(()) => Ok(Value::Nil),
+                    Err(Interrupt::Return(value)) => Ok(value),
+                    Err(Interrupt::Error(error)) => Err(self.traced(error)),
+                };
+                self.calls.pop();
+                result
+            }
+            _ => unreachable!("arity is only known for callables"),
+        }
+    }
+
+    // Runs the module at `path` in a global environment of its own, unless
+    // it ran already.
+    fn import(&mut self, keyword: &Token, path: &Token) -> Result<Rc<Module>, RuntimeError> {
+        let error = |message| RuntimeError::new(keyword, message);
+        let (file, found) = self
+            .modules
+            .find(path.lexeme.as_str())
+            .ok_or_else(|| error(format!("Cannot find module '{}'.", path.lexeme)))?;
+        if let Some(module) = self.modules.cached(&file) {
+            return Ok(module);
+        }
+        let statements = module::load(&found).map_err(error)?;
+        self.modules.enter(file, found.clone()).map_err(error)?;
+
+        let name = found.display().to_string();
+        let mut globals = Environment::default();
+        natives::define(&mut globals, self.script_args.clone());
+        let globals = Rc::new(RefCell::new(globals));
+        self.heap.track_environment(&globals);
+        let previous = (
+            std::mem::replace(&mut self.globals, Rc::clone(&globals)),
+            std::mem::replace(&mut self.environment, Rc::clone(&globals)),
+        );
+        self.calls.push(Call {
+            name: Symbol::intern(&name),
+            line: keyword.line,
+            module: true,
+        });
+        let result = statements.iter().try_for_each(|stmt| self.execute(stmt));
+        let result = match result {
+            // the resolver rejects `return` outside of functions
+            Ok(()) | Err(Interrupt::Return(_)) => {
+                let module = Rc::new(Module {
+                    name,
+                    globals,
+                    exports: module::exports(&statements),
+                });
+                self.heap.track(Object::Module(Rc::downgrade(&module)));
+                Ok(module)
+            }
+            Err(Interrupt::Error(error)) => Err(self.traced(error)),
+        };
+        self.calls.pop();
+        (self.globals, self.environment) = previous;
+
+        self.modules.leave(result.as_ref().ok());
+        result
+    }
+
+    fn lookup(&self, name: &Token, depth: Option<usize>) -> Evaluated {
+        match depth {
+            Some(depth) => Environment::ancestor(&self.environment, depth)
+                .borrow()
+                .get(name),
+            None => self.globals.borrow().get(name),
+        }
     }
 }
 
-impl Visitor<LiteralValue> for &Evaluation {
-    fn visit_literal(self, value: &Option<LiteralValue>) -> LiteralValue {
+impl Visitor<Evaluated> for &mut Evaluation {
+    fn visit_literal(self, value: &Option<LiteralValue>, _token: &Token) -> Evaluated {
         match value {
-            Some(t) => t.clone(),
-            None => LiteralValue::Nil,
+            Some(t) => Ok(Value::from(t)),
+            None => Ok(Value::Nil),
         }
     }
 
-    fn visit_binary(self, left: &Expr, op: &Token, right: &Expr) -> LiteralValue {
-        let left = left.accept(self);
-        let right = right.accept(self);
+    fn visit_binary(self, left: &Expr, op: &Token, right: &Expr) -> Evaluated {
+        let left = left.accept(&mut *self)?;
+        let right = right.accept(&mut *self)?;
 
-        match op.ttype {
-            TokenType::Minus => match (left, right) {
-                (LiteralValue::Number(l), LiteralValue::Number(r)) => LiteralValue::Number(l - r),
-                _ => panic!("operands must be numbers"),
-            },
+        let numbers = |l: &Value, r: &Value| match (l, r) {
+            (Value::Number(l), Value::Number(r)) => Ok((*l, *r)),
+            _ => Err(RuntimeError::new(
+                op,
+                "Operands must be numbers.".to_string(),
+            )),
+        };
+
+        let value = match op.ttype {
+            TokenType::Minus => {
+                let (l, r) = numbers(&left, &right)?;
+                Value::Number(l - r)
+            }
             TokenType::Plus => match (left, right) {
-                (LiteralValue::Number(l), LiteralValue::Number(r)) => LiteralValue::Number(l + r),
-                (LiteralValue::String(l), LiteralValue::String(r)) => {
-                    LiteralValue::String(format!("{}{}", l, r))
-                }
-                _ => panic!("operands must be numbers or strings"),
-            },
-            TokenType::Star => match (left, right) {
-                (LiteralValue::Number(l), LiteralValue::Number(r)) => LiteralValue::Number(l * r),
-                _ => panic!("operands must be numbers"),
-            },
-            TokenType::Slash => match (left, right) {
-                (LiteralValue::Number(l), LiteralValue::Number(r)) => LiteralValue::Number(l / r),
-                _ => panic!("operands must be numbers"),
-            },
-            TokenType::EqualEqual => match (left, right) {
-                (LiteralValue::Number(l), LiteralValue::Number(r)) => LiteralValue::Boolean(l == r),
-                (LiteralValue::String(l), LiteralValue::String(r)) => LiteralValue::Boolean(l == r),
-                (LiteralValue::Nil, LiteralValue::Nil) => LiteralValue::Boolean(true),
-                (LiteralValue::Boolean(l), LiteralValue::Boolean(r)) => {
-                    LiteralValue::Boolean(l == r)
+                (Value::Number(l), Value::Number(r)) => Value::Number(l + r),
+                (Value::String(l), Value::String(r)) => Value::String(format!("{}{}", l, r).into()),
+                _ => {
+                    return Err(RuntimeError::new(
+                        op,
+                        "Operands must be two numbers or two strings.".to_string(),
+                    ))
                 }
-                _ => panic!("unknown operands"),
             },
-            TokenType::BangEqual => match (left, right) {
-                (LiteralValue::Number(l), LiteralValue::Number(r)) => LiteralValue::Boolean(l != r),
-                (LiteralValue::String(l), LiteralValue::String(r)) => LiteralValue::Boolean(l != r),
-                (LiteralValue::Nil, LiteralValue::Nil) => LiteralValue::Boolean(false),
-                (LiteralValue::Boolean(l), LiteralValue::Boolean(r)) => {
-                    LiteralValue::Boolean(l != r)
-                }
-                _ => panic!("operands must be numbers or strings"),
-            },
-            TokenType::Less => match (left, right) {
-                (LiteralValue::Number(l), LiteralValue::Number(r)) => LiteralValue::Boolean(l < r),
-                _ => panic!("operands must be numbers"),
-            },
-            TokenType::Greater => match (left, right) {
-                (LiteralValue::Number(l), LiteralValue::Number(r)) => LiteralValue::Boolean(l > r),
-                _ => panic!("operands must be numbers"),
-            },
-            TokenType::LessEqual => match (left, right) {
-                (LiteralValue::Number(l), LiteralValue::Number(r)) => LiteralValue::Boolean(l <= r),
-                _ => panic!("operands must be numbers"),
-            },
-            TokenType::GreaterEqual => match (left, right) {
-                (LiteralValue::Number(l), LiteralValue::Number(r)) => LiteralValue::Boolean(l >= r),
-                _ => panic!("operands must be numbers"),
-            },
-            _ => panic!("unknown operator"),
-        }
+            TokenType::Star => {
+                let (l, r) = numbers(&left, &right)?;
+                Value::Number(l * r)
+            }
+            TokenType::Slash => {
+                let (l, r) = numbers(&left, &right)?;
+                Value::Number(l / r)
+            }
+            TokenType::EqualEqual => Value::Boolean(left.is_equal(&right)),
+            TokenType::BangEqual => Value::Boolean(!left.is_equal(&right)),
+            TokenType::Less => {
+                let (l, r) = numbers(&left, &right)?;
+                Value::Boolean(l < r)
+            }
+            TokenType::Greater => {
+                let (l, r) = numbers(&left, &right)?;
+                Value::Boolean(l > r)
+            }
+            TokenType::LessEqual => {
+                let (l, r) = numbers(&left, &right)?;
+                Value::Boolean(l <= r)
+            }
+            TokenType::GreaterEqual => {
+                let (l, r) = numbers(&left, &right)?;
+                Value::Boolean(l >= r)
+            }
+            _ => unreachable!("the parser only builds binary expressions from binary operators"),
+        };
+
+        Ok(value)
     }
 
-    fn visit_unary(self, op: &Token, right: &Expr) -> LiteralValue {
-        let right = right.accept(self);
+    fn visit_unary(self, op: &Token, right: &Expr) -> Evaluated {
+        let right = right.accept(self)?;
         match op.ttype {
-            TokenType::Bang => match right {
-                LiteralValue::Boolean(b) => LiteralValue::Boolean(!b),
-                _ => panic!("operand must be a boolean"),
-            },
+            TokenType::Bang => Ok(Value::Boolean(!right.is_truthy())),
             TokenType::Minus => match right {
-                LiteralValue::Number(n) => LiteralValue::Number(-n),
-                _ => panic!("operand must be a number"),
+                Value::Number(n) => Ok(Value::Number(-n)),
+                _ => Err(RuntimeError::new(
+                    op,
+                    "Operand must be a number.".to_string(),
+                )),
             },
-            _ => panic!("unknown operator"),
+            _ => unreachable!("the parser only builds unary expressions from unary operators"),
         }
     }
 
-    fn visit_grouped(self, expr: &Expr) -> LiteralValue {
+    fn visit_grouped(self, expr: &Expr) -> Evaluated {
         expr.accept(self)
     }
+
+    fn visit_logical(self, left: &Expr, op: &Token, right: &Expr) -> Evaluated {
+        let left = left.accept(&mut *self)?;
+        let held = left.is_truthy();
+        self.report(|hook, evaluation| hook.branch(evaluation, op, held));
+
+        // short-circuit, returning the operand that decided the result
+        match op.ttype {
+            TokenType::Or if held => Ok(left),
+            TokenType::And if !held => Ok(left),
+            _ => right.accept(self),
+        }
+    }
+
+    fn visit_variable(self, name: &Token, depth: &Cell<Option<usize>>) -> Evaluated {
+        self.lookup(name, depth.get())
+    }
+
+    fn visit_assign(self, name: &Token, value: &Expr, depth: &Cell<Option<usize>>) -> Evaluated {
+        let value = value.accept(&mut *self)?;
+        let environment = match depth.get() {
+            Some(depth) => Environment::ancestor(&self.environment, depth),
+            None => Rc::clone(&self.globals),
+        };
+        environment.borrow_mut().assign(name, value.clone())?;
+
+        Ok(value)
+    }
+
+    fn visit_call(self, callee: &Expr, paren: &Token, arguments: &[Expr]) -> Evaluated {
+        let callee = callee.accept(&mut *self)?;
+        let arguments = arguments
+            .iter()
+            .map(|argument| argument.accept(&mut *self))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        self.call(callee, paren, arguments)
+    }
+
+    fn visit_get(self, object: &Expr, name: &Token) -> Evaluated {
+        object.accept(&mut *self)?.property(&name.lexeme, name.line)
+    }
+}
+
+impl stmt::Visitor<Executed> for &mut Evaluation {
+    fn visit_expression(self, expr: &Expr) -> Executed {
+        expr.accept(self)?;
+        Ok(())
+    }
+
+    fn visit_print(self, expr: &Expr) -> Executed {
+        let value = expr.accept(&mut *self)?;
+        writeln!(self.output, "{}", stringify(&value)).expect("failed printing to stdout");
+        Ok(())
+    }
+
+    fn visit_var(self, name: &Token, initializer: Option<&Expr>, _doc: Option<&str>) -> Executed {
+        let value = match initializer {
+            Some(initializer) => initializer.accept(&mut *self)?,
+            None => Value::Nil,
+        };
+        self.environment
+            .borrow_mut()
+            .define(name.lexeme.clone(), value);
+        Ok(())
+    }
+
+    fn visit_block(self, statements: &[Stmt]) -> Executed {
+        let environment = Environment::with_enclosing(Rc::clone(&self.environment));
+        self.execute_block(statements, environment)
+    }
+
+    fn visit_if(
+        self,
+        keyword: &Token,
+        condition: &Expr,
+        then_branch: &Stmt,
+        else_branch: Option<&Stmt>,
+    ) -> Executed {
+        let held = condition.accept(&mut *self)?.is_truthy();
+        self.report(|hook, evaluation| hook.branch(evaluation, keyword, held));
+        if held {
+            self.execute(then_branch)
+        } else if let Some(else_branch) = else_branch {
+            self.execute(else_branch)
+        } else {
+            Ok(())
+        }
+    }
+
+    fn visit_while(self, condition: &Expr, body: &Stmt) -> Executed {
+        while condition.accept(&mut *self)?.is_truthy() {
+            self.execute(body)?;
+            self.report(|hook, evaluation| hook.iteration(evaluation));
+        }
+        Ok(())
+    }
+
+    // The initializer's variable lives in a scope of its own, shared by all
+    // iterations.
+    fn visit_for(
+        self,
+        initializer: Option<&Stmt>,
+        condition: Option<&Expr>,
+        increment: Option<&Expr>,
+        body: &Stmt,
+    ) -> Executed {
+        let environment = Environment::with_enclosing(Rc::clone(&self.environment));
+        self.with_environment(environment, |evaluation| {
+            // reported along with the loop, which starts with it
+            if let Some(initializer) = initializer {
+                initializer.accept(&mut *evaluation)?;
+            }
+            loop {
+                if let Some(condition) = condition {
+                    if !condition.accept(&mut *evaluation)?.is_truthy() {
+                        return Ok(());
+                    }
+                }
+                evaluation.execute(body)?;
+                evaluation.report(|hook, evaluation| hook.iteration(evaluation));
+                if let Some(increment) = increment {
+                    increment.accept(&mut *evaluation)?;
+                }
+            }
+        })
+    }
+
+    fn visit_function(self, declaration: &Rc<FunctionDecl>) -> Executed {
+        let function = Value::Function(Rc::new(LoxFunction {
+            declaration: Rc::clone(declaration),
+            closure: Rc::clone(&self.environment),
+            globals: Rc::clone(&self.globals),
+        }));
+        self.heap.track_value(&function);
+        self.environment
+            .borrow_mut()
+            .define(declaration.name.lexeme.clone(), function);
+        Ok(())
+    }
+
+    fn visit_return(self, _keyword: &Token, value: Option<&Expr>) -> Executed {
+        let value = match value {
+            Some(value) => value.accept(self)?,
+            None => Value::Nil,
+        };
+        Err(Interrupt::Return(value))
+    }
+
+    // A caught error is thrown again as it was, traceback and all. Any other
+    // value becomes the message of a new error.
+    fn visit_throw(self, keyword: &Token, value: &Expr) -> Executed {
+        let error = match value.accept(&mut *self)? {
+            Value::Error(error) => RuntimeError::clone(&error),
+            value => RuntimeError::new(keyword, stringify(&value)),
+        };
+        Err(Interrupt::Error(error))
+    }
+
+    // The `finally` block runs however the others ended, even by returning,
+    // and an error or return of its own replaces theirs.
+    fn visit_try(
+        self,
+        _keyword: &Token,
+        body: &[Stmt],
+        catch: Option<&Catch>,
+        finally: Option<&[Stmt]>,
+    ) -> Executed {
+        let environment = Environment::with_enclosing(Rc::clone(&self.environment));
+        let mut result = self.execute_block(body, environment);
+        if self.stopped {
+            return result;
+        }
+        if let (Err(Interrupt::Error(error)), Some(catch)) = (&result, catch) {
+            let error = Value::Error(Rc::new(self.traced(error.clone())));
+            let mut environment = Environment::with_enclosing(Rc::clone(&self.environment));
+            environment.define(catch.name.lexeme.clone(), error);
+            result = self.execute_block(&catch.body, environment);
+            if self.stopped {
+                return result;
+            }
+        }
+        if let Some(finally) = finally {
+            let environment = Environment::with_enclosing(Rc::clone(&self.environment));
+            self.execute_block(finally, environment)?;
+        }
+        result
+    }
+
+    fn visit_import(
+        self,
+        keyword: &Token,
+        path: &Token,
+        alias: Option<&Token>,
+        names: &[Token],
+    ) -> Executed {
+        let module = self.import(keyword, path)?;
+        if let Some(alias) = alias {
+            self.environment
+                .borrow_mut()
+                .define(alias.lexeme.clone(), Value::Module(module));
+            return Ok(());
+        }
+        for name in names {
+            let value = module.get(&name.lexeme, name.line)?;
+            self.environment
+                .borrow_mut()
+                .define(name.lexeme.clone(), value);
+        }
+        Ok(())
+    }
+    fn visit_trivia(self, _trivia: &Trivia) -> Executed {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        backend::{each_backend, Interpreter},
+        parser::Parser,
+        resolver::Resolver,
+        scanner::Scanner,
+    };
+
+    fn parse(source: &str) -> Vec<Stmt> {
+        let tokens = Scanner::new(source).scan_tokens();
+        let mut parser = Parser::new(tokens);
+        let statements = parser.parse();
+        assert!(parser.errors().is_empty(), "{:?}", parser.errors());
+        let mut resolver = Resolver::new();
+        resolver.resolve(&statements);
+        assert!(resolver.errors().is_empty());
+        statements
+    }
+
+    fn evaluate(evaluation: &mut dyn Interpreter, source: &str) -> Evaluated {
+        let tokens = Scanner::new(source).scan_tokens();
+        let expr = Parser::new(tokens).parse_single_expression().unwrap();
+        evaluation.evaluate(&expr)
+    }
+
+    #[test]
+    fn test_state_persists_across_programs() {
+        each_backend(&[], |evaluation| {
+            evaluation.interpret(&parse("var a = 1;")).unwrap();
+            evaluation
+                .interpret(&parse("{ var a = 10; } a = a + 1;"))
+                .unwrap();
+
+            let value = evaluate(evaluation, "a").unwrap();
+            assert!(matches!(value, Value::Number(n) if n == 2.0));
+        });
+    }
+
+    #[test]
+    fn test_control_flow() {
+        each_backend(&[], |evaluation| {
+            let program = r#"
+                var total = 0;
+                for (var i = 0; i < 5; i = i + 1) {
+                    if (i == 2) total = total + 100; else total = total + i;
+                }
+                var n = 3;
+                while (n > 0) n = n - 1;
+            "#;
+            evaluation.interpret(&parse(program)).unwrap();
+
+            let total = evaluate(evaluation, "total").unwrap();
+            assert!(matches!(total, Value::Number(n) if n == 108.0));
+            let n = evaluate(evaluation, "n").unwrap();
+            assert!(matches!(n, Value::Number(n) if n == 0.0));
+        });
+    }
+
+    #[test]
+    fn test_logical_operators_return_operands() {
+        each_backend(&[], |evaluation| {
+            let value = evaluate(evaluation, "nil or \"yes\"").unwrap();
+            assert!(matches!(value, Value::String(s) if &*s == "yes"));
+            let value = evaluate(evaluation, "false and undefined").unwrap();
+            assert!(matches!(value, Value::Boolean(false)));
+        });
+    }
+
+    #[test]
+    fn test_runtime_errors() {
+        each_backend(&[], |evaluation| {
+            let error = evaluate(evaluation, "1 +\n\"a\"").unwrap_err();
+            assert_eq!(error.line, 1);
+            assert_eq!(
+                error.message,
+                "Operands must be two numbers or two strings."
+            );
+
+            let error = evaluate(evaluation, "missing").unwrap_err();
+            assert_eq!(error.message, "Undefined variable 'missing'.");
+
+            let error = evaluation.interpret(&parse("missing = 1;")).unwrap_err();
+            assert_eq!(error.message, "Undefined variable 'missing'.");
+        });
+    }
+
+    #[test]
+    fn test_tracebacks() {
+        each_backend(&[], |evaluation| {
+            let program = "fun inner(x) {\n  return x + nil;\n}\nfun outer() {\n  return inner(1);\n}\nouter();";
+            let error = evaluation.interpret(&parse(program)).unwrap_err();
+            assert_eq!(
+                error.to_string(),
+                "error at line 2: Operands must be two numbers or two strings.\n\
+                 [line 2] in inner()\n[line 5] in outer()\n[line 7] in script"
+            );
+
+            evaluation.set_max_depth(50);
+            let error = evaluation
+                .interpret(&parse("fun f(n) {\n  return f(n + 1);\n}\nf(0);"))
+                .unwrap_err();
+            assert_eq!(
+                error.to_string(),
+                "error at line 2: Stack overflow.\n[line 2] in f()\n[line 2] in f()\n\
+                 [line 2] in f()\n[previous line repeated 47 more times]\n[line 4] in script"
+            );
+
+            // nothing is left of the calls
+            let error = evaluate(evaluation, "nil + 1").unwrap_err();
+            assert_eq!(
+                error.trace,
+                vec![TraceFrame {
+                    function: None,
+                    module: None,
+                    line: 1
+                }]
+            );
+        });
+    }
+
+    #[test]
+    fn test_nested_blocks_overflow_before_the_rust_stack() {
+        // far fewer calls than `MAX_DEPTH`, each deep in blocks
+        let body = format!("{}if (n > 0) f(n - 1);{}", "{".repeat(200), "}".repeat(200));
+        let program = format!(
+            "fun f(n) {{\n{}\n}}\nvar caught;\ntry {{ f(9000); }} catch (e) {{ caught = e; }}",
+            body
+        );
+        const STACK: usize = 16 << 20;
+        std::thread::Builder::new()
+            .stack_size(STACK)
+            .spawn(move || {
+                let mut evaluation = Evaluation::with_args(Vec::new());
+                evaluation.set_stack_size(STACK);
+                evaluation.interpret(&parse(&program)).unwrap();
+                let message = evaluate(&mut evaluation, "caught.message").unwrap();
+                assert!(matches!(message, Value::String(s) if &*s == "Stack overflow."));
+                let line = evaluate(&mut evaluation, "caught.line").unwrap();
+                assert!(matches!(line, Value::Number(n) if n == 2.0));
+            })
+            .unwrap()
+            .join()
+            .unwrap();
+    }
+
+    #[test]
+    fn test_exceptions() {
+        let program = r#"
+            var log = "";
+            fun risky(n) {
+                if (n > 1) throw "too big";
+                return n;
+            }
+            fun guarded() {
+                try {
+                    return risky(2);
+                } finally {
+                    log = log + "finally ";
+                }
+            }
+            var caught;
+            try {
+                guarded();
+            } catch (e) {
+                caught = e;
+            }
+            try {
+                nil + 1;
+            } catch (e) {
+                log = log + e.message;
+            }
+            var rethrown;
+            try {
+                try { throw caught; } catch (e) { rethrown = e; }
+            } finally {}
+        "#;
+        each_backend(&[], |evaluation| {
+            evaluation.interpret(&parse(program)).unwrap();
+            let mut value = |source: &str| evaluate(&mut *evaluation, source).unwrap();
+            assert_eq!(
+                stringify(&value("log")),
+                "finally Operands must be two numbers or two strings."
+            );
+            assert_eq!(value("caught").to_string(), "<error: too big>");
+            assert!(matches!(value("caught.line"), Value::Number(n) if n == 4.0));
+            let stack = "[line 4] in risky()\n[line 9] in guarded()\n[line 16] in script";
+            for error in ["caught", "rethrown"] {
+                let error = value(&format!("{}.stack", error));
+                assert!(matches!(error, Value::String(s) if &*s == stack));
+            }
+            let error = evaluate(evaluation, "caught.name").unwrap_err();
+            assert_eq!(error.message, "Errors have no property 'name'.");
+
+            // uncaught, with the traceback of where it was thrown
+            let error = evaluation
+                .interpret(&parse("fun f() {\n  throw 1 + 2;\n}\nf();"))
+                .unwrap_err();
+            assert_eq!(
+                error.to_string(),
+                "error at line 2: 3\n[line 2] in f()\n[line 4] in script"
+            );
+        });
+
+        // nor can a program catch being stopped by a debugger, which only the
+        // tree walker has
+        struct Stopper;
+        impl Hook for Stopper {
+            fn statement(&mut self, _: &mut Evaluation, line: usize) -> Result<(), RuntimeError> {
+                match line {
+                    2 => Err(RuntimeError {
+                        line,
+                        message: "Stopped.".to_string(),
+                        trace: Vec::new(),
+                    }),
+                    _ => Ok(()),
+                }
+            }
+            fn call(&mut self, _: &mut Evaluation, _: Symbol, _: usize) {}
+            fn returned(&mut self, _: &mut Evaluation) {}
+        }
+        let mut evaluation = Evaluation::with_args(Vec::new());
+        evaluation.interpret(&parse("var log = \"\";")).unwrap();
+        evaluation.set_hook(Box::new(Stopper));
+        let error = evaluation
+            .interpret(&parse(
+                "try {\n  print 1;\n} catch (e) {}\nfinally { log = nil; }",
+            ))
+            .unwrap_err();
+        assert_eq!(error.message, "Stopped.");
+        assert!(matches!(
+            evaluate(&mut evaluation, "log"),
+            Ok(Value::String(_))
+        ));
+    }
+
+    #[test]
+    fn test_native_calls() {
+        each_backend(&["a", "bc"], |evaluation| {
+            let value = evaluate(evaluation, "len(args())").unwrap();
+            assert!(matches!(value, Value::Number(n) if n == 2.0));
+            let value = evaluate(evaluation, "len(get(args(), 1))").unwrap();
+            assert!(matches!(value, Value::Number(n) if n == 2.0));
+
+            let error = evaluate(evaluation, "len()").unwrap_err();
+            assert_eq!(error.message, "Expected 1 arguments but got 0.");
+            let error = evaluate(evaluation, "get(args(), 2)").unwrap_err();
+            assert_eq!(error.message, "Index 2 out of range for list of length 2.");
+            let error = evaluate(evaluation, "\"len\"(1)").unwrap_err();
+            assert_eq!(error.message, "Can only call functions and classes.");
+        });
+    }
+
+    #[test]
+    fn test_functions_and_closures() {
+        each_backend(&[], |evaluation| {
+            let program = r#"
+                fun fib(n) {
+                    if (n < 2) return n;
+                    return fib(n - 1) + fib(n - 2);
+                }
+                var f = fib(10);
+
+                fun counter() {
+                    var count = 0;
+                    fun increment() {
+                        count = count + 1;
+                        return count;
+                    }
+                    return increment;
+                }
+                var next = counter();
+                next();
+                var c = next();
+
+                var a = "global";
+                var shadowed;
+                {
+                    fun show() { return a; }
+                    var a = "block";
+                    shadowed = show();
+                }
+            "#;
+            evaluation.interpret(&parse(program)).unwrap();
+
+            let f = evaluate(evaluation, "f").unwrap();
+            assert!(matches!(f, Value::Number(n) if n == 55.0));
+            let c = evaluate(evaluation, "c").unwrap();
+            assert!(matches!(c, Value::Number(n) if n == 2.0));
+            let shadowed = evaluate(evaluation, "shadowed").unwrap();
+            assert!(matches!(shadowed, Value::String(s) if &*s == "global"));
+
+            let error = evaluate(evaluation, "fib(1, 2)").unwrap_err();
+            assert_eq!(error.message, "Expected 1 arguments but got 2.");
+        });
+    }
 }
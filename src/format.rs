@@ -0,0 +1,392 @@
+use std::{cell::Cell, rc::Rc};
+
+use crate::{
+    expr::{self, Expr},
+    parser::Parser,
+    scanner::Scanner,
+    stmt::{self, Catch, FunctionDecl, Stmt},
+    token::{LiteralValue, Token, Trivia},
+};
+
+const INDENT: &str = "    ";
+
+// Formats `source` in the canonical style, keeping its comments and single
+// blank lines between statements. Returns the syntax errors instead if
+// there are any.
+pub fn format(source: &str) -> Result<String, Vec<String>> {
+    let mut scanner = Scanner::new(source);
+    let tokens = scanner.scan_tokens();
+    if !scanner.errors().is_empty() {
+        return Err(scanner.errors().iter().map(|e| e.to_string()).collect());
+    }
+
+    let mut parser = Parser::new(tokens);
+    let statements = parser.parse();
+    if !parser.errors().is_empty() {
+        return Err(parser.errors().iter().map(|e| e.to_string()).collect());
+    }
+
+    let mut formatter = Formatter::default();
+    formatter.statements(&statements);
+    Ok(formatter.output)
+}
+
+// Statements are written to `line`, which is indented and moved to `output`
+// once complete, so that nested statements can continue the line of their
+// parent, e.g., `if (a) print b;`.
+#[derive(Default)]
+struct Formatter {
+    output: String,
+    line: String,
+    depth: usize,
+}
+
+impl Formatter {
+    fn statements(&mut self, statements: &[Stmt]) {
+        let mut first = true;
+        let mut blank_line = false;
+        for stmt in statements {
+            match stmt {
+                Stmt::Trivia(Trivia::BlankLine) => blank_line = !first,
+                Stmt::Trivia(Trivia::Comment {
+                    text,
+                    trailing: true,
+                    ..
+                }) if !self.output.is_empty() => {
+                    self.output.pop();
+                    self.output.push(' ');
+                    self.output.push_str(text);
+                    self.output.push('\n');
+                }
+                stmt => {
+                    if blank_line {
+                        self.output.push('\n');
+                        blank_line = false;
+                    }
+                    match stmt {
+                        Stmt::Trivia(Trivia::Comment { text, .. }) => self.comment(text),
+                        stmt => stmt.accept(&mut *self),
+                    }
+                    self.end_line();
+                    first = false;
+                }
+            }
+        }
+    }
+
+    // Only the first line of a block comment is indented, the others are
+    // left as written.
+    fn comment(&mut self, text: &str) {
+        let mut lines = text.lines();
+        if let Some(first) = lines.next() {
+            self.write(first);
+        }
+        for line in lines {
+            self.end_line();
+            self.output.push_str(line);
+            self.output.push('\n');
+        }
+    }
+
+    fn doc(&mut self, doc: Option<&str>) {
+        // a doc comment can't be put in the middle of a line, e.g., in a for loop
+        let Some(doc) = doc.filter(|_| self.line.is_empty()) else {
+            return;
+        };
+        for line in doc.split('\n') {
+            match line {
+                "" => self.write("///"),
+                line => self.write(&format!("/// {}", line)),
+            }
+            self.end_line();
+        }
+    }
+
+    fn block(&mut self, statements: &[Stmt]) {
+        if statements.is_empty() {
+            self.write("{}");
+            return;
+        }
+
+        self.write("{");
+        self.end_line();
+        self.depth += 1;
+        self.statements(statements);
+        self.depth -= 1;
+        self.write("}");
+    }
+
+    fn expr(&self, expr: &Expr) -> String {
+        expr.accept(self)
+    }
+
+    fn write(&mut self, text: &str) {
+        self.line.push_str(text);
+    }
+
+    fn end_line(&mut self) {
+        if self.line.is_empty() {
+            return;
+        }
+        for _ in 0..self.depth {
+            self.output.push_str(INDENT);
+        }
+        self.output.push_str(&self.line);
+        self.output.push('\n');
+        self.line.clear();
+    }
+}
+
+impl expr::Visitor<String> for &Formatter {
+    fn visit_binary(self, left: &Expr, op: &Token, right: &Expr) -> String {
+        format!("{} {} {}", left.accept(self), op.lexeme, right.accept(self))
+    }
+    fn visit_unary(self, op: &Token, right: &Expr) -> String {
+        format!("{}{}", op.lexeme, right.accept(self))
+    }
+    // Numbers as written, since their value may print differently, e.g.,
+    // `0x1F` as `31` and `1e400` as `inf`.
+    fn visit_literal(self, value: &Option<LiteralValue>, token: &Token) -> String {
+        match value {
+            Some(LiteralValue::Number(_)) => token.lexeme.to_string(),
+            Some(value) => value.to_string(),
+            None => unreachable!("malformed literals are scan errors"),
+        }
+    }
+    fn visit_grouped(self, expr: &Expr) -> String {
+        format!("({})", expr.accept(self))
+    }
+    fn visit_logical(self, left: &Expr, op: &Token, right: &Expr) -> String {
+        format!("{} {} {}", left.accept(self), op.lexeme, right.accept(self))
+    }
+    fn visit_variable(self, name: &Token, _depth: &Cell<Option<usize>>) -> String {
+        name.lexeme.to_string()
+    }
+    fn visit_assign(self, name: &Token, value: &Expr, _depth: &Cell<Option<usize>>) -> String {
+        format!("{} = {}", name.lexeme, value.accept(self))
+    }
+    fn visit_call(self, callee: &Expr, _paren: &Token, arguments: &[Expr]) -> String {
+        let arguments = arguments
+            .iter()
+            .map(|argument| argument.accept(self))
+            .collect::<Vec<_>>()
+            .join(", ");
+        format!("{}({})", callee.accept(self), arguments)
+    }
+    fn visit_get(self, object: &Expr, name: &Token) -> String {
+        format!("{}.{}", object.accept(self), name.lexeme)
+    }
+}
+
+impl stmt::Visitor<()> for &mut Formatter {
+    fn visit_expression(self, expr: &Expr) {
+        let expr = self.expr(expr);
+        self.write(&format!("{};", expr));
+    }
+
+    fn visit_print(self, expr: &Expr) {
+        let expr = self.expr(expr);
+        self.write(&format!("print {};", expr));
+    }
+
+    fn visit_var(self, name: &Token, initializer: Option<&Expr>, doc: Option<&str>) {
+        self.doc(doc);
+        let var = match initializer {
+            Some(initializer) => format!("var {} = {};", name.lexeme, self.expr(initializer)),
+            None => format!("var {};", name.lexeme),
+        };
+        self.write(&var);
+    }
+
+    fn visit_block(self, statements: &[Stmt]) {
+        self.block(statements);
+    }
+
+    // A branch that isn't a block stays on the line of its `if` or `else`.
+    fn visit_if(
+        self,
+        _keyword: &Token,
+        condition: &Expr,
+        then_branch: &Stmt,
+        else_branch: Option<&Stmt>,
+    ) {
+        let condition = self.expr(condition);
+        self.write(&format!("if ({}) ", condition));
+        then_branch.accept(&mut *self);
+        if let Some(else_branch) = else_branch {
+            match then_branch {
+                Stmt::Block { .. } => self.write(" "),
+                _ => self.end_line(),
+            }
+            self.write("else ");
+            else_branch.accept(self);
+        }
+    }
+
+    fn visit_while(self, condition: &Expr, body: &Stmt) {
+        let condition = self.expr(condition);
+        self.write(&format!("while ({}) ", condition));
+        body.accept(self);
+    }
+
+    fn visit_for(
+        self,
+        initializer: Option<&Stmt>,
+        condition: Option<&Expr>,
+        increment: Option<&Expr>,
+        body: &Stmt,
+    ) {
+        self.write("for (");
+        match initializer {
+            Some(initializer) => initializer.accept(&mut *self),
+            None => self.write(";"),
+        }
+        if let Some(condition) = condition {
+            let condition = self.expr(condition);
+            self.write(&format!(" {}", condition));
+        }
+        self.write(";");
+        if let Some(increment) = increment {
+            let increment = self.expr(increment);
+            self.write(&format!(" {}", increment));
+        }
+        self.write(") ");
+        body.accept(self);
+    }
+
+    fn visit_function(self, declaration: &Rc<FunctionDecl>) {
+        self.doc(declaration.doc.as_deref());
+        let params = declaration
+            .params
+            .iter()
+            .map(|param| param.lexeme.as_str())
+            .collect::<Vec<_>>()
+            .join(", ");
+        self.write(&format!("fun {}({}) ", declaration.name.lexeme, params));
+        self.block(&declaration.body);
+    }
+
+    fn visit_return(self, _keyword: &Token, value: Option<&Expr>) {
+        match value {
+            Some(value) => {
+                let value = self.expr(value);
+                self.write(&format!("return {};", value));
+            }
+            None => self.write("return;"),
+        }
+    }
+
+    fn visit_throw(self, _keyword: &Token, value: &Expr) {
+        let value = self.expr(value);
+        self.write(&format!("throw {};", value));
+    }
+
+    fn visit_try(
+        self,
+        _keyword: &Token,
+        body: &[Stmt],
+        catch: Option<&Catch>,
+        finally: Option<&[Stmt]>,
+    ) {
+        self.write("try ");
+        self.block(body);
+        if let Some(catch) = catch {
+            self.write(&format!(" catch ({}) ", catch.name.lexeme));
+            self.block(&catch.body);
+        }
+        if let Some(finally) = finally {
+            self.write(" finally ");
+            self.block(finally);
+        }
+    }
+
+    fn visit_import(self, _keyword: &Token, path: &Token, alias: Option<&Token>, names: &[Token]) {
+        let import = match alias {
+            Some(alias) => format!("import \"{}\" as {};", path.lexeme, alias.lexeme),
+            None => {
+                let names = names
+                    .iter()
+                    .map(|name| name.lexeme.as_str())
+                    .collect::<Vec<_>>()
+                    .join(", ");
+                format!("from \"{}\" import {};", path.lexeme, names)
+            }
+        };
+        self.write(&import);
+    }
+
+    // Trivia is handled by `Formatter::statements`.
+    fn visit_trivia(self, _trivia: &Trivia) {}
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_format() {
+        let source = r#"
+// leading comment
+var   a=1;   // trailing
+/// Adds.
+fun add(x,y){return x+y;}
+
+
+if(a>0)print -a;else{print(a);}
+for(var i=0;i<2;i=i+1){
+  /* block */ print i;
+}
+for(;;){}
+while (false) a = a or !nil;
+{
+// only a comment
+}
+"#;
+        let expected = r#"// leading comment
+var a = 1; // trailing
+/// Adds.
+fun add(x, y) {
+    return x + y;
+}
+
+if (a > 0) print -a;
+else {
+    print (a);
+}
+for (var i = 0; i < 2; i = i + 1) {
+    /* block */
+    print i;
+}
+for (;;) {}
+while (false) a = a or !nil;
+{
+    // only a comment
+}
+"#;
+        assert_eq!(format(source).unwrap(), expected);
+        assert_eq!(format(expected).unwrap(), expected);
+    }
+
+    #[test]
+    fn test_format_moves_inner_comments_after_the_statement() {
+        let source = "var a = 1 + // one\n  2;\nprint a /* a */;\n";
+        let formatted = format(source).unwrap();
+        assert_eq!(formatted, "var a = 1 + 2; // one\nprint a; /* a */\n");
+        assert_eq!(format(&formatted).unwrap(), formatted);
+    }
+
+    #[test]
+    fn test_format_keeps_number_literals_as_written() {
+        let source = "var a = 0x1F + 0b1010 + 0o17 + 1_000 + 1e3;\nvar b = 1e300 * 10;\nvar c = 1e400;\nvar d = 2.50;\n";
+        assert_eq!(format(source).unwrap(), source);
+        assert_eq!(format(&format(source).unwrap()).unwrap(), source);
+    }
+
+    #[test]
+    fn test_format_errors() {
+        assert_eq!(
+            format("print ;").unwrap_err(),
+            vec!["error at line 1 at ';': Expect expression."]
+        );
+    }
+}
@@ -0,0 +1,425 @@
+use std::{
+    cell::RefCell,
+    collections::{HashMap, HashSet},
+    rc::{Rc, Weak},
+};
+
+use crate::{
+    environment::Environment,
+    value::{LoxFunction, Module, Value},
+    vm::{Closure, Upvalue},
+};
+
+// When the heap collects, from the command line.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct GcConfig {
+    // collect on every allocation, to find objects the collector misses
+    pub stress: bool,
+    // number of objects before the first collection
+    pub threshold: usize,
+    // after a collection, the next one starts once the live objects have
+    // grown by this factor
+    pub growth: f64,
+}
+
+impl Default for GcConfig {
+    fn default() -> Self {
+        GcConfig {
+            stress: false,
+            threshold: 1024,
+            growth: 2.0,
+        }
+    }
+}
+
+// A heap object that can be part of a reference cycle. Strings are owned by
+// their values and natives and compiled functions never point back into the
+// heap, so those are freed by reference counting alone.
+#[derive(Clone)]
+pub enum Object {
+    Environment(Weak<RefCell<Environment>>),
+    Function(Weak<LoxFunction>),
+    Closure(Weak<Closure>),
+    Upvalue(Weak<RefCell<Upvalue>>),
+    List(Weak<RefCell<Vec<Value>>>),
+    Module(Weak<Module>),
+}
+
+impl Object {
+    fn address(&self) -> *const () {
+        match self {
+            Object::Environment(weak) => weak.as_ptr() as *const (),
+            Object::Function(weak) => weak.as_ptr() as *const (),
+            Object::Closure(weak) => weak.as_ptr() as *const (),
+            Object::Upvalue(weak) => weak.as_ptr() as *const (),
+            Object::List(weak) => weak.as_ptr() as *const (),
+            Object::Module(weak) => weak.as_ptr() as *const (),
+        }
+    }
+
+    fn strong_count(&self) -> usize {
+        match self {
+            Object::Environment(weak) => weak.strong_count(),
+            Object::Function(weak) => weak.strong_count(),
+            Object::Closure(weak) => weak.strong_count(),
+            Object::Upvalue(weak) => weak.strong_count(),
+            Object::List(weak) => weak.strong_count(),
+            Object::Module(weak) => weak.strong_count(),
+        }
+    }
+
+    // The addresses of the objects this one holds, or `None` if it is being
+    // changed right now and can't be looked at.
+    fn children(&self) -> Option<Vec<*const ()>> {
+        let mut children = Vec::new();
+        match self {
+            Object::Environment(weak) => {
+                let environment = weak.upgrade()?;
+                let environment = environment.try_borrow().ok()?;
+                children.extend(environment.values().values().filter_map(address));
+                if let Some(enclosing) = environment.enclosing() {
+                    children.push(Rc::as_ptr(enclosing) as *const ());
+                }
+            }
+            Object::Function(weak) => {
+                let function = weak.upgrade()?;
+                children.push(Rc::as_ptr(&function.closure) as *const ());
+                children.push(Rc::as_ptr(&function.globals) as *const ());
+            }
+            Object::Closure(weak) => {
+                let closure = weak.upgrade()?;
+                children.extend(
+                    closure
+                        .upvalues
+                        .iter()
+                        .map(|upvalue| Rc::as_ptr(upvalue) as *const ()),
+                );
+                children.push(Rc::as_ptr(&closure.globals) as *const ());
+            }
+            Object::Upvalue(weak) => {
+                let upvalue = weak.upgrade()?;
+                let upvalue = upvalue.try_borrow().ok()?;
+                if let Upvalue::Closed(value) = &*upvalue {
+                    children.extend(address(value));
+                }
+            }
+            Object::List(weak) => {
+                let list = weak.upgrade()?;
+                children.extend(list.try_borrow().ok()?.iter().filter_map(address));
+            }
+            Object::Module(weak) => {
+                let module = weak.upgrade()?;
+                children.push(Rc::as_ptr(&module.globals) as *const ());
+            }
+        }
+        Some(children)
+    }
+
+    // Drops what the object holds, breaking the cycles it is part of. The
+    // contents are dropped after the borrow ends, since that may free other
+    // objects.
+    fn clear(&self) {
+        match self {
+            Object::Environment(weak) => {
+                if let Some(environment) = weak.upgrade() {
+                    let cleared = environment
+                        .try_borrow_mut()
+                        .map(|mut e| std::mem::take(&mut *e));
+                    drop(cleared);
+                }
+            }
+            Object::Upvalue(weak) => {
+                if let Some(upvalue) = weak.upgrade() {
+                    let closed = upvalue
+                        .try_borrow_mut()
+                        .map(|mut u| std::mem::replace(&mut *u, Upvalue::Closed(Value::Nil)));
+                    drop(closed);
+                }
+            }
+            Object::List(weak) => {
+                if let Some(list) = weak.upgrade() {
+                    let values = list.try_borrow_mut().map(|mut l| std::mem::take(&mut *l));
+                    drop(values);
+                }
+            }
+            // immutable, the cycles through them go through one of the above
+            Object::Function(_) | Object::Closure(_) | Object::Module(_) => {}
+        }
+    }
+}
+
+fn address(value: &Value) -> Option<*const ()> {
+    match value {
+        Value::Function(function) => Some(Rc::as_ptr(function) as *const ()),
+        Value::Closure(closure) => Some(Rc::as_ptr(closure) as *const ()),
+        Value::List(list) => Some(Rc::as_ptr(list) as *const ()),
+        Value::Module(module) => Some(Rc::as_ptr(module) as *const ()),
+        _ => None,
+    }
+}
+
+// Tracks the objects of an interpreter to free the reference cycles among
+// them, which reference counting alone never frees, e.g., a function stored
+// in the environment it closes over.
+//
+// The roots are found by trial deletion, subtracting from the reference count
+// of each object the references the heap objects hold to it: what is left
+// over is held from outside the heap, e.g., by the environment chain of the
+// tree walker, the VM stack and globals, the modules imported so far, or a
+// value being computed. Everything reachable from a root is marked live, and
+// the rest is swept by clearing it.
+pub struct Heap {
+    objects: Vec<Object>,
+    config: GcConfig,
+    next_gc: usize,
+    collections: usize,
+}
+
+impl Heap {
+    pub fn new(config: GcConfig) -> Self {
+        Heap {
+            objects: Vec::new(),
+            config,
+            next_gc: config.threshold,
+            collections: 0,
+        }
+    }
+
+    pub fn configure(&mut self, config: GcConfig) {
+        self.config = config;
+        self.next_gc = config.threshold.max(self.objects.len());
+    }
+
+    // The number of objects allocated and not yet known to be freed.
+    pub fn len(&self) -> usize {
+        self.objects.len()
+    }
+
+    pub fn collections(&self) -> usize {
+        self.collections
+    }
+
+    // Starts tracking `object`, then collects if the heap has grown past the
+    // threshold. The caller still holds the object, so it survives.
+    pub fn track(&mut self, object: Object) {
+        self.objects.push(object);
+        if self.config.stress || self.objects.len() > self.next_gc {
+            self.collect();
+        }
+    }
+
+    pub fn track_environment(&mut self, environment: &Rc<RefCell<Environment>>) {
+        self.track(Object::Environment(Rc::downgrade(environment)));
+    }
+
+    // Tracks the objects in a value made outside of the heap, e.g., the
+    // result of a native function.
+    pub fn track_value(&mut self, value: &Value) {
+        match value {
+            Value::Function(function) => self.track(Object::Function(Rc::downgrade(function))),
+            Value::Closure(closure) => self.track(Object::Closure(Rc::downgrade(closure))),
+            Value::List(list) => self.track(Object::List(Rc::downgrade(list))),
+            Value::Module(module) => self.track(Object::Module(Rc::downgrade(module))),
+            _ => {}
+        }
+    }
+
+    // Frees the unreachable objects, returning how many there were.
+    pub fn collect(&mut self) -> usize {
+        self.collections += 1;
+        let mut seen = HashSet::new();
+        self.objects
+            .retain(|object| object.strong_count() > 0 && seen.insert(object.address()));
+
+        let index = self
+            .objects
+            .iter()
+            .enumerate()
+            .map(|(i, object)| (object.address(), i))
+            .collect::<HashMap<_, _>>();
+        let children = self
+            .objects
+            .iter()
+            .map(|object| object.children())
+            .collect::<Vec<_>>();
+
+        // subtract the references from heap objects, leaving those from outside
+        let mut outside = self
+            .objects
+            .iter()
+            .map(|object| object.strong_count())
+            .collect::<Vec<_>>();
+        for child in children.iter().flatten().flatten() {
+            if let Some(&i) = index.get(child) {
+                outside[i] = outside[i].saturating_sub(1);
+            }
+        }
+
+        // mark; objects that can't be looked at are kept, with what they hold
+        let mut marked = vec![false; self.objects.len()];
+        let mut gray = (0..self.objects.len())
+            .filter(|&i| outside[i] > 0 || children[i].is_none())
+            .collect::<Vec<_>>();
+        while let Some(i) = gray.pop() {
+            if std::mem::replace(&mut marked[i], true) {
+                continue;
+            }
+            for child in children[i].iter().flatten() {
+                if let Some(&j) = index.get(child) {
+                    if !marked[j] {
+                        gray.push(j);
+                    }
+                }
+            }
+        }
+
+        // sweep
+        let garbage = (0..self.objects.len())
+            .filter(|&i| !marked[i])
+            .map(|i| self.objects[i].clone())
+            .collect::<Vec<_>>();
+        for object in &garbage {
+            object.clear();
+        }
+        self.objects.retain(|object| object.strong_count() > 0);
+
+        let grown = (self.objects.len() as f64 * self.config.growth) as usize;
+        self.next_gc = grown.max(self.config.threshold);
+        garbage.len()
+    }
+}
+
+impl Default for Heap {
+    fn default() -> Self {
+        Heap::new(GcConfig::default())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{backend::Backend, parser::Parser, resolver::Resolver, scanner::Scanner};
+    use clap::ValueEnum;
+
+    fn parse(source: &str) -> Vec<crate::stmt::Stmt> {
+        let tokens = Scanner::new(source).scan_tokens();
+        let statements = Parser::new(tokens).parse();
+        Resolver::new().resolve(&statements);
+        statements
+    }
+
+    #[test]
+    fn test_collects_cycles() {
+        // each function closes over the scope that holds it
+        let program = r#"
+            fun leak() {
+                var self;
+                fun f() { return self; }
+                self = f;
+            }
+            for (var i = 0; i < 10; i = i + 1) leak();
+        "#;
+        for backend in Backend::value_variants() {
+            let mut interpreter = backend.interpreter(Vec::new(), GcConfig::default());
+            interpreter.interpret(&parse("var kept = 1;")).unwrap();
+            let heap = interpreter.heap();
+            heap.collect();
+            let before = heap.len();
+
+            interpreter.interpret(&parse(program)).unwrap();
+            let heap = interpreter.heap();
+            assert!(heap.collect() >= 10, "{:?}", backend);
+            // only `leak` itself is left
+            assert!(heap.len() <= before + 2, "{:?}", backend);
+        }
+    }
+
+    #[test]
+    fn test_collects_cycles_through_modules() {
+        let dir = std::env::temp_dir().join(format!("lox-gc-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(
+            dir.join("keep.lox"),
+            "var kept;\nfun keep(value) { kept = value; }",
+        )
+        .unwrap();
+        std::fs::write(dir.join("main.lox"), "").unwrap();
+        let modules = || crate::module::Modules::new(Some(&dir.join("main.lox")), Vec::new());
+
+        for backend in Backend::value_variants() {
+            let mut interpreter = backend.interpreter(Vec::new(), GcConfig::default());
+            interpreter.set_modules(modules());
+            // the module holds itself in one of its globals
+            interpreter
+                .interpret(&parse("import \"keep.lox\" as m;\nm.keep(m);"))
+                .unwrap();
+            let module = match interpreter
+                .globals()
+                .into_iter()
+                .find(|(name, _)| name == "m")
+            {
+                Some((_, Value::Module(module))) => Rc::downgrade(&module),
+                _ => panic!("m is not a module"),
+            };
+
+            // nothing but the cycle holds it once the import is forgotten
+            interpreter.set_modules(modules());
+            interpreter.interpret(&parse("m = nil;")).unwrap();
+            interpreter.heap().collect();
+            assert!(module.upgrade().is_none(), "{:?}", backend);
+        }
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_keeps_reachable_objects() {
+        let program = r#"
+            fun counter() {
+                var count = 0;
+                fun next() { count = count + 1; return count; }
+                return next;
+            }
+            var next = counter();
+            fun call(f, unused) { return f(); }
+            call(next, counter());
+            var list = args();
+        "#;
+        let stress = GcConfig {
+            stress: true,
+            ..GcConfig::default()
+        };
+        for backend in Backend::value_variants() {
+            let mut interpreter = backend.interpreter(vec!["a".to_string()], stress);
+            interpreter.interpret(&parse(program)).unwrap();
+            assert!(interpreter.heap().collections() > 0);
+
+            interpreter.heap().collect();
+            let tokens = Scanner::new("next() + len(list)").scan_tokens();
+            let expr = Parser::new(tokens).parse_single_expression().unwrap();
+            let value = interpreter.evaluate(&expr).unwrap();
+            assert!(
+                matches!(value, Value::Number(n) if n == 3.0),
+                "{:?}",
+                backend
+            );
+        }
+    }
+
+    #[test]
+    fn test_threshold_grows() {
+        let mut heap = Heap::new(GcConfig {
+            stress: false,
+            threshold: 2,
+            growth: 2.0,
+        });
+        let lists = (0..5)
+            .map(|_| Rc::new(RefCell::new(Vec::new())))
+            .collect::<Vec<_>>();
+        for list in &lists {
+            heap.track_value(&Value::List(Rc::clone(list)));
+        }
+        // at 3 objects, and then at 7
+        assert_eq!(heap.collections(), 1);
+        assert_eq!(heap.len(), 5);
+    }
+}
@@ -0,0 +1,195 @@
+use crate::{
+    scanner::Scanner,
+    token::{Lexeme, TokenType},
+};
+
+const RESET: &str = "\x1b[0m";
+const KEYWORD: &str = "\x1b[1;35m";
+const CONSTANT: &str = "\x1b[33m";
+const STRING: &str = "\x1b[32m";
+const OPERATOR: &str = "\x1b[36m";
+const MATCHED_BRACKET: &str = "\x1b[1;4;34m";
+const UNMATCHED_BRACKET: &str = "\x1b[1;31m";
+
+// Colors `line` with ANSI escapes. The bracket under or just before the
+// cursor is highlighted along with its partner, or in red if it has none.
+pub fn highlight(line: &str, cursor: usize) -> String {
+    let tokens = Scanner::new(line).partial().collect::<Vec<_>>();
+    let brackets = match_brackets(&tokens);
+    let (focused, partner) = match bracket_at(&tokens, cursor) {
+        Some(i) => (Some(i), brackets[i]),
+        None => (None, None),
+    };
+
+    let mut highlighted = String::with_capacity(line.len() * 2);
+    let mut copied = 0;
+    for (i, token) in tokens.iter().enumerate() {
+        let color = if Some(i) == focused || Some(i) == partner {
+            match brackets[i] {
+                Some(_) => MATCHED_BRACKET,
+                None => UNMATCHED_BRACKET,
+            }
+        } else {
+            match color(&token.ttype) {
+                Some(color) => color,
+                None => continue,
+            }
+        };
+
+        let (start, end) = (token.span.start, token.span.end);
+        highlighted.push_str(&line[copied..start]);
+        highlighted.push_str(color);
+        highlighted.push_str(&line[start..end]);
+        highlighted.push_str(RESET);
+        copied = end;
+    }
+    highlighted.push_str(&line[copied..]);
+
+    highlighted
+}
+
+fn color(ttype: &TokenType) -> Option<&'static str> {
+    match ttype {
+        TokenType::And
+        | TokenType::As
+        | TokenType::Catch
+        | TokenType::Class
+        | TokenType::Else
+        | TokenType::Finally
+        | TokenType::Fun
+        | TokenType::For
+        | TokenType::From
+        | TokenType::If
+        | TokenType::Import
+        | TokenType::Or
+        | TokenType::Print
+        | TokenType::Return
+        | TokenType::Super
+        | TokenType::This
+        | TokenType::Throw
+        | TokenType::Try
+        | TokenType::Var
+        | TokenType::While => Some(KEYWORD),
+        TokenType::Number | TokenType::True | TokenType::False | TokenType::Nil => Some(CONSTANT),
+        TokenType::String => Some(STRING),
+        TokenType::Minus
+        | TokenType::Plus
+        | TokenType::Slash
+        | TokenType::Star
+        | TokenType::Bang
+        | TokenType::BangEqual
+        | TokenType::Equal
+        | TokenType::EqualEqual
+        | TokenType::Greater
+        | TokenType::GreaterEqual
+        | TokenType::Less
+        | TokenType::LessEqual => Some(OPERATOR),
+        _ => None,
+    }
+}
+
+// For every bracket token, the index of the token that closes or opens it.
+fn match_brackets(tokens: &[Lexeme]) -> Vec<Option<usize>> {
+    let mut partners = vec![None; tokens.len()];
+    let mut open: Vec<usize> = Vec::new();
+    for (i, token) in tokens.iter().enumerate() {
+        let opening = match token.ttype {
+            TokenType::LeftParen | TokenType::LeftBrace => {
+                open.push(i);
+                continue;
+            }
+            TokenType::RightParen => TokenType::LeftParen,
+            TokenType::RightBrace => TokenType::LeftBrace,
+            _ => continue,
+        };
+
+        if let Some(&j) = open.last() {
+            if tokens[j].ttype == opening {
+                open.pop();
+                partners[i] = Some(j);
+                partners[j] = Some(i);
+            }
+        }
+    }
+
+    partners
+}
+
+fn bracket_at(tokens: &[Lexeme], cursor: usize) -> Option<usize> {
+    let is_bracket = |token: &Lexeme| {
+        matches!(
+            token.ttype,
+            TokenType::LeftParen
+                | TokenType::RightParen
+                | TokenType::LeftBrace
+                | TokenType::RightBrace
+        )
+    };
+
+    let under = tokens
+        .iter()
+        .position(|t| is_bracket(t) && t.span.start == cursor);
+    under.or_else(|| {
+        tokens
+            .iter()
+            .position(|t| is_bracket(t) && t.span.end == cursor)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn strip(highlighted: &str) -> String {
+        let mut plain = String::new();
+        let mut chars = highlighted.chars();
+        while let Some(c) = chars.next() {
+            if c == '\x1b' {
+                chars.by_ref().take_while(|&c| c != 'm').for_each(drop);
+            } else {
+                plain.push(c);
+            }
+        }
+        plain
+    }
+
+    #[test]
+    fn test_highlight_preserves_text() {
+        for line in [
+            "var x = 1 + 2;",
+            "print \"unterminated",
+            "0b102 + @ é",
+            "// comment (",
+            "{ (a) }}",
+            "",
+        ] {
+            assert_eq!(strip(&highlight(line, 0)), line);
+        }
+    }
+
+    #[test]
+    fn test_highlight_tokens() {
+        assert_eq!(
+            highlight("var s = \"hi\";", 100),
+            format!("{KEYWORD}var{RESET} s {OPERATOR}={RESET} {STRING}\"hi\"{RESET};")
+        );
+        assert_eq!(
+            highlight("print \"open", 100),
+            format!("{KEYWORD}print{RESET} {STRING}\"open{RESET}")
+        );
+    }
+
+    #[test]
+    fn test_highlight_brackets() {
+        // cursor right after the closing paren
+        assert_eq!(
+            highlight("(1)", 3),
+            format!("{MATCHED_BRACKET}({RESET}{CONSTANT}1{RESET}{MATCHED_BRACKET}){RESET}")
+        );
+        assert_eq!(
+            highlight("(1", 0),
+            format!("{UNMATCHED_BRACKET}({RESET}{CONSTANT}1{RESET}")
+        );
+        assert_eq!(highlight("{)", 1), format!("{{{UNMATCHED_BRACKET}){RESET}"));
+    }
+}
@@ -0,0 +1,616 @@
+use std::{ops::Range, rc::Rc};
+
+use crate::{
+    expr::Expr,
+    parser::{ParseError, Parser},
+    scanner::{ScanError, Scanner},
+    stmt::Stmt,
+    symbol::Symbol,
+    token::{Span, Token, TokenType, Trivia},
+};
+
+// A token of a document, with where it is and the scan errors found since
+// the token before it.
+#[derive(Debug, Clone)]
+struct Lexed {
+    token: Token,
+    span: Span,
+    // the line and column just after it
+    end: (usize, usize),
+    errors: Vec<ScanError>,
+}
+
+// A top-level declaration and the comments before it, or the tokens skipped
+// after an error. Parsing it looked at the tokens `start..=end`, `end` being
+// where the next one starts.
+#[derive(Debug)]
+struct Item {
+    start: usize,
+    end: usize,
+    statements: usize,
+    errors: Vec<ParseError>,
+}
+
+// How much of a document an edit scanned and parsed again.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Reparsed {
+    pub tokens: usize,
+    pub declarations: usize,
+}
+
+// The source of a file being edited, kept scanned and parsed as it changes.
+//
+// An edit is scanned again from a couple of tokens before it, to catch tokens
+// that now join or split, until the scanner reaches the end of a line where a
+// token it found before ended. The rest of the text is unchanged, and so are
+// its tokens.
+// Top-level declarations are then parsed again from the first one that saw
+// a changed token, until one ends where an old one did. Everything else is
+// kept, with its positions moved to match the new text.
+//
+// The results are always those of scanning and parsing the whole source, so
+// the statements need to be resolved again after an edit.
+pub struct Document {
+    source: String,
+    // always ends with `EOF`
+    lexed: Vec<Lexed>,
+    items: Vec<Item>,
+    // those of the items in order, then the comments at the end
+    statements: Vec<Stmt>,
+}
+
+impl Document {
+    pub fn new(source: String) -> Self {
+        let mut document = Document {
+            source,
+            lexed: Vec::new(),
+            items: Vec::new(),
+            statements: Vec::new(),
+        };
+        let mut scanner = Scanner::new(&document.source);
+        let (lexed, _) = lex(&mut scanner, |_| None);
+        document.lexed = lexed;
+        let (items, statements, _) = document.parse_from(0, |_| None);
+        document.items = items;
+        document.statements = statements;
+        document
+    }
+
+    pub fn source(&self) -> &str {
+        &self.source
+    }
+
+    pub fn tokens(&self) -> impl Iterator<Item = &Token> {
+        self.lexed.iter().map(|lexed| &lexed.token)
+    }
+
+    // The byte offsets of each token, in the order of `tokens`.
+    pub fn spans(&self) -> impl Iterator<Item = Span> + '_ {
+        self.lexed.iter().map(|lexed| lexed.span)
+    }
+
+    pub fn statements(&self) -> &[Stmt] {
+        &self.statements
+    }
+
+    pub fn scan_errors(&self) -> impl Iterator<Item = &ScanError> {
+        self.lexed.iter().flat_map(|lexed| &lexed.errors)
+    }
+
+    pub fn parse_errors(&self) -> impl Iterator<Item = &ParseError> {
+        self.items.iter().flat_map(|item| &item.errors)
+    }
+
+    // Replaces the bytes in `range`, which must be on character boundaries,
+    // with `text`.
+    pub fn edit(&mut self, range: Range<usize>, text: &str) -> Reparsed {
+        let inserted = range.start..range.start + text.len();
+        let delta = text.len() as isize - range.len() as isize;
+
+        // a token ending right before the edit may run into it, and one more
+        // may have looked ahead past its end
+        let eof = self.lexed.len() - 1;
+        let before = self.lexed[..eof].partition_point(|lexed| lexed.span.end < range.start);
+        let first = before.saturating_sub(1);
+        self.source.replace_range(range, text);
+
+        let mut scanner = match first {
+            0 => Scanner::new(&self.source),
+            _ => {
+                let (line, column) = self.lexed[first - 1].end;
+                Scanner::resume(&self.source, self.lexed[first - 1].span.end, line, column)
+            }
+        };
+        let (old, source) = (&self.lexed, &self.source);
+        let (relexed, synced) = lex(&mut scanner, |lexed| {
+            if lexed.span.end < inserted.end {
+                return None;
+            }
+            let end = lexed.span.end.wrapping_add_signed(-delta);
+            let i = old[..eof].partition_point(|old| old.span.end < end);
+            if i == eof || old[i].span.end != end {
+                return None;
+            }
+            // at the end of a line, so everything after keeps its columns
+            let next = old[i + 1].span.start.wrapping_add_signed(delta);
+            let rest = source[lexed.span.end..next].trim_start_matches([' ', '\t', '\r']);
+            rest.starts_with('\n').then_some(i)
+        });
+
+        let tokens = relexed.len();
+        let (changed, shift) = match synced {
+            Some(last) => {
+                let lines = relexed[tokens - 1].end.0 as isize - self.lexed[last].end.0 as isize;
+                (first..last + 1, Shift { lines })
+            }
+            None => (first..self.lexed.len(), Shift { lines: 0 }),
+        };
+        let moved = (first + tokens) as isize - changed.end as isize;
+        self.lexed.splice(changed.clone(), relexed);
+        for lexed in &mut self.lexed[first + tokens..] {
+            shift.token(&mut lexed.token);
+            shift.line(&mut lexed.end.0);
+            lexed.span.start = lexed.span.start.wrapping_add_signed(delta);
+            lexed.span.end = lexed.span.end.wrapping_add_signed(delta);
+            for error in &mut lexed.errors {
+                shift.line(&mut error.line);
+            }
+        }
+
+        // declarations that saw a changed token are parsed again
+        let affected = self.items.partition_point(|item| item.end < changed.start);
+        let start = match affected {
+            0 => 0,
+            i => self.items[i - 1].end,
+        };
+        let items = &self.items;
+        let unchanged = first + tokens;
+        let (parsed, statements, reused) = self.parse_from(start, |end| {
+            if end < unchanged || synced.is_none() {
+                return None;
+            }
+            let old = end.wrapping_add_signed(-moved);
+            let i = items.partition_point(|item| item.start < old);
+            (i < items.len() && items[i].start == old).then_some(i)
+        });
+
+        let declarations = parsed.len();
+        let count = |items: &[Item]| items.iter().map(|item| item.statements).sum::<usize>();
+        let head = count(&self.items[..affected]);
+        // the comments at the end go with the last declaration
+        let (reused, tail) = match reused {
+            Some(i) => (i, head + count(&self.items[affected..i])),
+            None => (self.items.len(), self.statements.len()),
+        };
+        for statement in &mut self.statements[tail..] {
+            shift.stmt(statement);
+        }
+        self.statements.splice(head..tail, statements);
+        for item in &mut self.items[reused..] {
+            item.start = item.start.wrapping_add_signed(moved);
+            item.end = item.end.wrapping_add_signed(moved);
+            for error in &mut item.errors {
+                shift.line(&mut error.line);
+            }
+        }
+        self.items.splice(affected..reused, parsed);
+
+        Reparsed {
+            tokens,
+            declarations,
+        }
+    }
+
+    // Parses declarations from the token at `start` until the end, or until
+    // one ends at a token where `reuse` finds an old item to keep from.
+    // Returns the new items and their statements, with the comments at the
+    // end if it got there, or the old item.
+    fn parse_from(
+        &self,
+        mut start: usize,
+        reuse: impl Fn(usize) -> Option<usize>,
+    ) -> (Vec<Item>, Vec<Stmt>, Option<usize>) {
+        let eof = self.lexed.len() - 1;
+        let mut items = Vec::new();
+        let mut statements = Vec::new();
+        // the parser only gets the tokens up to `end`, more as needed
+        let mut size = 64;
+        while start < eof {
+            let end = eof.min(start + size);
+            let mut tokens = self.lexed[start..end]
+                .iter()
+                .map(|lexed| lexed.token.clone())
+                .collect::<Vec<_>>();
+            tokens.push(match end {
+                end if end == eof => self.lexed[eof].token.clone(),
+                _ => eof_token(),
+            });
+
+            let mut parser = Parser::new(tokens);
+            let mut offset = 0;
+            while start + offset < eof {
+                let errors = parser.errors().len();
+                let before = statements.len();
+                parser.parse_next(&mut statements);
+                // it looked at the end of the tokens, which may go on
+                if start + parser.position() >= end && end < eof {
+                    statements.truncate(before);
+                    break;
+                }
+
+                items.push(Item {
+                    start: start + offset,
+                    end: start + parser.position(),
+                    statements: statements.len() - before,
+                    errors: parser.errors()[errors..].to_vec(),
+                });
+                offset = parser.position();
+                if let Some(old) = reuse(start + offset) {
+                    return (items, statements, Some(old));
+                }
+            }
+            if start + offset == eof {
+                parser.finish(&mut statements);
+                return (items, statements, None);
+            }
+            start += offset;
+            size *= 2;
+        }
+
+        let trivia = &self.lexed[eof].token.trivia;
+        statements.extend(trivia.iter().cloned().map(Stmt::Trivia));
+        (items, statements, None)
+    }
+}
+
+// Scans until `sync` finds an old token that the last one ends with,
+// returning the tokens and the index of the old one.
+fn lex(
+    scanner: &mut Scanner,
+    sync: impl Fn(&Lexed) -> Option<usize>,
+) -> (Vec<Lexed>, Option<usize>) {
+    let mut lexed = Vec::new();
+    let mut errors = 0;
+    while let Some(lexeme) = scanner.next() {
+        let span = lexeme.span;
+        lexed.push(Lexed {
+            token: Token::from(lexeme),
+            span,
+            end: scanner.location(),
+            errors: scanner.errors()[errors..].to_vec(),
+        });
+        errors = scanner.errors().len();
+
+        let last = &lexed[lexed.len() - 1];
+        if last.token.ttype != TokenType::EOF {
+            if let Some(old) = sync(last) {
+                return (lexed, Some(old));
+            }
+        }
+    }
+    (lexed, None)
+}
+
+// Ends the tokens given to the parser before the end of the document.
+fn eof_token() -> Token {
+    Token {
+        ttype: TokenType::EOF,
+        lexeme: Symbol::intern(""),
+        literal: None,
+        line: 0,
+        column: 0,
+        doc: None,
+        trivia: Vec::new(),
+    }
+}
+
+// Moves what follows an edit by the lines it added or removed. Only whole
+// lines are kept, so columns stay the same.
+#[derive(Debug, Clone, Copy)]
+struct Shift {
+    lines: isize,
+}
+
+impl Shift {
+    fn line(&self, line: &mut usize) {
+        *line = line.wrapping_add_signed(self.lines);
+    }
+
+    fn token(&self, token: &mut Token) {
+        self.line(&mut token.line);
+        for trivia in &mut token.trivia {
+            self.trivia(trivia);
+        }
+    }
+
+    fn trivia(&self, trivia: &mut Trivia) {
+        if let Trivia::Comment { line, .. } = trivia {
+            self.line(line);
+        }
+    }
+
+    fn stmt(&self, stmt: &mut Stmt) {
+        if self.lines == 0 {
+            return;
+        }
+        match stmt {
+            Stmt::Expression { expr } | Stmt::Print { expr } => self.expr(expr),
+            Stmt::Var {
+                name, initializer, ..
+            } => {
+                self.token(name);
+                initializer.iter_mut().for_each(|expr| self.expr(expr));
+            }
+            Stmt::Block { statements } => statements.iter_mut().for_each(|stmt| self.stmt(stmt)),
+            Stmt::If {
+                keyword,
+                condition,
+                then_branch,
+                else_branch,
+            } => {
+                self.token(keyword);
+                self.expr(condition);
+                self.stmt(then_branch);
+                else_branch.iter_mut().for_each(|stmt| self.stmt(stmt));
+            }
+            Stmt::While { condition, body } => {
+                self.expr(condition);
+                self.stmt(body);
+            }
+            Stmt::For {
+                initializer,
+                condition,
+                increment,
+                body,
+            } => {
+                initializer.iter_mut().for_each(|stmt| self.stmt(stmt));
+                condition.iter_mut().for_each(|expr| self.expr(expr));
+                increment.iter_mut().for_each(|expr| self.expr(expr));
+                self.stmt(body);
+            }
+            Stmt::Function(declaration) => {
+                // copied if a function made from it still holds it
+                let declaration = Rc::make_mut(declaration);
+                self.token(&mut declaration.name);
+                declaration
+                    .params
+                    .iter_mut()
+                    .for_each(|param| self.token(param));
+                declaration.body.iter_mut().for_each(|stmt| self.stmt(stmt));
+            }
+            Stmt::Return { keyword, value } => {
+                self.token(keyword);
+                value.iter_mut().for_each(|expr| self.expr(expr));
+            }
+            Stmt::Throw { keyword, value } => {
+                self.token(keyword);
+                self.expr(value);
+            }
+            Stmt::Try {
+                keyword,
+                body,
+                catch,
+                finally,
+            } => {
+                self.token(keyword);
+                body.iter_mut().for_each(|stmt| self.stmt(stmt));
+                if let Some(catch) = catch {
+                    self.token(&mut catch.name);
+                    catch.body.iter_mut().for_each(|stmt| self.stmt(stmt));
+                }
+                finally
+                    .iter_mut()
+                    .flatten()
+                    .for_each(|stmt| self.stmt(stmt));
+            }
+            Stmt::Import {
+                keyword,
+                path,
+                alias,
+                names,
+            } => {
+                self.token(keyword);
+                self.token(path);
+                alias.iter_mut().for_each(|alias| self.token(alias));
+                names.iter_mut().for_each(|name| self.token(name));
+            }
+            Stmt::Trivia(trivia) => self.trivia(trivia),
+        }
+    }
+
+    fn expr(&self, expr: &mut Expr) {
+        match expr {
+            Expr::Binary { left, op, right } | Expr::Logical { left, op, right } => {
+                self.expr(left);
+                self.token(op);
+                self.expr(right);
+            }
+            Expr::Unary { op, right } => {
+                self.token(op);
+                self.expr(right);
+            }
+            Expr::Grouped { expr } => self.expr(expr),
+            Expr::Literal { token, .. } => self.token(token),
+            Expr::Variable { name, .. } => self.token(name),
+            Expr::Assign { name, value, .. } => {
+                self.token(name);
+                self.expr(value);
+            }
+            Expr::Call {
+                callee,
+                paren,
+                arguments,
+            } => {
+                self.expr(callee);
+                self.token(paren);
+                arguments.iter_mut().for_each(|expr| self.expr(expr));
+            }
+            Expr::Get { object, name } => {
+                self.expr(object);
+                self.token(name);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+
+    // What scanning and parsing all of `source` gives, to compare with.
+    fn parse(source: &str) -> String {
+        let mut scanner = Scanner::new(source);
+        let tokens = scanner.scan_tokens();
+        let mut parser = Parser::new(tokens.clone());
+        let statements = parser.parse();
+        format!(
+            "{:#?}\n{:#?}\n{:#?}\n{:#?}",
+            tokens,
+            statements,
+            scanner.errors(),
+            parser.errors()
+        )
+    }
+
+    fn parsed(document: &Document) -> String {
+        format!(
+            "{:#?}\n{:#?}\n{:#?}\n{:#?}",
+            document.tokens().collect::<Vec<_>>(),
+            document.statements(),
+            document.scan_errors().collect::<Vec<_>>(),
+            document.parse_errors().collect::<Vec<_>>()
+        )
+    }
+
+    #[test]
+    fn test_edit_reparses_one_declaration() {
+        let source =
+            "fun a() {\n  return 1;\n}\n\nfun b() {\n  return 2;\n}\n\n// c\nprint a() + b();\n";
+        let mut document = Document::new(source.to_string());
+
+        let two = source.find('2').unwrap();
+        let reparsed = document.edit(two..two + 1, "(2 +\n 3)");
+        assert_eq!(
+            reparsed,
+            Reparsed {
+                tokens: 7,
+                declarations: 1
+            }
+        );
+        assert_eq!(parsed(&document), parse(document.source()));
+
+        let Stmt::Print { expr } = &document.statements()[document.statements().len() - 1] else {
+            panic!("expected the call last");
+        };
+        let Expr::Binary { op, .. } = expr else {
+            panic!("expected a sum");
+        };
+        assert_eq!((op.line, op.column), (11, 11));
+    }
+
+    #[test]
+    fn test_edit_across_declarations() {
+        let mut document = Document::new("var a = \"x\";\nvar b = 2;\nprint a;".to_string());
+        // an unterminated string runs to the end
+        let reparsed = document.edit(10..11, "");
+        assert_eq!(reparsed.declarations, 1);
+        assert_eq!(parsed(&document), parse(document.source()));
+        assert_eq!(document.scan_errors().count(), 1);
+
+        document.edit(10..10, "\"");
+        assert_eq!(document.source(), "var a = \"x\";\nvar b = 2;\nprint a;");
+        assert_eq!(parsed(&document), parse(document.source()));
+        assert_eq!(document.scan_errors().count(), 0);
+    }
+
+    // Pieces of Lox that edits are made of, chosen to join into other tokens,
+    // open and close blocks, strings and comments, and cause errors.
+    const FRAGMENTS: &[&str] = &[
+        "var",
+        "fun",
+        "print",
+        "return",
+        "if",
+        "else",
+        "while",
+        "for",
+        " ",
+        "\n",
+        "\n\n",
+        "a",
+        "bé",
+        "f",
+        "x1",
+        "1",
+        "2.5",
+        "1.",
+        ".",
+        "0b1",
+        "=",
+        "==",
+        "!",
+        "<",
+        "+",
+        "-",
+        "*",
+        "/",
+        "(",
+        ")",
+        "{",
+        "}",
+        ",",
+        ";",
+        "\"",
+        "\"s\"",
+        "//",
+        "// c\n",
+        "/// doc\n",
+        "/*",
+        "*/",
+        "@",
+        "and",
+        "or",
+        "nil",
+        "true",
+        "var x = 1;\n",
+        "print x;\n",
+        "fun f(a, b) {\n  return a + b;\n}\n",
+        "if (a) print a; else print b;\n",
+        "while (x < 2) {\n  x = x + 1;\n}\n",
+        "{\n",
+        "}\n",
+    ];
+
+    fn fragments(max: usize) -> impl Strategy<Value = String> {
+        prop::collection::vec(prop::sample::select(FRAGMENTS), 0..max)
+            .prop_map(|fragments| fragments.concat())
+    }
+
+    // Edits given as fractions of the length of the source, so that they
+    // can be applied to whatever the source is by then.
+    fn edits() -> impl Strategy<Value = Vec<(f64, f64, String)>> {
+        prop::collection::vec((0.0..=1.0, 0.0..0.3, fragments(4)), 1..8)
+    }
+
+    fn char_boundary(source: &str, mut offset: usize) -> usize {
+        while !source.is_char_boundary(offset) {
+            offset -= 1;
+        }
+        offset
+    }
+
+    proptest! {
+        #[test]
+        fn test_edits_match_full_parse(source in fragments(60), edits in edits()) {
+            let mut document = Document::new(source);
+            for (at, length, text) in edits {
+                let len = document.source().len();
+                let start = char_boundary(document.source(), (at * len as f64) as usize);
+                let end = char_boundary(document.source(), len.min(start + (length * len as f64) as usize));
+                document.edit(start..end, &text);
+                prop_assert_eq!(parsed(&document), parse(document.source()));
+            }
+        }
+    }
+}
@@ -0,0 +1,611 @@
+use std::{
+    cell::Cell,
+    collections::{HashMap, HashSet},
+    rc::Rc,
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    environment::Environment,
+    expr::{self, Expr},
+    natives,
+    parser::Parser,
+    resolver::{Kind, Resolver},
+    scanner::Scanner,
+    stmt::{self, Catch, FunctionDecl, Stmt},
+    symbol::Symbol,
+    token::{LiteralValue, Token, TokenType, Trivia},
+    value::Value,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Rule {
+    UnusedVariable,
+    Shadowing,
+    UnreachableCode,
+    ConstantComparison,
+    WrongArity,
+    UndeclaredAssignment,
+    // a name in an allow comment that is no rule
+    #[serde(rename = "unknown-rule")]
+    Unknown,
+}
+
+impl Rule {
+    pub const ALL: [Rule; 7] = [
+        Rule::UnusedVariable,
+        Rule::Shadowing,
+        Rule::UnreachableCode,
+        Rule::ConstantComparison,
+        Rule::WrongArity,
+        Rule::UndeclaredAssignment,
+        Rule::Unknown,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Rule::UnusedVariable => "unused-variable",
+            Rule::Shadowing => "shadowing",
+            Rule::UnreachableCode => "unreachable-code",
+            Rule::ConstantComparison => "constant-comparison",
+            Rule::WrongArity => "wrong-arity",
+            Rule::UndeclaredAssignment => "undeclared-assignment",
+            Rule::Unknown => "unknown-rule",
+        }
+    }
+
+    fn from_name(name: &str) -> Option<Rule> {
+        Rule::ALL.into_iter().find(|rule| rule.name() == name)
+    }
+}
+
+// Which rules are enabled, read from a TOML file such as
+//
+//     [rules]
+//     shadowing = false
+//
+// Rules that aren't listed are enabled.
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    #[serde(default)]
+    rules: HashMap<Rule, bool>,
+}
+
+impl Config {
+    pub fn parse(text: &str) -> Result<Config, String> {
+        toml::from_str(text).map_err(|e| e.message().to_string())
+    }
+
+    fn is_enabled(&self, rule: Rule) -> bool {
+        self.rules.get(&rule).copied().unwrap_or(true)
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct Lint {
+    pub line: usize,
+    pub column: usize,
+    pub rule: Rule,
+    pub message: String,
+}
+
+impl Lint {
+    fn new(token: &Token, rule: Rule, message: String) -> Self {
+        Lint {
+            line: token.line,
+            column: token.column,
+            rule,
+            message,
+        }
+    }
+}
+
+impl std::fmt::Display for Lint {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} [{}]", self.message, self.rule.name())
+    }
+}
+
+// Lints `source`, which must be free of errors, i.e., pass `check`. Rules
+// can be turned off by `config`, or for a single line with a
+// `// lint: allow(rule, ...)` comment at its end or on the line before it.
+pub fn lint(source: &str, config: &Config) -> Vec<Lint> {
+    let tokens = Scanner::new(source).scan_tokens();
+    let mut lints = Vec::new();
+    let allowed = allowed_rules(source, &tokens, &mut lints);
+    let statements = Parser::new(tokens).parse();
+    finish(lints, &allowed, &statements, config)
+}
+
+// Lints `source` from the tokens and statements it was already scanned and
+// parsed into, such as those of a document being edited.
+pub fn lint_parsed<'a>(
+    source: &str,
+    tokens: impl IntoIterator<Item = &'a Token>,
+    statements: &[Stmt],
+    config: &Config,
+) -> Vec<Lint> {
+    let mut lints = Vec::new();
+    let allowed = allowed_rules(source, tokens, &mut lints);
+    finish(lints, &allowed, statements, config)
+}
+
+fn finish(
+    mut lints: Vec<Lint>,
+    allowed: &HashSet<(usize, Rule)>,
+    statements: &[Stmt],
+    config: &Config,
+) -> Vec<Lint> {
+    lints.extend(Linter::new(statements).lints);
+    lints.retain(|lint| config.is_enabled(lint.rule) && !allowed.contains(&(lint.line, lint.rule)));
+    lints.sort_by_key(|lint| (lint.line, lint.column));
+    lints
+}
+
+// The rules each `// lint: allow(...)` comment turns off, by line. Names
+// that aren't rules are reported to `lints`, as a typo would otherwise
+// leave the rule on without a word.
+fn allowed_rules<'a>(
+    source: &str,
+    tokens: impl IntoIterator<Item = &'a Token>,
+    lints: &mut Vec<Lint>,
+) -> HashSet<(usize, Rule)> {
+    let mut allowed = HashSet::new();
+    for trivia in tokens.into_iter().flat_map(|token| &token.trivia) {
+        let Trivia::Comment {
+            text,
+            line,
+            trailing,
+        } = trivia
+        else {
+            continue;
+        };
+        let Some(rules) = text
+            .strip_prefix("//")
+            .map(str::trim)
+            .and_then(|text| text.strip_prefix("lint: allow("))
+            .and_then(|text| text.strip_suffix(')'))
+        else {
+            continue;
+        };
+
+        let allowed_line = if *trailing { *line } else { line + 1 };
+        for name in rules.split(',').map(str::trim) {
+            match Rule::from_name(name) {
+                Some(rule) => {
+                    allowed.insert((allowed_line, rule));
+                }
+                None => {
+                    let column = source
+                        .lines()
+                        .nth(line - 1)
+                        .and_then(|text_line| text_line.find(text.as_str()))
+                        .map_or(1, |column| column + 1);
+                    lints.push(Lint {
+                        line: *line,
+                        column,
+                        rule: Rule::Unknown,
+                        message: format!("Unknown lint rule '{}'.", name),
+                    });
+                }
+            }
+        }
+    }
+
+    allowed
+}
+
+// Reports what the resolver's declarations and uses show up front, then
+// walks the program for the rest.
+struct Linter {
+    // the local declaration each variable read refers to, by position
+    reads: HashMap<(usize, usize), Option<usize>>,
+    // the number of parameters of each local function never assigned
+    arities: HashMap<usize, usize>,
+    // every global declared in the program, since functions may use globals
+    // declared after them, and the natives
+    globals: HashMap<Symbol, Option<usize>>,
+    lints: Vec<Lint>,
+}
+
+impl Linter {
+    fn new(statements: &[Stmt]) -> Self {
+        let mut resolver = Resolver::new();
+        resolver.resolve(statements);
+        let (declarations, uses) = (resolver.declarations(), resolver.uses());
+
+        let mut natives = Environment::default();
+        natives::define(&mut natives, Vec::new());
+        let mut globals = natives
+            .values()
+            .iter()
+            .map(|(name, value)| match value {
+                Value::Native(native) => (name.clone(), Some(native.arity)),
+                _ => (name.clone(), None),
+            })
+            .collect::<HashMap<_, _>>();
+        for declaration in declarations.iter().filter(|d| d.depth == 0) {
+            let arity = declaration
+                .function
+                .as_ref()
+                .map(|function| function.params.len());
+            // redeclared with a different arity, so unknown
+            let known = globals.get(&declaration.name.lexeme).copied();
+            let arity = match known {
+                Some(known) if known != arity => None,
+                _ => arity,
+            };
+            globals.insert(declaration.name.lexeme.clone(), arity);
+        }
+
+        let mut lints = Vec::new();
+        let mut used = HashSet::new();
+        let mut assigned = HashSet::new();
+        let mut reads = HashMap::new();
+        for usage in uses {
+            let name = &usage.name;
+            if !usage.assign {
+                used.extend(usage.declaration);
+                reads.insert((name.line, name.column), usage.declaration);
+            } else if usage.declaration.is_some() {
+                assigned.extend(usage.declaration);
+            } else if !globals.contains_key(&name.lexeme) {
+                lints.push(Lint::new(
+                    name,
+                    Rule::UndeclaredAssignment,
+                    format!("Assignment to undeclared variable '{}'.", name.lexeme),
+                ));
+            }
+        }
+
+        // assigning a global may make it any function, or none
+        for usage in uses.iter().filter(|u| u.assign && u.declaration.is_none()) {
+            if let Some(arity) = globals.get_mut(&usage.name.lexeme) {
+                *arity = None;
+            }
+        }
+
+        let mut arities = HashMap::new();
+        for (index, declaration) in declarations.iter().enumerate() {
+            if declaration.depth == 0 {
+                continue;
+            }
+            let name = &declaration.name;
+            if declaration.shadows.is_some() || globals.contains_key(&name.lexeme) {
+                lints.push(Lint::new(
+                    name,
+                    Rule::Shadowing,
+                    format!("'{}' shadows a variable of the same name.", name.lexeme),
+                ));
+            }
+
+            let kind = match declaration.kind {
+                Kind::Variable => "variable",
+                Kind::Parameter => "parameter",
+                Kind::Function => "function",
+                // catching an error without looking at it is fine
+                Kind::Catch => continue,
+            };
+            if !used.contains(&index) {
+                lints.push(Lint::new(
+                    name,
+                    Rule::UnusedVariable,
+                    format!("Unused {} '{}'.", kind, name.lexeme),
+                ));
+            }
+            if let Some(function) = &declaration.function {
+                if !assigned.contains(&index) {
+                    arities.insert(index, function.params.len());
+                }
+            }
+        }
+
+        let mut linter = Linter {
+            reads,
+            arities,
+            globals,
+            lints,
+        };
+        linter.statements(statements);
+        linter
+    }
+
+    // Reports the first statement that follows a `return`.
+    fn statements(&mut self, statements: &[Stmt]) {
+        let mut returned = None;
+        for stmt in statements {
+            if matches!(stmt, Stmt::Trivia(_)) {
+                continue;
+            }
+            if let Some(keyword) = returned.take() {
+                self.lints.push(Lint::new(
+                    keyword,
+                    Rule::UnreachableCode,
+                    "Code after this 'return' is never run.".to_string(),
+                ));
+            }
+            if let Stmt::Return { keyword, .. } = stmt {
+                returned = Some(keyword);
+            }
+            stmt.accept(&mut *self);
+        }
+    }
+
+    fn arity(&self, name: &Token) -> Option<usize> {
+        match self.reads.get(&(name.line, name.column)) {
+            Some(Some(local)) => self.arities.get(local).copied(),
+            _ => self.globals.get(&name.lexeme).copied().flatten(),
+        }
+    }
+
+    // Only literals are known, since a variable compared with itself may be
+    // NaN, or a string that can't be ordered.
+    fn comparison(&mut self, left: &Expr, op: &Token, right: &Expr) {
+        let always = match (left, right) {
+            (Expr::Literal { value: Some(l), .. }, Expr::Literal { value: Some(r), .. }) => {
+                let (l, r) = (Value::from(l), Value::from(r));
+                match (&op.ttype, &l, &r) {
+                    (TokenType::EqualEqual, _, _) => Some(l.is_equal(&r)),
+                    (TokenType::BangEqual, _, _) => Some(!l.is_equal(&r)),
+                    (TokenType::Less, Value::Number(l), Value::Number(r)) => Some(l < r),
+                    (TokenType::LessEqual, Value::Number(l), Value::Number(r)) => Some(l <= r),
+                    (TokenType::Greater, Value::Number(l), Value::Number(r)) => Some(l > r),
+                    (TokenType::GreaterEqual, Value::Number(l), Value::Number(r)) => Some(l >= r),
+                    _ => None,
+                }
+            }
+            _ => None,
+        };
+
+        if let Some(always) = always {
+            self.lints.push(Lint::new(
+                op,
+                Rule::ConstantComparison,
+                format!("This comparison is always {}.", always),
+            ));
+        }
+    }
+}
+
+impl expr::Visitor<()> for &mut Linter {
+    fn visit_binary(self, left: &Expr, op: &Token, right: &Expr) {
+        self.comparison(left, op, right);
+        left.accept(&mut *self);
+        right.accept(self);
+    }
+
+    fn visit_unary(self, _op: &Token, right: &Expr) {
+        right.accept(self);
+    }
+
+    fn visit_literal(self, _value: &Option<LiteralValue>, _token: &Token) {}
+
+    fn visit_grouped(self, expr: &Expr) {
+        expr.accept(self);
+    }
+
+    fn visit_logical(self, left: &Expr, _op: &Token, right: &Expr) {
+        left.accept(&mut *self);
+        right.accept(self);
+    }
+
+    fn visit_variable(self, _name: &Token, _depth: &Cell<Option<usize>>) {}
+
+    fn visit_assign(self, _name: &Token, value: &Expr, _depth: &Cell<Option<usize>>) {
+        value.accept(self);
+    }
+
+    fn visit_call(self, callee: &Expr, _paren: &Token, arguments: &[Expr]) {
+        if let Expr::Variable { name, .. } = callee {
+            match self.arity(name) {
+                Some(arity) if arity != arguments.len() => {
+                    self.lints.push(Lint::new(
+                        name,
+                        Rule::WrongArity,
+                        format!(
+                            "'{}' takes {} arguments but is called with {}.",
+                            name.lexeme,
+                            arity,
+                            arguments.len()
+                        ),
+                    ));
+                }
+                _ => {}
+            }
+        }
+
+        callee.accept(&mut *self);
+        for argument in arguments {
+            argument.accept(&mut *self);
+        }
+    }
+
+    fn visit_get(self, object: &Expr, _name: &Token) {
+        object.accept(self);
+    }
+}
+
+impl stmt::Visitor<()> for &mut Linter {
+    fn visit_expression(self, expr: &Expr) {
+        expr.accept(self);
+    }
+
+    fn visit_print(self, expr: &Expr) {
+        expr.accept(self);
+    }
+
+    fn visit_var(self, _name: &Token, initializer: Option<&Expr>, _doc: Option<&str>) {
+        if let Some(initializer) = initializer {
+            initializer.accept(self);
+        }
+    }
+
+    fn visit_block(self, statements: &[Stmt]) {
+        self.statements(statements);
+    }
+
+    fn visit_if(
+        self,
+        _keyword: &Token,
+        condition: &Expr,
+        then_branch: &Stmt,
+        else_branch: Option<&Stmt>,
+    ) {
+        condition.accept(&mut *self);
+        then_branch.accept(&mut *self);
+        if let Some(else_branch) = else_branch {
+            else_branch.accept(self);
+        }
+    }
+
+    fn visit_while(self, condition: &Expr, body: &Stmt) {
+        condition.accept(&mut *self);
+        body.accept(self);
+    }
+
+    fn visit_for(
+        self,
+        initializer: Option<&Stmt>,
+        condition: Option<&Expr>,
+        increment: Option<&Expr>,
+        body: &Stmt,
+    ) {
+        if let Some(initializer) = initializer {
+            initializer.accept(&mut *self);
+        }
+        if let Some(condition) = condition {
+            condition.accept(&mut *self);
+        }
+        if let Some(increment) = increment {
+            increment.accept(&mut *self);
+        }
+        body.accept(self);
+    }
+
+    fn visit_function(self, declaration: &Rc<FunctionDecl>) {
+        self.statements(&declaration.body);
+    }
+
+    fn visit_return(self, _keyword: &Token, value: Option<&Expr>) {
+        if let Some(value) = value {
+            value.accept(self);
+        }
+    }
+
+    fn visit_throw(self, _keyword: &Token, value: &Expr) {
+        value.accept(self);
+    }
+
+    fn visit_try(
+        self,
+        _keyword: &Token,
+        body: &[Stmt],
+        catch: Option<&Catch>,
+        finally: Option<&[Stmt]>,
+    ) {
+        self.statements(body);
+        if let Some(catch) = catch {
+            self.statements(&catch.body);
+        }
+        if let Some(finally) = finally {
+            self.statements(finally);
+        }
+    }
+
+    fn visit_import(
+        self,
+        _keyword: &Token,
+        _path: &Token,
+        _alias: Option<&Token>,
+        _names: &[Token],
+    ) {
+    }
+
+    fn visit_trivia(self, _trivia: &Trivia) {}
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lint_all(source: &str) -> Vec<String> {
+        lint(source, &Config::default())
+            .iter()
+            .map(|lint| format!("{}:{}: {}", lint.line, lint.column, lint))
+            .collect()
+    }
+
+    #[test]
+    fn test_rules() {
+        let source = r#"
+fun f(a, b) {
+    var unused = 1;
+    return a;
+    print a;
+}
+{
+    var a = 1;
+    { var a = 2; print a; }
+    print a == a;
+}
+print 1 < 2;
+f(1);
+len(1, 2);
+missing = 3;
+"#;
+        assert_eq!(
+            lint_all(source),
+            vec![
+                "2:10: Unused parameter 'b'. [unused-variable]",
+                "3:9: Unused variable 'unused'. [unused-variable]",
+                "4:5: Code after this 'return' is never run. [unreachable-code]",
+                "9:11: 'a' shadows a variable of the same name. [shadowing]",
+                "12:9: This comparison is always true. [constant-comparison]",
+                "13:1: 'f' takes 2 arguments but is called with 1. [wrong-arity]",
+                "14:1: 'len' takes 1 arguments but is called with 2. [wrong-arity]",
+                "15:1: Assignment to undeclared variable 'missing'. [undeclared-assignment]",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_assigned_functions_take_any_arguments() {
+        let arity = |source| {
+            lint(source, &Config::default())
+                .iter()
+                .filter(|lint| lint.rule == Rule::WrongArity)
+                .count()
+        };
+        assert_eq!(arity("fun f(a) {}\nf = clock;\nprint f();"), 0);
+        assert_eq!(arity("{\n  fun f(a) {}\n  f = clock;\n  print f();\n}"), 0);
+        assert_eq!(arity("fun f(a) {}\nvar g = f;\nprint f();"), 1);
+    }
+
+    #[test]
+    fn test_rules_can_be_turned_off() {
+        let source = r#"
+var x = 1;
+print 1 == 1; // lint: allow(constant-comparison)
+// lint: allow(undeclared-assignment, wrong-arity)
+y = len();
+{ var x = 2; print x; }
+"#;
+        assert_eq!(
+            lint_all(source),
+            vec!["6:7: 'x' shadows a variable of the same name. [shadowing]"]
+        );
+        assert_eq!(
+            lint_all("print 1; // lint: allow(shadowing, shadow)\nvar z = 0 / 0;\nprint z == z;"),
+            vec!["1:10: Unknown lint rule 'shadow'. [unknown-rule]"]
+        );
+
+        let config = Config::parse("[rules]\nshadowing = false").unwrap();
+        assert!(lint(source, &config).is_empty());
+
+        let error = Config::parse("[rules]\nunknown = true").unwrap_err();
+        assert!(error.contains("unknown variant `unknown`"), "{}", error);
+    }
+}
@@ -0,0 +1,737 @@
+use std::rc::Rc;
+
+use crate::{
+    chunk::{Chunk, Constant, Function, Handler, OpCode},
+    symbol::Symbol,
+};
+
+// A compiled script, as written by `interpret compile`:
+//
+//     magic       b"LOXC"
+//     version     u16
+//     checksum    u64, FNV-1a of the function that follows
+//     function    name, arity, upvalue count, code, constants, line table,
+//                 handlers as (start, end, target, depth)
+//
+// Integers are little endian and lengths are u32. Constants start with a tag:
+// 0 for a number (f64), 1 for a string, 2 for a nested function and 3 for the
+// name of a global.
+pub const MAGIC: &[u8; 4] = b"LOXC";
+pub const VERSION: u16 = 3;
+
+const HEADER_LEN: usize = 14;
+
+// Deeper than any function nesting the parser accepts, but shallow enough
+// to read without overflowing the stack.
+const MAX_NESTING: usize = 1000;
+
+const NUMBER: u8 = 0;
+const STRING: u8 = 1;
+const FUNCTION: u8 = 2;
+const NAME: u8 = 3;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct LoadError {
+    pub message: String,
+}
+
+impl LoadError {
+    fn new(message: impl Into<String>) -> Self {
+        LoadError {
+            message: message.into(),
+        }
+    }
+}
+
+impl std::fmt::Display for LoadError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+pub fn write(function: &Function) -> Vec<u8> {
+    let mut body = Vec::new();
+    write_function(&mut body, function);
+
+    let mut bytes = Vec::with_capacity(HEADER_LEN + body.len());
+    bytes.extend_from_slice(MAGIC);
+    bytes.extend_from_slice(&VERSION.to_le_bytes());
+    bytes.extend_from_slice(&checksum(&body).to_le_bytes());
+    bytes.extend_from_slice(&body);
+    bytes
+}
+
+// Reads a file written by `write`, checking that the VM can run it without
+// jumping, indexing or popping out of bounds.
+pub fn read(bytes: &[u8]) -> Result<Function, LoadError> {
+    if bytes.len() < MAGIC.len() || &bytes[..MAGIC.len()] != MAGIC {
+        return Err(LoadError::new("not a compiled Lox file"));
+    }
+    let version = match bytes.get(4..6) {
+        Some(&[low, high]) => u16::from_le_bytes([low, high]),
+        _ => return Err(LoadError::new("file is truncated")),
+    };
+    if version != VERSION {
+        return Err(LoadError::new(format!(
+            "compiled for format version {}, but this interpreter reads version {}",
+            version, VERSION
+        )));
+    }
+    if bytes.len() < HEADER_LEN {
+        return Err(LoadError::new("file is truncated"));
+    }
+
+    let body = &bytes[HEADER_LEN..];
+    let expected = u64::from_le_bytes(bytes[6..HEADER_LEN].try_into().unwrap());
+    if checksum(body) != expected {
+        return Err(LoadError::new("file is corrupt (checksum mismatch)"));
+    }
+
+    let mut reader = Reader {
+        bytes: body,
+        nesting: 0,
+    };
+    let function = reader.function()?;
+    if !reader.bytes.is_empty() {
+        return Err(LoadError::new("file has trailing bytes"));
+    }
+    // run without arguments or a closure around it
+    if function.arity != 0 || function.upvalues != 0 {
+        return Err(LoadError::new(
+            "the script takes arguments or captures variables",
+        ));
+    }
+    verify(&function)?;
+    Ok(function)
+}
+
+fn write_function(bytes: &mut Vec<u8>, function: &Function) {
+    write_str(bytes, &function.name);
+    write_len(bytes, function.arity);
+    write_len(bytes, function.upvalues);
+    write_len(bytes, function.chunk.code.len());
+    bytes.extend_from_slice(&function.chunk.code);
+
+    write_len(bytes, function.chunk.constants.len());
+    for constant in &function.chunk.constants {
+        match constant {
+            Constant::Number(n) => {
+                bytes.push(NUMBER);
+                bytes.extend_from_slice(&n.to_le_bytes());
+            }
+            Constant::String(s) => {
+                bytes.push(STRING);
+                write_str(bytes, s);
+            }
+            Constant::Function(function) => {
+                bytes.push(FUNCTION);
+                write_function(bytes, function);
+            }
+            Constant::Name(name) => {
+                bytes.push(NAME);
+                write_str(bytes, name);
+            }
+        }
+    }
+
+    write_len(bytes, function.chunk.lines.len());
+    for &(line, count) in &function.chunk.lines {
+        write_len(bytes, line);
+        write_len(bytes, count);
+    }
+
+    write_len(bytes, function.chunk.handlers.len());
+    for handler in &function.chunk.handlers {
+        for value in [handler.start, handler.end, handler.target, handler.depth] {
+            write_len(bytes, value);
+        }
+    }
+}
+
+fn write_len(bytes: &mut Vec<u8>, len: usize) {
+    let len = u32::try_from(len).expect("chunks are far smaller than 4 GiB");
+    bytes.extend_from_slice(&len.to_le_bytes());
+}
+
+fn write_str(bytes: &mut Vec<u8>, s: &str) {
+    write_len(bytes, s.len());
+    bytes.extend_from_slice(s.as_bytes());
+}
+
+// FNV-1a, to catch files damaged on the way rather than to resist tampering.
+fn checksum(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf29ce484222325, |hash, &byte| {
+        (hash ^ byte as u64).wrapping_mul(0x100000001b3)
+    })
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    nesting: usize,
+}
+
+impl Reader<'_> {
+    fn take(&mut self, len: usize) -> Result<&[u8], LoadError> {
+        if len > self.bytes.len() {
+            return Err(LoadError::new("file is truncated"));
+        }
+        let (taken, rest) = self.bytes.split_at(len);
+        self.bytes = rest;
+        Ok(taken)
+    }
+
+    fn byte(&mut self) -> Result<u8, LoadError> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn len(&mut self) -> Result<usize, LoadError> {
+        let bytes = self.take(4)?;
+        Ok(u32::from_le_bytes(bytes.try_into().unwrap()) as usize)
+    }
+
+    fn string(&mut self) -> Result<String, LoadError> {
+        let len = self.len()?;
+        let bytes = self.take(len)?;
+        String::from_utf8(bytes.to_vec()).map_err(|_| LoadError::new("invalid UTF-8 in a string"))
+    }
+
+    fn function(&mut self) -> Result<Function, LoadError> {
+        let name = self.string()?;
+        let arity = self.len()?;
+        let upvalues = self.len()?;
+        let len = self.len()?;
+        let code = self.take(len)?.to_vec();
+
+        // counts come from the file, so they don't size allocations up front
+        let mut constants = Vec::new();
+        for _ in 0..self.len()? {
+            constants.push(match self.byte()? {
+                NUMBER => Constant::Number(f64::from_le_bytes(self.take(8)?.try_into().unwrap())),
+                STRING => Constant::String(self.string()?.into()),
+                FUNCTION if self.nesting < MAX_NESTING => {
+                    self.nesting += 1;
+                    let function = self.function()?;
+                    self.nesting -= 1;
+                    Constant::Function(Rc::new(function))
+                }
+                FUNCTION => return Err(LoadError::new("functions are nested too deeply")),
+                NAME => Constant::Name(Symbol::intern(&self.string()?)),
+                tag => return Err(LoadError::new(format!("invalid constant tag {}", tag))),
+            });
+        }
+
+        let mut lines = Vec::new();
+        for _ in 0..self.len()? {
+            lines.push((self.len()?, self.len()?));
+        }
+
+        let mut handlers = Vec::new();
+        for _ in 0..self.len()? {
+            handlers.push(Handler {
+                start: self.len()?,
+                end: self.len()?,
+                target: self.len()?,
+                depth: self.len()?,
+            });
+        }
+
+        Ok(Function {
+            name,
+            arity,
+            upvalues,
+            chunk: Chunk {
+                code,
+                constants,
+                lines,
+                handlers,
+            },
+        })
+    }
+}
+
+// Checks that every instruction is valid with its operands in the code,
+// that constants have the type their instruction expects, that jumps and
+// handlers land on instructions and that the code ends with a `Return`. Then follows every
+// path through the code, as `stack`, for the values it leaves on the stack.
+fn verify(function: &Function) -> Result<(), LoadError> {
+    let error = |offset: usize, message: &str| {
+        LoadError::new(format!(
+            "invalid bytecode in '{}' at offset {}: {}",
+            function.name, offset, message
+        ))
+    };
+
+    let chunk = &function.chunk;
+    let code = &chunk.code;
+    if chunk.lines.iter().map(|&(_, count)| count).sum::<usize>() != code.len() {
+        return Err(error(0, "line table doesn't match the code"));
+    }
+
+    // the parser allows at most 255 parameters
+    if function.arity > u8::MAX as usize {
+        return Err(error(0, "too many parameters"));
+    }
+
+    let mut starts = vec![false; code.len()];
+    let mut instructions = Vec::new();
+    let mut jumps = Vec::new();
+    let mut offset = 0;
+    let mut last = None;
+    while offset < code.len() {
+        starts[offset] = true;
+        let op = OpCode::try_from(code[offset])
+            .map_err(|byte| error(offset, &format!("unknown opcode {}", byte)))?;
+        let operands = match op {
+            OpCode::Constant
+            | OpCode::GetGlobal
+            | OpCode::DefineGlobal
+            | OpCode::SetGlobal
+            | OpCode::Jump
+            | OpCode::JumpIfFalse
+            | OpCode::Loop
+            | OpCode::Closure
+            | OpCode::GetProperty
+            | OpCode::Import => 2,
+            OpCode::GetLocal
+            | OpCode::SetLocal
+            | OpCode::GetUpvalue
+            | OpCode::SetUpvalue
+            | OpCode::Call => 1,
+            _ => 0,
+        };
+        if offset + operands >= code.len() {
+            return Err(error(offset, "operand past the end of the code"));
+        }
+
+        let constant = || chunk.constants.get(chunk.read_u16(offset + 1) as usize);
+        let mut next = offset + 1 + operands;
+        match op {
+            OpCode::Constant => match constant() {
+                Some(Constant::Number(_) | Constant::String(_)) => {}
+                _ => return Err(error(offset, "expected a number or string constant")),
+            },
+            OpCode::GetGlobal | OpCode::DefineGlobal | OpCode::SetGlobal | OpCode::GetProperty => {
+                match constant() {
+                    Some(Constant::Name(_)) => {}
+                    _ => return Err(error(offset, "expected a name constant")),
+                }
+            }
+            OpCode::Import => match constant() {
+                Some(Constant::String(_)) => {}
+                _ => return Err(error(offset, "expected a string constant")),
+            },
+            OpCode::GetUpvalue | OpCode::SetUpvalue
+                if code[offset + 1] as usize >= function.upvalues =>
+            {
+                return Err(error(offset, "upvalue index out of range"));
+            }
+            OpCode::Jump | OpCode::JumpIfFalse => jumps.push((
+                offset,
+                next.checked_add(chunk.read_u16(offset + 1) as usize),
+            )),
+            OpCode::Loop => jumps.push((
+                offset,
+                next.checked_sub(chunk.read_u16(offset + 1) as usize),
+            )),
+            OpCode::Closure => {
+                let Some(Constant::Function(nested)) = constant() else {
+                    return Err(error(offset, "expected a function constant"));
+                };
+                for _ in 0..nested.upvalues {
+                    let Some(&[is_local, index]) = code.get(next..next + 2) else {
+                        return Err(error(offset, "operand past the end of the code"));
+                    };
+                    if is_local != 1 && index as usize >= function.upvalues {
+                        return Err(error(offset, "upvalue index out of range"));
+                    }
+                    next += 2;
+                }
+                verify(nested)?;
+            }
+            _ => {}
+        }
+        last = Some(op);
+        instructions.push((offset, op, next));
+        offset = next;
+    }
+
+    if last != Some(OpCode::Return) {
+        return Err(error(code.len(), "code doesn't end with a return"));
+    }
+    for (offset, target) in jumps {
+        if !target.is_some_and(|target| target < code.len() && starts[target]) {
+            return Err(error(offset, "jump to the middle of an instruction"));
+        }
+    }
+    for handler in &chunk.handlers {
+        let starts = |offset: usize| offset < code.len() && starts[offset];
+        let ends = handler.end == code.len() || starts(handler.end);
+        if !(handler.start < handler.end && starts(handler.start) && ends && starts(handler.target))
+        {
+            return Err(error(
+                handler.start,
+                "handler in the middle of an instruction",
+            ));
+        }
+    }
+    stack(function, &instructions).map_err(|(offset, message)| error(offset, message))
+}
+
+// What is on the stack of a frame before an instruction: whether each slot,
+// from the callee up, may be captured by a closure as an open upvalue.
+type Slots = Vec<bool>;
+
+// Checks that the instructions, as (offset, opcode, offset of the next),
+// never use more of the stack than there is, that the stack is as deep
+// whichever way an instruction is reached, and that captured slots are
+// only popped by `CloseUpvalue` or `Return`, which close their upvalues.
+// An instruction can also go on to its handler, with the stack cut back to
+// the depth of the handler, which it must not have gone below, and the
+// error pushed.
+fn stack(
+    function: &Function,
+    instructions: &[(usize, OpCode, usize)],
+) -> Result<(), (usize, &'static str)> {
+    let code = &function.chunk.code;
+    let index = |offset: usize| {
+        instructions
+            .binary_search_by_key(&offset, |&(offset, _, _)| offset)
+            .expect("jumps land on instructions")
+    };
+
+    // the callee and its arguments
+    let mut before: Vec<Option<Slots>> = vec![None; instructions.len()];
+    before[0] = Some(vec![false; 1 + function.arity]);
+    let mut pending = vec![0];
+    while let Some(i) = pending.pop() {
+        let (offset, op, next) = instructions[i];
+        let mut slots = before[i]
+            .clone()
+            .expect("pending instructions have a stack");
+        let depth = slots.len();
+        let operand = code.get(offset + 1).copied().unwrap_or(0) as usize;
+
+        let (needs, pops, pushes) = match op {
+            OpCode::Constant
+            | OpCode::Nil
+            | OpCode::True
+            | OpCode::False
+            | OpCode::GetLocal
+            | OpCode::GetGlobal
+            | OpCode::GetUpvalue
+            | OpCode::Closure
+            | OpCode::Import => (0, 0, 1),
+            OpCode::Pop
+            | OpCode::DefineGlobal
+            | OpCode::Print
+            | OpCode::CloseUpvalue
+            | OpCode::Return
+            | OpCode::Throw => (1, 1, 0),
+            OpCode::SetLocal | OpCode::SetGlobal | OpCode::SetUpvalue | OpCode::JumpIfFalse => {
+                (1, 0, 0)
+            }
+            OpCode::Not | OpCode::Negate | OpCode::GetProperty => (1, 1, 1),
+            OpCode::Equal
+            | OpCode::NotEqual
+            | OpCode::Greater
+            | OpCode::GreaterEqual
+            | OpCode::Less
+            | OpCode::LessEqual
+            | OpCode::Add
+            | OpCode::Subtract
+            | OpCode::Multiply
+            | OpCode::Divide => (2, 2, 1),
+            // the callee and the arguments, for the result
+            OpCode::Call => (operand + 1, operand + 1, 1),
+            OpCode::Jump | OpCode::Loop => (0, 0, 0),
+        };
+        if depth < needs {
+            return Err((offset, "stack underflow"));
+        }
+        if matches!(op, OpCode::GetLocal | OpCode::SetLocal) && operand >= depth {
+            return Err((offset, "local slot out of range"));
+        }
+        if op == OpCode::Closure {
+            let Some(Constant::Function(nested)) = function
+                .chunk
+                .constants
+                .get(function.chunk.read_u16(offset + 1) as usize)
+            else {
+                unreachable!("checked by verify");
+            };
+            for upvalue in 0..nested.upvalues {
+                let (is_local, slot) = (
+                    code[offset + 3 + upvalue * 2],
+                    code[offset + 4 + upvalue * 2],
+                );
+                if is_local == 1 {
+                    let Some(captured) = slots.get_mut(slot as usize) else {
+                        return Err((offset, "captured slot out of range"));
+                    };
+                    *captured = true;
+                }
+            }
+        }
+        let closes = matches!(op, OpCode::CloseUpvalue | OpCode::Return);
+        if !closes && slots[depth - pops..].contains(&true) {
+            return Err((offset, "captured slot popped without closing it"));
+        }
+        let caught = match function.chunk.handler(offset) {
+            Some(handler) if depth - pops < handler.depth => {
+                return Err((offset, "stack below the depth of its handler"));
+            }
+            Some(handler) => {
+                let mut caught = slots[..handler.depth].to_vec();
+                caught.push(false);
+                Some((handler.target, caught))
+            }
+            None => None,
+        };
+        slots.truncate(depth - pops);
+        slots.resize(depth - pops + pushes, false);
+
+        let successors = match op {
+            OpCode::Return | OpCode::Throw => vec![],
+            OpCode::Jump => vec![next + function.chunk.read_u16(offset + 1) as usize],
+            OpCode::Loop => vec![next - function.chunk.read_u16(offset + 1) as usize],
+            OpCode::JumpIfFalse => vec![next, next + function.chunk.read_u16(offset + 1) as usize],
+            _ => vec![next],
+        };
+        let successors = successors
+            .into_iter()
+            .map(|successor| (successor, slots.clone()))
+            .chain(caught);
+        for (successor, slots) in successors {
+            let j = index(successor);
+            match &mut before[j] {
+                Some(existing) if existing.len() != slots.len() => {
+                    return Err((successor, "stack depth differs between paths"));
+                }
+                // a slot captured on either path may be captured
+                Some(existing) => {
+                    let merged: Slots =
+                        existing.iter().zip(&slots).map(|(a, b)| *a || *b).collect();
+                    if merged != *existing {
+                        *existing = merged;
+                        pending.push(j);
+                    }
+                }
+                None => {
+                    before[j] = Some(slots.clone());
+                    pending.push(j);
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{compiler, disassembler, parser::Parser, resolver::Resolver, scanner::Scanner};
+
+    fn compile(source: &str) -> Function {
+        let tokens = Scanner::new(source).scan_tokens();
+        let statements = Parser::new(tokens).parse();
+        Resolver::new().resolve(&statements);
+        compiler::compile(&statements).unwrap()
+    }
+
+    const SOURCE: &str = r#"
+        fun counter(step) {
+            var count = 0;
+            fun next() { count = count + step; return count; }
+            return next;
+        }
+        var next = counter(1.5);
+        for (var i = 0; i < 3; i = i + 1) if (i > 0 and true) print next();
+        try { next(nil); } catch (e) { print e.message; } finally { print "done"; }
+    "#;
+
+    #[test]
+    fn test_round_trip() {
+        let function = compile(SOURCE);
+        let bytes = write(&function);
+        assert_eq!(&bytes[..4], MAGIC);
+
+        let read = read(&bytes).unwrap();
+        assert_eq!(
+            disassembler::disassemble(&read),
+            disassembler::disassemble(&function)
+        );
+        assert_eq!(read.chunk.lines, function.chunk.lines);
+        assert_eq!(read.chunk.handlers, function.chunk.handlers);
+        assert_eq!(read.chunk.handlers.len(), 2);
+    }
+
+    // The lines of a read file are those of the source, not of the
+    // statement before.
+    #[test]
+    fn test_errors_name_the_line_of_the_source() {
+        let read = read(&write(&compile("var a;\nvar b = nil + 1;"))).unwrap();
+        assert_eq!(read.chunk.line(0), 1);
+        // `Nil`, `DefineGlobal` and then the `Nil` of `b`
+        assert_eq!(read.chunk.line(4), 2);
+
+        let error = crate::vm::Vm::with_args(Vec::new())
+            .execute(read)
+            .unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "error at line 2: Operands must be two numbers or two strings.\n[line 2] in script"
+        );
+    }
+
+    #[test]
+    fn test_rejects_other_files() {
+        let mut bytes = write(&compile(SOURCE));
+
+        let error = read(b"print 1;").unwrap_err();
+        assert_eq!(error.message, "not a compiled Lox file");
+
+        bytes[4] = 2;
+        let error = read(&bytes).unwrap_err();
+        assert_eq!(
+            error.message,
+            "compiled for format version 2, but this interpreter reads version 3"
+        );
+        bytes[4] = 3;
+
+        let last = bytes.len() - 1;
+        bytes[last] ^= 1;
+        let error = read(&bytes).unwrap_err();
+        assert_eq!(error.message, "file is corrupt (checksum mismatch)");
+    }
+
+    // Damaged files with a valid checksum still must not panic.
+    #[test]
+    fn test_damaged_files_are_errors() {
+        let bytes = write(&compile(SOURCE));
+        for len in 0..bytes.len() {
+            assert!(read(&bytes[..len]).is_err());
+        }
+
+        let body = &bytes[HEADER_LEN..];
+        for i in 0..body.len() {
+            for flip in [0x01, 0x80, 0xff] {
+                let mut body = body.to_vec();
+                body[i] ^= flip;
+                let mut damaged = bytes[..6].to_vec();
+                damaged.extend_from_slice(&checksum(&body).to_le_bytes());
+                damaged.extend_from_slice(&body);
+                let _ = read(&damaged);
+            }
+        }
+
+        let mut function = compile("print 1;");
+        function.chunk.code[1] = 9;
+        let error = read(&write(&function)).unwrap_err();
+        assert_eq!(
+            error.message,
+            "invalid bytecode in 'script' at offset 0: expected a number or string constant"
+        );
+    }
+
+    fn function(name: &str, upvalues: usize, code: Vec<u8>, constants: Vec<Constant>) -> Function {
+        Function {
+            name: name.to_string(),
+            arity: 0,
+            upvalues,
+            chunk: Chunk {
+                lines: vec![(1, code.len())],
+                code,
+                constants,
+                handlers: Vec::new(),
+            },
+        }
+    }
+
+    // Well formed, but would have the VM index or pop past its stack.
+    #[test]
+    fn test_unbalanced_stacks_are_errors() {
+        use OpCode::*;
+        let error = |code: Vec<OpCode>, operands: &[(usize, u8)], constants| {
+            let mut code: Vec<u8> = code.into_iter().map(|op| op as u8).collect();
+            for &(offset, operand) in operands {
+                code.insert(offset, operand);
+            }
+            read(&write(&function("script", 0, code, constants)))
+                .unwrap_err()
+                .message
+        };
+
+        assert_eq!(
+            error(vec![GetLocal, Return], &[(1, 200)], vec![]),
+            "invalid bytecode in 'script' at offset 0: local slot out of range"
+        );
+        // the script itself is in the first slot
+        assert_eq!(
+            error(vec![Pop, Pop, Nil, Return], &[], vec![]),
+            "invalid bytecode in 'script' at offset 1: stack underflow"
+        );
+        assert_eq!(
+            error(vec![Add, Return], &[], vec![]),
+            "invalid bytecode in 'script' at offset 0: stack underflow"
+        );
+        assert_eq!(
+            error(vec![Nil, Call, Return], &[(2, 5)], vec![]),
+            "invalid bytecode in 'script' at offset 1: stack underflow"
+        );
+        assert_eq!(
+            error(
+                vec![True, JumpIfFalse, Nil, Nil, Return],
+                &[(2, 0), (3, 1)],
+                vec![]
+            ),
+            "invalid bytecode in 'script' at offset 5: stack depth differs between paths"
+        );
+
+        // a closure capturing the `nil` below it, which is then popped
+        let nested = function("f", 1, vec![Nil as u8, Return as u8], vec![]);
+        assert_eq!(
+            error(
+                vec![Nil, Closure, Pop, Pop, Nil, Return],
+                &[(2, 0), (3, 0), (4, 1), (5, 1)],
+                vec![crate::chunk::Constant::Function(Rc::new(nested))]
+            ),
+            "invalid bytecode in 'script' at offset 7: captured slot popped without closing it"
+        );
+
+        // a handler expecting the `nil` that the code it catches pops
+        let mut script = function(
+            "script",
+            0,
+            vec![Nil as u8, Pop as u8, Nil as u8, Return as u8],
+            vec![],
+        );
+        script.chunk.handlers.push(crate::chunk::Handler {
+            start: 1,
+            end: 2,
+            target: 2,
+            depth: 2,
+        });
+        assert_eq!(
+            read(&write(&script)).unwrap_err().message,
+            "invalid bytecode in 'script' at offset 1: stack below the depth of its handler"
+        );
+        // the error on top of what the code reaching the handler left
+        script.chunk.handlers[0] = crate::chunk::Handler {
+            start: 1,
+            end: 3,
+            target: 2,
+            depth: 1,
+        };
+        assert_eq!(
+            read(&write(&script)).unwrap_err().message,
+            "invalid bytecode in 'script' at offset 2: stack depth differs between paths"
+        );
+
+        let mut script = function("script", 0, vec![Nil as u8, Return as u8], vec![]);
+        script.arity = 1;
+        assert_eq!(
+            read(&write(&script)).unwrap_err().message,
+            "the script takes arguments or captures variables"
+        );
+    }
+}
@@ -0,0 +1,1114 @@
+use std::{collections::HashMap, io, rc::Rc};
+
+use lsp_server::{Connection, ErrorCode, Message, Notification, Request, Response};
+use lsp_types::{
+    notification::{
+        DidChangeTextDocument, DidCloseTextDocument, DidOpenTextDocument, Notification as _,
+        PublishDiagnostics,
+    },
+    request::{
+        DocumentSymbolRequest, Formatting, GotoDefinition, HoverRequest, References, Request as _,
+        SemanticTokensFullRequest,
+    },
+    Diagnostic, DiagnosticSeverity, DidChangeTextDocumentParams, DidCloseTextDocumentParams,
+    DidOpenTextDocumentParams, DocumentFormattingParams, DocumentSymbol, DocumentSymbolParams,
+    DocumentSymbolResponse, GotoDefinitionParams, GotoDefinitionResponse, Hover, HoverContents,
+    HoverParams, HoverProviderCapability, Location, MarkupContent, MarkupKind, NumberOrString,
+    OneOf, Position, PublishDiagnosticsParams, Range, ReferenceParams, SemanticToken,
+    SemanticTokenType, SemanticTokens, SemanticTokensFullOptions, SemanticTokensLegend,
+    SemanticTokensOptions, SemanticTokensParams, SemanticTokensResult,
+    SemanticTokensServerCapabilities, ServerCapabilities, SymbolKind,
+    TextDocumentContentChangeEvent, TextDocumentPositionParams, TextDocumentSyncCapability,
+    TextDocumentSyncKind, TextEdit, Uri,
+};
+
+use crate::{
+    analysis::{Analysis, Definition, Kind},
+    expr::Expr,
+    format,
+    incremental::Document,
+    lint,
+    resolver::Resolver,
+    stmt::{self, Catch, FunctionDecl, Stmt},
+    token::{Span, Token, TokenType, Trivia},
+};
+
+// The kinds of semantic tokens, in the order of their indices.
+const TOKEN_TYPES: [SemanticTokenType; 7] = [
+    SemanticTokenType::KEYWORD,
+    SemanticTokenType::STRING,
+    SemanticTokenType::NUMBER,
+    SemanticTokenType::OPERATOR,
+    SemanticTokenType::VARIABLE,
+    SemanticTokenType::FUNCTION,
+    SemanticTokenType::PARAMETER,
+];
+
+// Serves the language server protocol on stdin and stdout until the client
+// exits, linting with `config`.
+pub fn run(config: lint::Config) -> io::Result<()> {
+    let (connection, io_threads) = Connection::stdio();
+    serve(&connection, config)?;
+    drop(connection);
+    io_threads.join()
+}
+
+pub fn serve(connection: &Connection, config: lint::Config) -> io::Result<()> {
+    let capabilities = serde_json::to_value(capabilities())?;
+    connection
+        .initialize(capabilities)
+        .map_err(io::Error::other)?;
+
+    let mut server = Server {
+        files: HashMap::new(),
+        config,
+    };
+    for message in &connection.receiver {
+        match message {
+            Message::Request(request) => {
+                if connection
+                    .handle_shutdown(&request)
+                    .map_err(io::Error::other)?
+                {
+                    return Ok(());
+                }
+                let response = server.request(request);
+                connection
+                    .sender
+                    .send(response.into())
+                    .map_err(io::Error::other)?;
+            }
+            Message::Notification(notification) => {
+                if let Some(uri) = server.notification(notification) {
+                    let diagnostics = server.diagnostics(&uri);
+                    connection
+                        .sender
+                        .send(diagnostics.into())
+                        .map_err(io::Error::other)?;
+                }
+            }
+            // we never send requests, so there is nothing to respond to
+            Message::Response(_) => {}
+        }
+    }
+    Ok(())
+}
+
+fn capabilities() -> ServerCapabilities {
+    ServerCapabilities {
+        text_document_sync: Some(TextDocumentSyncCapability::Kind(
+            TextDocumentSyncKind::INCREMENTAL,
+        )),
+        definition_provider: Some(OneOf::Left(true)),
+        references_provider: Some(OneOf::Left(true)),
+        hover_provider: Some(HoverProviderCapability::Simple(true)),
+        document_symbol_provider: Some(OneOf::Left(true)),
+        semantic_tokens_provider: Some(SemanticTokensServerCapabilities::SemanticTokensOptions(
+            SemanticTokensOptions {
+                legend: SemanticTokensLegend {
+                    token_types: TOKEN_TYPES.to_vec(),
+                    token_modifiers: Vec::new(),
+                },
+                full: Some(SemanticTokensFullOptions::Bool(true)),
+                ..Default::default()
+            },
+        )),
+        document_formatting_provider: Some(OneOf::Left(true)),
+        ..Default::default()
+    }
+}
+
+// An open file, analyzed again after every change.
+struct File {
+    document: Document,
+    analysis: Analysis,
+}
+
+impl File {
+    fn new(document: Document) -> Self {
+        let analysis = Analysis::new(document.statements());
+        File { document, analysis }
+    }
+
+    fn lines(&self) -> Lines<'_> {
+        Lines::new(self.document.source())
+    }
+
+    // The definition of the name at `position`.
+    fn definition(&self, position: Position) -> Option<&Definition> {
+        let (line, column) = self.lines().column(position);
+        self.analysis.at(line, column)
+    }
+}
+
+struct Server {
+    files: HashMap<Uri, File>,
+    config: lint::Config,
+}
+
+impl Server {
+    fn request(&self, request: Request) -> Response {
+        match request.method.as_str() {
+            GotoDefinition::METHOD => self.respond::<GotoDefinition>(request, Server::definition),
+            References::METHOD => self.respond::<References>(request, Server::references),
+            HoverRequest::METHOD => self.respond::<HoverRequest>(request, Server::hover),
+            DocumentSymbolRequest::METHOD => {
+                self.respond::<DocumentSymbolRequest>(request, Server::symbols)
+            }
+            SemanticTokensFullRequest::METHOD => {
+                self.respond::<SemanticTokensFullRequest>(request, Server::semantic_tokens)
+            }
+            Formatting::METHOD => self.respond::<Formatting>(request, Server::format),
+            _ => Response::new_err(
+                request.id,
+                ErrorCode::MethodNotFound as i32,
+                format!("unknown request '{}'", request.method),
+            ),
+        }
+    }
+
+    fn respond<R: lsp_types::request::Request>(
+        &self,
+        request: Request,
+        handler: fn(&Server, R::Params) -> R::Result,
+    ) -> Response {
+        match serde_json::from_value(request.params) {
+            Ok(params) => Response::new_ok(request.id, handler(self, params)),
+            Err(error) => Response::new_err(
+                request.id,
+                ErrorCode::InvalidParams as i32,
+                error.to_string(),
+            ),
+        }
+    }
+
+    // Applies the notification, returning the file whose diagnostics may have
+    // changed.
+    fn notification(&mut self, notification: Notification) -> Option<Uri> {
+        match notification.method.as_str() {
+            DidOpenTextDocument::METHOD => {
+                let params: DidOpenTextDocumentParams =
+                    serde_json::from_value(notification.params).ok()?;
+                let document = Document::new(params.text_document.text);
+                let uri = params.text_document.uri;
+                self.files.insert(uri.clone(), File::new(document));
+                Some(uri)
+            }
+            DidChangeTextDocument::METHOD => {
+                let params: DidChangeTextDocumentParams =
+                    serde_json::from_value(notification.params).ok()?;
+                let uri = params.text_document.uri;
+                let File { mut document, .. } = self.files.remove(&uri)?;
+                for change in params.content_changes {
+                    apply(&mut document, change);
+                }
+                self.files.insert(uri.clone(), File::new(document));
+                Some(uri)
+            }
+            DidCloseTextDocument::METHOD => {
+                let params: DidCloseTextDocumentParams =
+                    serde_json::from_value(notification.params).ok()?;
+                let uri = params.text_document.uri;
+                self.files.remove(&uri);
+                // clear what was published for it
+                Some(uri)
+            }
+            _ => None,
+        }
+    }
+
+    // The errors of every phase, as `check` reports them, or the lints if
+    // there are none.
+    fn diagnostics(&self, uri: &Uri) -> Notification {
+        let mut diagnostics = Vec::new();
+        if let Some(file) = self.files.get(uri) {
+            let document = &file.document;
+            let lines = file.lines();
+            let diagnostic = |line, column, severity, message: String| Diagnostic {
+                range: lines.token_range(document, line, column),
+                severity: Some(severity),
+                source: Some("lox".to_string()),
+                message,
+                ..Default::default()
+            };
+
+            for error in document.scan_errors() {
+                let message = error.message.clone();
+                diagnostics.push(diagnostic(
+                    error.line,
+                    error.column,
+                    DiagnosticSeverity::ERROR,
+                    message,
+                ));
+            }
+            for error in document.parse_errors() {
+                let message = format!("at {}: {}", error.location, error.message);
+                diagnostics.push(diagnostic(
+                    error.line,
+                    error.column,
+                    DiagnosticSeverity::ERROR,
+                    message,
+                ));
+            }
+            let mut resolver = Resolver::new();
+            resolver.resolve(document.statements());
+            for error in resolver.errors() {
+                let message = format!("at {}: {}", error.location, error.message);
+                diagnostics.push(diagnostic(
+                    error.line,
+                    error.column,
+                    DiagnosticSeverity::ERROR,
+                    message,
+                ));
+            }
+
+            if diagnostics.is_empty() {
+                let lints = lint::lint_parsed(
+                    document.source(),
+                    document.tokens(),
+                    document.statements(),
+                    &self.config,
+                );
+                for lint in lints {
+                    let mut warning = diagnostic(
+                        lint.line,
+                        lint.column,
+                        DiagnosticSeverity::WARNING,
+                        lint.message,
+                    );
+                    warning.code = Some(NumberOrString::String(lint.rule.name().to_string()));
+                    diagnostics.push(warning);
+                }
+            }
+            diagnostics.sort_by_key(|d| (d.range.start.line, d.range.start.character));
+        }
+
+        let params = PublishDiagnosticsParams {
+            uri: uri.clone(),
+            diagnostics,
+            version: None,
+        };
+        Notification::new(PublishDiagnostics::METHOD.to_string(), params)
+    }
+
+    fn definition(&self, params: GotoDefinitionParams) -> Option<GotoDefinitionResponse> {
+        let TextDocumentPositionParams {
+            text_document,
+            position,
+        } = params.text_document_position_params;
+        let file = self.files.get(&text_document.uri)?;
+        let definition = file.definition(position)?;
+        let lines = file.lines();
+        let locations = definition
+            .declarations
+            .iter()
+            .map(|name| Location::new(text_document.uri.clone(), lines.name_range(name)))
+            .collect();
+        Some(GotoDefinitionResponse::Array(locations))
+    }
+
+    fn references(&self, params: ReferenceParams) -> Option<Vec<Location>> {
+        let TextDocumentPositionParams {
+            text_document,
+            position,
+        } = params.text_document_position;
+        let file = self.files.get(&text_document.uri)?;
+        let definition = file.definition(position)?;
+
+        let mut names: Vec<&Token> = definition.references.iter().collect();
+        if params.context.include_declaration {
+            names.extend(&definition.declarations);
+        }
+        names.sort_by_key(|name| (name.line, name.column));
+
+        let lines = file.lines();
+        let locations = names
+            .into_iter()
+            .map(|name| Location::new(text_document.uri.clone(), lines.name_range(name)))
+            .collect();
+        Some(locations)
+    }
+
+    fn hover(&self, params: HoverParams) -> Option<Hover> {
+        let TextDocumentPositionParams {
+            text_document,
+            position,
+        } = params.text_document_position_params;
+        let file = self.files.get(&text_document.uri)?;
+        let definition = file.definition(position)?;
+
+        let signature = match definition.kind {
+            Kind::Variable => format!("var {}", definition.name),
+            Kind::Parameter => format!("(parameter) {}", definition.name),
+            Kind::Function => {
+                let params: Vec<&str> = definition
+                    .params
+                    .iter()
+                    .map(|param| param.as_str())
+                    .collect();
+                format!("fun {}({})", definition.name, params.join(", "))
+            }
+            Kind::Native => format!("<native fn {}>", definition.name),
+        };
+        let mut value = format!("```lox\n{}\n```", signature);
+        match definition.arity {
+            Some(1) => value.push_str("\n\nTakes 1 argument."),
+            Some(arity) => value.push_str(&format!("\n\nTakes {} arguments.", arity)),
+            None => {}
+        }
+        if let Some(doc) = &definition.doc {
+            value.push_str("\n\n");
+            value.push_str(doc);
+        }
+
+        Some(Hover {
+            contents: HoverContents::Markup(MarkupContent {
+                kind: MarkupKind::Markdown,
+                value,
+            }),
+            range: None,
+        })
+    }
+
+    fn symbols(&self, params: DocumentSymbolParams) -> Option<DocumentSymbolResponse> {
+        let file = self.files.get(&params.text_document.uri)?;
+        let document = &file.document;
+        let tokens: Vec<(&Token, Span)> = document.tokens().zip(document.spans()).collect();
+        let mut symbols = Symbols {
+            tokens: &tokens,
+            indices: tokens
+                .iter()
+                .enumerate()
+                .map(|(index, (token, _))| ((token.line, token.column), index))
+                .collect(),
+            lines: file.lines(),
+            symbols: Vec::new(),
+        };
+        symbols.statements(document.statements());
+        Some(DocumentSymbolResponse::Nested(symbols.symbols))
+    }
+
+    // Keywords, literals, operators and names, the latter by what they refer
+    // to. Comments and punctuation are left to the editor's grammar.
+    fn semantic_tokens(&self, params: SemanticTokensParams) -> Option<SemanticTokensResult> {
+        let file = self.files.get(&params.text_document.uri)?;
+        let document = &file.document;
+        let lines = file.lines();
+
+        let mut data = Vec::new();
+        let mut previous = Position::new(0, 0);
+        for (token, span) in document.tokens().zip(document.spans()) {
+            let token_type = match token_type(token, &file.analysis) {
+                Some(token_type) => token_type,
+                None => continue,
+            };
+
+            // strings can span lines, which not every editor can show
+            let (mut start, end) = (lines.position(span.start), lines.position(span.end));
+            while start.line <= end.line {
+                let length = match start.line == end.line {
+                    true => end.character - start.character,
+                    false => lines.length(start.line) - start.character,
+                };
+                let delta_line = start.line - previous.line;
+                let delta_start = match delta_line {
+                    0 => start.character - previous.character,
+                    _ => start.character,
+                };
+                data.push(SemanticToken {
+                    delta_line,
+                    delta_start,
+                    length,
+                    token_type,
+                    token_modifiers_bitset: 0,
+                });
+                previous = start;
+                start = Position::new(start.line + 1, 0);
+            }
+        }
+
+        Some(SemanticTokensResult::Tokens(SemanticTokens {
+            result_id: None,
+            data,
+        }))
+    }
+
+    // Replaces the whole text, unless it has syntax errors.
+    fn format(&self, params: DocumentFormattingParams) -> Option<Vec<TextEdit>> {
+        let file = self.files.get(&params.text_document.uri)?;
+        let source = file.document.source();
+        let formatted = format::format(source).ok()?;
+        let lines = file.lines();
+        let range = Range::new(Position::new(0, 0), lines.position(source.len()));
+        Some(vec![TextEdit::new(range, formatted)])
+    }
+}
+
+fn apply(document: &mut Document, change: TextDocumentContentChangeEvent) {
+    match change.range {
+        Some(range) => {
+            let lines = Lines::new(document.source());
+            let range = lines.offset(range.start)..lines.offset(range.end);
+            document.edit(range, &change.text);
+        }
+        None => *document = Document::new(change.text),
+    }
+}
+
+// The index in `TOKEN_TYPES` of the kind of `token`, if it has one.
+fn token_type(token: &Token, analysis: &Analysis) -> Option<u32> {
+    let token_type = match token.ttype {
+        TokenType::String => SemanticTokenType::STRING,
+        TokenType::Number => SemanticTokenType::NUMBER,
+        TokenType::Minus
+        | TokenType::Plus
+        | TokenType::Slash
+        | TokenType::Star
+        | TokenType::Bang
+        | TokenType::BangEqual
+        | TokenType::Equal
+        | TokenType::EqualEqual
+        | TokenType::Greater
+        | TokenType::GreaterEqual
+        | TokenType::Less
+        | TokenType::LessEqual => SemanticTokenType::OPERATOR,
+        TokenType::Identifier => match analysis.at(token.line, token.column) {
+            Some(Definition {
+                kind: Kind::Function | Kind::Native,
+                ..
+            }) => SemanticTokenType::FUNCTION,
+            Some(Definition {
+                kind: Kind::Parameter,
+                ..
+            }) => SemanticTokenType::PARAMETER,
+            _ => SemanticTokenType::VARIABLE,
+        },
+        TokenType::And
+        | TokenType::As
+        | TokenType::Catch
+        | TokenType::Class
+        | TokenType::Else
+        | TokenType::False
+        | TokenType::Finally
+        | TokenType::Fun
+        | TokenType::For
+        | TokenType::From
+        | TokenType::If
+        | TokenType::Import
+        | TokenType::Nil
+        | TokenType::Or
+        | TokenType::Print
+        | TokenType::Return
+        | TokenType::Super
+        | TokenType::This
+        | TokenType::Throw
+        | TokenType::Try
+        | TokenType::True
+        | TokenType::Var
+        | TokenType::While => SemanticTokenType::KEYWORD,
+        _ => return None,
+    };
+    TOKEN_TYPES
+        .iter()
+        .position(|t| *t == token_type)
+        .map(|index| index as u32)
+}
+
+// Converts between positions in the source: byte offsets, the lines and
+// columns of tokens, which count characters from 1, and those of the
+// protocol, which count UTF-16 code units from 0.
+struct Lines<'a> {
+    source: &'a str,
+    // the offset of each line
+    starts: Vec<usize>,
+}
+
+impl<'a> Lines<'a> {
+    fn new(source: &'a str) -> Self {
+        let newlines = source.match_indices('\n').map(|(offset, _)| offset + 1);
+        Lines {
+            source,
+            starts: std::iter::once(0).chain(newlines).collect(),
+        }
+    }
+
+    // The text of a line, without its line break, or "" past the end.
+    fn line(&self, line: usize) -> &'a str {
+        let Some(&start) = self.starts.get(line) else {
+            return "";
+        };
+        let end = self
+            .starts
+            .get(line + 1)
+            .map_or(self.source.len(), |end| end - 1);
+        self.source[start..end].trim_end_matches('\r')
+    }
+
+    fn length(&self, line: u32) -> u32 {
+        self.line(line as usize).encode_utf16().count() as u32
+    }
+
+    fn position(&self, offset: usize) -> Position {
+        let line = self.starts.partition_point(|&start| start <= offset) - 1;
+        let character = self.source[self.starts[line]..offset]
+            .encode_utf16()
+            .count();
+        Position::new(line as u32, character as u32)
+    }
+
+    // Positions past the end of a line are at its end.
+    fn offset(&self, position: Position) -> usize {
+        let Some(&start) = self.starts.get(position.line as usize) else {
+            return self.source.len();
+        };
+        let mut units = 0;
+        for (offset, c) in self.line(position.line as usize).char_indices() {
+            if units >= position.character as usize {
+                return start + offset;
+            }
+            units += c.len_utf16();
+        }
+        start + self.line(position.line as usize).len()
+    }
+
+    // The line and column of the character at `position`.
+    fn column(&self, position: Position) -> (usize, usize) {
+        let line = position.line as usize;
+        let start = self.starts.get(line).copied().unwrap_or(self.source.len());
+        let characters = self.source[start..self.offset(position)].chars().count();
+        (line + 1, characters + 1)
+    }
+
+    fn locate(&self, line: usize, column: usize) -> Position {
+        let text = self.line(line.saturating_sub(1));
+        let character: usize = text
+            .chars()
+            .take(column.saturating_sub(1))
+            .map(char::len_utf16)
+            .sum();
+        Position::new(line.saturating_sub(1) as u32, character as u32)
+    }
+
+    fn name_range(&self, name: &Token) -> Range {
+        let start = self.locate(name.line, name.column);
+        let length = name.lexeme.encode_utf16().count() as u32;
+        Range::new(start, Position::new(start.line, start.character + length))
+    }
+
+    // That of the token at `line` and `column` where errors are reported, or
+    // an empty one if there is none, as at the end.
+    fn token_range(&self, document: &Document, line: usize, column: usize) -> Range {
+        let start = self.locate(line, column);
+        let span = document.tokens().zip(document.spans()).find(|(token, _)| {
+            token.line == line && token.column == column && token.ttype != TokenType::EOF
+        });
+        match span {
+            Some((_, span)) => Range::new(start, self.position(span.end).max(start)),
+            None => Range::new(start, start),
+        }
+    }
+}
+
+// The functions and variables declared in a document, with those declared
+// in a function as its children.
+struct Symbols<'a> {
+    tokens: &'a [(&'a Token, Span)],
+    // of each token by its line and column
+    indices: HashMap<(usize, usize), usize>,
+    lines: Lines<'a>,
+    symbols: Vec<DocumentSymbol>,
+}
+
+impl Symbols<'_> {
+    fn statements(&mut self, statements: &[Stmt]) {
+        for stmt in statements {
+            stmt.accept(&mut *self);
+        }
+    }
+
+    // From the keyword before `name` to the semicolon or brace that ends the
+    // declaration, found by counting brackets as the declaration may have
+    // errors.
+    fn range(&self, name: &Token, function: bool) -> Range {
+        let Some(&index) = self.indices.get(&(name.line, name.column)) else {
+            return self.lines.name_range(name);
+        };
+        let start = self.tokens[index.saturating_sub(1)].1.start;
+
+        let mut depth = 0;
+        let mut end = self.tokens[index].1.end;
+        for (token, span) in &self.tokens[index..] {
+            match token.ttype {
+                TokenType::LeftParen | TokenType::LeftBrace => depth += 1,
+                TokenType::RightParen | TokenType::RightBrace if depth == 0 => break,
+                TokenType::RightParen => depth -= 1,
+                TokenType::RightBrace => {
+                    depth -= 1;
+                    if function && depth == 0 {
+                        end = span.end;
+                        break;
+                    }
+                }
+                TokenType::Semicolon if !function && depth == 0 => {
+                    end = span.end;
+                    break;
+                }
+                TokenType::EOF => break,
+                _ => {}
+            }
+            end = span.end;
+        }
+        Range::new(self.lines.position(start), self.lines.position(end))
+    }
+
+    #[allow(deprecated)]
+    fn push(
+        &mut self,
+        name: &Token,
+        kind: SymbolKind,
+        detail: Option<String>,
+        children: Vec<DocumentSymbol>,
+    ) {
+        let function = kind == SymbolKind::FUNCTION;
+        self.symbols.push(DocumentSymbol {
+            name: name.lexeme.to_string(),
+            detail,
+            kind,
+            tags: None,
+            deprecated: None,
+            range: self.range(name, function),
+            selection_range: self.lines.name_range(name),
+            children: Some(children).filter(|children| !children.is_empty()),
+        });
+    }
+}
+
+impl stmt::Visitor<()> for &mut Symbols<'_> {
+    fn visit_expression(self, _expr: &Expr) {}
+
+    fn visit_print(self, _expr: &Expr) {}
+
+    fn visit_var(self, name: &Token, _initializer: Option<&Expr>, _doc: Option<&str>) {
+        self.push(name, SymbolKind::VARIABLE, None, Vec::new());
+    }
+
+    fn visit_block(self, statements: &[Stmt]) {
+        self.statements(statements);
+    }
+
+    fn visit_if(
+        self,
+        _keyword: &Token,
+        _condition: &Expr,
+        then_branch: &Stmt,
+        else_branch: Option<&Stmt>,
+    ) {
+        then_branch.accept(&mut *self);
+        if let Some(else_branch) = else_branch {
+            else_branch.accept(self);
+        }
+    }
+
+    fn visit_while(self, _condition: &Expr, body: &Stmt) {
+        body.accept(self);
+    }
+
+    fn visit_for(
+        self,
+        initializer: Option<&Stmt>,
+        _condition: Option<&Expr>,
+        _increment: Option<&Expr>,
+        body: &Stmt,
+    ) {
+        if let Some(initializer) = initializer {
+            initializer.accept(&mut *self);
+        }
+        body.accept(self);
+    }
+
+    fn visit_function(self, declaration: &Rc<FunctionDecl>) {
+        let outer = std::mem::take(&mut self.symbols);
+        self.statements(&declaration.body);
+        let children = std::mem::replace(&mut self.symbols, outer);
+
+        let params: Vec<&str> = declaration
+            .params
+            .iter()
+            .map(|param| param.lexeme.as_str())
+            .collect();
+        let detail = format!("fun({})", params.join(", "));
+        self.push(
+            &declaration.name,
+            SymbolKind::FUNCTION,
+            Some(detail),
+            children,
+        );
+    }
+
+    fn visit_return(self, _keyword: &Token, _value: Option<&Expr>) {}
+
+    fn visit_throw(self, _keyword: &Token, _value: &Expr) {}
+
+    fn visit_try(
+        self,
+        _keyword: &Token,
+        body: &[Stmt],
+        catch: Option<&Catch>,
+        finally: Option<&[Stmt]>,
+    ) {
+        self.statements(body);
+        if let Some(catch) = catch {
+            self.statements(&catch.body);
+        }
+        if let Some(finally) = finally {
+            self.statements(finally);
+        }
+    }
+
+    fn visit_import(self, _keyword: &Token, path: &Token, alias: Option<&Token>, names: &[Token]) {
+        if let Some(alias) = alias {
+            let detail = format!("\"{}\"", path.lexeme);
+            self.push(alias, SymbolKind::MODULE, Some(detail), Vec::new());
+        }
+        for name in names {
+            self.push(name, SymbolKind::VARIABLE, None, Vec::new());
+        }
+    }
+
+    fn visit_trivia(self, _trivia: &Trivia) {}
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        collections::VecDeque,
+        thread::{self, JoinHandle},
+    };
+
+    use lsp_server::RequestId;
+    use lsp_types::{
+        notification::{Exit, Initialized},
+        request::{Initialize, Shutdown},
+        InitializeParams, InitializedParams, ReferenceContext, TextDocumentIdentifier,
+        TextDocumentItem, VersionedTextDocumentIdentifier,
+    };
+
+    use super::*;
+
+    // Plays the part of an editor, talking to a server on another thread.
+    struct Client {
+        connection: Connection,
+        server: JoinHandle<io::Result<()>>,
+        // those received while waiting for a response
+        notifications: VecDeque<Notification>,
+        next_id: i32,
+    }
+
+    impl Client {
+        fn start() -> Self {
+            let (server, connection) = Connection::memory();
+            let server = thread::spawn(move || serve(&server, lint::Config::default()));
+            let mut client = Client {
+                connection,
+                server,
+                notifications: VecDeque::new(),
+                next_id: 0,
+            };
+            client.request::<Initialize>(InitializeParams::default());
+            client.notify::<Initialized>(InitializedParams {});
+            client
+        }
+
+        fn request<R: lsp_types::request::Request>(&mut self, params: R::Params) -> R::Result {
+            self.next_id += 1;
+            let id = RequestId::from(self.next_id);
+            let request = Request::new(id.clone(), R::METHOD.to_string(), params);
+            self.connection.sender.send(request.into()).unwrap();
+            loop {
+                match self.connection.receiver.recv().unwrap() {
+                    Message::Response(response) if response.id == id => {
+                        if let Some(error) = response.error {
+                            panic!("{} failed: {}", R::METHOD, error.message);
+                        }
+                        return serde_json::from_value(response.result.unwrap()).unwrap();
+                    }
+                    Message::Notification(notification) => {
+                        self.notifications.push_back(notification)
+                    }
+                    message => panic!("unexpected {:?}", message),
+                }
+            }
+        }
+
+        fn notify<N: lsp_types::notification::Notification>(&self, params: N::Params) {
+            let notification = Notification::new(N::METHOD.to_string(), params);
+            self.connection.sender.send(notification.into()).unwrap();
+        }
+
+        // The messages of the next diagnostics published.
+        fn diagnostics(&mut self) -> Vec<(u32, u32, String)> {
+            let notification = match self.notifications.pop_front() {
+                Some(notification) => notification,
+                None => match self.connection.receiver.recv().unwrap() {
+                    Message::Notification(notification) => notification,
+                    message => panic!("unexpected {:?}", message),
+                },
+            };
+            let params: PublishDiagnosticsParams =
+                notification.extract(PublishDiagnostics::METHOD).unwrap();
+            params
+                .diagnostics
+                .into_iter()
+                .map(|d| (d.range.start.line, d.range.start.character, d.message))
+                .collect()
+        }
+
+        fn open(&mut self, uri: &Uri, text: &str) {
+            self.notify::<DidOpenTextDocument>(DidOpenTextDocumentParams {
+                text_document: TextDocumentItem::new(
+                    uri.clone(),
+                    "lox".to_string(),
+                    1,
+                    text.to_string(),
+                ),
+            });
+        }
+
+        fn change(&mut self, uri: &Uri, range: Option<Range>, text: &str) {
+            self.notify::<DidChangeTextDocument>(DidChangeTextDocumentParams {
+                text_document: VersionedTextDocumentIdentifier::new(uri.clone(), 2),
+                content_changes: vec![TextDocumentContentChangeEvent {
+                    range,
+                    range_length: None,
+                    text: text.to_string(),
+                }],
+            });
+        }
+
+        fn shutdown(mut self) {
+            self.request::<Shutdown>(());
+            self.notify::<Exit>(());
+            self.server.join().unwrap().unwrap();
+        }
+    }
+
+    fn uri() -> Uri {
+        "file:///test.lox".parse().unwrap()
+    }
+
+    fn at(uri: &Uri, line: u32, character: u32) -> TextDocumentPositionParams {
+        TextDocumentPositionParams::new(
+            TextDocumentIdentifier::new(uri.clone()),
+            Position::new(line, character),
+        )
+    }
+
+    fn range(start: (u32, u32), end: (u32, u32)) -> Range {
+        Range::new(Position::new(start.0, start.1), Position::new(end.0, end.1))
+    }
+
+    #[test]
+    fn test_diagnostics() {
+        let mut client = Client::start();
+        let uri = uri();
+
+        client.open(&uri, "var a = 1\nprint a;\n");
+        assert_eq!(
+            client.diagnostics(),
+            vec![(
+                1,
+                0,
+                "at 'print': Expect ';' after variable declaration.".to_string()
+            )]
+        );
+
+        client.change(&uri, Some(range((0, 9), (0, 9))), ";");
+        assert_eq!(client.diagnostics(), vec![]);
+
+        // lints are only shown without errors
+        client.change(&uri, None, "{ var b = 1; }\n");
+        assert_eq!(
+            client.diagnostics(),
+            vec![(0, 6, "Unused variable 'b'.".to_string())]
+        );
+        // from the tokens of the edited document
+        client.change(
+            &uri,
+            Some(range((0, 14), (0, 14))),
+            " // lint: allow(unused-variable)",
+        );
+        assert_eq!(client.diagnostics(), vec![]);
+
+        client.notify::<DidCloseTextDocument>(DidCloseTextDocumentParams {
+            text_document: TextDocumentIdentifier::new(uri.clone()),
+        });
+        assert_eq!(client.diagnostics(), vec![]);
+        client.shutdown();
+    }
+
+    #[test]
+    fn test_navigation() {
+        let mut client = Client::start();
+        let uri = uri();
+        let source = "/// Adds \"é\" and b.\nfun add(a, b) {\n  return a + b;\n}\nvar s = \"é\"; print add(s, 1);\n";
+        client.open(&uri, source);
+        client.diagnostics();
+
+        // after the é, which is one UTF-16 unit but two bytes
+        let definition = client.request::<GotoDefinition>(GotoDefinitionParams {
+            text_document_position_params: at(&uri, 4, 21),
+            work_done_progress_params: Default::default(),
+            partial_result_params: Default::default(),
+        });
+        assert_eq!(
+            definition,
+            Some(GotoDefinitionResponse::Array(vec![Location::new(
+                uri.clone(),
+                range((1, 4), (1, 7))
+            )]))
+        );
+
+        let references = client.request::<References>(ReferenceParams {
+            text_document_position: at(&uri, 2, 9),
+            work_done_progress_params: Default::default(),
+            partial_result_params: Default::default(),
+            context: ReferenceContext {
+                include_declaration: true,
+            },
+        });
+        let ranges: Vec<Range> = references
+            .unwrap()
+            .into_iter()
+            .map(|location| location.range)
+            .collect();
+        assert_eq!(ranges, vec![range((1, 8), (1, 9)), range((2, 9), (2, 10))]);
+
+        let hover = client.request::<HoverRequest>(HoverParams {
+            text_document_position_params: at(&uri, 1, 5),
+            work_done_progress_params: Default::default(),
+        });
+        let Some(Hover {
+            contents: HoverContents::Markup(markup),
+            ..
+        }) = hover
+        else {
+            panic!("expected markup, got {:?}", hover);
+        };
+        assert_eq!(
+            markup.value,
+            "```lox\nfun add(a, b)\n```\n\nTakes 2 arguments.\n\nAdds \"é\" and b."
+        );
+
+        // nothing to find on a keyword
+        let definition = client.request::<GotoDefinition>(GotoDefinitionParams {
+            text_document_position_params: at(&uri, 2, 3),
+            work_done_progress_params: Default::default(),
+            partial_result_params: Default::default(),
+        });
+        assert_eq!(definition, None);
+        client.shutdown();
+    }
+
+    #[test]
+    fn test_symbols_tokens_and_formatting() {
+        let mut client = Client::start();
+        let uri = uri();
+        client.open(&uri, "fun f(x) { var y = x; }\nvar s = \"a\nb\";\n");
+        client.diagnostics();
+        let document = TextDocumentIdentifier::new(uri.clone());
+
+        let symbols = client.request::<DocumentSymbolRequest>(DocumentSymbolParams {
+            text_document: document.clone(),
+            work_done_progress_params: Default::default(),
+            partial_result_params: Default::default(),
+        });
+        let Some(DocumentSymbolResponse::Nested(symbols)) = symbols else {
+            panic!("expected nested symbols, got {:?}", symbols);
+        };
+        let summary: Vec<_> = symbols
+            .iter()
+            .map(|symbol| {
+                (
+                    symbol.name.as_str(),
+                    symbol.range,
+                    symbol.children.as_ref().map(Vec::len),
+                )
+            })
+            .collect();
+        assert_eq!(
+            summary,
+            vec![
+                ("f", range((0, 0), (0, 23)), Some(1)),
+                ("s", range((1, 0), (2, 3)), None),
+            ]
+        );
+        assert_eq!(
+            symbols[0].children.as_ref().unwrap()[0].range,
+            range((0, 11), (0, 21))
+        );
+
+        let tokens = client.request::<SemanticTokensFullRequest>(SemanticTokensParams {
+            text_document: document.clone(),
+            work_done_progress_params: Default::default(),
+            partial_result_params: Default::default(),
+        });
+        let Some(SemanticTokensResult::Tokens(tokens)) = tokens else {
+            panic!("expected tokens, got {:?}", tokens);
+        };
+        let types = TOKEN_TYPES;
+        let tokens: Vec<_> = tokens
+            .data
+            .iter()
+            .map(|t| {
+                (
+                    t.delta_line,
+                    t.delta_start,
+                    t.length,
+                    types[t.token_type as usize].as_str(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            tokens,
+            vec![
+                (0, 0, 3, "keyword"),
+                (0, 4, 1, "function"),
+                (0, 2, 1, "parameter"),
+                (0, 5, 3, "keyword"),
+                (0, 4, 1, "variable"),
+                (0, 2, 1, "operator"),
+                (0, 2, 1, "parameter"),
+                (1, 0, 3, "keyword"),
+                (0, 4, 1, "variable"),
+                (0, 2, 1, "operator"),
+                // the string is split at the line break
+                (0, 2, 2, "string"),
+                (1, 0, 2, "string"),
+            ]
+        );
+
+        let edits = client.request::<Formatting>(DocumentFormattingParams {
+            text_document: document.clone(),
+            options: Default::default(),
+            work_done_progress_params: Default::default(),
+        });
+        assert_eq!(
+            edits,
+            Some(vec![TextEdit::new(
+                range((0, 0), (3, 0)),
+                "fun f(x) {\n    var y = x;\n}\nvar s = \"a\nb\";\n".to_string()
+            )])
+        );
+
+        // not with syntax errors
+        client.change(&uri, None, "print (;");
+        client.diagnostics();
+        let edits = client.request::<Formatting>(DocumentFormattingParams {
+            text_document: document,
+            options: Default::default(),
+            work_done_progress_params: Default::default(),
+        });
+        assert_eq!(edits, None);
+        client.shutdown();
+    }
+}
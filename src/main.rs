@@ -9,70 +9,899 @@
     missing_docs
 )]
 
-use clap::Parser;
-use std::io::{self, Write};
+use clap::{Parser, Subcommand, ValueEnum};
+use std::{
+    cell::RefCell,
+    ffi::OsString,
+    io::{self, Read},
+    path::{Path, PathBuf},
+    process::ExitCode,
+    rc::Rc,
+};
 
+mod analysis;
+mod backend;
+mod check;
+mod chunk;
+mod compiler;
+mod coverage;
+mod dap;
+mod debug;
+mod debugger;
+mod disassembler;
+mod environment;
 mod expr;
+mod format;
+mod gc;
+mod highlight;
+mod incremental;
+mod lint;
+mod loxc;
+mod lsp;
+mod module;
+mod natives;
 mod parser;
+mod profile;
+mod repl;
+mod resolver;
 mod scanner;
+mod stmt;
+mod symbol;
 mod token;
+mod value;
+mod vm;
 
+use backend::Backend;
+use gc::GcConfig;
 use scanner::Scanner;
 
+// Exit codes from sysexits.h
+const EX_USAGE: u8 = 64;
+const EX_DATAERR: u8 = 65;
+const EX_NOINPUT: u8 = 66;
+const EX_SOFTWARE: u8 = 70;
+const EX_IOERR: u8 = 74;
+const EX_CONFIG: u8 = 78;
+
+// Read by `lint` unless another file is given with `--config`.
+const LINT_CONFIG: &str = ".loxlint.toml";
+
 #[derive(Parser, Debug)]
-#[command(version, about, long_about = None)]
+#[command(version, about, long_about = None, args_conflicts_with_subcommands = true)]
 struct Args {
+    #[command(subcommand)]
+    command: Option<Command>,
+
+    /// Print the syntax tree instead of running the program
     #[arg(short, long)]
     pretty: bool,
 
-    #[arg(short, long)]
+    /// Run programs by walking the syntax tree or on the bytecode VM
+    #[arg(long, value_enum, default_value_t = Backend::Tree)]
+    backend: Backend,
+
+    /// Print the bytecode of the program instead of running it
+    #[arg(long, conflicts_with = "pretty")]
+    disassemble: bool,
+
+    /// Run the program on the VM, printing the stack and each instruction
+    /// to stderr before running it
+    #[arg(long, conflicts_with_all = ["pretty", "disassemble"])]
+    trace_exec: bool,
+
+    /// After the program, print how often each function and line ran, and
+    /// for how long, to stderr
+    #[arg(long, conflicts_with_all = ["pretty", "disassemble", "trace_exec"])]
+    profile: bool,
+
+    /// Write the time spent in each stack of calls to FILE, in the folded
+    /// format of flamegraph tools
+    #[arg(long, value_name = "FILE", conflicts_with_all = ["pretty", "disassemble", "trace_exec"])]
+    flamegraph: Option<String>,
+
+    /// Write which lines and branches of the script ran to FILE
+    #[arg(long, value_name = "FILE", conflicts_with_all = ["pretty", "disassemble", "trace_exec", "profile", "flamegraph"])]
+    coverage: Option<String>,
+
+    /// Format of the --coverage file
+    #[arg(long, value_enum, default_value_t = coverage::Format::Lcov, requires = "coverage")]
+    coverage_format: coverage::Format,
+
+    /// Collect garbage on every allocation, to test the collector
+    #[arg(long)]
+    gc_stress: bool,
+
+    /// Number of heap objects before the first garbage collection
+    #[arg(long, value_name = "OBJECTS", default_value_t = GcConfig::default().threshold)]
+    gc_threshold: usize,
+
+    /// Factor the live heap grows by before the next collection
+    #[arg(long, value_name = "FACTOR", default_value_t = GcConfig::default().growth, value_parser = parse_growth)]
+    gc_growth: f64,
+
+    /// Most function calls in progress before a stack overflow error, at most 15000
+    #[arg(long, value_name = "CALLS", default_value_t = backend::MAX_DEPTH, value_parser = parse_max_depth)]
+    max_depth: usize,
+
+    /// Look for imported modules in DIR, after the directory of the file
+    /// importing them and before those of `LOX_PATH` (repeatable)
+    #[arg(long = "module-path", value_name = "DIR")]
+    module_path: Vec<String>,
+
+    /// Run the script at this path (same as passing it as the first argument)
+    #[arg(short, long, conflicts_with = "eval")]
     file: Option<String>,
+
+    /// Run SOURCE instead of a script
+    #[arg(short, long, value_name = "SOURCE")]
+    eval: Option<String>,
+
+    /// Script to run, or `-` to read it from stdin, followed by the
+    /// arguments returned by `args()`. Starts the REPL when omitted.
+    #[arg(value_name = "SCRIPT [ARGS]", trailing_var_arg = true)]
+    script: Vec<String>,
+}
+
+// What `run` does with a program, from the command line.
+struct RunOptions {
+    pretty: bool,
+    disassemble: bool,
+    trace_exec: bool,
+    backend: Backend,
+    gc: GcConfig,
+    max_depth: usize,
+    profile: bool,
+    flamegraph: Option<String>,
+    coverage: Option<String>,
+    coverage_format: coverage::Format,
+    // where modules are found after the directory of the script
+    search_path: Vec<PathBuf>,
+}
+
+impl RunOptions {
+    // Whether the program is run with a hook telling a profiler or the
+    // coverage about it.
+    fn instrumented(&self) -> bool {
+        self.profile || self.flamegraph.is_some() || self.coverage.is_some()
+    }
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Report errors in scripts without running them
+    Check {
+        /// Scripts to check
+        #[arg(required = true)]
+        files: Vec<String>,
+
+        /// How to print the errors
+        #[arg(long, value_enum, default_value_t = Format::Text)]
+        format: Format,
+    },
+    /// Report likely mistakes in scripts, such as unused variables
+    Lint {
+        /// Scripts to lint
+        #[arg(required = true)]
+        files: Vec<String>,
+
+        /// How to print the warnings
+        #[arg(long, value_enum, default_value_t = Format::Text)]
+        format: Format,
+
+        /// TOML file turning rules off, instead of `.loxlint.toml`
+        #[arg(long, value_name = "FILE")]
+        config: Option<String>,
+    },
+    /// Compile a script to bytecode that runs without parsing it again
+    Compile {
+        /// Script to compile, or `-` to read it from stdin
+        file: String,
+
+        /// Where to write the bytecode, instead of the script path with a
+        /// `.loxc` extension
+        #[arg(short, long, value_name = "FILE")]
+        output: Option<String>,
+    },
+    /// Rewrite scripts in the canonical style
+    Fmt {
+        /// Scripts to format, or `-` to format stdin to stdout
+        #[arg(required = true)]
+        files: Vec<String>,
+
+        /// List the scripts that aren't formatted instead of rewriting them
+        #[arg(long)]
+        check: bool,
+    },
+    /// Debug a script from a gdb-style prompt
+    Debug {
+        /// Script to debug
+        script: String,
+
+        /// Arguments returned by `args()`
+        #[arg(trailing_var_arg = true, allow_hyphen_values = true)]
+        args: Vec<String>,
+    },
+    /// Debug scripts for a client of the debug adapter protocol on stdin
+    /// and stdout
+    Dap,
+    /// Serve the language server protocol on stdin and stdout, for editors
+    Lsp {
+        /// TOML file turning lint rules off, instead of `.loxlint.toml`
+        #[arg(long, value_name = "FILE")]
+        config: Option<String>,
+    },
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug)]
+enum Format {
+    /// One `file:line:column: error: message` line per error
+    Text,
+    /// A JSON array of `{file, line, column, message}` objects
+    Json,
+}
+
+fn main() -> ExitCode {
+    std::thread::Builder::new()
+        .stack_size(backend::STACK_SIZE)
+        .spawn(|| interpret(std::env::args_os()))
+        .expect("failed to start the interpreter thread")
+        .join()
+        .unwrap_or_else(|panic| std::panic::resume_unwind(panic))
+}
+
+// Runs the command line `args`, the first of which is the program name.
+fn interpret(args: impl IntoIterator<Item = impl Into<OsString> + Clone>) -> ExitCode {
+    let args = match Args::try_parse_from(args) {
+        Ok(args) => args,
+        Err(error) => {
+            // --help and --version are "errors" printed to stdout
+            let _ = error.print();
+            return match error.use_stderr() {
+                true => ExitCode::from(EX_USAGE),
+                false => ExitCode::SUCCESS,
+            };
+        }
+    };
+
+    match args.command {
+        Some(Command::Check { files, format }) => return check(&files, format),
+        Some(Command::Lint {
+            files,
+            format,
+            config,
+        }) => return lint(&files, format, config.as_deref()),
+        Some(Command::Compile { file, output }) => return compile(&file, output),
+        Some(Command::Fmt { files, check }) => return fmt(&files, check),
+        Some(Command::Lsp { config }) => return serve_lsp(config.as_deref()),
+        Some(Command::Debug { script, args }) => return debug(&script, args),
+        Some(Command::Dap) => {
+            return match dap::run() {
+                Ok(()) => ExitCode::SUCCESS,
+                Err(error) => {
+                    eprintln!("error: {}", error);
+                    ExitCode::from(EX_IOERR)
+                }
+            };
+        }
+        None => {}
+    }
+
+    let options = RunOptions {
+        pretty: args.pretty,
+        disassemble: args.disassemble,
+        trace_exec: args.trace_exec,
+        backend: args.backend,
+        gc: GcConfig {
+            stress: args.gc_stress,
+            threshold: args.gc_threshold,
+            growth: args.gc_growth,
+        },
+        max_depth: args.max_depth,
+        profile: args.profile,
+        flamegraph: args.flamegraph,
+        coverage: args.coverage,
+        coverage_format: args.coverage_format,
+        search_path: module::search_path(&args.module_path),
+    };
+    // only the tree-walking interpreter has hooks
+    if options.instrumented() && options.backend == Backend::Vm {
+        eprintln!("error: --profile, --flamegraph and --coverage need --backend tree");
+        return ExitCode::from(EX_USAGE);
+    }
+
+    // with -e or --file, every positional argument belongs to the script
+    let (path, script_args) = match (args.eval, args.file) {
+        (Some(source), _) => return run("-", &source, &options, args.script),
+        (None, Some(path)) => (path, args.script),
+        (None, None) => match args.script.split_first() {
+            Some((path, script_args)) => (path.clone(), script_args.to_vec()),
+            None if options.disassemble || options.trace_exec => {
+                eprintln!("error: --disassemble and --trace-exec need a script");
+                return ExitCode::from(EX_USAGE);
+            }
+            None => {
+                let search_path = options.search_path;
+                return match repl::Repl::new(args.pretty, args.backend, options.gc, search_path)
+                    .run()
+                {
+                    Ok(()) => ExitCode::SUCCESS,
+                    Err(error) => {
+                        eprintln!("error: {}", error);
+                        ExitCode::from(EX_IOERR)
+                    }
+                };
+            }
+        },
+    };
+
+    if path.ends_with(".loxc") {
+        return run_compiled(&path, &options, script_args);
+    }
+    match read_script(&path) {
+        Ok(source) => run(&path, &source, &options, script_args),
+        Err(error) => {
+            eprintln!("error: {}", error);
+            ExitCode::from(EX_NOINPUT)
+        }
+    }
 }
 
-fn main() -> io::Result<()> {
-    let args = Args::parse();
+// The heap must at least stay the same size between collections.
+fn parse_growth(growth: &str) -> Result<f64, String> {
+    match growth.parse::<f64>() {
+        Ok(growth) if growth >= 1.0 => Ok(growth),
+        Ok(_) => Err("must be at least 1".to_string()),
+        Err(error) => Err(error.to_string()),
+    }
+}
+
+// Deeper recursion would overflow the interpreter thread's Rust stack.
+fn parse_max_depth(max_depth: &str) -> Result<usize, String> {
+    match max_depth.parse::<usize>() {
+        Ok(max_depth) if max_depth <= backend::MAX_DEPTH_LIMIT => Ok(max_depth),
+        Ok(_) => Err(format!("must be at most {}", backend::MAX_DEPTH_LIMIT)),
+        Err(error) => Err(error.to_string()),
+    }
+}
 
-    if let Some(file_path) = args.file {
-        let file_content = std::fs::read_to_string(file_path)?;
-        run(&file_content);
+// Reads the script at `path`, or stdin when the path is `-`.
+fn read_script(path: &str) -> Result<String, String> {
+    if path == "-" {
+        let mut source = String::new();
+        return io::stdin()
+            .read_to_string(&mut source)
+            .map(|_| source)
+            .map_err(|e| format!("cannot read stdin: {}", e));
+    }
+
+    std::fs::read_to_string(path).map_err(|e| format!("cannot read '{}': {}", path, e))
+}
+
+// Checks every file, even after one has errors, and prints the errors of
+// all of them together.
+fn check(files: &[String], format: Format) -> ExitCode {
+    let mut diagnostics = Vec::new();
+    let mut unreadable = false;
+    for file in files {
+        match read_script(file) {
+            Ok(source) => diagnostics.extend(check::check(file, &source)),
+            Err(error) => {
+                eprintln!("error: {}", error);
+                unreadable = true;
+            }
+        }
+    }
+
+    match format {
+        Format::Text => {
+            for diagnostic in &diagnostics {
+                println!("{}", diagnostic);
+            }
+        }
+        Format::Json => match serde_json::to_string_pretty(&diagnostics) {
+            Ok(json) => println!("{}", json),
+            Err(error) => {
+                eprintln!("error: {}", error);
+                return ExitCode::from(EX_SOFTWARE);
+            }
+        },
+    }
+
+    if unreadable {
+        ExitCode::from(EX_NOINPUT)
+    } else if !diagnostics.is_empty() {
+        ExitCode::from(EX_DATAERR)
     } else {
-        loop {
-            let mut input = String::new();
-            print!("> ");
-            io::stdout().flush()?;
+        ExitCode::SUCCESS
+    }
+}
 
-            io::stdin().read_line(&mut input)?;
-            let input = input.trim();
+// Lints every file that passes `check`; the others only get their errors
+// reported.
+fn lint(files: &[String], format: Format, config: Option<&str>) -> ExitCode {
+    let config = match read_lint_config(config) {
+        Ok(config) => config,
+        Err(error) => {
+            eprintln!("error: {}", error);
+            return ExitCode::from(EX_CONFIG);
+        }
+    };
 
-            if input.eq_ignore_ascii_case("exit") {
-                break;
+    let mut status = ExitCode::SUCCESS;
+    let mut lints = Vec::new();
+    for file in files {
+        let source = match read_script(file) {
+            Ok(source) => source,
+            Err(error) => {
+                eprintln!("error: {}", error);
+                status = ExitCode::from(EX_NOINPUT);
+                continue;
+            }
+        };
+        let diagnostics = check::check(file, &source);
+        if !diagnostics.is_empty() {
+            for diagnostic in diagnostics {
+                eprintln!("{}", diagnostic);
+            }
+            status = ExitCode::from(EX_DATAERR);
+            continue;
+        }
+        lints.extend(
+            lint::lint(&source, &config)
+                .into_iter()
+                .map(|lint| (file, lint)),
+        );
+    }
+
+    match format {
+        Format::Text => {
+            for (file, lint) in &lints {
+                println!("{}:{}:{}: warning: {}", file, lint.line, lint.column, lint);
+            }
+        }
+        Format::Json => {
+            #[derive(serde::Serialize)]
+            struct FileLint<'a> {
+                file: &'a str,
+                #[serde(flatten)]
+                lint: &'a lint::Lint,
             }
 
-            run(input);
+            let lints = lints
+                .iter()
+                .map(|(file, lint)| FileLint { file, lint })
+                .collect::<Vec<_>>();
+            match serde_json::to_string_pretty(&lints) {
+                Ok(json) => println!("{}", json),
+                Err(error) => {
+                    eprintln!("error: {}", error);
+                    return ExitCode::from(EX_SOFTWARE);
+                }
+            }
         }
     }
 
-    Ok(())
+    if status == ExitCode::SUCCESS && !lints.is_empty() {
+        status = ExitCode::FAILURE;
+    }
+    status
+}
+
+// The default config file is optional, one given on the command line isn't.
+fn read_lint_config(path: Option<&str>) -> Result<lint::Config, String> {
+    let text = match path {
+        Some(path) => {
+            std::fs::read_to_string(path).map_err(|e| format!("cannot read '{}': {}", path, e))?
+        }
+        None => match std::fs::read_to_string(LINT_CONFIG) {
+            Ok(text) => text,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(lint::Config::default()),
+            Err(e) => return Err(format!("cannot read '{}': {}", LINT_CONFIG, e)),
+        },
+    };
+
+    let path = path.unwrap_or(LINT_CONFIG);
+    lint::Config::parse(&text).map_err(|e| format!("invalid config '{}': {}", path, e))
 }
 
-fn run(input: &str) {
-    let mut scanner = Scanner::new(input.to_string());
+// Lints the open files with the same config as `lint`.
+fn serve_lsp(config: Option<&str>) -> ExitCode {
+    let config = match read_lint_config(config) {
+        Ok(config) => config,
+        Err(error) => {
+            eprintln!("error: {}", error);
+            return ExitCode::from(EX_CONFIG);
+        }
+    };
+
+    match lsp::run(config) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(error) => {
+            eprintln!("error: {}", error);
+            ExitCode::from(EX_IOERR)
+        }
+    }
+}
+
+// Writes the bytecode of `file` next to it, or to `output`.
+fn compile(file: &str, output: Option<String>) -> ExitCode {
+    let output = match output {
+        Some(output) => output,
+        None if file == "-" => {
+            eprintln!("error: --output is required when compiling stdin");
+            return ExitCode::from(EX_USAGE);
+        }
+        None => Path::new(file)
+            .with_extension("loxc")
+            .to_string_lossy()
+            .into_owned(),
+    };
+
+    let source = match read_script(file) {
+        Ok(source) => source,
+        Err(error) => {
+            eprintln!("error: {}", error);
+            return ExitCode::from(EX_NOINPUT);
+        }
+    };
+    let Some(statements) = parse_program(&source) else {
+        return ExitCode::from(EX_DATAERR);
+    };
+    let function = match compiler::compile(&statements) {
+        Ok(function) => function,
+        Err(error) => {
+            eprintln!("{}", error);
+            return ExitCode::from(EX_DATAERR);
+        }
+    };
+
+    if let Err(error) = std::fs::write(&output, loxc::write(&function)) {
+        eprintln!("error: cannot write '{}': {}", output, error);
+        return ExitCode::from(EX_IOERR);
+    }
+    ExitCode::SUCCESS
+}
+
+// Formats every file in place, or lists the ones that would change with
+// `check`. Files with syntax errors are left alone.
+fn fmt(files: &[String], check: bool) -> ExitCode {
+    let mut status = ExitCode::SUCCESS;
+    for file in files {
+        let source = match read_script(file) {
+            Ok(source) => source,
+            Err(error) => {
+                eprintln!("error: {}", error);
+                status = ExitCode::from(EX_NOINPUT);
+                continue;
+            }
+        };
+        let formatted = match format::format(&source) {
+            Ok(formatted) => formatted,
+            Err(errors) => {
+                for error in errors {
+                    eprintln!("{}: {}", file, error);
+                }
+                status = ExitCode::from(EX_DATAERR);
+                continue;
+            }
+        };
+
+        if check {
+            if formatted != source {
+                println!("{}", file);
+                status = ExitCode::FAILURE;
+            }
+        } else if file == "-" {
+            print!("{}", formatted);
+        } else if formatted != source {
+            if let Err(error) = std::fs::write(file, formatted) {
+                eprintln!("error: cannot write '{}': {}", file, error);
+                status = ExitCode::from(EX_IOERR);
+            }
+        }
+    }
+
+    status
+}
+
+// Scans, parses and resolves `input`, printing the errors if there are any.
+fn parse_program(input: &str) -> Option<Vec<stmt::Stmt>> {
+    let mut scanner = Scanner::new(input);
     let tokens = scanner.scan_tokens();
-    let mut p = parser::Parser::new(tokens.clone());
+    if !scanner.errors().is_empty() {
+        for error in scanner.errors() {
+            eprintln!("{}", error);
+        }
+        return None;
+    }
 
-    let ast = p.parse();
-    let evaluator = expr::Evaluation::new(ast);
-    evaluator.interpret();
+    let mut p = parser::Parser::new(tokens);
+
+    let statements = p.parse();
+    if !p.errors().is_empty() {
+        for error in p.errors() {
+            eprintln!("{}", error);
+        }
+        return None;
+    }
+
+    let mut resolver = resolver::Resolver::new();
+    resolver.resolve(&statements);
+    if !resolver.errors().is_empty() {
+        for error in resolver.errors() {
+            eprintln!("{}", error);
+        }
+        return None;
+    }
+
+    Some(statements)
+}
+
+// `path` is where `input` was read from, or `-` for stdin and `--eval`.
+fn run(path: &str, input: &str, options: &RunOptions, script_args: Vec<String>) -> ExitCode {
+    let Some(statements) = parse_program(input) else {
+        return ExitCode::from(EX_DATAERR);
+    };
+
+    if options.pretty {
+        expr::Prettify.pretty_print(&statements);
+        return ExitCode::SUCCESS;
+    }
+    if options.disassemble {
+        return match compiler::compile(&statements) {
+            Ok(function) => {
+                print!("{}", disassembler::disassemble(&function));
+                ExitCode::SUCCESS
+            }
+            Err(error) => {
+                eprintln!("{}", error);
+                ExitCode::from(EX_DATAERR)
+            }
+        };
+    }
+
+    if options.coverage.is_some() {
+        return cover(path, input, &statements, options, script_args);
+    }
+    if options.instrumented() {
+        return profile(path, input, &statements, options, script_args);
+    }
+
+    // compiled first, since the program isn't run at all if it can't be
+    if options.backend == Backend::Vm || options.trace_exec {
+        let function = match compiler::compile(&statements) {
+            Ok(function) => function,
+            Err(error) => {
+                eprintln!("{}", error);
+                return ExitCode::from(EX_DATAERR);
+            }
+        };
+        let mut vm = vm::Vm::with_args(script_args);
+        vm.heap().configure(options.gc);
+        if options.trace_exec {
+            vm.set_trace(Box::new(io::stderr()));
+        }
+        vm.set_max_depth(options.max_depth);
+        vm.set_modules(modules(path, options));
+        if let Err(error) = vm.execute(function) {
+            eprintln!("{}", error);
+            return ExitCode::from(EX_SOFTWARE);
+        }
+        return ExitCode::SUCCESS;
+    }
+
+    let mut interpreter = Backend::Tree.interpreter(script_args, options.gc);
+    interpreter.set_max_depth(options.max_depth);
+    interpreter.set_modules(modules(path, options));
+    if let Err(error) = interpreter.interpret(&statements) {
+        eprintln!("{}", error);
+        return ExitCode::from(EX_SOFTWARE);
+    }
+
+    ExitCode::SUCCESS
+}
+
+// Imports are found from the directory of the script at `path`, or the
+// current one for stdin and `--eval`.
+fn modules(path: &str, options: &RunOptions) -> module::Modules {
+    let script = (path != "-").then(|| Path::new(path));
+    module::Modules::new(script, options.search_path.clone())
+}
+
+fn debug(path: &str, script_args: Vec<String>) -> ExitCode {
+    let source = match read_script(path) {
+        Ok(source) => source,
+        Err(error) => {
+            eprintln!("error: {}", error);
+            return ExitCode::from(EX_NOINPUT);
+        }
+    };
+    let Some(statements) = parse_program(&source) else {
+        return ExitCode::from(EX_DATAERR);
+    };
+
+    debug::run(Path::new(path), &source, &statements, script_args);
+    ExitCode::SUCCESS
+}
+
+// Runs the program on the tree-walking interpreter, which tells the
+// profiler about it, then reports what ran even if it stopped with an error.
+fn profile(
+    path: &str,
+    source: &str,
+    statements: &[stmt::Stmt],
+    options: &RunOptions,
+    script_args: Vec<String>,
+) -> ExitCode {
+    let profile = Rc::new(RefCell::new(profile::Profile::new()));
+    let mut evaluation = expr::Evaluation::with_args(script_args);
+    evaluation.heap().configure(options.gc);
+    evaluation.set_max_depth(options.max_depth);
+    evaluation.set_modules(modules(path, options));
+    evaluation.set_hook(Box::new(profile::Profiler(Rc::clone(&profile))));
+
+    profile.borrow_mut().start();
+    let result = evaluation.interpret(statements);
+    profile.borrow_mut().finish();
+
+    let mut code = ExitCode::SUCCESS;
+    if let Err(error) = result {
+        eprintln!("{}", error);
+        code = ExitCode::from(EX_SOFTWARE);
+    }
+    let profile = profile.borrow();
+    if options.profile {
+        eprint!("{}", profile.report(source));
+    }
+    if let Some(path) = &options.flamegraph {
+        if let Err(error) = std::fs::write(path, profile.folded()) {
+            eprintln!("error: cannot write '{}': {}", path, error);
+            return ExitCode::from(EX_IOERR);
+        }
+    }
+    code
+}
+
+// Runs the program on the tree-walking interpreter, recording which lines
+// and branches of it ran, even if it stopped with an error.
+fn cover(
+    path: &str,
+    source: &str,
+    statements: &[stmt::Stmt],
+    options: &RunOptions,
+    script_args: Vec<String>,
+) -> ExitCode {
+    let coverage = Rc::new(RefCell::new(coverage::Coverage::new(source, statements)));
+    let mut evaluation = expr::Evaluation::with_args(script_args);
+    evaluation.heap().configure(options.gc);
+    evaluation.set_max_depth(options.max_depth);
+    evaluation.set_modules(modules(path, options));
+    evaluation.set_hook(Box::new(coverage::Collector(Rc::clone(&coverage))));
+
+    let mut code = ExitCode::SUCCESS;
+    if let Err(error) = evaluation.interpret(statements) {
+        eprintln!("{}", error);
+        code = ExitCode::from(EX_SOFTWARE);
+    }
+
+    let coverage = coverage.borrow();
+    let report = match options.coverage_format {
+        coverage::Format::Lcov => coverage.lcov(path),
+        coverage::Format::Cobertura => {
+            let timestamp = std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)
+                .map_or(0, |elapsed| elapsed.as_secs());
+            coverage.cobertura(path, timestamp)
+        }
+    };
+    let output = options.coverage.as_deref().unwrap();
+    if let Err(error) = std::fs::write(output, report) {
+        eprintln!("error: cannot write '{}': {}", output, error);
+        return ExitCode::from(EX_IOERR);
+    }
+    code
+}
+
+// Runs a file written by `compile`, always on the VM.
+fn run_compiled(path: &str, options: &RunOptions, script_args: Vec<String>) -> ExitCode {
+    if options.pretty {
+        eprintln!("error: --pretty needs the source of the script");
+        return ExitCode::from(EX_USAGE);
+    }
+    if options.instrumented() {
+        eprintln!("error: --profile, --flamegraph and --coverage need the source of the script");
+        return ExitCode::from(EX_USAGE);
+    }
+
+    let bytes = match std::fs::read(path) {
+        Ok(bytes) => bytes,
+        Err(error) => {
+            eprintln!("error: cannot read '{}': {}", path, error);
+            return ExitCode::from(EX_NOINPUT);
+        }
+    };
+    let function = match loxc::read(&bytes) {
+        Ok(function) => function,
+        Err(error) => {
+            eprintln!("error: cannot load '{}': {}", path, error);
+            return ExitCode::from(EX_DATAERR);
+        }
+    };
+
+    if options.disassemble {
+        print!("{}", disassembler::disassemble(&function));
+        return ExitCode::SUCCESS;
+    }
+
+    let mut vm = vm::Vm::with_args(script_args);
+    vm.heap().configure(options.gc);
+    if options.trace_exec {
+        vm.set_trace(Box::new(io::stderr()));
+    }
+    vm.set_max_depth(options.max_depth);
+    vm.set_modules(modules(path, options));
+    if let Err(error) = vm.execute(function) {
+        eprintln!("{}", error);
+        return ExitCode::from(EX_SOFTWARE);
+    }
+
+    ExitCode::SUCCESS
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    fn exit_code(args: &[&str]) -> ExitCode {
+        interpret(std::iter::once("interpret").chain(args.iter().copied()))
+    }
+
+    #[test]
+    fn test_exit_codes() {
+        let locals = (0..256)
+            .map(|i| format!("var v{} = {};", i, i))
+            .collect::<String>();
+        let too_many_locals = format!("{{ {} }}", locals);
+        for backend in ["tree", "vm"] {
+            let run = |source: &str| exit_code(&["--backend", backend, "-e", source]);
+            assert_eq!(run("var a = 1;"), ExitCode::SUCCESS);
+            assert_eq!(run("var = 1;"), ExitCode::from(EX_DATAERR));
+            assert_eq!(run("nil + 1;"), ExitCode::from(EX_SOFTWARE));
+        }
+        // only the compiler limits the locals of a function
+        assert_eq!(
+            exit_code(&["--backend", "vm", "-e", &too_many_locals]),
+            ExitCode::from(EX_DATAERR)
+        );
+        assert_eq!(exit_code(&["--backend", "lisp"]), ExitCode::from(EX_USAGE));
+        assert_eq!(
+            exit_code(&["--max-depth", "15001"]),
+            ExitCode::from(EX_USAGE)
+        );
+    }
+
+    #[test]
+    fn test_pretty_prints_instead_of_running() {
+        // a runtime error, had it run
+        assert_eq!(
+            exit_code(&["--pretty", "-e", "print nil + 1;"]),
+            ExitCode::SUCCESS
+        );
+        // reported like any parse error, rather than as an unexpected token
+        assert_eq!(
+            exit_code(&["--pretty", "-e", "= 1;"]),
+            ExitCode::from(EX_DATAERR)
+        );
+        assert_eq!(
+            exit_code(&["--pretty", "--disassemble", "-e", "print 1;"]),
+            ExitCode::from(EX_USAGE)
+        );
+    }
+
     #[test]
     fn test_scanner_simple() {
         let input = "123 + 456";
-        let mut scanner = Scanner::new(input.to_string());
+        let mut scanner = Scanner::new(input);
         let tokens = scanner.scan_tokens();
 
         assert_eq!(tokens.len(), 4);
@@ -0,0 +1,245 @@
+use std::{
+    collections::{HashMap, HashSet},
+    env,
+    path::{Path, PathBuf},
+    rc::Rc,
+};
+
+use crate::{check, stmt::Stmt, symbol::Symbol, value::Module};
+
+// Read for the directories searched for modules after those given with
+// `--module-path`, separated like those of `PATH`.
+const PATH_VARIABLE: &str = "LOX_PATH";
+
+// Where an interpreter finds the modules a program imports, and those it
+// has already run, each of which is only run once.
+pub struct Modules {
+    // searched after the directory of the file importing the module
+    search_path: Vec<PathBuf>,
+    // by canonical path
+    cache: HashMap<PathBuf, Rc<Module>>,
+    // the files being run, the script first, by canonical path and as found
+    loading: Vec<(PathBuf, PathBuf)>,
+}
+
+impl Modules {
+    // `script` is the file of the program, if it has one. Otherwise modules
+    // are found from the current directory.
+    pub fn new(script: Option<&Path>, search_path: Vec<PathBuf>) -> Self {
+        let loading = script
+            .and_then(|script| Some((script.canonicalize().ok()?, script.to_path_buf())))
+            .into_iter()
+            .collect();
+        Modules {
+            search_path,
+            cache: HashMap::new(),
+            loading,
+        }
+    }
+
+    // The canonical path of the module `path`, and the path it was found at,
+    // looking in the directory of the file importing it first.
+    pub fn find(&self, path: &str) -> Option<(PathBuf, PathBuf)> {
+        let importing = self
+            .loading
+            .last()
+            .and_then(|(_, found)| found.parent())
+            .unwrap_or(Path::new(""));
+        std::iter::once(importing)
+            .chain(self.search_path.iter().map(PathBuf::as_path))
+            .map(|dir| dir.join(path))
+            .find_map(|found| Some((found.canonicalize().ok()?, found)))
+    }
+
+    pub fn cached(&self, file: &Path) -> Option<Rc<Module>> {
+        self.cache.get(file).cloned()
+    }
+
+    // Starts running the module `file`, unless that would import it again
+    // while it runs.
+    pub fn enter(&mut self, file: PathBuf, found: PathBuf) -> Result<(), String> {
+        if let Some(start) = self
+            .loading
+            .iter()
+            .position(|(loading, _)| *loading == file)
+        {
+            let cycle = self.loading[start..]
+                .iter()
+                .map(|(_, found)| found)
+                .chain([&found])
+                .map(|found| found.display().to_string())
+                .collect::<Vec<_>>();
+            return Err(format!("Import cycle: {}.", cycle.join(" -> ")));
+        }
+        self.loading.push((file, found));
+        Ok(())
+    }
+
+    // Done running the module last entered, which is kept if it ran.
+    pub fn leave(&mut self, module: Option<&Rc<Module>>) {
+        if let Some((file, _)) = self.loading.pop() {
+            if let Some(module) = module {
+                self.cache.insert(file, Rc::clone(module));
+            }
+        }
+    }
+}
+
+// The directories of `--module-path`, then those of `LOX_PATH`.
+pub fn search_path(dirs: &[String]) -> Vec<PathBuf> {
+    let mut search_path: Vec<PathBuf> = dirs.iter().map(PathBuf::from).collect();
+    if let Some(path) = env::var_os(PATH_VARIABLE) {
+        search_path.extend(env::split_paths(&path).filter(|dir| !dir.as_os_str().is_empty()));
+    }
+    search_path
+}
+
+// Reads, parses and resolves the module found at `found`, with all of its
+// errors if it has any.
+pub fn load(found: &Path) -> Result<Vec<Stmt>, String> {
+    let name = found.display().to_string();
+    let source = std::fs::read_to_string(found)
+        .map_err(|e| format!("Cannot read module '{}': {}.", name, e))?;
+    let (statements, diagnostics) = check::parse(&name, &source);
+    if !diagnostics.is_empty() {
+        let errors = diagnostics
+            .iter()
+            .map(ToString::to_string)
+            .collect::<Vec<_>>();
+        return Err(format!("Cannot import '{}':\n{}", name, errors.join("\n")));
+    }
+    Ok(statements)
+}
+
+// The variables a module declares at its top level, which are those other
+// files can import from it.
+pub fn exports(statements: &[Stmt]) -> HashSet<Symbol> {
+    let mut exports = HashSet::new();
+    for stmt in statements {
+        match stmt {
+            Stmt::Var { name, .. } => {
+                exports.insert(name.lexeme.clone());
+            }
+            Stmt::Function(declaration) => {
+                exports.insert(declaration.name.lexeme.clone());
+            }
+            Stmt::Import { alias, names, .. } => {
+                exports.extend(alias.iter().chain(names).map(|name| name.lexeme.clone()));
+            }
+            _ => {}
+        }
+    }
+    exports
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        backend::{each_backend, Interpreter},
+        parser::Parser,
+        resolver::Resolver,
+        scanner::Scanner,
+        value::Value,
+    };
+
+    #[test]
+    fn test_imports() {
+        let dir = env::temp_dir().join(format!("lox-modules-{}", std::process::id()));
+        let lib = dir.join("lib");
+        std::fs::create_dir_all(&lib).unwrap();
+        let write = |path: &Path, source: &str| std::fs::write(path, source).unwrap();
+        write(
+            &dir.join("math.lox"),
+            "var runs = 0;\nfun square(x) { return x * x; }\nfun total() { return runs; }\nruns = runs + 1;",
+        );
+        write(
+            &lib.join("twice.lox"),
+            "from \"../math.lox\" import square;\nvar four = square(2);",
+        );
+        write(&lib.join("a.lox"), "import \"b.lox\" as b;");
+        write(&lib.join("b.lox"), "import \"a.lox\" as a;");
+        write(&lib.join("broken.lox"), "var = 1;");
+        write(
+            &lib.join("failing.lox"),
+            "fun f() {\n  return nil + 1;\n}\nf();",
+        );
+        write(&dir.join("main.lox"), "");
+
+        // each program with the modules of a fresh run
+        let run = |interpreter: &mut dyn Interpreter, source: &str| {
+            let statements = Parser::new(Scanner::new(source).scan_tokens()).parse();
+            Resolver::new().resolve(&statements);
+            interpreter.set_modules(Modules::new(Some(&dir.join("main.lox")), vec![lib.clone()]));
+            interpreter.interpret(&statements)
+        };
+
+        each_backend(&[], |interpreter| {
+            // run once, however often imported, and a module of their own
+            run(
+                interpreter,
+                "import \"math.lox\" as m;\nfrom \"twice.lox\" import four;\n\
+                 from \"math.lox\" import total;\nvar runs = m.total() + total();\n\
+                 { import \"math.lox\" as local; runs = runs + 10 * local.runs; }",
+            )
+            .unwrap();
+            let globals = interpreter.globals();
+            let global = |name: &str| globals.iter().find(|(n, _)| n == name).unwrap().1.clone();
+            assert!(matches!(global("runs"), Value::Number(n) if n == 12.0));
+            assert!(matches!(global("four"), Value::Number(n) if n == 4.0));
+            assert_eq!(
+                global("m").to_string(),
+                format!("<module {}>", dir.join("math.lox").display())
+            );
+            let tokens = Scanner::new("m.runs").scan_tokens();
+            let expr = Parser::new(tokens).parse_single_expression().unwrap();
+            assert!(matches!(interpreter.evaluate(&expr).unwrap(), Value::Number(n) if n == 1.0));
+
+            let mut error = |source: &str| run(&mut *interpreter, source).unwrap_err();
+            assert_eq!(
+                error("import \"failing.lox\" as m;").to_string(),
+                format!(
+                    "error at line 2: Operands must be two numbers or two strings.\n\
+                     [line 2] in f()\n[line 4] in {}/failing.lox\n[line 1] in script",
+                    lib.display()
+                )
+            );
+            let mut error = |source: &str| run(&mut *interpreter, source).unwrap_err().message;
+            assert_eq!(
+                error("import \"a.lox\" as a;"),
+                format!(
+                    "Import cycle: {0}/a.lox -> {0}/b.lox -> {0}/a.lox.",
+                    lib.display()
+                )
+            );
+            assert_eq!(
+                error("import \"main.lox\" as main;"),
+                format!("Import cycle: {0}/main.lox -> {0}/main.lox.", dir.display())
+            );
+            assert_eq!(
+                error("from \"math.lox\" import cube;"),
+                format!(
+                    "Module '{}' has no variable 'cube'.",
+                    dir.join("math.lox").display()
+                )
+            );
+            assert_eq!(
+                error("import \"missing.lox\" as m;"),
+                "Cannot find module 'missing.lox'."
+            );
+            assert_eq!(
+                error("import \"broken.lox\" as m;"),
+                format!(
+                    "Cannot import '{0}/broken.lox':\n{0}/broken.lox:1:5: error: at '=': Expect variable name.",
+                    lib.display()
+                )
+            );
+            assert_eq!(
+                error("var x = 1;\nx.y;"),
+                "Only modules and errors have properties."
+            );
+        });
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
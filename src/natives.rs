@@ -0,0 +1,69 @@
+use std::{
+    cell::RefCell,
+    rc::Rc,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use crate::{
+    environment::Environment,
+    symbol::Symbol,
+    value::{NativeFunction, Value},
+};
+
+// Defines the native functions in `globals`. `script_args` are the
+// command-line arguments following the script path, returned by `args()`.
+pub fn define(globals: &mut Environment, script_args: Vec<String>) {
+    let natives = [
+        native("clock", 0, |_| {
+            let elapsed = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_err(|e| e.to_string())?;
+            Ok(Value::Number(elapsed.as_secs_f64()))
+        }),
+        native("args", 0, move |_| {
+            let args = script_args
+                .iter()
+                .map(|arg| Value::String(arg.as_str().into()))
+                .collect();
+            Ok(Value::List(Rc::new(RefCell::new(args))))
+        }),
+        native("len", 1, |args| match &args[0] {
+            Value::String(s) => Ok(Value::Number(s.chars().count() as f64)),
+            Value::List(list) => Ok(Value::Number(list.borrow().len() as f64)),
+            value => Err(format!("Can't take the length of a {}.", value.type_name())),
+        }),
+        native("get", 2, |args| match (&args[0], &args[1]) {
+            (Value::List(list), Value::Number(index)) => {
+                let list = list.borrow();
+                if index.fract() != 0.0 || *index < 0.0 || *index >= list.len() as f64 {
+                    return Err(format!(
+                        "Index {} out of range for list of length {}.",
+                        index,
+                        list.len()
+                    ));
+                }
+                Ok(list[*index as usize].clone())
+            }
+            _ => Err("Arguments must be a list and a number.".to_string()),
+        }),
+    ];
+
+    for function in natives {
+        globals.define(
+            Symbol::intern(function.name),
+            Value::Native(Rc::new(function)),
+        );
+    }
+}
+
+fn native(
+    name: &'static str,
+    arity: usize,
+    function: impl Fn(&[Value]) -> Result<Value, String> + 'static,
+) -> NativeFunction {
+    NativeFunction {
+        name,
+        arity,
+        function: Box::new(function),
+    }
+}
@@ -1,28 +1,458 @@
+use std::{cell::Cell, rc::Rc};
+
 use crate::{
     expr::Expr,
-    token::{Token, TokenType},
+    stmt::{Catch, FunctionDecl, Stmt},
+    token::{Token, TokenType, Trivia},
 };
 
+const MAX_ARGUMENTS: usize = 255;
+
+#[derive(Debug, Clone)]
+pub struct ParseError {
+    pub line: usize,
+    pub column: usize,
+    // where the error was found, e.g., `'='` or `end`
+    pub location: String,
+    pub message: String,
+}
+
+impl ParseError {
+    fn new(token: &Token, message: &str) -> Self {
+        let location = match token.ttype {
+            TokenType::EOF => "end".to_string(),
+            _ => format!("'{}'", token.lexeme),
+        };
+
+        ParseError {
+            line: token.line,
+            column: token.column,
+            location,
+            message: message.to_string(),
+        }
+    }
+}
+
+impl std::fmt::Display for ParseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "error at line {} at {}: {}",
+            self.line, self.location, self.message
+        )
+    }
+}
+
 pub struct Parser {
     tokens: Vec<Token>,
+    errors: Vec<ParseError>,
     cursor: usize,
 }
 
 impl Parser {
     pub fn new(tokens: Vec<Token>) -> Self {
-        Parser { tokens, cursor: 0 }
+        Parser {
+            tokens,
+            errors: Vec::new(),
+            cursor: 0,
+        }
+    }
+
+    pub fn parse(&mut self) -> Vec<Stmt> {
+        let mut statements = Vec::new();
+        while !self.finished() {
+            self.parse_next(&mut statements);
+        }
+        self.finish(&mut statements);
+
+        statements
+    }
+
+    // Parses the next top-level declaration along with the comments before
+    // it, or skips past it after an error. The result depends only on the
+    // tokens up to and including the one at the new `position`, which is
+    // what lets a document be reparsed a declaration at a time.
+    pub fn parse_next(&mut self, statements: &mut Vec<Stmt>) {
+        if let Err(error) = self.push_declaration(statements) {
+            self.errors.push(error);
+            self.synchronize();
+        }
+    }
+
+    // Pushes the comments after the last declaration, unless an unfinished
+    // one took them.
+    pub fn finish(&mut self, statements: &mut Vec<Stmt>) {
+        self.push_trivia(statements);
+    }
+
+    // The index of the next token to parse.
+    pub fn position(&self) -> usize {
+        self.cursor
     }
 
-    pub fn parse(&mut self) -> Expr {
-        self.parse_expression()
+    // Parses the whole input as a single expression, e.g., `1 + 2` typed into the REPL.
+    pub fn parse_single_expression(&mut self) -> Result<Expr, ParseError> {
+        let expr = self.parse_expression()?;
+        if !self.finished() {
+            return Err(self.error("Expect end of expression."));
+        }
+
+        Ok(expr)
+    }
+
+    pub fn errors(&self) -> &[ParseError] {
+        &self.errors
+    }
+
+    // Pushes the next declaration along with the comments around it, so the
+    // formatter can keep them. Comments inside the declaration are moved
+    // after it.
+    fn push_declaration(&mut self, statements: &mut Vec<Stmt>) -> Result<(), ParseError> {
+        self.push_trivia(statements);
+        let start = self.cursor;
+        statements.push(self.parse_declaration()?);
+
+        for token in &mut self.tokens[start + 1..self.cursor] {
+            for trivia in std::mem::take(&mut token.trivia) {
+                if trivia != Trivia::BlankLine {
+                    statements.push(Stmt::Trivia(trivia));
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    // Pushes the comments and blank lines before the current token.
+    fn push_trivia(&mut self, statements: &mut Vec<Stmt>) {
+        let trivia = std::mem::take(&mut self.tokens[self.cursor].trivia);
+        statements.extend(trivia.into_iter().map(Stmt::Trivia));
+    }
+
+    fn parse_declaration(&mut self) -> Result<Stmt, ParseError> {
+        match self.current().ttype {
+            TokenType::Var => self.parse_var_declaration(),
+            TokenType::Fun => self.parse_function_declaration(),
+            TokenType::Import | TokenType::From => self.parse_import_declaration(),
+            _ => self.parse_statement(),
+        }
     }
 
-    fn parse_expression(&mut self) -> Expr {
-        self.parse_equality()
+    fn parse_function_declaration(&mut self) -> Result<Stmt, ParseError> {
+        let doc = self.current().doc.clone();
+        self.advance();
+
+        let name = self.consume(TokenType::Identifier, "Expect function name.")?;
+        self.consume(TokenType::LeftParen, "Expect '(' after function name.")?;
+        let mut params = Vec::new();
+        if self.current().ttype != TokenType::RightParen {
+            loop {
+                if params.len() >= MAX_ARGUMENTS {
+                    let error = self.error("Can't have more than 255 parameters.");
+                    self.errors.push(error);
+                }
+                params.push(self.consume(TokenType::Identifier, "Expect parameter name.")?);
+                if !self.matches(TokenType::Comma) {
+                    break;
+                }
+            }
+        }
+        self.consume(TokenType::RightParen, "Expect ')' after parameters.")?;
+
+        self.consume(TokenType::LeftBrace, "Expect '{' before function body.")?;
+        let body = self.parse_block()?;
+
+        Ok(Stmt::Function(Rc::new(FunctionDecl {
+            name,
+            params,
+            body,
+            doc,
+        })))
     }
 
-    fn parse_equality(&mut self) -> Expr {
-        let mut expr = self.parse_comparison();
+    fn parse_var_declaration(&mut self) -> Result<Stmt, ParseError> {
+        let doc = self.current().doc.clone();
+        self.advance();
+
+        let name = self.consume(TokenType::Identifier, "Expect variable name.")?;
+        let initializer = if self.matches(TokenType::Equal) {
+            Some(self.parse_expression()?)
+        } else {
+            None
+        };
+        self.consume(
+            TokenType::Semicolon,
+            "Expect ';' after variable declaration.",
+        )?;
+
+        Ok(Stmt::Var {
+            name,
+            initializer,
+            doc,
+        })
+    }
+
+    // `import "path" as alias;` or `from "path" import a, b;`
+    fn parse_import_declaration(&mut self) -> Result<Stmt, ParseError> {
+        let keyword = self.current().clone();
+        self.advance();
+
+        let path = self.consume(TokenType::String, "Expect module path.")?;
+        let mut alias = None;
+        let mut names = Vec::new();
+        if keyword.ttype == TokenType::Import {
+            self.consume(TokenType::As, "Expect 'as' after module path.")?;
+            alias = Some(self.consume(TokenType::Identifier, "Expect module name.")?);
+        } else {
+            self.consume(TokenType::Import, "Expect 'import' after module path.")?;
+            loop {
+                names.push(self.consume(TokenType::Identifier, "Expect variable name.")?);
+                if !self.matches(TokenType::Comma) {
+                    break;
+                }
+            }
+        }
+        self.consume(TokenType::Semicolon, "Expect ';' after import.")?;
+
+        Ok(Stmt::Import {
+            keyword,
+            path,
+            alias,
+            names,
+        })
+    }
+
+    fn parse_statement(&mut self) -> Result<Stmt, ParseError> {
+        match self.current().ttype {
+            TokenType::Print => {
+                self.advance();
+                let expr = self.parse_expression()?;
+                self.consume(TokenType::Semicolon, "Expect ';' after value.")?;
+
+                Ok(Stmt::Print { expr })
+            }
+            TokenType::LeftBrace => {
+                self.advance();
+                Ok(Stmt::Block {
+                    statements: self.parse_block()?,
+                })
+            }
+            TokenType::If => self.parse_if_statement(),
+            TokenType::While => self.parse_while_statement(),
+            TokenType::For => self.parse_for_statement(),
+            TokenType::Try => self.parse_try_statement(),
+            TokenType::Throw => {
+                let keyword = self.current().clone();
+                self.advance();
+                let value = self.parse_expression()?;
+                self.consume(TokenType::Semicolon, "Expect ';' after thrown value.")?;
+
+                Ok(Stmt::Throw { keyword, value })
+            }
+            TokenType::Return => {
+                let keyword = self.current().clone();
+                self.advance();
+                let value = if self.current().ttype != TokenType::Semicolon {
+                    Some(self.parse_expression()?)
+                } else {
+                    None
+                };
+                self.consume(TokenType::Semicolon, "Expect ';' after return value.")?;
+
+                Ok(Stmt::Return { keyword, value })
+            }
+            _ => {
+                let expr = self.parse_expression()?;
+                self.consume(TokenType::Semicolon, "Expect ';' after expression.")?;
+
+                Ok(Stmt::Expression { expr })
+            }
+        }
+    }
+
+    // Expects the opening brace to be consumed already.
+    fn parse_block(&mut self) -> Result<Vec<Stmt>, ParseError> {
+        let mut statements = Vec::new();
+        while self.current().ttype != TokenType::RightBrace && !self.finished() {
+            self.push_declaration(&mut statements)?;
+        }
+        self.push_trivia(&mut statements);
+        self.consume(TokenType::RightBrace, "Expect '}' after block.")?;
+
+        Ok(statements)
+    }
+
+    fn parse_if_statement(&mut self) -> Result<Stmt, ParseError> {
+        let keyword = self.current().clone();
+        self.advance();
+        self.consume(TokenType::LeftParen, "Expect '(' after 'if'.")?;
+        let condition = self.parse_expression()?;
+        self.consume(TokenType::RightParen, "Expect ')' after if condition.")?;
+
+        let then_branch = Box::new(self.parse_statement()?);
+        let else_branch = if self.matches(TokenType::Else) {
+            Some(Box::new(self.parse_statement()?))
+        } else {
+            None
+        };
+
+        Ok(Stmt::If {
+            keyword,
+            condition,
+            then_branch,
+            else_branch,
+        })
+    }
+
+    fn parse_try_statement(&mut self) -> Result<Stmt, ParseError> {
+        let keyword = self.current().clone();
+        self.advance();
+        self.consume(TokenType::LeftBrace, "Expect '{' after 'try'.")?;
+        let body = self.parse_block()?;
+
+        let catch = if self.matches(TokenType::Catch) {
+            self.consume(TokenType::LeftParen, "Expect '(' after 'catch'.")?;
+            let name = self.consume(TokenType::Identifier, "Expect error variable name.")?;
+            self.consume(TokenType::RightParen, "Expect ')' after error variable.")?;
+            self.consume(TokenType::LeftBrace, "Expect '{' before catch body.")?;
+            Some(Catch {
+                name,
+                body: self.parse_block()?,
+            })
+        } else {
+            None
+        };
+        let finally = if self.matches(TokenType::Finally) {
+            self.consume(TokenType::LeftBrace, "Expect '{' after 'finally'.")?;
+            Some(self.parse_block()?)
+        } else {
+            None
+        };
+        if catch.is_none() && finally.is_none() {
+            return Err(self.error("Expect 'catch' or 'finally' after try block."));
+        }
+
+        Ok(Stmt::Try {
+            keyword,
+            body,
+            catch,
+            finally,
+        })
+    }
+
+    fn parse_while_statement(&mut self) -> Result<Stmt, ParseError> {
+        self.advance();
+        self.consume(TokenType::LeftParen, "Expect '(' after 'while'.")?;
+        let condition = self.parse_expression()?;
+        self.consume(TokenType::RightParen, "Expect ')' after condition.")?;
+        let body = Box::new(self.parse_statement()?);
+
+        Ok(Stmt::While { condition, body })
+    }
+
+    fn parse_for_statement(&mut self) -> Result<Stmt, ParseError> {
+        self.advance();
+        self.consume(TokenType::LeftParen, "Expect '(' after 'for'.")?;
+
+        let initializer = match self.current().ttype {
+            TokenType::Semicolon => {
+                self.advance();
+                None
+            }
+            TokenType::Var => Some(Box::new(self.parse_var_declaration()?)),
+            _ => {
+                let expr = self.parse_expression()?;
+                self.consume(TokenType::Semicolon, "Expect ';' after expression.")?;
+                Some(Box::new(Stmt::Expression { expr }))
+            }
+        };
+
+        let condition = if self.current().ttype != TokenType::Semicolon {
+            Some(self.parse_expression()?)
+        } else {
+            None
+        };
+        self.consume(TokenType::Semicolon, "Expect ';' after loop condition.")?;
+
+        let increment = if self.current().ttype != TokenType::RightParen {
+            Some(self.parse_expression()?)
+        } else {
+            None
+        };
+        self.consume(TokenType::RightParen, "Expect ')' after for clauses.")?;
+        let body = Box::new(self.parse_statement()?);
+
+        Ok(Stmt::For {
+            initializer,
+            condition,
+            increment,
+            body,
+        })
+    }
+
+    fn parse_expression(&mut self) -> Result<Expr, ParseError> {
+        self.parse_assignment()
+    }
+
+    fn parse_assignment(&mut self) -> Result<Expr, ParseError> {
+        let expr = self.parse_or()?;
+
+        if self.current().ttype == TokenType::Equal {
+            let equals = self.current().clone();
+            self.advance();
+            let value = self.parse_assignment()?;
+
+            return match expr {
+                Expr::Variable { name, .. } => Ok(Expr::Assign {
+                    name,
+                    depth: Cell::new(None),
+                    value: Box::new(value),
+                }),
+                _ => Err(ParseError::new(&equals, "Invalid assignment target.")),
+            };
+        }
+
+        Ok(expr)
+    }
+
+    fn parse_or(&mut self) -> Result<Expr, ParseError> {
+        let mut expr = self.parse_and()?;
+
+        while self.current().ttype == TokenType::Or {
+            let operator = self.current().clone();
+            self.advance();
+
+            let right = self.parse_and()?;
+            expr = Expr::Logical {
+                left: Box::new(expr),
+                op: operator,
+                right: Box::new(right),
+            }
+        }
+
+        Ok(expr)
+    }
+
+    fn parse_and(&mut self) -> Result<Expr, ParseError> {
+        let mut expr = self.parse_equality()?;
+
+        while self.current().ttype == TokenType::And {
+            let operator = self.current().clone();
+            self.advance();
+
+            let right = self.parse_equality()?;
+            expr = Expr::Logical {
+                left: Box::new(expr),
+                op: operator,
+                right: Box::new(right),
+            }
+        }
+
+        Ok(expr)
+    }
+
+    fn parse_equality(&mut self) -> Result<Expr, ParseError> {
+        let mut expr = self.parse_comparison()?;
         while matches!(
             self.current().ttype,
             TokenType::EqualEqual | TokenType::BangEqual
@@ -30,7 +460,7 @@ impl Parser {
             let operator = self.current().clone();
             self.advance();
 
-            let right = self.parse_comparison();
+            let right = self.parse_comparison()?;
             expr = Expr::Binary {
                 left: Box::new(expr),
                 op: operator,
@@ -38,11 +468,11 @@ impl Parser {
             }
         }
 
-        expr
+        Ok(expr)
     }
 
-    fn parse_comparison(&mut self) -> Expr {
-        let mut expr = self.parse_term();
+    fn parse_comparison(&mut self) -> Result<Expr, ParseError> {
+        let mut expr = self.parse_term()?;
 
         while matches!(
             self.current().ttype,
@@ -51,7 +481,7 @@ impl Parser {
             let operator = self.current().clone();
             self.advance();
 
-            let right = self.parse_term();
+            let right = self.parse_term()?;
             expr = Expr::Binary {
                 left: Box::new(expr),
                 op: operator,
@@ -59,17 +489,17 @@ impl Parser {
             }
         }
 
-        expr
+        Ok(expr)
     }
 
-    fn parse_term(&mut self) -> Expr {
-        let mut expr = self.parse_factor();
+    fn parse_term(&mut self) -> Result<Expr, ParseError> {
+        let mut expr = self.parse_factor()?;
 
         while matches!(self.current().ttype, TokenType::Plus | TokenType::Minus) {
             let operator = self.current().clone();
             self.advance();
 
-            let right = self.parse_factor();
+            let right = self.parse_factor()?;
             expr = Expr::Binary {
                 left: Box::new(expr),
                 op: operator,
@@ -77,17 +507,17 @@ impl Parser {
             }
         }
 
-        expr
+        Ok(expr)
     }
 
-    fn parse_factor(&mut self) -> Expr {
-        let mut expr = self.parse_unary();
+    fn parse_factor(&mut self) -> Result<Expr, ParseError> {
+        let mut expr = self.parse_unary()?;
 
         while matches!(self.current().ttype, TokenType::Slash | TokenType::Star) {
             let operator = self.current().clone();
             self.advance();
 
-            let right = self.parse_unary();
+            let right = self.parse_unary()?;
             expr = Expr::Binary {
                 left: Box::new(expr),
                 op: operator,
@@ -95,57 +525,339 @@ impl Parser {
             }
         }
 
-        expr
+        Ok(expr)
     }
 
-    fn parse_unary(&mut self) -> Expr {
+    fn parse_unary(&mut self) -> Result<Expr, ParseError> {
         match self.current().ttype {
             TokenType::Minus | TokenType::Bang => {
                 let operator = self.current().clone();
                 self.advance();
 
-                Expr::Unary {
+                Ok(Expr::Unary {
                     op: operator,
-                    right: Box::new(self.parse_unary()),
+                    right: Box::new(self.parse_unary()?),
+                })
+            }
+            _ => self.parse_call(),
+        }
+    }
+
+    fn parse_call(&mut self) -> Result<Expr, ParseError> {
+        let mut expr = self.parse_primary()?;
+
+        loop {
+            if self.matches(TokenType::Dot) {
+                let name =
+                    self.consume(TokenType::Identifier, "Expect property name after '.'.")?;
+                expr = Expr::Get {
+                    object: Box::new(expr),
+                    name,
+                };
+                continue;
+            }
+            if !self.matches(TokenType::LeftParen) {
+                break;
+            }
+
+            let mut arguments = Vec::new();
+            if self.current().ttype != TokenType::RightParen {
+                loop {
+                    if arguments.len() >= MAX_ARGUMENTS {
+                        // reported without unwinding, the call itself still parses
+                        let error = self.error("Can't have more than 255 arguments.");
+                        self.errors.push(error);
+                    }
+                    arguments.push(self.parse_expression()?);
+                    if !self.matches(TokenType::Comma) {
+                        break;
+                    }
                 }
             }
-            _ => self.parse_primary(),
+            let paren = self.consume(TokenType::RightParen, "Expect ')' after arguments.")?;
+
+            expr = Expr::Call {
+                callee: Box::new(expr),
+                paren,
+                arguments,
+            };
         }
+
+        Ok(expr)
     }
 
-    fn parse_primary(&mut self) -> Expr {
+    fn parse_primary(&mut self) -> Result<Expr, ParseError> {
         match self.current().ttype {
             TokenType::Number
             | TokenType::String
             | TokenType::True
             | TokenType::False
             | TokenType::Nil => {
-                let value = self.current().literal.clone();
+                let token = self.current().clone();
+                self.advance();
+
+                Ok(Expr::Literal {
+                    value: token.literal.clone(),
+                    token,
+                })
+            }
+
+            TokenType::Identifier => {
+                let name = self.current().clone();
                 self.advance();
 
-                Expr::Literal { value }
+                Ok(Expr::Variable {
+                    name,
+                    depth: Cell::new(None),
+                })
             }
 
             TokenType::LeftParen => {
                 self.advance();
-                let expr = self.parse_expression();
-                self.advance(); // consume right paren
+                let expr = self.parse_expression()?;
+                self.consume(TokenType::RightParen, "Expect ')' after expression.")?;
 
-                Expr::Grouped {
+                Ok(Expr::Grouped {
                     expr: Box::new(expr),
+                })
+            }
+
+            _ => Err(self.error("Expect expression.")),
+        }
+    }
+
+    // Skips tokens until the start of the next statement, so that one
+    // mistake is reported once rather than as a cascade of errors.
+    fn synchronize(&mut self) {
+        // always skip the offending token, or a bad statement keyword would loop forever
+        let skipped = self.current().ttype.clone();
+        self.advance();
+        if skipped == TokenType::Semicolon {
+            return;
+        }
+
+        while !self.finished() {
+            match self.current().ttype {
+                TokenType::Semicolon => {
+                    self.advance();
+                    return;
                 }
+                TokenType::Class
+                | TokenType::Fun
+                | TokenType::Var
+                | TokenType::For
+                | TokenType::If
+                | TokenType::While
+                | TokenType::Print
+                | TokenType::Return
+                | TokenType::Throw
+                | TokenType::Try
+                | TokenType::Import
+                | TokenType::From => return,
+                _ => self.advance(),
             }
+        }
+    }
+
+    fn consume(&mut self, ttype: TokenType, message: &str) -> Result<Token, ParseError> {
+        if self.current().ttype == ttype {
+            let token = self.current().clone();
+            self.advance();
+            return Ok(token);
+        }
 
-            _ => todo!("not ready"),
+        Err(self.error(message))
+    }
+
+    fn matches(&mut self, ttype: TokenType) -> bool {
+        if self.current().ttype == ttype {
+            self.advance();
+            return true;
         }
+
+        false
+    }
+
+    fn error(&self, message: &str) -> ParseError {
+        ParseError::new(self.current(), message)
     }
 
-    // TODO: this is an operation that should never fail. Return EOF token, or something...
+    // The scanner always ends the token stream with EOF, and the cursor never
+    // moves past it, so this never fails.
     fn current(&self) -> &Token {
         &self.tokens[self.cursor]
     }
 
     fn advance(&mut self) {
-        self.cursor += 1
+        if !self.finished() {
+            self.cursor += 1
+        }
+    }
+
+    fn finished(&self) -> bool {
+        self.current().ttype == TokenType::EOF
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{expr::Prettify, scanner::Scanner};
+
+    fn parse(source: &str) -> (Vec<Stmt>, Vec<ParseError>) {
+        let tokens = Scanner::new(source).scan_tokens();
+        let mut parser = Parser::new(tokens);
+        let statements = parser.parse();
+        (statements, parser.errors().to_vec())
+    }
+
+    #[test]
+    fn test_parse_statements() {
+        let (statements, errors) = parse("/// doc\nvar a = 1; a = a or 2; { print -a; }");
+        assert!(errors.is_empty(), "{:?}", errors);
+
+        let printed = statements
+            .iter()
+            .map(|stmt| stmt.accept(&Prettify))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            printed,
+            vec![
+                "(doc \"doc\" (var a 1))",
+                "(; (= a (or a 2)))",
+                "(block (print (- a)))",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse_for_loop() {
+        let (statements, errors) = parse("for (var i = 0; i < 2; i = i + 1) print i;\nfor (;;) {}");
+        assert!(errors.is_empty(), "{:?}", errors);
+        let statements = statements
+            .iter()
+            .map(|stmt| stmt.accept(&Prettify))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            statements,
+            vec![
+                "(for (var i 0) (< i 2) (= i (+ i 1)) (print i))",
+                "(for () () () (block))"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse_imports() {
+        let (statements, errors) =
+            parse("import \"a.lox\" as a;\nfrom \"b.lox\" import b, c;\nprint a.f(b).g;");
+        assert!(errors.is_empty(), "{:?}", errors);
+        let statements = statements
+            .iter()
+            .map(|stmt| stmt.accept(&Prettify))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            statements,
+            vec![
+                "(import \"a.lox\" a)",
+                "(from \"b.lox\" b c)",
+                "(print (. (call (. a f) b) g))"
+            ]
+        );
+
+        let (_, errors) = parse("import \"a.lox\";\nfrom \"b.lox\" import;\na.1;");
+        let errors = errors.iter().map(|e| e.to_string()).collect::<Vec<_>>();
+        assert_eq!(
+            errors,
+            vec![
+                "error at line 1 at ';': Expect 'as' after module path.",
+                "error at line 2 at ';': Expect variable name.",
+                "error at line 3 at '1': Expect property name after '.'.",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse_exceptions() {
+        let (statements, errors) = parse(
+            "try { throw 1; } catch (e) { print e; } finally { print 2; }\ntry {} finally {}",
+        );
+        assert!(errors.is_empty(), "{:?}", errors);
+        let statements = statements
+            .iter()
+            .map(|stmt| stmt.accept(&Prettify))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            statements,
+            vec![
+                "(try (block (throw 1)) (catch e (print e)) (finally (print 2)))",
+                "(try (block) (finally))"
+            ]
+        );
+
+        let (_, errors) = parse("try {} print 1;\ntry {} catch e {}\nthrow;");
+        let errors = errors.iter().map(|e| e.to_string()).collect::<Vec<_>>();
+        assert_eq!(
+            errors,
+            vec![
+                "error at line 1 at 'print': Expect 'catch' or 'finally' after try block.",
+                "error at line 2 at 'e': Expect '(' after 'catch'.",
+                "error at line 3 at ';': Expect expression.",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_comments_are_kept_as_trivia() {
+        let source = "// a\nprint 1; // b\n\n{ print /* c */ 2;\n// d\n}\n// e";
+        let (statements, errors) = parse(source);
+        assert!(errors.is_empty(), "{:?}", errors);
+
+        let comment = |text: &str, line, trailing| Trivia::Comment {
+            text: text.to_string(),
+            line,
+            trailing,
+        };
+        let trivia = |statements: &[Stmt]| {
+            statements
+                .iter()
+                .filter_map(|stmt| match stmt {
+                    Stmt::Trivia(trivia) => Some(trivia.clone()),
+                    _ => None,
+                })
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            trivia(&statements),
+            vec![
+                comment("// a", 1, false),
+                comment("// b", 2, true),
+                Trivia::BlankLine,
+                comment("// e", 7, false)
+            ]
+        );
+        let Stmt::Block { statements } = &statements[4] else {
+            panic!("expected a block");
+        };
+        assert_eq!(
+            trivia(statements),
+            vec![comment("/* c */", 4, true), comment("// d", 5, false)]
+        );
+    }
+
+    #[test]
+    fn test_errors_are_synchronized() {
+        let (statements, errors) = parse("var = 1;\nprint 2;\n1 = 2;\nprint (3;\nreturn");
+        let errors = errors.iter().map(|e| e.to_string()).collect::<Vec<_>>();
+        assert_eq!(
+            errors,
+            vec![
+                "error at line 1 at '=': Expect variable name.",
+                "error at line 3 at '=': Invalid assignment target.",
+                "error at line 4 at ';': Expect ')' after expression.",
+                "error at line 5 at end: Expect expression.",
+            ]
+        );
+        assert_eq!(statements.len(), 1);
     }
 }
@@ -0,0 +1,272 @@
+use std::{
+    cell::RefCell,
+    collections::HashMap,
+    fmt::Write,
+    rc::Rc,
+    time::{Duration, Instant},
+};
+
+use crate::{
+    expr::{Evaluation, Hook, RuntimeError},
+    symbol::Symbol,
+};
+
+// The number of lines in the report, those run most often.
+const HOTTEST_LINES: usize = 20;
+
+// How often a function was called and how long it ran, in total.
+#[derive(Default)]
+struct Function {
+    calls: u64,
+    // counting recursive calls once, within the outermost
+    inclusive: Duration,
+    exclusive: Duration,
+    // calls in progress
+    active: usize,
+}
+
+// A stack of calls, which is its caller's stack and one more call. Only
+// the time spent in the last one counts towards it.
+struct Stack {
+    // None for the script
+    name: Option<Symbol>,
+    caller: Option<usize>,
+    callees: HashMap<Symbol, usize>,
+    exclusive: Duration,
+}
+
+// A call in progress, and where in `stacks` it is.
+struct Call {
+    stack: usize,
+    start: Instant,
+    // spent in the calls it made
+    callees: Duration,
+}
+
+// What ran in a program, and for how long, recorded by the `Profiler` hook.
+pub struct Profile {
+    functions: HashMap<Option<Symbol>, Function>,
+    stacks: Vec<Stack>,
+    calls: Vec<Call>,
+    // the number of statements run, by line
+    lines: Vec<u64>,
+}
+
+impl Profile {
+    pub fn new() -> Self {
+        Profile {
+            functions: HashMap::new(),
+            stacks: vec![Stack {
+                name: None,
+                caller: None,
+                callees: HashMap::new(),
+                exclusive: Duration::ZERO,
+            }],
+            calls: Vec::new(),
+            lines: Vec::new(),
+        }
+    }
+
+    // Starts timing the script itself, around which everything else is.
+    pub fn start(&mut self) {
+        let script = self.functions.entry(None).or_default();
+        script.calls += 1;
+        script.active += 1;
+        self.calls.push(Call {
+            stack: 0,
+            start: Instant::now(),
+            callees: Duration::ZERO,
+        });
+    }
+
+    // Stops timing whatever is still running, which is the script unless
+    // it stopped with an error.
+    pub fn finish(&mut self) {
+        while !self.calls.is_empty() {
+            self.returned();
+        }
+    }
+
+    fn statement(&mut self, line: usize) {
+        if self.lines.len() <= line {
+            self.lines.resize(line + 1, 0);
+        }
+        self.lines[line] += 1;
+    }
+
+    fn call(&mut self, name: Symbol) {
+        let caller = self.calls.last().map_or(0, |call| call.stack);
+        let stack = match self.stacks[caller].callees.get(&name) {
+            Some(&stack) => stack,
+            None => {
+                self.stacks.push(Stack {
+                    name: Some(name.clone()),
+                    caller: Some(caller),
+                    callees: HashMap::new(),
+                    exclusive: Duration::ZERO,
+                });
+                let stack = self.stacks.len() - 1;
+                self.stacks[caller].callees.insert(name.clone(), stack);
+                stack
+            }
+        };
+
+        let function = self.functions.entry(Some(name)).or_default();
+        function.calls += 1;
+        function.active += 1;
+        self.calls.push(Call {
+            stack,
+            start: Instant::now(),
+            callees: Duration::ZERO,
+        });
+    }
+
+    fn returned(&mut self) {
+        let Some(call) = self.calls.pop() else {
+            return;
+        };
+        let inclusive = call.start.elapsed();
+        let exclusive = inclusive.saturating_sub(call.callees);
+        if let Some(caller) = self.calls.last_mut() {
+            caller.callees += inclusive;
+        }
+
+        let stack = &mut self.stacks[call.stack];
+        stack.exclusive += exclusive;
+        let function = self.functions.get_mut(&stack.name).unwrap();
+        function.exclusive += exclusive;
+        function.active -= 1;
+        if function.active == 0 {
+            function.inclusive += inclusive;
+        }
+    }
+
+    // The functions by the time spent in them, then the lines run most often.
+    pub fn report(&self, source: &str) -> String {
+        let mut functions: Vec<_> = self.functions.iter().collect();
+        functions.sort_by(|(a_name, a), (b_name, b)| {
+            (b.exclusive, name(b_name)).cmp(&(a.exclusive, name(a_name)))
+        });
+        let mut report = String::from("     calls  inclusive ms  exclusive ms  function\n");
+        for (function_name, function) in functions {
+            let _ = writeln!(
+                report,
+                "{:>10}  {:>12.3}  {:>12.3}  {}",
+                function.calls,
+                function.inclusive.as_secs_f64() * 1000.0,
+                function.exclusive.as_secs_f64() * 1000.0,
+                name(function_name)
+            );
+        }
+
+        let mut lines: Vec<_> = (1..self.lines.len())
+            .filter(|&line| self.lines[line] > 0)
+            .collect();
+        lines.sort_by_key(|&line| std::cmp::Reverse(self.lines[line]));
+        let source: Vec<_> = source.lines().collect();
+        report.push_str("\n     count      line  source\n");
+        for line in lines.into_iter().take(HOTTEST_LINES) {
+            let text = source.get(line - 1).map_or("", |text| text.trim());
+            let _ = writeln!(report, "{:>10}  {:>8}  {}", self.lines[line], line, text);
+        }
+        report
+    }
+
+    // The microseconds spent in each stack of calls, e.g., `script;fib;fib 12`,
+    // the input of flamegraph tools.
+    pub fn folded(&self) -> String {
+        let mut folded = String::new();
+        for (index, stack) in self.stacks.iter().enumerate() {
+            let micros = stack.exclusive.as_micros();
+            if micros == 0 {
+                continue;
+            }
+            let mut names = Vec::new();
+            let mut next = Some(index);
+            while let Some(index) = next {
+                names.push(name(&self.stacks[index].name));
+                next = self.stacks[index].caller;
+            }
+            names.reverse();
+            let _ = writeln!(folded, "{} {}", names.join(";"), micros);
+        }
+        folded
+    }
+}
+
+fn name(function: &Option<Symbol>) -> &str {
+    function.as_ref().map_or("script", Symbol::as_str)
+}
+
+// Records the program it is the hook of into the shared profile.
+pub struct Profiler(pub Rc<RefCell<Profile>>);
+
+impl Hook for Profiler {
+    fn statement(&mut self, _evaluation: &mut Evaluation, line: usize) -> Result<(), RuntimeError> {
+        self.0.borrow_mut().statement(line);
+        Ok(())
+    }
+
+    fn call(&mut self, _evaluation: &mut Evaluation, name: Symbol, _line: usize) {
+        self.0.borrow_mut().call(name);
+    }
+
+    fn returned(&mut self, _evaluation: &mut Evaluation) {
+        self.0.borrow_mut().returned();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{parser::Parser, resolver::Resolver, scanner::Scanner};
+
+    const PROGRAM: &str = "\
+fun fib(n) {
+  if (n < 2) return n;
+  return fib(n - 1) + fib(n - 2);
+}
+fun run() {
+  return fib(5);
+}
+run();
+run();";
+
+    #[test]
+    fn test_profile() {
+        let statements = Parser::new(Scanner::new(PROGRAM).scan_tokens()).parse();
+        Resolver::new().resolve(&statements);
+
+        let profile = Rc::new(RefCell::new(Profile::new()));
+        let mut evaluation = Evaluation::with_args(Vec::new());
+        evaluation.set_hook(Box::new(Profiler(Rc::clone(&profile))));
+        profile.borrow_mut().start();
+        evaluation.interpret(&statements).unwrap();
+        profile.borrow_mut().finish();
+        let profile = profile.borrow();
+
+        let calls = |function: Option<&str>| profile.functions[&function.map(Symbol::intern)].calls;
+        assert_eq!(
+            (calls(None), calls(Some("run")), calls(Some("fib"))),
+            (1, 2, 30)
+        );
+        let fib = &profile.functions[&Some(Symbol::intern("fib"))];
+        assert!(fib.inclusive >= fib.exclusive);
+        let run = &profile.functions[&Some(Symbol::intern("run"))];
+        assert!(run.inclusive >= fib.inclusive);
+
+        // the `return` on line 2 is a statement of its own
+        assert_eq!(profile.lines, vec![0, 1, 46, 14, 0, 1, 2, 0, 1, 1]);
+        let report = profile.report(PROGRAM);
+        assert!(report.contains("\n        46         2  if (n < 2) return n;\n"));
+
+        // one stack for each depth of the recursion, which need not have
+        // taken a whole microsecond
+        assert_eq!(profile.stacks.len(), 7);
+        for line in profile.folded().lines() {
+            let (stack, micros) = line.rsplit_once(' ').unwrap();
+            assert!(stack == "script" || stack.starts_with("script;run"));
+            assert!(micros.parse::<u64>().unwrap() > 0);
+        }
+    }
+}
@@ -0,0 +1,463 @@
+use std::{
+    borrow::Cow,
+    io::{self, IsTerminal, Write},
+    path::PathBuf,
+    time::Instant,
+};
+
+use rustyline::{
+    completion::Completer,
+    error::ReadlineError,
+    highlight::{CmdKind, Highlighter},
+    history::DefaultHistory,
+    Context, Editor, Helper, Hinter, Validator,
+};
+
+use crate::{
+    backend::{Backend, Interpreter},
+    expr::Prettify,
+    gc::GcConfig,
+    highlight::highlight,
+    module::Modules,
+    parser::Parser,
+    resolver::Resolver,
+    scanner::{Scanner, KEYWORDS},
+    token::TokenType,
+};
+
+const HISTORY_FILE: &str = ".lox_history";
+
+const HELP: &str = "\
+:help           show this message
+:load <file>    run a file in the current session
+:reset          discard all variables
+:env            list global variables and their types
+:ast            toggle printing the syntax tree of each entry
+:tokens         toggle printing the tokens of each entry
+:time           toggle timing each entry
+:gc             collect garbage and show the size of the heap
+exit            leave the REPL (or press Ctrl-D)";
+
+pub struct Repl {
+    interpreter: Box<dyn Interpreter>,
+    backend: Backend,
+    gc: GcConfig,
+    // where modules are found after the current directory
+    search_path: Vec<PathBuf>,
+    input: Input,
+    pretty: bool,
+    show_ast: bool,
+    show_tokens: bool,
+    show_time: bool,
+}
+
+// Line editing needs a terminal; piped input is read line by line instead.
+enum Input {
+    Editor(Box<Editor<ReplHelper, DefaultHistory>>),
+    Plain,
+}
+
+enum Line {
+    Text(String),
+    Interrupted,
+    Eof,
+}
+
+impl Repl {
+    pub fn new(pretty: bool, backend: Backend, gc: GcConfig, search_path: Vec<PathBuf>) -> Self {
+        Repl {
+            interpreter: interpreter(backend, gc, &search_path),
+            backend,
+            gc,
+            search_path,
+            input: Input::new(),
+            pretty,
+            show_ast: false,
+            show_tokens: false,
+            show_time: false,
+        }
+    }
+
+    pub fn run(&mut self) -> io::Result<()> {
+        self.input.load_history();
+        while let Some(source) = self.read_entry()? {
+            let entry = source.trim();
+            if entry.eq_ignore_ascii_case("exit") {
+                break;
+            }
+            if entry.is_empty() {
+                continue;
+            }
+
+            self.input.add_history(&source);
+            if entry.starts_with(':') {
+                self.command(entry);
+            } else {
+                self.eval(&source);
+            }
+        }
+        self.input.save_history();
+
+        Ok(())
+    }
+
+    // Reads lines until they form a complete entry. Returns None on Ctrl-D.
+    fn read_entry(&mut self) -> io::Result<Option<String>> {
+        let globals = self.interpreter.globals();
+        self.input
+            .set_globals(globals.into_iter().map(|(name, _)| name).collect());
+
+        let mut source = String::new();
+        let mut prompt = "> ";
+        loop {
+            match self.input.read_line(prompt)? {
+                Line::Text(line) => source.push_str(&line),
+                Line::Interrupted => {
+                    // Ctrl-C abandons the current entry
+                    source.clear();
+                    prompt = "> ";
+                    continue;
+                }
+                Line::Eof => {
+                    println!();
+                    return Ok(None);
+                }
+            }
+
+            if source.trim_start().starts_with(':') || !is_incomplete(&source) {
+                return Ok(Some(source));
+            }
+            prompt = "... ";
+        }
+    }
+
+    fn eval(&mut self, source: &str) {
+        let started = Instant::now();
+        self.eval_source(source);
+        if self.show_time {
+            eprintln!("took {:?}", started.elapsed());
+        }
+    }
+
+    fn eval_source(&mut self, source: &str) {
+        let mut scanner = Scanner::new(source);
+        let tokens = scanner.scan_tokens();
+        if self.show_tokens {
+            for token in &tokens {
+                println!("{:>4} {:?} '{}'", token.line, token.ttype, token.lexeme);
+            }
+        }
+        if !scanner.errors().is_empty() {
+            for error in scanner.errors() {
+                eprintln!("{}", error);
+            }
+            return;
+        }
+
+        let mut parser = Parser::new(tokens.clone());
+        let statements = parser.parse();
+        if !parser.errors().is_empty() {
+            // a bare expression such as `1 + 2` is not a statement, but its value is printed
+            match Parser::new(tokens).parse_single_expression() {
+                Ok(expr) => {
+                    if self.show_ast || self.pretty {
+                        println!("{}", expr.accept(&Prettify));
+                    }
+                    if !self.pretty {
+                        match self.interpreter.evaluate(&expr) {
+                            Ok(value) => println!("{}", value),
+                            Err(error) => eprintln!("{}", error),
+                        }
+                    }
+                }
+                Err(_) => {
+                    for error in parser.errors() {
+                        eprintln!("{}", error);
+                    }
+                }
+            }
+            return;
+        }
+
+        let mut resolver = Resolver::new();
+        resolver.resolve(&statements);
+        if !resolver.errors().is_empty() {
+            for error in resolver.errors() {
+                eprintln!("{}", error);
+            }
+            return;
+        }
+
+        if self.show_ast || self.pretty {
+            Prettify.pretty_print(&statements);
+        }
+        if !self.pretty {
+            if let Err(error) = self.interpreter.interpret(&statements) {
+                eprintln!("{}", error);
+            }
+        }
+    }
+
+    // Meta-commands start with a colon and never reach the scanner.
+    fn command(&mut self, line: &str) {
+        let (command, argument) = match line.split_once(char::is_whitespace) {
+            Some((command, argument)) => (command, argument.trim()),
+            None => (line, ""),
+        };
+
+        match (command, argument) {
+            (":help", "") => println!("{}", HELP),
+            (":load", "") => eprintln!("usage: :load <file>"),
+            (":load", path) => match std::fs::read_to_string(path) {
+                Ok(source) => self.eval(&source),
+                Err(error) => eprintln!("error: cannot read '{}': {}", path, error),
+            },
+            (":reset", "") => {
+                self.interpreter = interpreter(self.backend, self.gc, &self.search_path);
+                println!("environment reset");
+            }
+            (":env", "") => {
+                let mut globals = self.interpreter.globals();
+                globals.sort_by(|(a, _), (b, _)| a.cmp(b));
+                for (name, value) in globals {
+                    println!("{}: {} = {}", name, value.type_name(), value);
+                }
+            }
+            (":ast", "") => {
+                self.show_ast = !self.show_ast;
+                println!("ast dump {}", on_off(self.show_ast));
+            }
+            (":tokens", "") => {
+                self.show_tokens = !self.show_tokens;
+                println!("token dump {}", on_off(self.show_tokens));
+            }
+            (":time", "") => {
+                self.show_time = !self.show_time;
+                println!("timing {}", on_off(self.show_time));
+            }
+            (":gc", "") => {
+                let heap = self.interpreter.heap();
+                let freed = heap.collect();
+                println!(
+                    "freed {} objects, {} live after {} collections",
+                    freed,
+                    heap.len(),
+                    heap.collections()
+                );
+            }
+            _ => eprintln!("error: unknown command '{}', try :help", line),
+        }
+    }
+}
+
+fn interpreter(backend: Backend, gc: GcConfig, search_path: &[PathBuf]) -> Box<dyn Interpreter> {
+    let mut interpreter = backend.interpreter(Vec::new(), gc);
+    interpreter.set_modules(Modules::new(None, search_path.to_vec()));
+    interpreter
+}
+
+impl Input {
+    fn new() -> Self {
+        if !io::stdin().is_terminal() {
+            return Input::Plain;
+        }
+
+        match Editor::new() {
+            Ok(mut editor) => {
+                editor.set_helper(Some(ReplHelper::default()));
+                Input::Editor(Box::new(editor))
+            }
+            Err(_) => Input::Plain,
+        }
+    }
+
+    fn read_line(&mut self, prompt: &str) -> io::Result<Line> {
+        match self {
+            Input::Editor(editor) => match editor.readline(prompt) {
+                Ok(mut line) => {
+                    line.push('\n');
+                    Ok(Line::Text(line))
+                }
+                Err(ReadlineError::Interrupted) => Ok(Line::Interrupted),
+                Err(ReadlineError::Eof) => Ok(Line::Eof),
+                Err(ReadlineError::Io(error)) => Err(error),
+                Err(error) => Err(io::Error::other(error)),
+            },
+            Input::Plain => {
+                print!("{}", prompt);
+                io::stdout().flush()?;
+
+                let mut line = String::new();
+                match io::stdin().read_line(&mut line)? {
+                    0 => Ok(Line::Eof),
+                    _ => Ok(Line::Text(line)),
+                }
+            }
+        }
+    }
+
+    fn set_globals(&mut self, globals: Vec<String>) {
+        if let Input::Editor(editor) = self {
+            if let Some(helper) = editor.helper_mut() {
+                helper.globals = globals;
+            }
+        }
+    }
+
+    fn add_history(&mut self, entry: &str) {
+        if let Input::Editor(editor) = self {
+            let _ = editor.add_history_entry(entry.trim_end());
+        }
+    }
+
+    // A missing or unreadable history file is not worth interrupting the session for.
+    fn load_history(&mut self) {
+        if let (Input::Editor(editor), Some(path)) = (self, history_path()) {
+            let _ = editor.load_history(&path);
+        }
+    }
+
+    fn save_history(&mut self) {
+        if let (Input::Editor(editor), Some(path)) = (self, history_path()) {
+            let _ = editor.save_history(&path);
+        }
+    }
+}
+
+fn on_off(enabled: bool) -> &'static str {
+    if enabled {
+        "on"
+    } else {
+        "off"
+    }
+}
+
+fn history_path() -> Option<PathBuf> {
+    std::env::home_dir().map(|home| home.join(HISTORY_FILE))
+}
+
+// Completes keywords and global names, and highlights the line as it is
+// typed. Reverse search (Ctrl-R) and history navigation come with the editor.
+#[derive(Default, Helper, Hinter, Validator)]
+struct ReplHelper {
+    globals: Vec<String>,
+}
+
+impl Completer for ReplHelper {
+    type Candidate = String;
+
+    fn complete(
+        &self,
+        line: &str,
+        pos: usize,
+        _ctx: &Context<'_>,
+    ) -> rustyline::Result<(usize, Vec<String>)> {
+        let start = line[..pos]
+            .char_indices()
+            .rev()
+            .take_while(|(_, c)| c.is_alphanumeric() || *c == '_')
+            .last()
+            .map_or(pos, |(i, _)| i);
+
+        Ok((start, completions(&line[start..pos], &self.globals)))
+    }
+}
+
+impl Highlighter for ReplHelper {
+    fn highlight<'l>(&self, line: &'l str, pos: usize) -> Cow<'l, str> {
+        Cow::Owned(highlight(line, pos))
+    }
+
+    // the bracket highlight depends on the cursor, so redraw on every change
+    fn highlight_char(&self, _line: &str, _pos: usize, _kind: CmdKind) -> bool {
+        true
+    }
+}
+
+fn completions(prefix: &str, globals: &[String]) -> Vec<String> {
+    if prefix.is_empty() {
+        return Vec::new();
+    }
+
+    let mut candidates = KEYWORDS
+        .iter()
+        .map(|(keyword, _)| keyword.to_string())
+        .chain(globals.iter().cloned())
+        .filter(|candidate| candidate.starts_with(prefix))
+        .collect::<Vec<_>>();
+    candidates.sort();
+    candidates.dedup();
+    candidates
+}
+
+// An entry is incomplete when it ends inside a string, a block comment, or an
+// unclosed `(` or `{`.
+fn is_incomplete(source: &str) -> bool {
+    let mut scanner = Scanner::new(source);
+    let tokens = scanner.scan_tokens();
+    if scanner.is_incomplete() {
+        return true;
+    }
+
+    let depth = tokens.iter().fold(0, |depth, token| match token.ttype {
+        TokenType::LeftParen | TokenType::LeftBrace => depth + 1,
+        TokenType::RightParen | TokenType::RightBrace => depth - 1,
+        _ => depth,
+    });
+    depth > 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::ValueEnum;
+
+    #[test]
+    fn test_is_incomplete() {
+        assert!(!is_incomplete("print 1;\n"));
+        assert!(!is_incomplete("1 + 2\n"));
+        assert!(is_incomplete("{\n"));
+        assert!(is_incomplete("if (true) {\n  print (1 +\n"));
+        assert!(is_incomplete("print \"multi\n"));
+        assert!(is_incomplete("/* comment\n"));
+        assert!(!is_incomplete("{\n}\n"));
+        // too many closing brackets can't be fixed by more input
+        assert!(!is_incomplete("}\n"));
+    }
+
+    #[test]
+    fn test_load_and_reset_commands() {
+        let name = format!("interpret-repl-{}.lox", std::process::id());
+        let path = std::env::temp_dir().join(name);
+        std::fs::write(&path, "var loaded = 1 + 2;").unwrap();
+
+        let is_loaded = |repl: &Repl| {
+            repl.interpreter
+                .globals()
+                .iter()
+                .any(|(name, _)| name == "loaded")
+        };
+        for &backend in Backend::value_variants() {
+            let mut repl = Repl::new(false, backend, GcConfig::default(), Vec::new());
+            repl.command(&format!(":load {}", path.display()));
+            assert!(is_loaded(&repl), "{:?}", backend);
+
+            repl.command(":reset");
+            assert!(!is_loaded(&repl), "{:?}", backend);
+        }
+
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_completions() {
+        let globals = vec!["fizz".to_string(), "value".to_string()];
+        assert_eq!(
+            completions("f", &globals),
+            vec!["false", "finally", "fizz", "for", "from", "fun"]
+        );
+        assert_eq!(completions("va", &globals), vec!["value", "var"]);
+        assert_eq!(completions("", &globals), Vec::<String>::new());
+        assert_eq!(completions("xyz", &globals), Vec::<String>::new());
+    }
+}
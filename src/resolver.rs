@@ -0,0 +1,434 @@
+use std::{cell::Cell, collections::HashMap, rc::Rc};
+
+use crate::{
+    expr::{self, Expr},
+    stmt::{self, Catch, FunctionDecl, Stmt},
+    symbol::Symbol,
+    token::{LiteralValue, Token, Trivia},
+};
+
+pub struct ResolveError {
+    pub line: usize,
+    pub column: usize,
+    pub location: String,
+    pub message: String,
+}
+
+impl ResolveError {
+    fn new(token: &Token, message: &str) -> Self {
+        ResolveError {
+            line: token.line,
+            column: token.column,
+            location: format!("'{}'", token.lexeme),
+            message: message.to_string(),
+        }
+    }
+}
+
+impl std::fmt::Display for ResolveError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "error at line {} at {}: {}",
+            self.line, self.location, self.message
+        )
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum FunctionType {
+    None,
+    Function,
+}
+
+// What declared a name. Imports declare variables.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Kind {
+    Variable,
+    Parameter,
+    Function,
+    // the error of a `catch`
+    Catch,
+}
+
+// A name as declared, in `depth` scopes, or none for a global.
+pub struct Declaration {
+    pub name: Token,
+    pub kind: Kind,
+    pub depth: usize,
+    // the local of the same name it hides, in an enclosing scope
+    pub shadows: Option<usize>,
+    pub function: Option<Rc<FunctionDecl>>,
+    pub doc: Option<String>,
+}
+
+// A name read or assigned, with the index of the local declaration it
+// refers to, or none for a global, which is found by name.
+pub struct Use {
+    pub name: Token,
+    pub declaration: Option<usize>,
+    pub assign: bool,
+}
+
+// A name in a scope, `defined` but while its initializer is resolved.
+struct Local {
+    defined: bool,
+    declaration: usize,
+}
+
+// Works out, for every local variable, how many scopes separate its use
+// from its declaration, and reports the mistakes that can be found without
+// running the program. Globals are left unresolved and looked up by name.
+//
+// Every declaration and use is recorded along the way, in the order of the
+// source, for the tools that need to know what each name refers to.
+pub struct Resolver {
+    scopes: Vec<HashMap<Symbol, Local>>,
+    function: FunctionType,
+    errors: Vec<ResolveError>,
+    declarations: Vec<Declaration>,
+    uses: Vec<Use>,
+}
+
+impl Resolver {
+    pub fn new() -> Self {
+        Resolver {
+            scopes: Vec::new(),
+            function: FunctionType::None,
+            errors: Vec::new(),
+            declarations: Vec::new(),
+            uses: Vec::new(),
+        }
+    }
+
+    pub fn resolve(&mut self, statements: &[Stmt]) {
+        for stmt in statements {
+            stmt.accept(&mut *self);
+        }
+    }
+
+    pub fn errors(&self) -> &[ResolveError] {
+        &self.errors
+    }
+
+    pub fn declarations(&self) -> &[Declaration] {
+        &self.declarations
+    }
+
+    pub fn uses(&self) -> &[Use] {
+        &self.uses
+    }
+
+    fn resolve_function(&mut self, declaration: &FunctionDecl, function: FunctionType) {
+        let enclosing = std::mem::replace(&mut self.function, function);
+        self.scopes.push(HashMap::new());
+        for param in &declaration.params {
+            self.declare(param, Kind::Parameter);
+            self.define(param);
+        }
+        self.resolve(&declaration.body);
+        self.scopes.pop();
+        self.function = enclosing;
+    }
+
+    // A variable being initialized isn't there yet, so its initializer
+    // refers to the one it hides, if only to be reported.
+    fn resolve_local(&mut self, name: &Token, depth: &Cell<Option<usize>>, assign: bool) {
+        let found = self
+            .scopes
+            .iter()
+            .rev()
+            .enumerate()
+            .find_map(|(depth, scope)| match scope.get(&name.lexeme) {
+                Some(local) if local.defined => Some((depth, local.declaration)),
+                _ => None,
+            });
+        depth.set(found.map(|(depth, _)| depth));
+        self.uses.push(Use {
+            name: name.clone(),
+            declaration: found.map(|(_, declaration)| declaration),
+            assign,
+        });
+    }
+
+    fn declare(&mut self, name: &Token, kind: Kind) {
+        self.declare_with(name, kind, None, None);
+    }
+
+    fn declare_with(
+        &mut self,
+        name: &Token,
+        kind: Kind,
+        function: Option<&Rc<FunctionDecl>>,
+        doc: Option<&str>,
+    ) {
+        let index = self.declarations.len();
+        let shadows = self
+            .scopes
+            .iter()
+            .rev()
+            .skip(1)
+            .find_map(|scope| scope.get(&name.lexeme).map(|local| local.declaration));
+        self.declarations.push(Declaration {
+            name: name.clone(),
+            kind,
+            depth: self.scopes.len(),
+            shadows,
+            function: function.cloned(),
+            doc: doc.map(str::to_string),
+        });
+
+        let Some(scope) = self.scopes.last_mut() else {
+            return;
+        };
+        let local = Local {
+            defined: false,
+            declaration: index,
+        };
+        if scope.insert(name.lexeme.clone(), local).is_some() {
+            self.errors.push(ResolveError::new(
+                name,
+                "Already a variable with this name in this scope.",
+            ));
+        }
+    }
+
+    fn define(&mut self, name: &Token) {
+        if let Some(local) = self
+            .scopes
+            .last_mut()
+            .and_then(|scope| scope.get_mut(&name.lexeme))
+        {
+            local.defined = true;
+        }
+    }
+}
+
+impl expr::Visitor<()> for &mut Resolver {
+    fn visit_binary(self, left: &Expr, _op: &Token, right: &Expr) {
+        left.accept(&mut *self);
+        right.accept(self);
+    }
+
+    fn visit_unary(self, _op: &Token, right: &Expr) {
+        right.accept(self);
+    }
+
+    fn visit_grouped(self, expr: &Expr) {
+        expr.accept(self);
+    }
+
+    fn visit_literal(self, _value: &Option<LiteralValue>, _token: &Token) {}
+
+    fn visit_logical(self, left: &Expr, _op: &Token, right: &Expr) {
+        left.accept(&mut *self);
+        right.accept(self);
+    }
+
+    fn visit_variable(self, name: &Token, depth: &Cell<Option<usize>>) {
+        let declared = self.scopes.last().and_then(|scope| scope.get(&name.lexeme));
+        if declared.is_some_and(|local| !local.defined) {
+            self.errors.push(ResolveError::new(
+                name,
+                "Can't read local variable in its own initializer.",
+            ));
+        }
+        self.resolve_local(name, depth, false);
+    }
+
+    fn visit_assign(self, name: &Token, value: &Expr, depth: &Cell<Option<usize>>) {
+        value.accept(&mut *self);
+        self.resolve_local(name, depth, true);
+    }
+
+    fn visit_call(self, callee: &Expr, _paren: &Token, arguments: &[Expr]) {
+        callee.accept(&mut *self);
+        for argument in arguments {
+            argument.accept(&mut *self);
+        }
+    }
+
+    // Only the object is resolved, the module has the name.
+    fn visit_get(self, object: &Expr, _name: &Token) {
+        object.accept(self);
+    }
+}
+
+impl stmt::Visitor<()> for &mut Resolver {
+    fn visit_expression(self, expr: &Expr) {
+        expr.accept(self);
+    }
+
+    fn visit_print(self, expr: &Expr) {
+        expr.accept(self);
+    }
+
+    fn visit_var(self, name: &Token, initializer: Option<&Expr>, doc: Option<&str>) {
+        self.declare_with(name, Kind::Variable, None, doc);
+        if let Some(initializer) = initializer {
+            initializer.accept(&mut *self);
+        }
+        self.define(name);
+    }
+
+    fn visit_block(self, statements: &[Stmt]) {
+        self.scopes.push(HashMap::new());
+        self.resolve(statements);
+        self.scopes.pop();
+    }
+
+    fn visit_if(
+        self,
+        _keyword: &Token,
+        condition: &Expr,
+        then_branch: &Stmt,
+        else_branch: Option<&Stmt>,
+    ) {
+        condition.accept(&mut *self);
+        then_branch.accept(&mut *self);
+        if let Some(else_branch) = else_branch {
+            else_branch.accept(self);
+        }
+    }
+
+    fn visit_while(self, condition: &Expr, body: &Stmt) {
+        condition.accept(&mut *self);
+        body.accept(self);
+    }
+
+    fn visit_for(
+        self,
+        initializer: Option<&Stmt>,
+        condition: Option<&Expr>,
+        increment: Option<&Expr>,
+        body: &Stmt,
+    ) {
+        self.scopes.push(HashMap::new());
+        if let Some(initializer) = initializer {
+            initializer.accept(&mut *self);
+        }
+        if let Some(condition) = condition {
+            condition.accept(&mut *self);
+        }
+        if let Some(increment) = increment {
+            increment.accept(&mut *self);
+        }
+        body.accept(&mut *self);
+        self.scopes.pop();
+    }
+
+    fn visit_function(self, declaration: &Rc<FunctionDecl>) {
+        // defined before the body so the function can call itself
+        let doc = declaration.doc.as_deref();
+        self.declare_with(&declaration.name, Kind::Function, Some(declaration), doc);
+        self.define(&declaration.name);
+        self.resolve_function(declaration, FunctionType::Function);
+    }
+
+    fn visit_return(self, keyword: &Token, value: Option<&Expr>) {
+        if self.function == FunctionType::None {
+            self.errors.push(ResolveError::new(
+                keyword,
+                "Can't return from top-level code.",
+            ));
+        }
+        if let Some(value) = value {
+            value.accept(self);
+        }
+    }
+
+    fn visit_throw(self, _keyword: &Token, value: &Expr) {
+        value.accept(self);
+    }
+
+    // Each block is a scope, that of `catch` starting with the error.
+    fn visit_try(
+        self,
+        _keyword: &Token,
+        body: &[Stmt],
+        catch: Option<&Catch>,
+        finally: Option<&[Stmt]>,
+    ) {
+        self.scopes.push(HashMap::new());
+        self.resolve(body);
+        self.scopes.pop();
+        if let Some(catch) = catch {
+            self.scopes.push(HashMap::new());
+            self.declare(&catch.name, Kind::Catch);
+            self.define(&catch.name);
+            self.resolve(&catch.body);
+            self.scopes.pop();
+        }
+        if let Some(finally) = finally {
+            self.scopes.push(HashMap::new());
+            self.resolve(finally);
+            self.scopes.pop();
+        }
+    }
+
+    fn visit_import(self, _keyword: &Token, _path: &Token, alias: Option<&Token>, names: &[Token]) {
+        for name in alias.into_iter().chain(names) {
+            self.declare(name, Kind::Variable);
+            self.define(name);
+        }
+    }
+
+    fn visit_trivia(self, _trivia: &Trivia) {}
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{parser::Parser, scanner::Scanner};
+
+    fn resolve(source: &str) -> (Vec<Stmt>, Vec<String>) {
+        let tokens = Scanner::new(source).scan_tokens();
+        let statements = Parser::new(tokens).parse();
+        let mut resolver = Resolver::new();
+        resolver.resolve(&statements);
+        let errors = resolver.errors().iter().map(|e| e.to_string()).collect();
+        (statements, errors)
+    }
+
+    #[test]
+    fn test_resolve_depths() {
+        let (statements, errors) = resolve("var g; { var a; { a; g; } }");
+        assert!(errors.is_empty());
+
+        let Stmt::Block { statements } = &statements[1] else {
+            panic!("expected a block");
+        };
+        let Stmt::Block { statements } = &statements[1] else {
+            panic!("expected a block");
+        };
+        let depths: Vec<_> = statements
+            .iter()
+            .map(|stmt| match stmt {
+                Stmt::Expression {
+                    expr: Expr::Variable { depth, .. },
+                } => depth.get(),
+                _ => panic!("expected a variable"),
+            })
+            .collect();
+        assert_eq!(depths, vec![Some(1), None]);
+    }
+
+    #[test]
+    fn test_resolve_errors() {
+        let (_, errors) = resolve(
+            "{ var a = 1; var a = 2; }\n\
+             { var b = b; }\n\
+             return 1;\n\
+             fun f(c, c) { return c; }",
+        );
+        assert_eq!(
+            errors,
+            vec![
+                "error at line 1 at 'a': Already a variable with this name in this scope.",
+                "error at line 2 at 'b': Can't read local variable in its own initializer.",
+                "error at line 3 at 'return': Can't return from top-level code.",
+                "error at line 4 at 'c': Already a variable with this name in this scope.",
+            ]
+        );
+    }
+}
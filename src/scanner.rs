@@ -1,24 +1,105 @@
-use crate::token::{LiteralValue, Token, TokenType};
+use crate::{
+    symbol::Symbol,
+    token::{Lexeme, LiteralValue, Span, Token, TokenType, Trivia},
+};
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ScanError {
+    pub line: usize,
+    pub column: usize,
+    pub message: String,
+}
+
+impl std::fmt::Display for ScanError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "error at line {}: {}", self.line, self.message)
+    }
+}
 
-pub struct Scanner {
-    source: Vec<char>,
-    tokens: Vec<Token>,
+// Scans tokens lazily, as an iterator, or all at once with `scan_tokens`.
+// Lexemes are slices of the source, which is never copied while scanning.
+// Only the front of the scanner is zero-copy, though: the parser takes
+// `Token`s, with each distinct text interned once, since the syntax trees
+// it builds outlive the source, e.g., a function declared in the REPL.
+pub struct Scanner<'src> {
+    source: &'src str,
+    // the token just scanned, until `next` returns it
+    scanned: Option<Lexeme<'src>>,
+    emitted: bool,
+    finished: bool,
+    errors: Vec<ScanError>,
+    doc: Option<String>,
+    doc_line: usize,
+    trivia: Vec<Trivia>,
+    // true until something other than whitespace is found on the current line
+    blank_line: bool,
+    incomplete: bool,
+    partial: bool,
+    // byte offsets of the current position and of the token being scanned
     cursor: usize,
+    start: usize,
+    // 1-based, counted in characters, of the current position and of the
+    // token being scanned
+    column: usize,
+    start_column: usize,
     line: usize,
 }
 
-impl Scanner {
-    pub fn new(source: String) -> Self {
+impl<'src> Scanner<'src> {
+    pub fn new(source: &'src str) -> Self {
         Scanner {
-            source: source.chars().collect(),
-            tokens: Vec::new(),
+            source,
+            scanned: None,
+            emitted: false,
+            finished: false,
+            errors: Vec::new(),
+            doc: None,
+            doc_line: 0,
+            trivia: Vec::new(),
+            blank_line: true,
+            incomplete: false,
+            partial: false,
             cursor: 0,
+            start: 0,
+            column: 1,
+            start_column: 1,
             line: 1,
         }
     }
 
+    // Scans from `offset`, just after a token that ends at `line` and
+    // `column`, e.g., to lex a document again after an edit.
+    pub fn resume(source: &'src str, offset: usize, line: usize, column: usize) -> Self {
+        Scanner {
+            emitted: true,
+            blank_line: false,
+            cursor: offset,
+            column,
+            line,
+            ..Scanner::new(source)
+        }
+    }
+
+    // Scans input that may be incomplete or malformed, e.g., a line being
+    // edited. Unterminated strings still produce tokens, without a literal
+    // value, so every token keeps its span, as malformed numbers always do.
+    // Errors are collected as usual and never printed.
+    pub fn partial(mut self) -> Self {
+        self.partial = true;
+        self
+    }
+
+    // Scans the rest of the source, interning the lexemes for the parser.
+    // The text of each token is copied here, the first time it is seen.
     pub fn scan_tokens(&mut self) -> Vec<Token> {
-        while !self.finished() {
+        self.map(Token::from).collect()
+    }
+
+    // Scans until a token is found, or the end of the source.
+    fn scan_token(&mut self) {
+        while self.scanned.is_none() && !self.finished() {
+            self.start = self.cursor;
+            self.start_column = self.column;
             let c = self.current();
             match c {
                 '(' => self.parse_single_char(TokenType::LeftParen, "("),
@@ -39,131 +120,303 @@ impl Scanner {
                 '"' => self.parse_string(),
                 ' ' | '\r' | '\t' => self.advance(), // ignore whitespace
                 '\n' => {
+                    if self.blank_line {
+                        self.add_blank_line();
+                    }
                     self.advance();
-                    self.line += 1;
+                    self.new_line();
                 }
                 c => {
-                    if c.is_digit(10) {
+                    if c.is_ascii_digit() {
                         self.parse_number();
                     } else if c.is_alphabetic() {
                         self.parse_identifier();
                     } else {
-                        eprintln!("error at line {}: Unexpected character '{}'", self.line, c);
+                        self.error(
+                            self.line,
+                            self.column,
+                            format!("Unexpected character '{}'", c),
+                        );
                         self.advance();
                     }
                 }
             }
         }
-        self.add_token(TokenType::EOF, "");
+    }
 
-        self.tokens.clone()
+    // Where the scanner is, e.g., just after the token it returned last.
+    pub fn location(&self) -> (usize, usize) {
+        (self.line, self.column)
     }
 
-    fn parse_single_char(&mut self, ttype: TokenType, lexeme: &str) {
-        self.add_token(ttype, lexeme);
+    pub fn errors(&self) -> &[ScanError] {
+        &self.errors
+    }
+
+    // True if the source ended inside a string or block comment, i.e., more
+    // input could still complete it.
+    pub fn is_incomplete(&self) -> bool {
+        self.incomplete
+    }
+
+    fn parse_single_char(&mut self, ttype: TokenType, lexeme: &'static str) {
         self.advance();
+        self.add_token(ttype, lexeme);
     }
 
     fn parse_bang(&mut self) {
-        if self.peek() == '=' {
+        self.advance();
+        if self.matches('=') {
             self.add_token(TokenType::BangEqual, "!=");
-            self.advance();
         } else {
             self.add_token(TokenType::Bang, "!");
         }
-        self.advance()
     }
 
     fn parse_equal(&mut self) {
-        if self.peek() == '=' {
+        self.advance();
+        if self.matches('=') {
             self.add_token(TokenType::EqualEqual, "==");
-            self.advance();
         } else {
             self.add_token(TokenType::Equal, "=");
         }
-        self.advance()
     }
 
     fn parse_greater(&mut self) {
-        if self.peek() == '=' {
+        self.advance();
+        if self.matches('=') {
             self.add_token(TokenType::GreaterEqual, ">=");
-            self.advance();
         } else {
             self.add_token(TokenType::Greater, ">");
         }
-        self.advance()
     }
 
     fn parse_less(&mut self) {
-        if self.peek() == '=' {
+        self.advance();
+        if self.matches('=') {
             self.add_token(TokenType::LessEqual, "<=");
-            self.advance();
         } else {
             self.add_token(TokenType::Less, "<");
         }
-        self.advance()
     }
 
+    // Comments are kept as trivia of the next token.
     fn parse_slash(&mut self) {
-        if self.peek() == '/' {
-            while self.current() != '\n' && !self.finished() {
-                self.advance();
+        let trailing = !self.blank_line;
+        match self.peek() {
+            '/' => self.parse_line_comment(trailing),
+            '*' => self.parse_block_comment(trailing),
+            _ => self.parse_single_char(TokenType::Slash, "/"),
+        }
+    }
+
+    // `///` starts a doc comment, but `////` is an ordinary comment. Consecutive
+    // doc comment lines are joined and attached to the next declaration.
+    fn parse_line_comment(&mut self, trailing: bool) {
+        let start = self.cursor;
+        while self.current() != '\n' && !self.finished() {
+            self.advance();
+        }
+
+        let text = self.source[start..self.cursor].trim_end();
+        if let Some(doc) = text.strip_prefix("///").filter(|doc| !doc.starts_with('/')) {
+            let doc = doc.strip_prefix(' ').unwrap_or(doc);
+            match &mut self.doc {
+                Some(existing) => {
+                    existing.push('\n');
+                    existing.push_str(doc);
+                }
+                None => {
+                    self.doc = Some(doc.to_string());
+                    self.doc_line = self.line;
+                }
             }
         } else {
-            self.add_token(TokenType::Slash, "/");
+            self.add_comment(text.to_string(), self.line, trailing);
         }
-        self.advance();
+        self.blank_line = false;
+    }
+
+    // Block comments nest, e.g., /* outer /* inner */ still outer */
+    fn parse_block_comment(&mut self, trailing: bool) {
+        let (line, column) = (self.line, self.column);
+        let start = self.cursor;
+        let mut depth = 0;
+        while !self.finished() {
+            match (self.current(), self.peek()) {
+                ('/', '*') => {
+                    depth += 1;
+                    self.advance();
+                }
+                ('*', '/') => {
+                    depth -= 1;
+                    self.advance();
+                }
+                _ => {}
+            }
+            let newline = self.current() == '\n';
+            self.advance();
+            if newline {
+                self.new_line();
+            }
+
+            if depth == 0 {
+                let text = self.source[start..self.cursor].to_string();
+                self.add_comment(text, line, trailing);
+                return;
+            }
+        }
+
+        self.incomplete = true;
+        self.errors.push(ScanError {
+            line,
+            column,
+            message: "unterminated block comment".to_string(),
+        });
     }
 
     fn parse_string(&mut self) {
+        let (line, column) = (self.line, self.column);
         let start = self.cursor + 1;
         self.advance();
         while self.current() != '"' && !self.finished() {
-            if self.current() == '\n' {
-                self.line += 1;
-            }
-
+            let newline = self.current() == '\n';
             self.advance();
+            if newline {
+                self.new_line();
+            }
         }
 
         if self.finished() {
-            eprintln!("error at line {}: unterminated string", self.line);
+            self.incomplete = true;
+            self.errors.push(ScanError {
+                line,
+                column,
+                message: "unterminated string".to_string(),
+            });
+            if self.partial {
+                self.add_token_with_literal(TokenType::String, &self.source[start..], None);
+            }
             return;
         }
 
-        let end = self.cursor;
-        let value = self.source[start..end].iter().collect::<String>();
+        let value = &self.source[start..self.cursor];
+        self.advance();
 
         self.add_token_with_literal(
             TokenType::String,
-            &value.clone(), // TODO(ben): is this necessary?
-            Some(LiteralValue::String(value)),
+            value,
+            Some(LiteralValue::String(Symbol::intern(value))),
         );
-
-        self.advance();
     }
 
     fn parse_number(&mut self) {
+        let (line, column) = (self.line, self.column);
         let start = self.cursor;
-        while self.current().is_digit(10) {
+        let radix = match (self.current(), self.peek()) {
+            ('0', 'x' | 'X') => Some((16, "hexadecimal")),
+            ('0', 'o' | 'O') => Some((8, "octal")),
+            ('0', 'b' | 'B') => Some((2, "binary")),
+            _ => None,
+        };
+
+        let value = match radix {
+            Some((radix, name)) => {
+                self.advance();
+                self.advance();
+                self.parse_radix_number(radix, name)
+            }
+            None => self.parse_decimal_number(),
+        };
+
+        if value.is_err() {
+            // skip the rest of the malformed literal to avoid a cascade of errors
+            while self.current().is_ascii_alphanumeric() || self.current() == '_' {
+                self.advance();
+            }
+        }
+        let number = &self.source[start..self.cursor];
+        match value {
+            Ok(value) => self.add_token_with_literal(
+                TokenType::Number,
+                number,
+                Some(LiteralValue::Number(value)),
+            ),
+            // still a token, so the parser doesn't report a missing operand
+            Err(message) => {
+                let message = format!("{} in number literal '{}'", message, number);
+                self.error(line, column, message);
+                self.add_token_with_literal(TokenType::Number, number, None);
+            }
+        }
+    }
+
+    // e.g., 0xff, 0o17, 0b1010_1010. Digits outside of the radix are consumed
+    // so that `0b102` is reported as one bad literal rather than `0b10` and `2`.
+    fn parse_radix_number(&mut self, radix: u32, name: &str) -> Result<f64, String> {
+        let digits = self.scan_digits(|c| c.is_ascii_alphanumeric())?;
+        if digits.is_empty() {
+            return Err(format!("missing {} digits", name));
+        }
+        if let Some(c) = digits.chars().find(|c| !c.is_digit(radix)) {
+            return Err(format!("invalid {} digit '{}'", name, c));
+        }
+
+        u64::from_str_radix(&digits, radix)
+            .map(|n| n as f64)
+            .map_err(|_| "value out of range".to_string())
+    }
+
+    // e.g., 12, 3.14, 1_000, 1.5e-3. Matches 3\.1 but not 3\. or \.5; the dot is
+    // left for the caller so that `3.` scans as a number followed by a dot.
+    fn parse_decimal_number(&mut self) -> Result<f64, String> {
+        let mut number = self.scan_digits(|c| c.is_ascii_digit())?;
+
+        if self.current() == '.' && self.peek().is_ascii_digit() {
             self.advance();
+            number.push('.');
+            number.push_str(&self.scan_digits(|c| c.is_ascii_digit())?);
         }
 
-        // e.g., matches 3\.1 but not 3\.
-        if self.current() == '.' && self.peek().is_digit(10) {
+        // `1else` is a number followed by a keyword, not a malformed exponent.
+        if matches!(self.current(), 'e' | 'E') && !self.peek().is_alphabetic() {
             self.advance();
-            while self.current().is_digit(10) {
+            number.push('e');
+            if matches!(self.current(), '+' | '-') {
+                number.push(self.current());
                 self.advance();
             }
+
+            let exponent = self.scan_digits(|c| c.is_ascii_digit())?;
+            if exponent.is_empty() {
+                return Err("missing exponent digits".to_string());
+            }
+            number.push_str(&exponent);
         }
-        let end = self.cursor;
 
-        let number = self.source[start..end].iter().collect::<String>();
-        self.add_token_with_literal(
-            TokenType::Number,
-            &number,
-            Some(LiteralValue::Number(number.parse().unwrap())), // TODO(ben): handle error
-        );
+        number.parse().map_err(|_| "invalid number".to_string())
+    }
+
+    // Consumes digits accepted by `is_digit` along with `_` separators, and
+    // returns the digits without separators. A separator must sit between
+    // two digits.
+    fn scan_digits(&mut self, is_digit: impl Fn(char) -> bool) -> Result<String, String> {
+        let mut digits = String::new();
+        let mut separator_ok = false;
+        while is_digit(self.current()) || self.current() == '_' {
+            if self.current() == '_' {
+                if !separator_ok || !is_digit(self.peek()) {
+                    return Err("misplaced digit separator '_'".to_string());
+                }
+                separator_ok = false;
+            } else {
+                digits.push(self.current());
+                separator_ok = true;
+            }
+            self.advance();
+        }
+
+        Ok(digits)
     }
 
     fn parse_identifier(&mut self) {
@@ -171,53 +424,114 @@ impl Scanner {
         while self.current().is_alphanumeric() {
             self.advance();
         }
-        let end = self.cursor;
-
-        let text = self.source[start..end].iter().collect::<String>();
-        let (ttype, value) = get_keyword_token_type(&text);
-        self.add_token_with_literal(ttype, &text, value);
+        let text = &self.source[start..self.cursor];
+        let (ttype, value) = get_keyword_token_type(text);
+        self.add_token_with_literal(ttype, text, value);
     }
 
-    fn add_token(&mut self, ttype: TokenType, lexeme: &str) {
-        self.tokens.push(Token {
-            ttype,
-            lexeme: lexeme.to_string(),
-            literal: None,
-            line: self.line,
-        });
+    fn add_token(&mut self, ttype: TokenType, lexeme: &'src str) {
+        self.add_token_with_literal(ttype, lexeme, None);
     }
 
     fn add_token_with_literal(
         &mut self,
         ttype: TokenType,
-        lexeme: &str,
+        lexeme: &'src str,
         literal: Option<LiteralValue>,
     ) {
-        self.tokens.push(Token {
+        // a doc comment only documents the declaration that directly follows it,
+        // anywhere else it is kept as ordinary comments
+        let mut doc = self.doc.take();
+        if !matches!(ttype, TokenType::Fun | TokenType::Class | TokenType::Var) {
+            for (i, line) in doc
+                .take()
+                .iter()
+                .flat_map(|doc| doc.split('\n'))
+                .enumerate()
+            {
+                let text = match line {
+                    "" => "///".to_string(),
+                    line => format!("/// {}", line),
+                };
+                self.trivia.push(Trivia::Comment {
+                    text,
+                    line: self.doc_line + i,
+                    trailing: false,
+                });
+            }
+        }
+        self.blank_line = false;
+
+        self.emitted = true;
+        self.scanned = Some(Lexeme {
             ttype,
-            lexeme: lexeme.to_string(),
+            text: lexeme,
             literal,
             line: self.line,
+            column: self.start_column,
+            span: Span {
+                start: self.start,
+                end: self.cursor,
+            },
+            doc,
+            trivia: std::mem::take(&mut self.trivia),
         });
     }
 
-    fn current(&self) -> char {
-        if self.finished() {
-            return '\0';
+    // Consecutive blank lines are kept as one, and leading ones are dropped.
+    fn add_blank_line(&mut self) {
+        let leading = !self.emitted && self.trivia.is_empty();
+        if !leading && self.trivia.last() != Some(&Trivia::BlankLine) {
+            self.trivia.push(Trivia::BlankLine);
         }
-        self.source[self.cursor]
+    }
+
+    fn add_comment(&mut self, text: String, line: usize, trailing: bool) {
+        self.trivia.push(Trivia::Comment {
+            text,
+            line,
+            trailing,
+        });
+        self.blank_line = false;
+    }
+
+    fn error(&mut self, line: usize, column: usize, message: String) {
+        self.errors.push(ScanError {
+            line,
+            column,
+            message,
+        });
+    }
+
+    // Expects the newline to be consumed already.
+    fn new_line(&mut self) {
+        self.line += 1;
+        self.column = 1;
+        self.blank_line = true;
+    }
+
+    fn current(&self) -> char {
+        self.source[self.cursor..].chars().next().unwrap_or('\0')
     }
 
     fn advance(&mut self) {
-        self.cursor += 1;
+        if !self.finished() {
+            self.cursor += self.current().len_utf8();
+        }
+        self.column += 1;
     }
 
-    fn peek(&self) -> char {
-        if self.finished() || self.cursor + 1 >= self.source.len() {
-            return '\0';
+    fn matches(&mut self, expected: char) -> bool {
+        if self.current() == expected {
+            self.advance();
+            return true;
         }
 
-        self.source[self.cursor + 1]
+        false
+    }
+
+    fn peek(&self) -> char {
+        self.source[self.cursor..].chars().nth(1).unwrap_or('\0')
     }
 
     fn finished(&self) -> bool {
@@ -225,34 +539,70 @@ impl Scanner {
     }
 }
 
-#[cfg_attr(any(), rustfmt::skip)]
-fn get_keyword_token_type(text: &str) -> (TokenType, Option<LiteralValue>) {
-    match text {
-        "and"    => (TokenType::And,    None),
-        "class"  => (TokenType::Class,  None),
-        "else"   => (TokenType::Else,   None),
-        "false"  => (TokenType::False,  Some(LiteralValue::Boolean(false))),
-        "for"    => (TokenType::For,    None),
-        "fun"    => (TokenType::Fun,    None),
-        "if"     => (TokenType::If,     None),
-        "nil"    => (TokenType::Nil,    Some(LiteralValue::Nil)),
-        "or"     => (TokenType::Or,     None),
-        "print"  => (TokenType::Print,  None),
-        "return" => (TokenType::Return, None),
-        "super"  => (TokenType::Super,  None),
-        "this"   => (TokenType::This,   None),
-        "true"   => (TokenType::True,   Some(LiteralValue::Boolean(true))),
-        "var"    => (TokenType::Var,    None),
-        "while"  => (TokenType::While,  None),
-        _        => (TokenType::Identifier, None),
+impl<'src> Iterator for Scanner<'src> {
+    type Item = Lexeme<'src>;
+
+    // The last token is always `EOF`.
+    fn next(&mut self) -> Option<Lexeme<'src>> {
+        self.scan_token();
+        if self.scanned.is_none() && !self.finished {
+            self.finished = true;
+            self.start = self.cursor;
+            self.start_column = self.column;
+            self.add_token(TokenType::EOF, "");
+        }
+        self.scanned.take()
     }
 }
 
+// The reserved words and the tokens they scan as, for the scanner and for
+// completion in the REPL.
+pub const KEYWORDS: [(&str, TokenType); 23] = [
+    ("and", TokenType::And),
+    ("as", TokenType::As),
+    ("catch", TokenType::Catch),
+    ("class", TokenType::Class),
+    ("else", TokenType::Else),
+    ("false", TokenType::False),
+    ("finally", TokenType::Finally),
+    ("for", TokenType::For),
+    ("from", TokenType::From),
+    ("fun", TokenType::Fun),
+    ("if", TokenType::If),
+    ("import", TokenType::Import),
+    ("nil", TokenType::Nil),
+    ("or", TokenType::Or),
+    ("print", TokenType::Print),
+    ("return", TokenType::Return),
+    ("super", TokenType::Super),
+    ("this", TokenType::This),
+    ("throw", TokenType::Throw),
+    ("true", TokenType::True),
+    ("try", TokenType::Try),
+    ("var", TokenType::Var),
+    ("while", TokenType::While),
+];
+
+pub fn get_keyword_token_type(text: &str) -> (TokenType, Option<LiteralValue>) {
+    let ttype = KEYWORDS
+        .iter()
+        .find(|(keyword, _)| *keyword == text)
+        .map_or(TokenType::Identifier, |(_, ttype)| ttype.clone());
+    let value = match ttype {
+        TokenType::False => Some(LiteralValue::Boolean(false)),
+        TokenType::True => Some(LiteralValue::Boolean(true)),
+        TokenType::Nil => Some(LiteralValue::Nil),
+        _ => None,
+    };
+    (ttype, value)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
 
     #[test]
+    #[allow(clippy::approx_constant)]
     fn test_scanner() {
         let source = r#"
             // This is a comment
@@ -265,66 +615,69 @@ mod tests {
             }
         "#;
 
-        let mut scanner = Scanner::new(source.to_string());
+        let mut scanner = Scanner::new(source);
         let tokens = scanner.scan_tokens();
         let expected = vec![
-            (TokenType::Var, "var", None, 2),
-            (TokenType::Identifier, "x", None, 2),
-            (TokenType::Equal, "=", None, 2),
+            (TokenType::Var, "var", None, 3),
+            (TokenType::Identifier, "x", None, 3),
+            (TokenType::Equal, "=", None, 3),
             (
                 TokenType::Number,
                 "3.14",
                 Some(LiteralValue::Number(3.14)),
-                2,
+                3,
             ),
-            (TokenType::Semicolon, ";", None, 2),
-            (TokenType::Var, "var", None, 3),
-            (TokenType::Identifier, "y", None, 3),
-            (TokenType::Equal, "=", None, 3),
+            (TokenType::Semicolon, ";", None, 3),
+            (TokenType::Var, "var", None, 4),
+            (TokenType::Identifier, "y", None, 4),
+            (TokenType::Equal, "=", None, 4),
             (
                 TokenType::String,
                 "Hello, world!",
-                Some(LiteralValue::String("Hello, world!".to_string())),
-                3,
+                Some(LiteralValue::String(Symbol::intern("Hello, world!"))),
+                4,
             ),
-            (TokenType::Semicolon, ";", None, 3),
-            (TokenType::If, "if", None, 4),
-            (TokenType::LeftParen, "(", None, 4),
-            (TokenType::Identifier, "x", None, 4),
-            (TokenType::LessEqual, "<=", None, 4),
+            (TokenType::Semicolon, ";", None, 4),
+            (TokenType::If, "if", None, 5),
+            (TokenType::LeftParen, "(", None, 5),
+            (TokenType::Identifier, "x", None, 5),
+            (TokenType::LessEqual, "<=", None, 5),
             (
                 TokenType::Number,
                 "3.14",
                 Some(LiteralValue::Number(3.14)),
-                4,
+                5,
             ),
-            (TokenType::RightParen, ")", None, 4),
-            (TokenType::LeftBrace, "{", None, 4),
-            (TokenType::Print, "print", None, 5),
-            (TokenType::Identifier, "y", None, 5),
-            (TokenType::Semicolon, ";", None, 5),
-            (TokenType::RightBrace, "}", None, 6),
-            (TokenType::Else, "else", None, 6),
-            (TokenType::LeftBrace, "{", None, 6),
-            (TokenType::Print, "print", None, 7),
+            (TokenType::RightParen, ")", None, 5),
+            (TokenType::LeftBrace, "{", None, 5),
+            (TokenType::Print, "print", None, 6),
+            (TokenType::Identifier, "y", None, 6),
+            (TokenType::Semicolon, ";", None, 6),
+            (TokenType::RightBrace, "}", None, 7),
+            (TokenType::Else, "else", None, 7),
+            (TokenType::LeftBrace, "{", None, 7),
+            (TokenType::Print, "print", None, 8),
             (
                 TokenType::String,
                 "x is greater than pi",
-                Some(LiteralValue::String("x is greater than pi".to_string())),
-                7,
+                Some(LiteralValue::String(Symbol::intern("x is greater than pi"))),
+                8,
             ),
-            (TokenType::Semicolon, ";", None, 7),
-            (TokenType::RightBrace, "}", None, 8),
-            (TokenType::EOF, "", None, 9),
+            (TokenType::Semicolon, ";", None, 8),
+            (TokenType::RightBrace, "}", None, 9),
+            (TokenType::EOF, "", None, 10),
         ];
 
         let expected = expected
             .into_iter()
             .map(|(ttype, lexeme, literal, line)| Token {
                 ttype,
-                lexeme: lexeme.to_string(),
+                lexeme: Symbol::intern(lexeme),
                 literal,
                 line,
+                column: 0,
+                doc: None,
+                trivia: Vec::new(),
             })
             .collect::<Vec<Token>>();
 
@@ -337,4 +690,285 @@ mod tests {
             // TODO(ben): check literals
         }
     }
+
+    fn scan(source: &str) -> (Vec<Token>, Vec<ScanError>) {
+        let mut scanner = Scanner::new(source);
+        let tokens = scanner.scan_tokens();
+        (tokens, scanner.errors().to_vec())
+    }
+
+    fn number(token: &Token) -> f64 {
+        match token.literal {
+            Some(LiteralValue::Number(n)) => n,
+            _ => panic!("expected a number literal, got {:?}", token),
+        }
+    }
+
+    #[test]
+    fn test_number_literals() {
+        let cases = [
+            ("0", 0.0),
+            ("42", 42.0),
+            ("3.25", 3.25),
+            ("1_000_000", 1_000_000.0),
+            ("1_0.5_0", 10.5),
+            ("0x1F", 31.0),
+            ("0XfF", 255.0),
+            ("0xdead_beef", 3_735_928_559.0),
+            ("0o17", 15.0),
+            ("0b1010", 10.0),
+            ("0b1111_0000", 240.0),
+            ("1e3", 1000.0),
+            ("1.5e-3", 0.0015),
+            ("2E+2", 200.0),
+            ("6.02e2_3", 6.02e23),
+        ];
+
+        for (source, expected) in cases {
+            let (tokens, errors) = scan(source);
+            assert!(errors.is_empty(), "{}: {:?}", source, errors);
+            assert_eq!(tokens.len(), 2, "{}", source);
+            assert_eq!(tokens[0].ttype, TokenType::Number);
+            assert_eq!(tokens[0].lexeme, source);
+            assert_eq!(number(&tokens[0]), expected, "{}", source);
+        }
+    }
+
+    #[test]
+    fn test_number_trailing_and_leading_dot() {
+        let (tokens, errors) = scan("3.");
+        assert!(errors.is_empty());
+        assert_eq!(tokens[0].ttype, TokenType::Number);
+        assert_eq!(number(&tokens[0]), 3.0);
+        assert_eq!(tokens[1].ttype, TokenType::Dot);
+        assert_eq!(tokens[2].ttype, TokenType::EOF);
+
+        let (tokens, errors) = scan(".5");
+        assert!(errors.is_empty());
+        assert_eq!(tokens[0].ttype, TokenType::Dot);
+        assert_eq!(tokens[1].ttype, TokenType::Number);
+        assert_eq!(number(&tokens[1]), 5.0);
+        assert_eq!(tokens[2].ttype, TokenType::EOF);
+    }
+
+    #[test]
+    fn test_number_followed_by_identifier() {
+        let (tokens, errors) = scan("1else");
+        assert!(errors.is_empty());
+        assert_eq!(tokens[0].ttype, TokenType::Number);
+        assert_eq!(tokens[1].ttype, TokenType::Else);
+    }
+
+    #[test]
+    fn test_malformed_number_literals() {
+        let cases = [
+            ("0x", "missing hexadecimal digits in number literal '0x'"),
+            ("0b", "missing binary digits in number literal '0b'"),
+            (
+                "0b102",
+                "invalid binary digit '2' in number literal '0b102'",
+            ),
+            ("0o8", "invalid octal digit '8' in number literal '0o8'"),
+            (
+                "0xfg",
+                "invalid hexadecimal digit 'g' in number literal '0xfg'",
+            ),
+            (
+                "1__0",
+                "misplaced digit separator '_' in number literal '1__0'",
+            ),
+            (
+                "10_",
+                "misplaced digit separator '_' in number literal '10_'",
+            ),
+            (
+                "0x_1",
+                "misplaced digit separator '_' in number literal '0x_1'",
+            ),
+            ("1e", "missing exponent digits in number literal '1e'"),
+            ("1.5e+", "missing exponent digits in number literal '1.5e+'"),
+            (
+                "0x1_0000_0000_0000_0000",
+                "value out of range in number literal '0x1_0000_0000_0000_0000'",
+            ),
+        ];
+
+        // reported where the literal starts, which is still a token, so
+        // nothing else is reported after it
+        for (source, message) in cases {
+            let (tokens, errors) = scan(&format!("var a = {};", source));
+            assert_eq!(errors.len(), 1, "{}: {:?}", source, errors);
+            assert_eq!(errors[0].message, message);
+            assert_eq!((errors[0].line, errors[0].column), (1, 9));
+            assert_eq!(tokens.len(), 6, "{}: {:?}", source, tokens);
+            assert_eq!(tokens[3].ttype, TokenType::Number);
+            assert!(tokens[3].literal.is_none());
+
+            let mut parser = crate::parser::Parser::new(tokens);
+            parser.parse();
+            assert!(
+                parser.errors().is_empty(),
+                "{}: {:?}",
+                source,
+                parser.errors()
+            );
+        }
+    }
+
+    #[test]
+    fn test_keywords() {
+        for (keyword, ttype) in KEYWORDS {
+            let (tokens, _) = scan(keyword);
+            assert_eq!(tokens[0].ttype, ttype);
+        }
+        assert!(matches!(
+            get_keyword_token_type("nil").1,
+            Some(LiteralValue::Nil)
+        ));
+        assert_eq!(get_keyword_token_type("classy").0, TokenType::Identifier);
+    }
+
+    #[test]
+    fn test_block_comments() {
+        let source = "1 /* one\n /* nested\n */ still a comment */ + 2\n3";
+        let (tokens, errors) = scan(source);
+        assert!(errors.is_empty(), "{:?}", errors);
+
+        let scanned = tokens
+            .iter()
+            .map(|t| (t.ttype.clone(), t.line))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            scanned,
+            vec![
+                (TokenType::Number, 1),
+                (TokenType::Plus, 3),
+                (TokenType::Number, 3),
+                (TokenType::Number, 4),
+                (TokenType::EOF, 4),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_unterminated_block_comment() {
+        let (tokens, errors) = scan("1\n/* outer /* inner */\n");
+        assert_eq!(
+            errors,
+            vec![ScanError {
+                line: 2,
+                column: 1,
+                message: "unterminated block comment".to_string(),
+            }]
+        );
+        assert_eq!(tokens.len(), 2);
+    }
+
+    #[test]
+    fn test_unterminated_string() {
+        let (tokens, errors) = scan("var s =\n  \"open;\nprint s;\n");
+        assert_eq!(
+            errors,
+            vec![ScanError {
+                line: 2,
+                column: 3,
+                message: "unterminated string".to_string(),
+            }]
+        );
+        assert_eq!(tokens.len(), 4);
+    }
+
+    #[test]
+    fn test_doc_comments() {
+        let source = r#"
+            /// The answer.
+            ///   Indented line.
+            var x = 42;
+            //// not a doc comment
+            var y;
+            /// dangling
+            print x;
+            var z;
+        "#;
+        let (tokens, errors) = scan(source);
+        assert!(errors.is_empty(), "{:?}", errors);
+
+        let docs = tokens
+            .iter()
+            .filter(|t| t.doc.is_some())
+            .map(|t| (t.line, t.doc.clone().unwrap()))
+            .collect::<Vec<_>>();
+        assert_eq!(docs, vec![(4, "The answer.\n  Indented line.".to_string())]);
+    }
+
+    #[test]
+    fn test_spans() {
+        let source = "var é = \"hé\" >= 10;";
+        let spans = Scanner::new(source)
+            .map(|t| &source[t.span.start..t.span.end])
+            .collect::<Vec<_>>();
+        assert_eq!(spans, vec!["var", "é", "=", "\"hé\"", ">=", "10", ";", ""]);
+    }
+
+    #[test]
+    fn test_unicode_columns() {
+        let source = "var café = \"日本\";\n  print café;";
+        let scanned = Scanner::new(source)
+            .map(|t| (t.text, t.line, t.column))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            scanned,
+            vec![
+                ("var", 1, 1),
+                ("café", 1, 5),
+                ("=", 1, 10),
+                ("日本", 1, 12),
+                (";", 1, 16),
+                ("print", 2, 3),
+                ("café", 2, 9),
+                (";", 2, 13),
+                ("", 2, 14),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_lazy() {
+        let mut scanner = Scanner::new("print 1; @");
+        assert_eq!(scanner.next().unwrap().ttype, TokenType::Print);
+        assert!(scanner.errors().is_empty());
+
+        let rest = scanner.by_ref().map(|t| t.ttype).collect::<Vec<_>>();
+        assert_eq!(
+            rest,
+            vec![TokenType::Number, TokenType::Semicolon, TokenType::EOF]
+        );
+        assert_eq!(scanner.errors().len(), 1);
+        assert!(scanner.next().is_none());
+    }
+
+    #[test]
+    fn test_scan_partial() {
+        let source = "print 0b12 + \"open";
+        let mut scanner = Scanner::new(source).partial();
+        let tokens = scanner.by_ref().collect::<Vec<_>>();
+        assert_eq!(scanner.errors().len(), 2);
+
+        let scanned = tokens
+            .iter()
+            .map(|t| (t.ttype.clone(), &source[t.span.start..t.span.end]))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            scanned,
+            vec![
+                (TokenType::Print, "print"),
+                (TokenType::Number, "0b12"),
+                (TokenType::Plus, "+"),
+                (TokenType::String, "\"open"),
+                (TokenType::EOF, ""),
+            ]
+        );
+        assert!(tokens[1].literal.is_none());
+        assert!(tokens[3].literal.is_none());
+    }
 }
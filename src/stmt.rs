@@ -0,0 +1,198 @@
+use std::rc::Rc;
+
+use crate::{
+    expr::Expr,
+    token::{Token, Trivia},
+};
+
+#[derive(Debug, Clone)]
+pub enum Stmt {
+    Expression {
+        expr: Expr,
+    },
+    Print {
+        expr: Expr,
+    },
+    Var {
+        name: Token,
+        initializer: Option<Expr>,
+        doc: Option<String>,
+    },
+    Block {
+        statements: Vec<Stmt>,
+    },
+    If {
+        keyword: Token,
+        condition: Expr,
+        then_branch: Box<Stmt>,
+        else_branch: Option<Box<Stmt>>,
+    },
+    While {
+        condition: Expr,
+        body: Box<Stmt>,
+    },
+    For {
+        initializer: Option<Box<Stmt>>,
+        condition: Option<Expr>,
+        increment: Option<Expr>,
+        body: Box<Stmt>,
+    },
+    // shared with the functions created from it, which outlive the statement
+    Function(Rc<FunctionDecl>),
+    Return {
+        keyword: Token,
+        value: Option<Expr>,
+    },
+    Throw {
+        keyword: Token,
+        value: Expr,
+    },
+    // at least one of `catch` and `finally`
+    Try {
+        keyword: Token,
+        body: Vec<Stmt>,
+        catch: Option<Catch>,
+        finally: Option<Vec<Stmt>>,
+    },
+    // `import "path" as alias;` binds the module itself, and
+    // `from "path" import a, b;` some of its variables, leaving `alias` None.
+    Import {
+        keyword: Token,
+        path: Token,
+        alias: Option<Token>,
+        names: Vec<Token>,
+    },
+    // comments and blank lines between statements, only used by the formatter
+    Trivia(Trivia),
+}
+
+#[derive(Debug, Clone)]
+pub struct FunctionDecl {
+    pub name: Token,
+    pub params: Vec<Token>,
+    pub body: Vec<Stmt>,
+    pub doc: Option<String>,
+}
+
+// `catch (name) { body }`, with the error caught in `name`.
+#[derive(Debug, Clone)]
+pub struct Catch {
+    pub name: Token,
+    pub body: Vec<Stmt>,
+}
+
+impl Stmt {
+    pub fn accept<R, V: Visitor<R>>(&self, visitor: V) -> R {
+        match self {
+            Stmt::Expression { expr } => visitor.visit_expression(expr),
+            Stmt::Print { expr } => visitor.visit_print(expr),
+            Stmt::Var {
+                name,
+                initializer,
+                doc,
+            } => visitor.visit_var(name, initializer.as_ref(), doc.as_deref()),
+            Stmt::Block { statements } => visitor.visit_block(statements),
+            Stmt::If {
+                keyword,
+                condition,
+                then_branch,
+                else_branch,
+            } => visitor.visit_if(keyword, condition, then_branch, else_branch.as_deref()),
+            Stmt::While { condition, body } => visitor.visit_while(condition, body),
+            Stmt::For {
+                initializer,
+                condition,
+                increment,
+                body,
+            } => visitor.visit_for(
+                initializer.as_deref(),
+                condition.as_ref(),
+                increment.as_ref(),
+                body,
+            ),
+            Stmt::Function(declaration) => visitor.visit_function(declaration),
+            Stmt::Return { keyword, value } => visitor.visit_return(keyword, value.as_ref()),
+            Stmt::Throw { keyword, value } => visitor.visit_throw(keyword, value),
+            Stmt::Try {
+                keyword,
+                body,
+                catch,
+                finally,
+            } => visitor.visit_try(keyword, body, catch.as_ref(), finally.as_deref()),
+            Stmt::Import {
+                keyword,
+                path,
+                alias,
+                names,
+            } => visitor.visit_import(keyword, path, alias.as_ref(), names),
+            Stmt::Trivia(trivia) => visitor.visit_trivia(trivia),
+        }
+    }
+
+    // The line of the first token it keeps, which is where it starts but for
+    // the keywords of `print`, `if`, `while` and `for`. Blocks keep none.
+    pub fn line(&self) -> Option<usize> {
+        match self {
+            Stmt::Expression { expr } | Stmt::Print { expr } => Some(expr.line()),
+            Stmt::Var { name, .. } => Some(name.line),
+            Stmt::Block { .. } | Stmt::Trivia(_) => None,
+            Stmt::If { condition, .. } | Stmt::While { condition, .. } => Some(condition.line()),
+            Stmt::For {
+                initializer,
+                condition,
+                increment,
+                body,
+            } => initializer
+                .as_ref()
+                .and_then(|stmt| stmt.line())
+                .or(condition.as_ref().map(Expr::line))
+                .or(increment.as_ref().map(Expr::line))
+                .or_else(|| body.line()),
+            Stmt::Function(declaration) => Some(declaration.name.line),
+            Stmt::Return { keyword, .. }
+            | Stmt::Throw { keyword, .. }
+            | Stmt::Try { keyword, .. }
+            | Stmt::Import { keyword, .. } => Some(keyword.line),
+        }
+    }
+}
+
+pub trait Visitor<R> {
+    fn visit_expression(self, expr: &Expr) -> R;
+    fn visit_print(self, expr: &Expr) -> R;
+    fn visit_var(self, name: &Token, initializer: Option<&Expr>, doc: Option<&str>) -> R;
+    fn visit_block(self, statements: &[Stmt]) -> R;
+    fn visit_if(
+        self,
+        keyword: &Token,
+        condition: &Expr,
+        then_branch: &Stmt,
+        else_branch: Option<&Stmt>,
+    ) -> R;
+    fn visit_while(self, condition: &Expr, body: &Stmt) -> R;
+    fn visit_for(
+        self,
+        initializer: Option<&Stmt>,
+        condition: Option<&Expr>,
+        increment: Option<&Expr>,
+        body: &Stmt,
+    ) -> R;
+    fn visit_function(self, declaration: &Rc<FunctionDecl>) -> R;
+    fn visit_return(self, keyword: &Token, value: Option<&Expr>) -> R;
+    fn visit_throw(self, keyword: &Token, value: &Expr) -> R;
+    fn visit_try(
+        self,
+        keyword: &Token,
+        body: &[Stmt],
+        catch: Option<&Catch>,
+        finally: Option<&[Stmt]>,
+    ) -> R;
+    fn visit_import(
+        self,
+        keyword: &Token,
+        path: &Token,
+        alias: Option<&Token>,
+        names: &[Token],
+    ) -> R;
+    fn visit_trivia(self, trivia: &Trivia) -> R;
+}
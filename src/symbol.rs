@@ -0,0 +1,140 @@
+use std::{
+    cell::RefCell,
+    collections::HashMap,
+    fmt,
+    hash::{Hash, Hasher},
+    ops::Deref,
+    rc::{Rc, Weak},
+};
+
+// An interned string. Lexemes and string literals are stored once, so
+// cloning a symbol is counting a reference, and comparing or hashing one
+// never looks at the text.
+#[derive(Clone)]
+pub struct Symbol(Rc<str>);
+
+// Where each string in use was interned. A string is freed with its last
+// symbol, so editors scanning the source over and over only keep the text
+// that is still there; the table forgets it the next time it is purged.
+struct Table {
+    symbols: HashMap<Box<str>, Weak<str>>,
+    // the number of entries to purge the freed strings at
+    purge_at: usize,
+}
+
+// Purges are rare at first, then once the table doubles.
+const FIRST_PURGE: usize = 1024;
+
+thread_local! {
+    static TABLE: RefCell<Table> = RefCell::new(Table {
+        symbols: HashMap::new(),
+        purge_at: FIRST_PURGE,
+    });
+}
+
+impl Symbol {
+    pub fn intern(string: &str) -> Symbol {
+        TABLE.with(|table| {
+            let mut table = table.borrow_mut();
+            if let Some(symbol) = table.symbols.get(string).and_then(Weak::upgrade) {
+                return Symbol(symbol);
+            }
+
+            if table.symbols.len() >= table.purge_at {
+                table.symbols.retain(|_, symbol| symbol.strong_count() > 0);
+                table.purge_at = (table.symbols.len() * 2).max(FIRST_PURGE);
+            }
+            let symbol: Rc<str> = Rc::from(string);
+            table.symbols.insert(string.into(), Rc::downgrade(&symbol));
+            Symbol(symbol)
+        })
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+
+    // The text itself, for values that keep it without copying it.
+    pub fn to_rc(&self) -> Rc<str> {
+        Rc::clone(&self.0)
+    }
+}
+
+// Interned strings are equal only to themselves.
+impl PartialEq for Symbol {
+    fn eq(&self, other: &Self) -> bool {
+        Rc::ptr_eq(&self.0, &other.0)
+    }
+}
+
+impl Eq for Symbol {}
+
+impl Hash for Symbol {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        std::ptr::hash(Rc::as_ptr(&self.0) as *const u8, state);
+    }
+}
+
+impl Deref for Symbol {
+    type Target = str;
+
+    fn deref(&self) -> &str {
+        self.as_str()
+    }
+}
+
+impl From<&str> for Symbol {
+    fn from(string: &str) -> Self {
+        Symbol::intern(string)
+    }
+}
+
+impl PartialEq<str> for Symbol {
+    fn eq(&self, other: &str) -> bool {
+        self.as_str() == other
+    }
+}
+
+impl PartialEq<&str> for Symbol {
+    fn eq(&self, other: &&str) -> bool {
+        self.as_str() == *other
+    }
+}
+
+impl fmt::Debug for Symbol {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:?}", self.as_str())
+    }
+}
+
+impl fmt::Display for Symbol {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_intern() {
+        let a = Symbol::intern("counter");
+        let b = Symbol::intern(&String::from("counter"));
+        assert_eq!(a, b);
+        assert_ne!(a, Symbol::intern("count"));
+        assert_eq!(a.as_str(), "counter");
+        assert_eq!(a, "counter");
+        assert_eq!(format!("{} {:?}", a, a), "counter \"counter\"");
+    }
+
+    #[test]
+    fn test_freed_strings_are_purged() {
+        let kept = Symbol::intern("kept");
+        for i in 0..FIRST_PURGE * 4 {
+            Symbol::intern(&format!("typed{}", i));
+        }
+        TABLE.with(|table| assert!(table.borrow().symbols.len() <= FIRST_PURGE + 1));
+        assert_eq!(Symbol::intern("kept"), kept);
+    }
+}
@@ -1,15 +1,76 @@
+use crate::symbol::Symbol;
+
 #[derive(Debug, Clone)]
 pub struct Token {
     pub ttype: TokenType,
-    pub lexeme: String,
+    pub lexeme: Symbol,
+    pub literal: Option<LiteralValue>,
+    pub line: usize,
+    // 1-based, counted in characters
+    pub column: usize,
+    // `///` doc comment preceding a `fun`, `class` or `var` keyword.
+    pub doc: Option<String>,
+    // comments and blank lines between the previous token and this one
+    pub trivia: Vec<Trivia>,
+}
+
+// A token as scanned, its text borrowed from the source. Tools that only
+// look at the tokens, like the highlighter, use these as they are, and the
+// parser gets `Token`s with the text interned, since syntax trees may outlive
+// the source they were parsed from.
+#[derive(Debug, Clone)]
+pub struct Lexeme<'src> {
+    pub ttype: TokenType,
+    pub text: &'src str,
     pub literal: Option<LiteralValue>,
     pub line: usize,
+    pub column: usize,
+    // where `text` is in the source, with the quotes of a string
+    pub span: Span,
+    pub doc: Option<String>,
+    pub trivia: Vec<Trivia>,
+}
+
+impl From<Lexeme<'_>> for Token {
+    fn from(lexeme: Lexeme<'_>) -> Self {
+        Token {
+            ttype: lexeme.ttype,
+            lexeme: Symbol::intern(lexeme.text),
+            literal: lexeme.literal,
+            line: lexeme.line,
+            column: lexeme.column,
+            doc: lexeme.doc,
+            trivia: lexeme.trivia,
+        }
+    }
+}
+
+// Source text that isn't part of the program but is kept for the formatter.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Trivia {
+    // `text` includes the `//` or `/* */`, and `line` is where it starts. A
+    // trailing comment starts on the same line as the token before it.
+    Comment {
+        text: String,
+        line: usize,
+        trailing: bool,
+    },
+    // one or more empty lines
+    BlankLine,
+}
+
+// Byte offsets into the source, `end` being exclusive. For strings this
+// includes the quotes, unlike the lexeme.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
 }
 
 #[derive(Debug, Clone)]
 pub enum LiteralValue {
     Number(f64),
-    String(String),
+    String(Symbol),
     Boolean(bool),
     Nil,
 }
@@ -25,6 +86,7 @@ impl std::fmt::Display for LiteralValue {
     }
 }
 
+#[allow(clippy::upper_case_acronyms)]
 #[derive(Debug, Clone, PartialEq)]
 pub enum TokenType {
     // Single-character tokens
@@ -57,19 +119,26 @@ pub enum TokenType {
 
     // Keywords
     And,
+    As,
+    Catch,
     Class,
     Else,
     False,
+    Finally,
     Fun,
     For,
+    From,
     If,
+    Import,
     Nil,
     Or,
     Print,
     Return,
     Super,
     This,
+    Throw,
     True,
+    Try,
     Var,
     While,
 
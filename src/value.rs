@@ -0,0 +1,178 @@
+use std::{cell::RefCell, collections::HashSet, fmt, rc::Rc};
+
+use crate::{
+    environment::Environment, expr::RuntimeError, stmt::FunctionDecl, symbol::Symbol,
+    token::LiteralValue, vm::Closure,
+};
+
+// A value at runtime. Literals from the source become numbers, strings,
+// booleans or nil; everything else only exists while the program runs.
+#[derive(Debug, Clone)]
+pub enum Value {
+    Number(f64),
+    String(Rc<str>),
+    Boolean(bool),
+    Nil,
+    Native(Rc<NativeFunction>),
+    Function(Rc<LoxFunction>),
+    // a function compiled for the bytecode VM
+    Closure(Rc<Closure>),
+    List(Rc<RefCell<Vec<Value>>>),
+    Module(Rc<Module>),
+    // caught by a `catch`, or thrown
+    Error(Rc<RuntimeError>),
+}
+
+pub struct LoxFunction {
+    pub declaration: Rc<FunctionDecl>,
+    pub closure: Rc<RefCell<Environment>>,
+    // those of the module it was declared in, or the script
+    pub globals: Rc<RefCell<Environment>>,
+}
+
+// The closure may contain the function itself, so it is left out.
+impl fmt::Debug for LoxFunction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "<fn {}>", self.declaration.name.lexeme)
+    }
+}
+
+// A module, whose top-level variables are in a global environment of its
+// own.
+pub struct Module {
+    // the path it was found at
+    pub name: String,
+    pub globals: Rc<RefCell<Environment>>,
+    // the variables declared at its top level, leaving out the natives
+    pub exports: HashSet<Symbol>,
+}
+
+impl Module {
+    // The variable `name`, looked up on `line`.
+    pub fn get(&self, name: &Symbol, line: usize) -> Result<Value, RuntimeError> {
+        let value = match self.exports.contains(name) {
+            true => self.globals.borrow().values().get(name).cloned(),
+            false => {
+                let message = format!("Module '{}' has no variable '{}'.", self.name, name);
+                return Err(RuntimeError::at(line, message));
+            }
+        };
+        value.ok_or_else(|| RuntimeError::at(line, format!("Undefined variable '{}'.", name)))
+    }
+}
+
+// Its variables may include functions holding its environment.
+impl fmt::Debug for Module {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "<module {}>", self.name)
+    }
+}
+
+pub struct NativeFunction {
+    pub name: &'static str,
+    pub arity: usize,
+    #[allow(clippy::type_complexity)]
+    pub function: Box<dyn Fn(&[Value]) -> Result<Value, String>>,
+}
+
+impl fmt::Debug for NativeFunction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "<native fn {}>", self.name)
+    }
+}
+
+impl Value {
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            Value::Number(_) => "number",
+            Value::String(_) => "string",
+            Value::Boolean(_) => "boolean",
+            Value::Nil => "nil",
+            Value::Native(_) | Value::Function(_) | Value::Closure(_) => "function",
+            Value::List(_) => "list",
+            Value::Module(_) => "module",
+            Value::Error(_) => "error",
+        }
+    }
+
+    // The property `name` of a module or an error, looked up on `line`.
+    pub fn property(&self, name: &Symbol, line: usize) -> Result<Value, RuntimeError> {
+        match self {
+            Value::Module(module) => module.get(name, line),
+            Value::Error(error) => error.property(name, line),
+            _ => Err(RuntimeError::at(
+                line,
+                "Only modules and errors have properties.".to_string(),
+            )),
+        }
+    }
+
+    // `nil` and `false` are falsey, everything else is truthy.
+    pub fn is_truthy(&self) -> bool {
+        !matches!(self, Value::Nil | Value::Boolean(false))
+    }
+
+    // Values of different types are never equal. Functions, lists, modules
+    // and errors are equal only to themselves.
+    pub fn is_equal(&self, other: &Value) -> bool {
+        match (self, other) {
+            (Value::Number(l), Value::Number(r)) => l == r,
+            (Value::String(l), Value::String(r)) => l == r,
+            (Value::Boolean(l), Value::Boolean(r)) => l == r,
+            (Value::Nil, Value::Nil) => true,
+            (Value::Native(l), Value::Native(r)) => Rc::ptr_eq(l, r),
+            (Value::Function(l), Value::Function(r)) => Rc::ptr_eq(l, r),
+            (Value::Closure(l), Value::Closure(r)) => Rc::ptr_eq(l, r),
+            (Value::List(l), Value::List(r)) => Rc::ptr_eq(l, r),
+            (Value::Module(l), Value::Module(r)) => Rc::ptr_eq(l, r),
+            (Value::Error(l), Value::Error(r)) => Rc::ptr_eq(l, r),
+            _ => false,
+        }
+    }
+}
+
+impl From<&LiteralValue> for Value {
+    fn from(literal: &LiteralValue) -> Self {
+        match literal {
+            LiteralValue::Number(n) => Value::Number(*n),
+            LiteralValue::String(s) => Value::String(s.to_rc()),
+            LiteralValue::Boolean(b) => Value::Boolean(*b),
+            LiteralValue::Nil => Value::Nil,
+        }
+    }
+}
+
+// Strings are quoted, as in the REPL. Use `stringify` for `print`.
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Value::Number(n) => write!(f, "{}", n),
+            Value::String(s) => write!(f, "\"{}\"", s),
+            Value::Boolean(b) => write!(f, "{}", b),
+            Value::Nil => write!(f, "nil"),
+            Value::Native(native) => write!(f, "<native fn {}>", native.name),
+            Value::Function(function) => write!(f, "<fn {}>", function.declaration.name.lexeme),
+            Value::Closure(closure) => write!(f, "<fn {}>", closure.function.name),
+            Value::List(list) => {
+                write!(f, "[")?;
+                for (i, value) in list.borrow().iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{}", value)?;
+                }
+                write!(f, "]")
+            }
+            Value::Module(module) => write!(f, "{:?}", module),
+            Value::Error(error) => write!(f, "<error: {}>", error.message),
+        }
+    }
+}
+
+// Unlike `Display`, strings are printed without quotes.
+pub fn stringify(value: &Value) -> String {
+    match value {
+        Value::String(s) => s.to_string(),
+        value => value.to_string(),
+    }
+}
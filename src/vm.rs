@@ -0,0 +1,767 @@
+use std::{cell::RefCell, fmt, io::Write, rc::Rc};
+
+use crate::{
+    backend::MAX_DEPTH,
+    chunk::{Constant, Function, OpCode},
+    compiler, disassembler,
+    environment::Environment,
+    expr::{Expr, RuntimeError, TraceFrame},
+    gc::{Heap, Object},
+    module::{self, Modules},
+    natives,
+    stmt::Stmt,
+    symbol::Symbol,
+    value::{stringify, Module, Value},
+};
+
+// A function along with the variables it closed over.
+pub struct Closure {
+    pub function: Rc<Function>,
+    pub upvalues: Vec<Rc<RefCell<Upvalue>>>,
+    // those of the module it was declared in, or the script
+    pub globals: Rc<RefCell<Environment>>,
+}
+
+// The upvalues may contain the closure itself, so they are left out.
+impl fmt::Debug for Closure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "<fn {}>", self.function.name)
+    }
+}
+
+// A captured variable, on the stack while its scope is running and moved
+// into the upvalue once the scope ends.
+pub enum Upvalue {
+    Open(usize),
+    Closed(Value),
+}
+
+struct CallFrame {
+    closure: Rc<Closure>,
+    ip: usize,
+    // stack slot of the function being called, followed by its locals
+    base: usize,
+    // the module whose top level this is, imported once it returns
+    module: Option<Rc<Module>>,
+}
+
+impl CallFrame {
+    fn read_byte(&mut self) -> u8 {
+        let byte = self.closure.function.chunk.code[self.ip];
+        self.ip += 1;
+        byte
+    }
+
+    fn read_u16(&mut self) -> u16 {
+        let value = self.closure.function.chunk.read_u16(self.ip);
+        self.ip += 2;
+        value
+    }
+
+    fn constant(&mut self) -> &Constant {
+        let index = self.read_u16() as usize;
+        &self.closure.function.chunk.constants[index]
+    }
+
+    fn name(&mut self) -> Symbol {
+        match self.constant() {
+            Constant::Name(name) => name.clone(),
+            constant => unreachable!("global names are strings, not {:?}", constant),
+        }
+    }
+}
+
+// What importing a module leaves to do: nothing if it ran already, or
+// running its top level, which returns the module.
+enum Import {
+    Ran(Rc<Module>),
+    Run(Rc<Closure>, Rc<Module>),
+}
+
+// Runs the bytecode produced by `compiler`, with the same semantics as
+// `expr::Evaluation`. Globals persist from one program to the next.
+pub struct Vm {
+    stack: Vec<Value>,
+    // the frames of the callers of the running function
+    frames: Vec<CallFrame>,
+    // those of the script
+    globals: Rc<RefCell<Environment>>,
+    // sorted by stack slot
+    open_upvalues: Vec<Rc<RefCell<Upvalue>>>,
+    heap: Heap,
+    // the most calls in progress before a stack overflow
+    max_depth: usize,
+    modules: Modules,
+    // for the natives of every module
+    script_args: Vec<String>,
+    // where the stack and each instruction are printed before it runs
+    trace: Option<Box<dyn Write>>,
+}
+
+impl Vm {
+    // `script_args` are the command-line arguments returned by `args()`.
+    pub fn with_args(script_args: Vec<String>) -> Self {
+        let mut globals = Environment::default();
+        natives::define(&mut globals, script_args.clone());
+
+        let globals = Rc::new(RefCell::new(globals));
+        let mut heap = Heap::default();
+        heap.track_environment(&globals);
+        Vm {
+            stack: Vec::new(),
+            frames: Vec::new(),
+            globals,
+            open_upvalues: Vec::new(),
+            heap,
+            max_depth: MAX_DEPTH,
+            modules: Modules::new(None, module::search_path(&[])),
+            script_args,
+            trace: None,
+        }
+    }
+
+    // Where imports are found, by default from the current directory and
+    // then `LOX_PATH`.
+    pub fn set_modules(&mut self, modules: Modules) {
+        self.modules = modules;
+    }
+
+    pub fn heap(&mut self) -> &mut Heap {
+        &mut self.heap
+    }
+
+    pub fn set_max_depth(&mut self, max_depth: usize) {
+        self.max_depth = max_depth;
+    }
+
+    pub fn set_trace(&mut self, trace: Box<dyn Write>) {
+        self.trace = Some(trace);
+    }
+
+    pub fn globals(&self) -> Vec<(String, Value)> {
+        self.globals
+            .borrow()
+            .values()
+            .iter()
+            .map(|(name, value)| (name.to_string(), value.clone()))
+            .collect()
+    }
+
+    // Expects `statements` to have been resolved.
+    pub fn interpret(&mut self, statements: &[Stmt]) -> Result<(), RuntimeError> {
+        let function = compiler::compile(statements).map_err(|error| RuntimeError {
+            line: error.line,
+            message: error.message,
+            trace: Vec::new(),
+        })?;
+        self.execute(function).map(|_| ())
+    }
+
+    pub fn evaluate(&mut self, expr: &Expr) -> Result<Value, RuntimeError> {
+        let function = compiler::compile_expression(expr).map_err(|error| RuntimeError {
+            line: error.line,
+            message: error.message,
+            trace: Vec::new(),
+        })?;
+        self.execute(function)
+    }
+
+    // Runs the top-level `function`, e.g., from a `.loxc` file, and returns
+    // its result. After an error the stack is reset, so the VM can be used
+    // again.
+    pub fn execute(&mut self, function: Function) -> Result<Value, RuntimeError> {
+        let closure = Rc::new(Closure {
+            function: Rc::new(function),
+            upvalues: Vec::new(),
+            globals: Rc::clone(&self.globals),
+        });
+        self.stack.push(Value::Closure(Rc::clone(&closure)));
+        let mut frame = CallFrame {
+            closure,
+            ip: 0,
+            base: self.stack.len() - 1,
+            module: None,
+        };
+
+        let mut result = self.run(&mut frame);
+        if let Err(error) = &mut result {
+            if error.trace.is_empty() {
+                error.trace = self.traceback(&frame, error.line);
+            }
+            // none of the modules being run is kept
+            let modules = std::iter::once(&frame)
+                .chain(&self.frames)
+                .filter(|frame| frame.module.is_some())
+                .count();
+            for _ in 0..modules {
+                self.modules.leave(None);
+            }
+            self.stack.clear();
+            self.frames.clear();
+            self.open_upvalues.clear();
+        }
+        result
+    }
+
+    // Innermost first, from `frame`, which stopped on `line`, down to the
+    // script.
+    fn traceback(&self, frame: &CallFrame, line: usize) -> Vec<TraceFrame> {
+        // callers are just past their call instruction
+        let callers = self.frames.iter().rev().map(|caller| {
+            let line = caller.closure.function.chunk.line(caller.ip - 1);
+            (caller, line)
+        });
+        let mut trace: Vec<_> = std::iter::once((frame, line))
+            .chain(callers)
+            .map(|(frame, line)| match &frame.module {
+                Some(module) => TraceFrame {
+                    function: None,
+                    module: Some(module.name.clone()),
+                    line,
+                },
+                None => TraceFrame {
+                    function: Some(frame.closure.function.name.clone()),
+                    module: None,
+                    line,
+                },
+            })
+            .collect();
+        trace.last_mut().unwrap().function = None;
+        trace
+    }
+
+    // Runs until the script returns, catching the errors there are handlers
+    // for.
+    fn run(&mut self, frame: &mut CallFrame) -> Result<Value, RuntimeError> {
+        loop {
+            match self.dispatch(frame) {
+                Ok(value) => return Ok(value),
+                Err(error) => self.catch(frame, error)?,
+            }
+        }
+    }
+
+    // Unwinds to the innermost handler around the instruction `frame`
+    // failed in, or around a call in progress, and gives it the error with
+    // its traceback. Without one, the error is returned and nothing is
+    // unwound.
+    fn catch(
+        &mut self,
+        frame: &mut CallFrame,
+        mut error: RuntimeError,
+    ) -> Result<(), RuntimeError> {
+        // each frame is past the opcode of the instruction it is in
+        let handler =
+            |frame: &CallFrame| frame.closure.function.chunk.handler(frame.ip - 1).copied();
+        let unwound = std::iter::once(&*frame)
+            .chain(self.frames.iter().rev())
+            .position(|frame| handler(frame).is_some());
+        let Some(unwound) = unwound else {
+            return Err(error);
+        };
+
+        if error.trace.is_empty() {
+            error.trace = self.traceback(frame, error.line);
+        }
+        for _ in 0..unwound {
+            if frame.module.is_some() {
+                self.modules.leave(None);
+            }
+            *frame = self.frames.pop().expect("the handler is in a caller");
+        }
+        let handler = handler(frame).expect("found above");
+        let depth = frame.base + handler.depth;
+        self.close_upvalues(depth);
+        self.stack.truncate(depth);
+        self.stack.push(Value::Error(Rc::new(error)));
+        frame.ip = handler.target;
+        Ok(())
+    }
+
+    // Runs instructions until the script returns or one of them fails.
+    fn dispatch(&mut self, frame: &mut CallFrame) -> Result<Value, RuntimeError> {
+        loop {
+            let offset = frame.ip;
+            if let Some(trace) = &mut self.trace {
+                Self::trace_instruction(trace, &self.stack, frame);
+            }
+            let error = |frame: &CallFrame, message: String| RuntimeError {
+                line: frame.closure.function.chunk.line(offset),
+                message,
+                trace: Vec::new(),
+            };
+
+            let byte = frame.read_byte();
+            let op = OpCode::try_from(byte)
+                .unwrap_or_else(|byte| panic!("invalid opcode {} at {}", byte, offset));
+            match op {
+                OpCode::Constant => {
+                    let value = match frame.constant() {
+                        Constant::Number(n) => Value::Number(*n),
+                        Constant::String(s) => Value::String(s.clone()),
+                        constant => unreachable!("{:?} isn't a value", constant),
+                    };
+                    self.stack.push(value);
+                }
+                OpCode::Nil => self.stack.push(Value::Nil),
+                OpCode::True => self.stack.push(Value::Boolean(true)),
+                OpCode::False => self.stack.push(Value::Boolean(false)),
+                OpCode::Pop => {
+                    self.pop();
+                }
+                OpCode::GetLocal => {
+                    let slot = frame.base + frame.read_byte() as usize;
+                    self.stack.push(self.stack[slot].clone());
+                }
+                OpCode::SetLocal => {
+                    let slot = frame.base + frame.read_byte() as usize;
+                    self.stack[slot] = self.peek().clone();
+                }
+                OpCode::GetGlobal => {
+                    let name = frame.name();
+                    let value = frame.closure.globals.borrow().values().get(&name).cloned();
+                    match value {
+                        Some(value) => self.stack.push(value),
+                        None => {
+                            return Err(error(frame, format!("Undefined variable '{}'.", name)))
+                        }
+                    }
+                }
+                OpCode::DefineGlobal => {
+                    let name = frame.name();
+                    let value = self.pop();
+                    frame.closure.globals.borrow_mut().define(name, value);
+                }
+                OpCode::SetGlobal => {
+                    let name = frame.name();
+                    let value = self.peek().clone();
+                    let defined = frame.closure.globals.borrow().values().contains_key(&name);
+                    if !defined {
+                        return Err(error(frame, format!("Undefined variable '{}'.", name)));
+                    }
+                    frame.closure.globals.borrow_mut().define(name, value);
+                }
+                OpCode::GetUpvalue => {
+                    let index = frame.read_byte() as usize;
+                    let value = match &*frame.closure.upvalues[index].borrow() {
+                        Upvalue::Open(slot) => self.stack[*slot].clone(),
+                        Upvalue::Closed(value) => value.clone(),
+                    };
+                    self.stack.push(value);
+                }
+                OpCode::SetUpvalue => {
+                    let index = frame.read_byte() as usize;
+                    let value = self.peek().clone();
+                    match &mut *frame.closure.upvalues[index].borrow_mut() {
+                        Upvalue::Open(slot) => self.stack[*slot] = value,
+                        Upvalue::Closed(closed) => *closed = value,
+                    }
+                }
+                OpCode::Equal => {
+                    let (a, b) = self.pop_pair();
+                    self.stack.push(Value::Boolean(a.is_equal(&b)));
+                }
+                OpCode::NotEqual => {
+                    let (a, b) = self.pop_pair();
+                    self.stack.push(Value::Boolean(!a.is_equal(&b)));
+                }
+                OpCode::Greater
+                | OpCode::GreaterEqual
+                | OpCode::Less
+                | OpCode::LessEqual
+                | OpCode::Subtract
+                | OpCode::Multiply
+                | OpCode::Divide => {
+                    let (a, b) = match self.pop_pair() {
+                        (Value::Number(a), Value::Number(b)) => (a, b),
+                        _ => return Err(error(frame, "Operands must be numbers.".to_string())),
+                    };
+                    self.stack.push(match op {
+                        OpCode::Greater => Value::Boolean(a > b),
+                        OpCode::GreaterEqual => Value::Boolean(a >= b),
+                        OpCode::Less => Value::Boolean(a < b),
+                        OpCode::LessEqual => Value::Boolean(a <= b),
+                        OpCode::Subtract => Value::Number(a - b),
+                        OpCode::Multiply => Value::Number(a * b),
+                        _ => Value::Number(a / b),
+                    });
+                }
+                OpCode::Add => {
+                    let value = match self.pop_pair() {
+                        (Value::Number(a), Value::Number(b)) => Value::Number(a + b),
+                        (Value::String(a), Value::String(b)) => {
+                            Value::String(format!("{}{}", a, b).into())
+                        }
+                        _ => {
+                            return Err(error(
+                                frame,
+                                "Operands must be two numbers or two strings.".to_string(),
+                            ))
+                        }
+                    };
+                    self.stack.push(value);
+                }
+                OpCode::Not => {
+                    let value = self.pop();
+                    self.stack.push(Value::Boolean(!value.is_truthy()));
+                }
+                OpCode::Negate => match self.pop() {
+                    Value::Number(n) => self.stack.push(Value::Number(-n)),
+                    _ => return Err(error(frame, "Operand must be a number.".to_string())),
+                },
+                OpCode::Print => println!("{}", stringify(&self.pop())),
+                OpCode::Jump => {
+                    let jump = frame.read_u16() as usize;
+                    frame.ip += jump;
+                }
+                OpCode::JumpIfFalse => {
+                    let jump = frame.read_u16() as usize;
+                    if !self.peek().is_truthy() {
+                        frame.ip += jump;
+                    }
+                }
+                OpCode::Loop => {
+                    let jump = frame.read_u16() as usize;
+                    frame.ip -= jump;
+                }
+                OpCode::Call => {
+                    let count = frame.read_byte() as usize;
+                    let callee = self.stack[self.stack.len() - count - 1].clone();
+                    match callee {
+                        Value::Closure(closure) => {
+                            let arity = closure.function.arity;
+                            if count != arity {
+                                let message =
+                                    format!("Expected {} arguments but got {}.", arity, count);
+                                return Err(error(frame, message));
+                            }
+                            // the script is at the bottom, not a call
+                            if self.frames.len() >= self.max_depth {
+                                return Err(error(frame, "Stack overflow.".to_string()));
+                            }
+                            let callee = CallFrame {
+                                closure,
+                                ip: 0,
+                                base: self.stack.len() - count - 1,
+                                module: None,
+                            };
+                            self.frames.push(std::mem::replace(frame, callee));
+                        }
+                        Value::Native(native) => {
+                            if count != native.arity {
+                                let message = format!(
+                                    "Expected {} arguments but got {}.",
+                                    native.arity, count
+                                );
+                                return Err(error(frame, message));
+                            }
+                            let arguments = self.stack.len() - count;
+                            let result = (native.function)(&self.stack[arguments..])
+                                .map_err(|message| error(frame, message))?;
+                            self.heap.track_value(&result);
+                            self.stack.truncate(arguments - 1);
+                            self.stack.push(result);
+                        }
+                        _ => {
+                            let message = "Can only call functions and classes.".to_string();
+                            return Err(error(frame, message));
+                        }
+                    }
+                }
+                OpCode::Closure => {
+                    let function = match frame.constant() {
+                        Constant::Function(function) => Rc::clone(function),
+                        constant => {
+                            unreachable!("closures are made of functions, not {:?}", constant)
+                        }
+                    };
+                    let mut upvalues = Vec::with_capacity(function.upvalues);
+                    for _ in 0..function.upvalues {
+                        let is_local = frame.read_byte() == 1;
+                        let index = frame.read_byte() as usize;
+                        upvalues.push(match is_local {
+                            true => self.capture_upvalue(frame.base + index),
+                            false => Rc::clone(&frame.closure.upvalues[index]),
+                        });
+                    }
+                    let closure = Value::Closure(Rc::new(Closure {
+                        function,
+                        upvalues,
+                        globals: Rc::clone(&frame.closure.globals),
+                    }));
+                    self.heap.track_value(&closure);
+                    self.stack.push(closure);
+                }
+                OpCode::CloseUpvalue => {
+                    self.close_upvalues(self.stack.len() - 1);
+                    self.pop();
+                }
+                OpCode::Return => {
+                    let result = self.pop();
+                    self.close_upvalues(frame.base);
+                    self.stack.truncate(frame.base);
+                    let result = match frame.module.take() {
+                        Some(module) => {
+                            self.modules.leave(Some(&module));
+                            Value::Module(module)
+                        }
+                        None => result,
+                    };
+                    match self.frames.pop() {
+                        Some(caller) => {
+                            *frame = caller;
+                            self.stack.push(result);
+                        }
+                        None => return Ok(result),
+                    }
+                }
+                OpCode::Throw => {
+                    return Err(match self.pop() {
+                        Value::Error(error) => RuntimeError::clone(&error),
+                        value => error(frame, stringify(&value)),
+                    });
+                }
+                OpCode::GetProperty => {
+                    let name = frame.name();
+                    let line = frame.closure.function.chunk.line(offset);
+                    let value = self.pop().property(&name, line)?;
+                    self.stack.push(value);
+                }
+                OpCode::Import => {
+                    let path = match frame.constant() {
+                        Constant::String(path) => Rc::clone(path),
+                        constant => unreachable!("module paths are strings, not {:?}", constant),
+                    };
+                    let line = frame.closure.function.chunk.line(offset);
+                    match self.import(&path, line)? {
+                        Import::Ran(module) => self.stack.push(Value::Module(module)),
+                        Import::Run(closure, module) => {
+                            self.stack.push(Value::Closure(Rc::clone(&closure)));
+                            let top_level = CallFrame {
+                                closure,
+                                ip: 0,
+                                base: self.stack.len() - 1,
+                                module: Some(module),
+                            };
+                            self.frames.push(std::mem::replace(frame, top_level));
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    // Finds the module at `path`, imported on `line`, and unless it ran
+    // already, compiles its top level to run in a global environment of its
+    // own.
+    fn import(&mut self, path: &str, line: usize) -> Result<Import, RuntimeError> {
+        let error = |message| RuntimeError::at(line, message);
+        let (file, found) = self
+            .modules
+            .find(path)
+            .ok_or_else(|| error(format!("Cannot find module '{}'.", path)))?;
+        if let Some(module) = self.modules.cached(&file) {
+            return Ok(Import::Ran(module));
+        }
+        let statements = module::load(&found).map_err(error)?;
+        let name = found.display().to_string();
+        let function = compiler::compile(&statements).map_err(|e| {
+            error(format!(
+                "Cannot import '{0}':\n{0}:{1}: error: {2}",
+                name, e.line, e.message
+            ))
+        })?;
+        self.modules.enter(file, found).map_err(error)?;
+
+        let mut globals = Environment::default();
+        natives::define(&mut globals, self.script_args.clone());
+        let globals = Rc::new(RefCell::new(globals));
+        self.heap.track_environment(&globals);
+        let closure = Rc::new(Closure {
+            function: Rc::new(function),
+            upvalues: Vec::new(),
+            globals: Rc::clone(&globals),
+        });
+        self.heap.track(Object::Closure(Rc::downgrade(&closure)));
+        let module = Rc::new(Module {
+            name,
+            globals,
+            exports: module::exports(&statements),
+        });
+        self.heap.track(Object::Module(Rc::downgrade(&module)));
+        Ok(Import::Run(closure, module))
+    }
+
+    // e.g., `          [ <fn script> ][ 1 ]` and then the instruction
+    fn trace_instruction(trace: &mut dyn Write, stack: &[Value], frame: &CallFrame) {
+        let stack = stack
+            .iter()
+            .map(|value| format!("[ {} ]", value))
+            .collect::<String>();
+        let (instruction, _) = disassembler::instruction(&frame.closure.function.chunk, frame.ip);
+        writeln!(trace, "          {}\n{}", stack, instruction).expect("failed printing the trace");
+    }
+
+    fn pop(&mut self) -> Value {
+        self.stack.pop().expect("the compiler balances the stack")
+    }
+
+    // Pops the two operands of a binary operator, left first.
+    fn pop_pair(&mut self) -> (Value, Value) {
+        let b = self.pop();
+        let a = self.pop();
+        (a, b)
+    }
+
+    fn peek(&self) -> &Value {
+        self.stack.last().expect("the compiler balances the stack")
+    }
+
+    fn capture_upvalue(&mut self, slot: usize) -> Rc<RefCell<Upvalue>> {
+        let position = self
+            .open_upvalues
+            .iter()
+            .position(|upvalue| matches!(&*upvalue.borrow(), Upvalue::Open(open) if *open >= slot));
+        if let Some(i) = position {
+            if matches!(&*self.open_upvalues[i].borrow(), Upvalue::Open(open) if *open == slot) {
+                return Rc::clone(&self.open_upvalues[i]);
+            }
+        }
+
+        let upvalue = Rc::new(RefCell::new(Upvalue::Open(slot)));
+        self.heap.track(Object::Upvalue(Rc::downgrade(&upvalue)));
+        let i = position.unwrap_or(self.open_upvalues.len());
+        self.open_upvalues.insert(i, Rc::clone(&upvalue));
+        upvalue
+    }
+
+    // Closes the upvalues of the stack slots from `slot` up.
+    fn close_upvalues(&mut self, slot: usize) {
+        let first = self
+            .open_upvalues
+            .iter()
+            .position(|upvalue| matches!(&*upvalue.borrow(), Upvalue::Open(open) if *open >= slot));
+        let Some(first) = first else {
+            return;
+        };
+        for upvalue in self.open_upvalues.drain(first..) {
+            let mut upvalue = upvalue.borrow_mut();
+            if let Upvalue::Open(open) = *upvalue {
+                *upvalue = Upvalue::Closed(self.stack[open].clone());
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{parser::Parser, resolver::Resolver, scanner::Scanner};
+
+    fn run(vm: &mut Vm, source: &str) -> Result<(), RuntimeError> {
+        let tokens = Scanner::new(source).scan_tokens();
+        let statements = Parser::new(tokens).parse();
+        Resolver::new().resolve(&statements);
+        vm.interpret(&statements)
+    }
+
+    fn global(vm: &Vm, name: &str) -> Value {
+        vm.globals
+            .borrow()
+            .values()
+            .get(&Symbol::intern(name))
+            .cloned()
+            .unwrap()
+    }
+
+    // What the VM traces, shared with the test reading it.
+    struct Trace(Rc<RefCell<Vec<u8>>>);
+
+    impl Write for Trace {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.borrow_mut().write(buf)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_trace_names_the_line_of_each_instruction() {
+        let trace = Rc::new(RefCell::new(Vec::new()));
+        let mut vm = Vm::with_args(Vec::new());
+        vm.set_trace(Box::new(Trace(Rc::clone(&trace))));
+        run(&mut vm, "var a;\nvar b =\n  0;\nb = a;").unwrap();
+
+        let trace = String::from_utf8(trace.take()).unwrap();
+        let instructions = trace
+            .lines()
+            .filter(|line| !line.starts_with(' '))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            instructions,
+            [
+                "0000    1 Nil",
+                "0001    | DefineGlobal        0 'a'",
+                "0004    3 Constant            1 '0'",
+                "0007    2 DefineGlobal        2 'b'",
+                "0010    4 GetGlobal           0 'a'",
+                "0013    | SetGlobal           2 'b'",
+                "0016    | Pop",
+                "0017    | Nil",
+                "0018    | Return",
+            ]
+        );
+        assert!(trace.starts_with("          [ <fn script> ]\n"));
+    }
+
+    #[test]
+    fn test_upvalues_are_shared_and_closed() {
+        let mut vm = Vm::with_args(Vec::new());
+        let program = r#"
+            var get;
+            var set;
+            {
+                var a = 1;
+                fun g() { return a; }
+                fun s(value) { a = value; }
+                get = g;
+                set = s;
+            }
+            set(5);
+            var shared = get();
+
+            var first;
+            for (var i = 0; i < 3; i = i + 1) {
+                var j = i;
+                fun f() { return j; }
+                if (i == 0) first = f;
+            }
+            var captured = first();
+        "#;
+        run(&mut vm, program).unwrap();
+
+        assert!(matches!(global(&vm, "shared"), Value::Number(n) if n == 5.0));
+        assert!(matches!(global(&vm, "captured"), Value::Number(n) if n == 0.0));
+        assert_eq!(global(&vm, "get").to_string(), "<fn g>");
+    }
+
+    #[test]
+    fn test_errors_reset_the_stack() {
+        let mut vm = Vm::with_args(Vec::new());
+        let error = run(&mut vm, "fun f() { return f(); }\nf();").unwrap_err();
+        assert_eq!(error.message, "Stack overflow.");
+        assert_eq!(error.trace.len(), MAX_DEPTH + 1);
+        assert!(vm.stack.is_empty() && vm.frames.is_empty());
+
+        let error = run(&mut vm, "fun g(a) {\n  return -a;\n}\ng(\"x\");").unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "error at line 2: Operand must be a number.\n[line 2] in g()\n[line 4] in script"
+        );
+
+        run(&mut vm, "var ok = g(1);").unwrap();
+        assert!(matches!(global(&vm, "ok"), Value::Number(n) if n == -1.0));
+    }
+}